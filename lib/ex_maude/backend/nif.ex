@@ -125,11 +125,1105 @@ defmodule ExMaude.Backend.NIF do
     end
 
     @doc false
-    @spec execute(reference(), String.t()) :: binary() | {:ok, String.t()} | {:error, term()}
+    @spec start_with_opts(String.t(), keyword()) :: reference() | {:error, term()}
+    def start_with_opts(_maude_path, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec start_async(String.t(), keyword()) :: reference() | {:error, term()}
+    def start_async(_maude_path, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec start_mock(
+            %{String.t() => String.t() | {String.t(), String.t()}}
+            | [{String.t(), String.t() | {String.t(), String.t()}}]
+          ) :: reference() | {:error, term()}
+    def start_mock(_transcript) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec await_ready(reference(), non_neg_integer()) :: :ok | {:error, term()}
+    def await_ready(_handle, _timeout) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec lifecycle(reference()) :: :starting | :ready | :draining | :stopped | :broken
+    def lifecycle(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec execute(reference(), iodata()) ::
+            binary()
+            | {:ok, String.t()}
+            | {:spilled, String.t(), non_neg_integer()}
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
     def execute(_handle, _command) do
       :erlang.nif_error(:nif_not_loaded)
     end
 
+    @doc false
+    @spec execute(reference(), iodata(), keyword()) ::
+            String.t()
+            | tuple()
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def execute(_handle, _command, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec execute_io(reference(), iodata()) ::
+            String.t()
+            | {:spilled, String.t(), non_neg_integer()}
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def execute_io(_handle, _command) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec execute_io(reference(), iodata(), keyword()) ::
+            String.t()
+            | tuple()
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def execute_io(_handle, _command, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec execute_term(reference(), atom(), String.t(), String.t()) ::
+            String.t()
+            | {:spilled, String.t(), non_neg_integer()}
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def execute_term(_handle, _kind, _module, _term) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec execute_term(reference(), atom(), String.t(), String.t(), keyword()) ::
+            String.t()
+            | tuple()
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def execute_term(_handle, _kind, _module, _term, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec execute_parsed(reference(), iodata()) ::
+            {String.t(), String.t() | nil, list()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def execute_parsed(_handle, _command) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec execute_parsed(reference(), iodata(), keyword()) ::
+            {String.t(), String.t() | nil, list()}
+            | boolean()
+            | integer()
+            | float()
+            | binary()
+            | atom()
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def execute_parsed(_handle, _command, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec reduce_in(reference(), String.t(), String.t()) ::
+            {String.t(), String.t() | nil, list()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def reduce_in(_handle, _module, _term) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec check(reference(), String.t(), String.t()) ::
+            boolean()
+            | {:error, {:not_bool, String.t()} | {:undecided, tuple()} | term()}
+            | {:error, :desync, String.t()}
+    def check(_handle, _module, _term) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec lease(reference(), pid()) :: :ok | {:error, :leased | term()}
+    def lease(_handle, _pid) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec lease(reference(), pid(), keyword()) :: :ok | {:error, :leased | term()}
+    def lease(_handle, _pid, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec release(reference()) :: :ok | {:error, :leased}
+    def release(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec execute_traced(reference(), iodata(), keyword()) ::
+            %{output: String.t(), events: [map()]}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def execute_traced(_handle, _command, _trace_opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec search_next(reference(), pos_integer()) ::
+            [%{number: pos_integer(), state: non_neg_integer(), substitution: list()}]
+            | :exhausted
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def search_next(_handle, _n) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec unify(reference(), String.t(), String.t()) ::
+            %{
+              unifiers: [%{number: pos_integer(), substitution: list()}],
+              complete: boolean()
+            }
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def unify(_handle, _module, _problem) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec unify(reference(), String.t(), String.t(), keyword()) ::
+            %{
+              unifiers: [%{number: pos_integer(), substitution: list()}],
+              complete: boolean()
+            }
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def unify(_handle, _module, _problem, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec variant_unify(reference(), String.t(), String.t()) ::
+            %{
+              unifiers: [%{number: pos_integer(), substitution: list()}],
+              complete: boolean()
+            }
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def variant_unify(_handle, _module, _problem) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec variant_unify(reference(), String.t(), String.t(), keyword()) ::
+            %{
+              unifiers: [%{number: pos_integer(), substitution: list()}],
+              complete: boolean()
+            }
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def variant_unify(_handle, _module, _problem, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec match(reference(), String.t(), String.t(), String.t()) ::
+            %{
+              matches: [
+                %{number: pos_integer(), substitution: list(), context: :whole | tuple() | nil}
+              ],
+              complete: boolean()
+            }
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def match(_handle, _module, _pattern, _subject) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec match(reference(), String.t(), String.t(), String.t(), keyword()) ::
+            %{
+              matches: [
+                %{number: pos_integer(), substitution: list(), context: :whole | tuple() | nil}
+              ],
+              complete: boolean()
+            }
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def match(_handle, _module, _pattern, _subject, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec describe_module(reference(), String.t()) ::
+            %{
+              name: String.t(),
+              sorts: [String.t()],
+              subsorts: [{String.t(), String.t()}],
+              ops: [
+                %{
+                  name: String.t(),
+                  arity: [String.t()],
+                  coarity: String.t(),
+                  attributes: [String.t()]
+                }
+              ],
+              equations: non_neg_integer(),
+              rules: non_neg_integer(),
+              memberships: non_neg_integer()
+            }
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def describe_module(_handle, _module) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec list_modules(reference()) ::
+            [%{name: String.t(), kind: :fmod | :mod | :fth | :th | :smod | :sth | :omod | :oth}]
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def list_modules(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec module_exists?(reference(), String.t()) ::
+            boolean() | {:error, :desync, String.t()} | {:error, term()}
+    def module_exists?(_handle, _module) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec send_bytes(reference(), binary()) :: :ok | {:error, term()}
+    def send_bytes(_handle, _data) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec recv_until(reference(), binary(), non_neg_integer()) :: binary() | {:error, term()}
+    def recv_until(_handle, _pattern, _timeout) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec resync(reference(), non_neg_integer()) :: :ok | {:error, term()}
+    def resync(_handle, _timeout) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec build_term(term()) :: String.t() | {:error, term()}
+    def build_term(_ast) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec loop_send(reference(), String.t()) :: String.t() | {:error, term()}
+    def loop_send(_handle, _input) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec validate_install(String.t(), keyword()) :: %{
+            ok: boolean(),
+            version: String.t() | nil,
+            prelude: boolean(),
+            loaded: [String.t()],
+            failed: [{String.t(), String.t()}],
+            error: String.t() | nil
+          }
+    def validate_install(_maude_path, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec discover_maude() :: [
+            %{
+              path: String.t(),
+              source: :env | :path | :common,
+              version: String.t() | nil,
+              error: String.t() | nil
+            }
+          ]
+    def discover_maude do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec pool_start(String.t(), pos_integer(), keyword()) :: reference() | {:error, term()}
+    def pool_start(_maude_path, _size, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec pool_execute(reference(), iodata()) ::
+            String.t()
+            | tuple()
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def pool_execute(_pool, _command) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec pool_execute(reference(), iodata(), keyword()) ::
+            String.t()
+            | tuple()
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def pool_execute(_pool, _command, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec pool_execute_batch(reference(), [iodata()], keyword()) ::
+            [
+              String.t()
+              | tuple()
+              | {:error, map(), String.t()}
+              | {:error, :desync, String.t()}
+              | {:error, term()}
+            ]
+            | {:error, term()}
+    def pool_execute_batch(_pool, _commands, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec pool_reload(reference(), [Path.t()], keyword()) :: :ok | {:error, term()}
+    def pool_reload(_pool, _files, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec pool_prewarm(reference(), non_neg_integer()) :: non_neg_integer() | {:error, term()}
+    def pool_prewarm(_pool, _n) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec pool_resize(reference(), pos_integer()) :: pos_integer() | {:error, term()}
+    def pool_resize(_pool, _size) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec pool_status(reference()) ::
+            %{
+              size: non_neg_integer(),
+              ready: non_neg_integer(),
+              busy: non_neg_integer(),
+              spares: non_neg_integer(),
+              spares_pending: non_neg_integer(),
+              recycled: non_neg_integer(),
+              commands: [non_neg_integer()]
+            }
+            | {:error, term()}
+    def pool_status(_pool) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec pool_stop(reference()) :: :ok | {:error, term()}
+    def pool_stop(_pool) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec stats(reference()) :: %{
+            commands: non_neg_integer(),
+            rewrites: non_neg_integer(),
+            cpu_ms: non_neg_integer(),
+            real_ms: non_neg_integer(),
+            rewrites_per_second: float() | nil,
+            invalid_utf8: non_neg_integer(),
+            cache_hits: non_neg_integer(),
+            cache_misses: non_neg_integer(),
+            bytes_read: non_neg_integer(),
+            queue_depth: non_neg_integer(),
+            overloaded: non_neg_integer(),
+            slow_lock_waits: non_neg_integer(),
+            longest_lock_wait_ms: non_neg_integer(),
+            lock_waiting_ms: non_neg_integer()
+          }
+    def stats(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec write_progress(reference()) ::
+            %{written: non_neg_integer(), total: pos_integer()} | nil
+    def write_progress(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec config(reference()) :: %{
+            maude_path: String.t(),
+            args: [String.t()],
+            preload: [String.t()],
+            select: String.t() | nil,
+            env: :inherit,
+            os_pid: non_neg_integer(),
+            prompt: String.t(),
+            trim: :both | :trailing | :none,
+            startup_timeout: non_neg_integer(),
+            lock_wait_threshold: non_neg_integer(),
+            spill_threshold: non_neg_integer(),
+            spill_dir: String.t(),
+            max_memory: non_neg_integer() | nil,
+            max_cpu_seconds: non_neg_integer() | nil,
+            pipeline_depth: non_neg_integer(),
+            cache_size: non_neg_integer(),
+            full_maude: String.t() | nil,
+            history_size: non_neg_integer(),
+            read_buffer_size: pos_integer(),
+            max_line_length: non_neg_integer(),
+            read_only: boolean(),
+            max_queue: non_neg_integer() | nil,
+            sequence_check: boolean(),
+            wire_log: String.t() | nil,
+            sandbox: String.t() | nil
+          }
+    def config(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec select_module(reference(), String.t()) ::
+            :ok | {:error, :desync, String.t()} | {:error, term()}
+    def select_module(_handle, _module) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec current_module(reference()) ::
+            String.t() | nil | {:error, :desync, String.t()} | {:error, term()}
+    def current_module(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec submit(reference(), iodata()) :: non_neg_integer() | {:error, term()}
+    def submit(_handle, _command) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec collect(reference(), non_neg_integer()) ::
+            String.t()
+            | {:spilled, String.t(), non_neg_integer()}
+            | {:error, map(), String.t()}
+            | {:error, term()}
+    def collect(_handle, _ticket) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec collect(reference(), non_neg_integer(), keyword()) ::
+            String.t() | tuple() | {:error, map(), String.t()} | {:error, term()}
+    def collect(_handle, _ticket, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec send_command(reference(), iodata()) :: :ok | {:error, term()}
+    def send_command(_handle, _command) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec await_output(reference(), non_neg_integer()) ::
+            String.t()
+            | {:error, map(), String.t()}
+            | {:error, term()}
+    def await_output(_handle, _timeout) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec bench(reference(), iodata(), pos_integer()) ::
+            %{
+              iterations: pos_integer(),
+              total_us: non_neg_integer(),
+              min_us: non_neg_integer(),
+              mean_us: non_neg_integer(),
+              p50_us: non_neg_integer(),
+              p95_us: non_neg_integer(),
+              p99_us: non_neg_integer(),
+              max_us: non_neg_integer(),
+              histogram: [{pos_integer(), pos_integer()}]
+            }
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def bench(_handle, _command, _iterations) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec proc_info(reference()) ::
+            %{
+              os_pid: non_neg_integer(),
+              rss_bytes: non_neg_integer(),
+              user_cpu_ms: non_neg_integer(),
+              system_cpu_ms: non_neg_integer(),
+              threads: non_neg_integer(),
+              uptime_ms: non_neg_integer(),
+              paused: boolean()
+            }
+            | {:error, term()}
+    def proc_info(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec load_string(reference(), iodata()) ::
+            [String.t()]
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def load_string(_handle, _source) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec search_graph(reference()) ::
+            map() | {:error, map(), String.t()} | {:error, :desync, String.t()} | {:error, term()}
+    def search_graph(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec search_path(reference(), non_neg_integer()) ::
+            map() | {:error, map(), String.t()} | {:error, :desync, String.t()} | {:error, term()}
+    def search_path(_handle, _state) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec subscribe(reference(), pid()) :: :ok | {:error, term()}
+    def subscribe(_handle, _pid) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec subscribe(reference(), pid(), keyword()) :: :ok | {:error, term()}
+    def subscribe(_handle, _pid, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec unsubscribe(reference()) :: :ok
+    def unsubscribe(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec subscribe_events(reference(), pid()) :: :ok | {:error, term()}
+    def subscribe_events(_handle, _pid) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec unsubscribe_events(reference(), pid()) :: boolean()
+    def unsubscribe_events(_handle, _pid) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec pause(reference()) :: :ok | {:error, term()}
+    def pause(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec resume(reference()) :: :ok | {:error, term()}
+    def resume(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec history(reference(), non_neg_integer()) :: [
+            %{command: String.t(), response: String.t(), at: non_neg_integer()}
+          ]
+    def history(_handle, _n) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec export_transcript(reference(), String.t()) :: :ok | {:error, term()}
+    def export_transcript(_handle, _path) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec version(reference()) ::
+            %{
+              version: String.t() | nil,
+              release: {non_neg_integer(), non_neg_integer(), non_neg_integer()} | nil,
+              capabilities: %{
+                variant_unify: boolean(),
+                get_variants: boolean(),
+                vu_narrow: boolean(),
+                fvu_narrow: boolean(),
+                smt: boolean()
+              }
+            }
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def version(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec smt_check(reference(), String.t(), String.t()) ::
+            :sat | :unsat | :unknown | {:error, :desync, String.t()} | {:error, term()}
+    def smt_check(_handle, _module, _formula) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec smt_search(reference(), String.t(), String.t()) ::
+            [
+              %{
+                number: pos_integer(),
+                state: map(),
+                substitution: list(),
+                constraint: map()
+              }
+            ]
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def smt_search(_handle, _module, _query) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec smt_search(reference(), String.t(), String.t(), keyword()) ::
+            [
+              %{
+                number: pos_integer(),
+                state: map(),
+                substitution: list(),
+                constraint: map()
+              }
+            ]
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def smt_search(_handle, _module, _query, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec vu_narrow(reference(), String.t(), String.t()) ::
+            [
+              %{
+                number: pos_integer(),
+                state: map(),
+                substitution: list(),
+                unifier: list(),
+                steps: [map()]
+              }
+            ]
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def vu_narrow(_handle, _module, _query) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec vu_narrow(reference(), String.t(), String.t(), keyword()) ::
+            [
+              %{
+                number: pos_integer(),
+                state: map(),
+                substitution: list(),
+                unifier: list(),
+                steps: [map()]
+              }
+            ]
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def vu_narrow(_handle, _module, _query, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec fvu_narrow(reference(), String.t(), String.t()) ::
+            [
+              %{
+                number: pos_integer(),
+                state: map(),
+                substitution: list(),
+                unifier: list(),
+                steps: [map()]
+              }
+            ]
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def fvu_narrow(_handle, _module, _query) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec fvu_narrow(reference(), String.t(), String.t(), keyword()) ::
+            [
+              %{
+                number: pos_integer(),
+                state: map(),
+                substitution: list(),
+                unifier: list(),
+                steps: [map()]
+              }
+            ]
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def fvu_narrow(_handle, _module, _query, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec os_pid(reference()) :: non_neg_integer() | {:error, term()}
+    def os_pid(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec signal(reference(), atom() | pos_integer()) :: :ok | {:error, term()}
+    def signal(_handle, _signal) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec run(reference(), tuple()) ::
+            String.t()
+            | {:spilled, String.t(), non_neg_integer()}
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def run(_handle, _command) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec run(reference(), tuple(), keyword()) ::
+            String.t()
+            | tuple()
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def run(_handle, _command, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec set_option(reference(), atom(), boolean()) ::
+            :ok | {:error, :desync, String.t()} | {:error, term()}
+    def set_option(_handle, _option, _value) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec get_options(reference()) :: [{atom(), boolean()}]
+    def get_options(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec start_shadowed(String.t(), keyword()) :: reference() | {:error, term()}
+    def start_shadowed(_maude_path, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec shadowed_execute(reference(), iodata()) ::
+            String.t() | {:error, :desync, String.t()} | {:error, term()}
+    def shadowed_execute(_handle, _command) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec shadowed_execute(reference(), iodata(), keyword()) ::
+            String.t() | tuple() | {:error, :desync, String.t()} | {:error, term()}
+    def shadowed_execute(_handle, _command, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec shadowed_status(reference()) ::
+            %{
+              primary_alive: boolean(),
+              standby: boolean(),
+              failovers: non_neg_integer(),
+              journal: non_neg_integer()
+            }
+            | {:error, term()}
+    def shadowed_status(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec shadowed_stop(reference()) :: :ok | {:error, term()}
+    def shadowed_stop(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec start_hibernating(String.t(), pos_integer(), keyword()) ::
+            reference() | {:error, term()}
+    def start_hibernating(_maude_path, _idle_timeout, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec hibernating_execute(reference(), iodata()) ::
+            String.t() | {:error, :desync, String.t()} | {:error, term()}
+    def hibernating_execute(_handle, _command) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec hibernating_execute(reference(), iodata(), keyword()) ::
+            String.t() | tuple() | {:error, :desync, String.t()} | {:error, term()}
+    def hibernating_execute(_handle, _command, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec hibernating_status(reference()) ::
+            %{
+              awake: boolean(),
+              hibernations: non_neg_integer(),
+              journal: non_neg_integer(),
+              idle_ms: non_neg_integer()
+            }
+            | {:error, term()}
+    def hibernating_status(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec hibernating_stop(reference()) :: :ok | {:error, term()}
+    def hibernating_stop(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec start_resilient(String.t(), keyword()) :: reference() | {:error, term()}
+    def start_resilient(_maude_path, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec resilient_execute(reference(), iodata()) ::
+            String.t()
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def resilient_execute(_handle, _command) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec resilient_execute(reference(), iodata(), keyword()) ::
+            String.t()
+            | tuple()
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def resilient_execute(_handle, _command, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec resilient_status(reference()) ::
+            %{
+              alive: boolean(),
+              restarts: non_neg_integer(),
+              journal: non_neg_integer()
+            }
+            | {:error, term()}
+    def resilient_status(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec resilient_stop(reference()) :: :ok | {:error, term()}
+    def resilient_stop(_handle) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec resilient_subscribe_events(reference(), pid()) :: :ok | {:error, term()}
+    def resilient_subscribe_events(_handle, _pid) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec resilient_unsubscribe_events(reference(), pid()) :: boolean() | {:error, term()}
+    def resilient_unsubscribe_events(_handle, _pid) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec execute_profiled(reference(), iodata()) ::
+            %{output: String.t(), entries: [map()]}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def execute_profiled(_ref, _command) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec erewrite(reference(), String.t(), String.t(), keyword()) ::
+            %{
+              result: map(),
+              configuration: %{objects: [map()], messages: [map()]} | nil,
+              rewrites: non_neg_integer() | nil,
+              timed_out: boolean()
+            }
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def erewrite(_ref, _module, _term, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec execute_compare(reference(), reference(), iodata()) ::
+            %{
+              equal: boolean(),
+              compared: :terms | :lines,
+              differences: [
+                %{
+                  item: String.t(),
+                  path: [non_neg_integer()],
+                  left: String.t() | nil,
+                  right: String.t() | nil
+                }
+              ],
+              left_output: String.t(),
+              right_output: String.t()
+            }
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def execute_compare(_left, _right, _command) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec execute_expect(reference(), iodata(), String.t()) ::
+            %{
+              equal: boolean(),
+              compared: :terms | :lines,
+              differences: [
+                %{
+                  item: String.t(),
+                  path: [non_neg_integer()],
+                  left: String.t() | nil,
+                  right: String.t() | nil
+                }
+              ],
+              left_output: String.t(),
+              right_output: String.t()
+            }
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def execute_expect(_ref, _command, _expected) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec start_named(String.t(), String.t()) :: :ok | {:error, term()}
+    def start_named(_name, _maude_path) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec execute_named(String.t(), iodata()) ::
+            String.t()
+            | tuple()
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def execute_named(_name, _command) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec execute_named(String.t(), iodata(), keyword()) ::
+            String.t()
+            | tuple()
+            | {:error, map(), String.t()}
+            | {:error, :desync, String.t()}
+            | {:error, term()}
+    def execute_named(_name, _command, _opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec list_named() :: [String.t()] | {:error, term()}
+    def list_named do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec stop_named(String.t()) :: :ok | {:error, term()}
+    def stop_named(_name) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    # Only present when the NIF is built with `--features chaos`
+    @doc false
+    @spec chaos_configure(keyword()) :: :ok | {:error, term()}
+    def chaos_configure(_opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec chaos_disable() :: :ok | {:error, term()}
+    def chaos_disable do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec orphan_check() :: [non_neg_integer()] | {:error, term()}
+    def orphan_check do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec orphan_check(keyword()) :: [non_neg_integer()] | {:error, term()}
+    def orphan_check(_opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
     @doc false
     @spec stop(reference()) :: :ok | {:error, term()}
     def stop(_handle) do
@@ -141,6 +1235,23 @@ defmodule ExMaude.Backend.NIF do
     def alive(_handle) do
       :erlang.nif_error(:nif_not_loaded)
     end
+
+    @doc false
+    @spec threads_configure(keyword()) :: :ok | {:error, term()}
+    def threads_configure(_opts) do
+      :erlang.nif_error(:nif_not_loaded)
+    end
+
+    @doc false
+    @spec threads_info() :: %{
+            threads: non_neg_integer(),
+            idle: non_neg_integer(),
+            queued: non_neg_integer(),
+            max_threads: pos_integer()
+          }
+    def threads_info do
+      :erlang.nif_error(:nif_not_loaded)
+    end
   end
 
   # Client API
@@ -166,15 +1277,44 @@ defmodule ExMaude.Backend.NIF do
   ## Options
 
     * `:timeout` - Maximum time to wait in milliseconds (default: 30000)
+    * `:scheduler` - Dirty scheduler class the NIF call runs on: `:io`
+      (default) or `:cpu`. The call mostly waits on Maude's output, so `:io`
+      keeps many concurrent commands from occupying the dirty CPU schedulers.
+    * `:format` - Output representation, converted in Rust:
+      * `:raw` - Maude's output as printed (default)
+      * `:clean` - Without the command echo and stats lines
+      * `:parsed` - Result term as nested `{op, sort, args}` tuples
+      * `:json` - Result term as a JSON string
+      * `:transcript` - Everything Maude wrote, untrimmed and ending with
+        its prompt, as `%{raw: binary, response_start: n, response_end: n,
+        prompt_start: n}`; the offsets are bytes into `raw`, and `:trim`
+        is ignored
+    * `:decode` - Handling of output that isn't valid UTF-8:
+      * `:replace` - Replace invalid bytes with U+FFFD (default); counted in
+        the `invalid_utf8` stat
+      * `:raise` - Return an error instead
+      * `:binary` - Return the bytes as Maude wrote them, unformatted
+    * `:trim` - Whitespace removed from around the output: `:both`
+      (default), `:trailing`, or `:none`, e.g. to keep the indentation of
+      LaTeX output or a result string's surrounding spaces
+    * `:meta` - When `true`, return `{:ok, {output, meta}}` where `meta` is
+      `%{duration_us: n, bytes_read: n, cached: boolean, restarted: boolean}`,
+      measured in Rust whether or not Maude's `show timing` is on
+    * `:filter` - A line prefix or list of prefixes, e.g. `["Solution",
+      "result"]`; only the output lines starting with one are returned.
+      Applied in Rust as the output is read, so a command printing
+      megabytes costs no more than the lines kept. Filtered calls bypass
+      the response cache.
 
   """
   @spec execute(GenServer.server(), String.t(), keyword()) ::
-          {:ok, String.t()} | {:error, term()}
+          {:ok, String.t() | tuple() | map()} | {:error, term()}
   def execute(server, command, opts \\ []) do
     timeout = Keyword.get(opts, :timeout, @default_timeout)
+    native_opts = Keyword.take(opts, [:scheduler, :format, :decode, :trim, :meta, :filter])
 
     try do
-      GenServer.call(server, {:execute, command}, timeout + 1_000)
+      GenServer.call(server, {:execute, command, native_opts}, timeout + 1_000)
     catch
       :exit, {:timeout, _} -> {:error, Error.timeout(timeout)}
     end
@@ -189,6 +1329,35 @@ defmodule ExMaude.Backend.NIF do
     GenServer.call(server, {:load_file, path}, @default_timeout)
   end
 
+  @doc """
+  Returns cumulative rewrite statistics for the Maude process.
+
+  Totals are summed from the stats line Maude prints after each command, so
+  throughput can be tracked in rewrites rather than requests. The same
+  totals are emitted as `[:ex_maude, :server, :stats]` telemetry after
+  every command.
+
+  When the NIF is built with the `lock-watch` cargo feature, the stats also
+  count waits for the process's internal locks that took longer than the
+  `:lock_wait_threshold` spawn option (`slow_lock_waits`,
+  `longest_lock_wait_ms`), and `lock_waiting_ms` gives the age of the
+  oldest wait still in progress. Without the feature these are always 0.
+
+  `queue_depth` is the number of callers waiting for their turn with the
+  process right now, and `overloaded` counts the calls refused with
+  `{:error, :overloaded}` because the `:max_queue` spawn option's limit of
+  waiting callers was reached.
+
+  ## Examples
+
+      {:ok, %{rewrites: rewrites, cpu_ms: cpu_ms}} = ExMaude.Backend.NIF.stats(server)
+
+  """
+  @spec stats(GenServer.server()) :: {:ok, map()} | {:error, term()}
+  def stats(server) do
+    GenServer.call(server, :stats)
+  end
+
   @impl ExMaude.Backend
   @doc """
   Checks if the NIF backend is alive and initialized.
@@ -245,13 +1414,18 @@ defmodule ExMaude.Backend.NIF do
   end
 
   @impl GenServer
-  def handle_call({:execute, command}, _from, %{initialized: true, handle: handle} = state) do
+  def handle_call(
+        {:execute, command, native_opts},
+        _from,
+        %{initialized: true, handle: handle} = state
+      ) do
     result =
       try do
-        case Native.execute(handle, command) do
-          result when is_binary(result) -> {:ok, result}
+        case native_execute(handle, command, native_opts) do
           {:ok, result} -> {:ok, result}
+          {:error, reason, raw} -> {:error, maude_error(reason, raw)}
           {:error, _} = err -> err
+          result -> {:ok, result}
         end
       rescue
         e ->
@@ -259,10 +1433,11 @@ defmodule ExMaude.Backend.NIF do
       end
 
     emit_telemetry(:command_complete, %{success: match?({:ok, _}, result)})
+    emit_stats(handle)
     {:reply, result, state}
   end
 
-  def handle_call({:execute, _command}, _from, state) do
+  def handle_call({:execute, _command, _native_opts}, _from, state) do
     {:reply,
      {:error,
       Error.exception(
@@ -287,6 +1462,9 @@ defmodule ExMaude.Backend.NIF do
           {:ok, _} ->
             :ok
 
+          {:error, %{message: message} = reason, raw} ->
+            {:error, Error.new(:load_error, message, details: reason, raw_output: raw)}
+
           {:error, _} = err ->
             err
         end
@@ -307,6 +1485,19 @@ defmodule ExMaude.Backend.NIF do
       )}, state}
   end
 
+  def handle_call(:stats, _from, %{initialized: true, handle: handle} = state) do
+    {:reply, {:ok, Native.stats(handle)}, state}
+  end
+
+  def handle_call(:stats, _from, state) do
+    {:reply,
+     {:error,
+      Error.exception(
+        :not_implemented,
+        "NIF backend not yet implemented. Compile the Rustler NIF to enable."
+      )}, state}
+  end
+
   def handle_call(:alive?, _from, %{initialized: true, handle: handle} = state) do
     alive =
       try do
@@ -382,6 +1573,52 @@ defmodule ExMaude.Backend.NIF do
     end
   end
 
+  # Maude's complaint about a command, as classified by the NIF
+  defp maude_error(%{kind: kind, message: message} = reason, raw) do
+    type =
+      case kind do
+        :no_such_module -> :module_not_found
+        kind when kind in [:parse_error, :sort_error, :file_not_found] -> kind
+        _ -> :unknown
+      end
+
+    Error.new(type, message, details: reason, raw_output: raw)
+  end
+
+  defp native_execute(handle, command, native_opts) do
+    format_opts =
+      [
+        format: Keyword.get(native_opts, :format, :raw),
+        decode: Keyword.get(native_opts, :decode, :replace)
+      ] ++ Keyword.take(native_opts, [:trim, :meta, :filter])
+
+    case Keyword.get(native_opts, :scheduler, :io) do
+      :cpu -> Native.execute(handle, command, format_opts)
+      _io -> Native.execute_io(handle, command, format_opts)
+    end
+  end
+
+  defp emit_stats(handle) do
+    stats = Native.stats(handle)
+
+    emit_telemetry(
+      :stats,
+      Map.take(stats, [
+        :commands,
+        :rewrites,
+        :cpu_ms,
+        :real_ms,
+        :invalid_utf8,
+        :cache_hits,
+        :cache_misses,
+        :slow_lock_waits,
+        :longest_lock_wait_ms
+      ])
+    )
+  rescue
+    _ -> :ok
+  end
+
   defp emit_telemetry(event, measurements) do
     :telemetry.execute(
       [:ex_maude, :server, event],
@@ -45,6 +45,26 @@ defmodule ExMaude.Telemetry do
   - Measurements: `%{duration: integer}`
   - Metadata: `%{result: :ok | :error}`
 
+  ### NIF Backend Events
+
+  Emitted by `ExMaude.Backend.NIF` after each command, with the cumulative
+  rewrite totals of its Maude process (see `ExMaude.Backend.NIF.stats/1`).
+
+  `[:ex_maude, :server, :stats]`
+  - Measurements: `%{commands: integer, rewrites: integer, cpu_ms: integer,
+    real_ms: integer, invalid_utf8: integer, cache_hits: integer,
+    cache_misses: integer, slow_lock_waits: integer,
+    longest_lock_wait_ms: integer, time: integer}`
+  - Metadata: `%{pid: pid, backend: :nif}`
+
+  `cache_hits` and `cache_misses` count the commands answered from, and
+  looked up in vain in, the response cache of the `:cache_size` spawn
+  option; both stay 0 without it.
+
+  `slow_lock_waits` and `longest_lock_wait_ms` stay 0 unless the NIF is
+  built with the `lock-watch` cargo feature, which times waits for the
+  process's internal locks.
+
   ### IoT Events
 
   Emitted for IoT conflict detection operations.
@@ -158,6 +178,7 @@ defmodule ExMaude.Telemetry do
       [:ex_maude, :command, :exception],
       [:ex_maude, :pool, :checkout, :start],
       [:ex_maude, :pool, :checkout, :stop],
+      [:ex_maude, :server, :stats],
       [:ex_maude, :iot, :detect_conflicts, :start],
       [:ex_maude, :iot, :detect_conflicts, :stop]
     ]
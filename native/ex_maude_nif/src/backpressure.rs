@@ -0,0 +1,24 @@
+//! Refusing commands once too many wait for a process.
+//!
+//! Callers take turns with a process in arrival order, each blocking a
+//! dirty scheduler thread while it waits. Under a burst they pile up
+//! behind a slow command, tying up every dirty thread the VM has with no
+//! work done. A process started with `max_queue: n` lets at most `n`
+//! callers wait while one has its turn; the next execute call fails at
+//! once with `{:error, :overloaded}`, for the caller to shed the load or
+//! retry elsewhere. `stats/1` reports the current `queue_depth` and how
+//! many calls were turned away as `overloaded`.
+//!
+//! Only callers' turns are counted and bounded. The NIF's own exchanges -
+//! pool workers, heartbeat pings, startup - and callers waiting out a
+//! queued lease aren't.
+
+/// Message for a command refused because the queue is full; the NIFs
+/// return the `:overloaded` atom instead.
+pub const OVERLOADED: &str = "process is overloaded";
+
+/// Callers waiting while another has its turn, from the tickets handed out
+/// and the one being served.
+pub fn depth(next: u64, serving: u64) -> u64 {
+    next.saturating_sub(serving).saturating_sub(1)
+}
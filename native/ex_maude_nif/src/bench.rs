@@ -0,0 +1,131 @@
+//! Latency measurement for one command, run repeatedly inside the NIF.
+//!
+//! Timing `execute/2` from Elixir adds a NIF call, a scheduler hop, and
+//! term conversion to every sample. `bench/3` instead runs the command in
+//! a loop over a single exchange and times each round trip - write, Maude's
+//! work, and the read up to the prompt - so the numbers say what the pipe
+//! and Maude cost on the host and theory at hand. Other callers wait until
+//! the run is over.
+//!
+//! The report gives the spread as percentiles and as a histogram with
+//! power-of-two microsecond buckets.
+
+use crate::diagnostics::{self, Outcome};
+use crate::failure::Failure;
+use crate::input::Input;
+use crate::process::{micros, MaudeProcess, Response};
+use crate::reply;
+use rustler::{Env, LocalPid, NifMap, NifResult, ResourceArc};
+use std::time::Instant;
+
+/// Timings returned by `bench/3`, in microseconds.
+#[derive(Debug, NifMap)]
+pub struct BenchReport {
+    pub iterations: u64,
+    pub total_us: u64,
+    pub min_us: u64,
+    pub mean_us: u64,
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+    /// `{upper_us, count}` for each non-empty bucket, in increasing order:
+    /// `count` samples took at most `upper_us` and more than the bucket
+    /// below.
+    pub histogram: Vec<(u64, u64)>,
+}
+
+impl BenchReport {
+    fn new(mut samples: Vec<u64>) -> BenchReport {
+        samples.sort_unstable();
+        let total: u64 = samples.iter().sum();
+
+        let mut histogram: Vec<(u64, u64)> = Vec::new();
+        for &sample in &samples {
+            let upper = sample.max(1).next_power_of_two();
+            match histogram.last_mut() {
+                Some((bound, count)) if *bound == upper => *count += 1,
+                _ => histogram.push((upper, 1)),
+            }
+        }
+
+        BenchReport {
+            iterations: samples.len() as u64,
+            total_us: total,
+            min_us: samples[0],
+            mean_us: total / samples.len() as u64,
+            p50_us: percentile(&samples, 50),
+            p95_us: percentile(&samples, 95),
+            p99_us: percentile(&samples, 99),
+            max_us: samples[samples.len() - 1],
+            histogram,
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted, non-empty `samples`.
+fn percentile(samples: &[u64], p: usize) -> u64 {
+    let rank = (samples.len() * p).div_ceil(100).max(1);
+    samples[rank - 1]
+}
+
+fn measure(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    command: &Input,
+    iterations: u64,
+) -> Result<BenchReport, Failure> {
+    if iterations == 0 {
+        return Err("iterations must be at least 1".into());
+    }
+
+    let parts = command.parts();
+    let exchange = process.begin_by(caller)?;
+    let mut samples = Vec::with_capacity(iterations.min(1 << 20) as usize);
+
+    for iteration in 0..iterations {
+        let start = Instant::now();
+        let response = exchange.execute_trimmed(&parts, None)?;
+        samples.push(micros(start.elapsed()));
+
+        if let Response::Spilled(spill) = response {
+            let _ = std::fs::remove_file(&spill.path);
+        }
+
+        let stderr = exchange.take_stderr()?;
+        if let Some(complaint) = diagnostics::parse(&stderr).into_iter().next() {
+            return Err(format!(
+                "bench failed on iteration {}: {}",
+                iteration + 1,
+                complaint.message
+            )
+            .into());
+        }
+    }
+
+    Ok(BenchReport::new(samples))
+}
+
+/// Run `command` `iterations` times and report how long each run took.
+///
+/// Output is discarded; a response that spilled has its file removed.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `command` - Maude command to time
+/// * `iterations` - Number of runs, at least 1
+///
+/// # Returns
+/// * `Ok(BenchReport)` - Map of `iterations`, `total_us`, `min_us`,
+///   `mean_us`, `p50_us`, `p95_us`, `p99_us`, `max_us`, and `histogram`
+/// * `Err` - If `iterations` is 0, Maude reports a warning or error for
+///   the command, or I/O fails
+#[rustler::nif(schedule = "DirtyCpu")]
+fn bench<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    command: Input<'a>,
+    iterations: u64,
+) -> NifResult<Outcome<BenchReport>> {
+    reply(measure(&process, env.pid(), &command, iterations))
+}
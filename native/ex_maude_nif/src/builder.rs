@@ -0,0 +1,364 @@
+//! Maude term builder.
+//!
+//! The inverse of [`crate::term`]: renders an Elixir AST into a Maude term
+//! string that is safe to splice into a command. Every token is validated,
+//! strings are escaped, and compound arguments of mixfix operators are
+//! always parenthesized, so the result parses the same way regardless of
+//! operator precedences and can't smuggle in a `.` or a second command.
+//!
+//! Accepted AST forms:
+//!
+//! * `{op, sort, [args]}` - as returned by `execute_parsed/2`
+//! * `{op, [args]}` - application without a sort
+//! * `{:string, "text"}` - string literal, escaped as needed
+//! * `{:qid, "name"}` - quoted identifier `'name`
+//! * `{:var, "X", "Nat"}` - variable `X:Nat`
+//! * integers, floats, `true`, and `false`
+//! * binaries - constants such as `"0"` or `"nil"`
+//!
+//! Operators containing `_` are mixfix: `"_+_"` with two arguments renders
+//! as `a + b`, `"<_:_|_>"` as `< o : C | attrs >`. A binary mixfix
+//! operator given more than two arguments is rendered as a chain
+//! (`a + b + c`), matching the flattened form the parser produces. If the
+//! argument count doesn't fit the pattern, Maude's prefix form `_+_(a, b)`
+//! is used instead.
+
+use crate::term;
+use rustler::{Atom, Decoder, NifResult, Term};
+
+rustler::atoms! {
+    string,
+    qid,
+    var,
+    nil,
+}
+
+/// AST accepted by the builder.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ast {
+    App {
+        op: String,
+        sort: Option<String>,
+        args: Vec<Ast>,
+    },
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Qid(String),
+    Var(String, String),
+}
+
+impl<'a> Decoder<'a> for Ast {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        if let Ok(value) = term.decode::<i64>() {
+            return Ok(Ast::Int(value));
+        }
+        if let Ok(value) = term.decode::<f64>() {
+            return Ok(Ast::Float(value));
+        }
+        if let Ok(value) = term.decode::<bool>() {
+            return Ok(constant(if value { "true" } else { "false" }));
+        }
+        if let Ok(value) = term.decode::<String>() {
+            return Ok(constant(&value));
+        }
+        if let Ok((tag, value)) = term.decode::<(Atom, String)>() {
+            if tag == string() {
+                return Ok(Ast::Str(value));
+            }
+            if tag == qid() {
+                return Ok(Ast::Qid(value));
+            }
+        }
+        if let Ok((tag, name, sort)) = term.decode::<(Atom, String, String)>() {
+            if tag == var() {
+                return Ok(Ast::Var(name, sort));
+            }
+        }
+        if let Ok((op, sort, args)) = term.decode::<(String, Term<'a>, Vec<Ast>)>() {
+            let sort = if sort.decode::<Atom>().is_ok_and(|atom| atom == nil()) {
+                None
+            } else {
+                Some(sort.decode::<String>()?)
+            };
+            return Ok(Ast::App { op, sort, args });
+        }
+        if let Ok((op, args)) = term.decode::<(String, Vec<Ast>)>() {
+            return Ok(Ast::App {
+                op,
+                sort: None,
+                args,
+            });
+        }
+
+        Err(rustler::Error::BadArg)
+    }
+}
+
+fn constant(op: &str) -> Ast {
+    Ast::App {
+        op: op.to_string(),
+        sort: None,
+        args: Vec::new(),
+    }
+}
+
+/// Render an AST as a Maude term.
+pub fn render(ast: &Ast) -> Result<String, String> {
+    match ast {
+        Ast::Int(value) => Ok(value.to_string()),
+        Ast::Float(value) => render_float(*value),
+        Ast::Str(value) => Ok(escape_string(value)),
+        Ast::Qid(name) => {
+            check_token(name, "qid", &['(', ')', '[', ']', '{', '}', ',', '`', '\''])?;
+            Ok(format!("'{}", name))
+        }
+        Ast::Var(name, sort) => {
+            check_token(
+                name,
+                "variable name",
+                &['(', ')', '[', ']', '{', '}', ',', ':'],
+            )?;
+            check_token(sort, "sort", &['(', ')', ':'])?;
+            Ok(format!("{}:{}", name, sort))
+        }
+        Ast::App { op, sort, args } => {
+            let term = render_app(op, args)?;
+
+            match sort {
+                // Variables already carry their sort in the name
+                Some(sort) if !op.contains(':') || !args.is_empty() => {
+                    check_token(sort, "sort", &['(', ')', ':'])?;
+                    Ok(format!("({}).{}", term, sort))
+                }
+                _ => Ok(term),
+            }
+        }
+    }
+}
+
+fn render_app(op: &str, args: &[Ast]) -> Result<String, String> {
+    if args.is_empty() {
+        return render_constant(op);
+    }
+
+    let parts: Vec<&str> = op.split('_').collect();
+    let holes = parts.len() - 1;
+
+    if holes == args.len() {
+        return render_mixfix(&parts, args);
+    }
+
+    // Flattened chain of a binary infix operator, e.g. `_+_` over 3 args
+    if holes == 2 && parts[0].is_empty() && parts[2].is_empty() && args.len() > 2 {
+        let separator = vec![parts[1]; args.len() - 1];
+        let mut chain = vec![""];
+        chain.extend(separator);
+        chain.push("");
+        return render_mixfix(&chain, args);
+    }
+
+    check_token(op, "operator", &['(', ')', ','])?;
+    let args: Vec<String> = args.iter().map(render).collect::<Result<_, _>>()?;
+    Ok(format!("{}({})", op, args.join(", ")))
+}
+
+/// Interleave the literal parts of a mixfix pattern with the arguments.
+fn render_mixfix(parts: &[&str], args: &[Ast]) -> Result<String, String> {
+    let mut tokens: Vec<String> = Vec::new();
+
+    for (i, part) in parts.iter().enumerate() {
+        for token in part.split_whitespace() {
+            check_token(token, "operator", &['(', ')'])?;
+            tokens.push(token.to_string());
+        }
+        if let Some(arg) = args.get(i) {
+            tokens.push(render_argument(arg)?);
+        }
+    }
+
+    Ok(tokens.join(" "))
+}
+
+/// Render an argument, parenthesizing anything that isn't a single token.
+fn render_argument(arg: &Ast) -> Result<String, String> {
+    let rendered = render(arg)?;
+
+    match arg {
+        Ast::App { op, args, .. } if !args.is_empty() && op.contains('_') => {
+            Ok(format!("({})", rendered))
+        }
+        _ => Ok(rendered),
+    }
+}
+
+fn render_constant(op: &str) -> Result<String, String> {
+    // String literals round-trip from the parser as constants. They are
+    // decoded and escaped again, so a `"` inside can't end the literal
+    if op.len() >= 2 && op.starts_with('"') && op.ends_with('"') {
+        let text = term::unescape(op)
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(|| format!("invalid string literal: {:?}", op))?;
+        return Ok(escape_string(&text));
+    }
+
+    check_token(op, "constant", &['(', ')', '[', ']', ',', '"'])?;
+    Ok(op.to_string())
+}
+
+fn render_float(value: f64) -> Result<String, String> {
+    if value.is_nan() {
+        return Err("NaN has no Maude representation".to_string());
+    }
+    if value.is_infinite() {
+        return Ok(if value > 0.0 { "Infinity" } else { "-Infinity" }.to_string());
+    }
+
+    // Maude floats need a fractional part or exponent to lex as Float
+    let rendered = format!("{:?}", value);
+    if rendered.contains('.') || rendered.contains('e') {
+        Ok(rendered)
+    } else {
+        Ok(format!("{}.0", rendered))
+    }
+}
+
+/// Quote and escape a string literal, keeping it on one line.
+fn escape_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => {
+                let mut buf = [0u8; 4];
+                for byte in c.encode_utf8(&mut buf).bytes() {
+                    escaped.push_str(&format!("\\{:03o}", byte));
+                }
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+/// Reject tokens that could change how the surrounding command is read.
+fn check_token(token: &str, what: &str, forbidden: &[char]) -> Result<(), String> {
+    let valid = !token.is_empty()
+        && token != "."
+        && !token
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || forbidden.contains(&c));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid {}: {:?}", what, token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(op: &str, args: Vec<Ast>) -> Ast {
+        Ast::App {
+            op: op.to_string(),
+            sort: None,
+            args,
+        }
+    }
+
+    /// Whether `rendered` reads back as one string literal holding `text`.
+    fn is_literal_of(rendered: &str, text: &str) -> bool {
+        let parsed = term::parse(rendered).unwrap();
+        parsed.args.is_empty() && term::unescape(&parsed.op) == Some(text.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn renders_mixfix_with_compound_arguments_parenthesized() {
+        let sum = app(
+            "_+_",
+            vec![Ast::Int(1), app("_*_", vec![Ast::Int(2), Ast::Int(3)])],
+        );
+        assert_eq!(render(&sum).unwrap(), "1 + (2 * 3)");
+
+        let chain = app("_+_", vec![Ast::Int(1), Ast::Int(2), Ast::Int(3)]);
+        assert_eq!(render(&chain).unwrap(), "1 + 2 + 3");
+
+        let object = app(
+            "<_:_|_>",
+            vec![Ast::Qid("a".into()), constant("Account"), constant("none")],
+        );
+        assert_eq!(render(&object).unwrap(), "< 'a : Account | none >");
+
+        let prefix = app(
+            "f",
+            vec![Ast::Var("N".into(), "Nat".into()), Ast::Float(1.0)],
+        );
+        assert_eq!(render(&prefix).unwrap(), "f(N:Nat, 1.0)");
+    }
+
+    #[test]
+    fn escapes_strings() {
+        let rendered = render(&Ast::Str("a\" . quit . \"\n".into())).unwrap();
+        assert_eq!(rendered, r#""a\" . quit . \"\n""#);
+        assert!(is_literal_of(&rendered, "a\" . quit . \"\n"));
+
+        let control = render(&Ast::Str("\u{1}\u{85}".into())).unwrap();
+        assert_eq!(control, r#""\001\302\205""#);
+    }
+
+    #[test]
+    fn re_escapes_string_constants() {
+        // Parsed results hand string literals back as constants
+        assert_eq!(render(&constant(r#""a\nb""#)).unwrap(), r#""a\nb""#);
+
+        for hostile in [
+            r#""a" . quit . ""#,
+            r#""a" . quit . "b""#,
+            r#""" . red 1 . """#,
+            r#""\" . quit . \"""#,
+        ] {
+            let rendered = render(&constant(hostile)).unwrap();
+            let decoded = term::unescape(hostile).unwrap();
+            assert!(
+                is_literal_of(&rendered, std::str::from_utf8(&decoded).unwrap()),
+                "{} rendered as {}",
+                hostile,
+                rendered
+            );
+        }
+
+        assert!(render(&constant(r#""bad \q escape""#)).is_err());
+        assert!(render(&constant(r#""\377""#)).is_err());
+    }
+
+    #[test]
+    fn refuses_tokens_that_break_out() {
+        for op in [".", "a . quit", "a\nquit", "f(x)", "a,b", "\"open"] {
+            assert!(render(&constant(op)).is_err(), "{:?} was accepted", op);
+        }
+        assert!(render(&Ast::Qid("a b".into())).is_err());
+        assert!(render(&Ast::Qid("a'b".into())).is_err());
+        assert!(render(&Ast::Var("X".into(), "Nat) . quit . (".into())).is_err());
+        assert!(render(&Ast::Var("X:Y".into(), "Nat".into())).is_err());
+        assert!(render(&app("_)_", vec![Ast::Int(1), Ast::Int(2)])).is_err());
+        assert!(render(&app("f(", vec![Ast::Int(1)])).is_err());
+
+        let sorted = Ast::App {
+            op: "none".into(),
+            sort: Some("Configuration) . quit".into()),
+            args: Vec::new(),
+        };
+        assert!(render(&sorted).is_err());
+        assert!(render(&Ast::Float(f64::NAN)).is_err());
+    }
+}
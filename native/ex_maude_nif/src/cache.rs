@@ -0,0 +1,164 @@
+//! Responses to repeated commands, served without asking Maude.
+//!
+//! With the `:cache_size` spawn option set, the execute NIFs keep the
+//! responses of up to that many `reduce` and `search` commands, least
+//! recently used first out, and answer the same command again from memory.
+//! Workloads that evaluate the same few expressions over and over skip the
+//! round trip to the subprocess entirely.
+//!
+//! Only an input holding a single command is cached, split into commands
+//! as [`crate::readonly`] splits them. A response is only reused while
+//! nothing could have changed it: every command that changes interpreter
+//! state - loading or defining a module, `select`, `set` (see
+//! [`crate::command::is_mutating`]), wherever it comes in the input - and
+//! any raw I/O empties the cache. Only complete answers are kept: not a search with
+//! solutions left for `continue`, a response that spilled to a file or was
+//! read in chunks, or one Maude complained about. A search answered from
+//! the cache isn't Maude's last search, so it leaves nothing for `continue`
+//! and `show path` to use.
+//!
+//! What is kept is the response as read from Maude, before rendering: the
+//! `:format` and `:decode` options are per call, so one entry answers the
+//! command whichever it is asked in, and a hit is rendered like any fresh
+//! response. Only the trim, applied while reading, is part of the key.
+//!
+//! `stats/1` counts `cache_hits` and `cache_misses`.
+
+use crate::format::Trim;
+use crate::process::Response;
+use crate::readonly;
+use std::collections::{BTreeMap, HashMap};
+
+/// Commands whose responses may be cached, by keyword.
+const CACHEABLE: &[&str] = &["reduce", "red", "search"];
+
+/// A command, with the trim its response got.
+pub type Key = (Trim, Vec<u8>);
+
+/// A cached response; spilled ones are never kept.
+#[derive(Clone)]
+enum Cached {
+    Text(String),
+    Invalid { raw: Vec<u8>, text: String },
+}
+
+impl Cached {
+    fn response(&self) -> Response {
+        match self.clone() {
+            Cached::Text(text) => Response::Text(text),
+            Cached::Invalid { raw, text } => Response::Invalid { raw, text },
+        }
+    }
+}
+
+/// Least-recently-used responses of one process.
+#[derive(Default)]
+pub struct Cache {
+    /// Entries kept; `0` turns caching off.
+    capacity: usize,
+    /// Incremented on every use, to order entries by recency.
+    clock: u64,
+    entries: HashMap<Key, (u64, Cached)>,
+    /// Clock reading of each entry's last use to its key, oldest first.
+    recency: BTreeMap<u64, Key>,
+}
+
+impl Cache {
+    pub fn new(capacity: usize) -> Cache {
+        Cache {
+            capacity,
+            ..Cache::default()
+        }
+    }
+
+    /// The key for `command`, if caching is on and it may be cached.
+    pub fn key(&self, command: &[&[u8]], trim: Trim) -> Option<Key> {
+        if self.capacity == 0 {
+            return None;
+        }
+
+        match readonly::keywords(command).as_slice() {
+            [keyword] if CACHEABLE.contains(&keyword.as_str()) => Some((trim, command.concat())),
+            _ => None,
+        }
+    }
+
+    /// Whether responses are kept at all.
+    pub fn is_on(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// The cached response for `key`, marking it recently used.
+    pub fn get(&mut self, key: &Key) -> Option<Response> {
+        self.clock += 1;
+        let (used, cached) = self.entries.get_mut(key)?;
+
+        self.recency.remove(used);
+        self.recency.insert(self.clock, key.clone());
+        *used = self.clock;
+        Some(cached.response())
+    }
+
+    /// Keep `response` for `key`, evicting the least recently used entry if
+    /// the cache is full.
+    pub fn put(&mut self, key: Key, response: &Response) {
+        let cached = match response {
+            Response::Text(text) => Cached::Text(text.clone()),
+            Response::Invalid { raw, text } => Cached::Invalid {
+                raw: raw.clone(),
+                text: text.clone(),
+            },
+            Response::Spilled(_) | Response::Chunks(_) => return,
+        };
+
+        // `continue` could still add to it
+        let text = match &cached {
+            Cached::Text(text) | Cached::Invalid { text, .. } => text,
+        };
+        if key.1.trim_ascii_start().starts_with(b"search")
+            && text.contains("Solution ")
+            && !text.contains("No more solutions.")
+        {
+            return;
+        }
+
+        self.clock += 1;
+        if let Some((used, _)) = self.entries.remove(&key) {
+            self.recency.remove(&used);
+        }
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+
+        self.recency.insert(self.clock, key.clone());
+        self.entries.insert(key, (self.clock, cached));
+    }
+
+    /// Forget every response, as interpreter state has changed.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(command: &str) -> Option<Key> {
+        Cache::new(4).key(&[command.as_bytes()], Trim::Both)
+    }
+
+    #[test]
+    fn keys_only_a_single_command() {
+        assert!(key("red 1 + 1 .").is_some());
+        assert!(key("search [1] in NAT : 0 =>* N:Nat .").is_some());
+        assert!(key("red 1 . select FOO .").is_none());
+        assert!(key("red 1 .\nred 2 .").is_none());
+        assert!(key("show modules .").is_none());
+        assert!(Cache::new(0).key(&[b"red 1 ."], Trim::Both).is_none());
+    }
+}
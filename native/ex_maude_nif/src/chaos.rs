@@ -0,0 +1,172 @@
+//! Fault injection for chaos testing.
+//!
+//! Only compiled with the `chaos` cargo feature. Once `chaos_configure/1`
+//! is called, every command run through [`MaudeProcess::execute`] may be
+//! delayed, have its child killed, or have its output truncated, each with
+//! a configured probability. Decisions come from a seeded generator so a
+//! failing run can be reproduced with the same seed and command sequence.
+//!
+//! [`MaudeProcess::execute`]: crate::process::MaudeProcess::execute
+
+use crate::error;
+use rustler::{Atom, Decoder, NifResult, Term};
+use std::sync::Mutex;
+use std::time::Duration;
+
+rustler::atoms! {
+    ok,
+    seed,
+    delay,
+    max_delay_ms,
+    kill,
+    truncate,
+}
+
+/// Fault probabilities, decoded from a keyword list.
+///
+/// * `:seed` - Generator seed (default: `1`)
+/// * `:delay` - Probability of delaying a read (default: `0.0`)
+/// * `:max_delay_ms` - Upper bound for an injected delay (default: `1000`)
+/// * `:kill` - Probability of killing the child before a command (default: `0.0`)
+/// * `:truncate` - Probability of truncating a command's output (default: `0.0`)
+#[derive(Debug, Clone)]
+pub struct ChaosOptions {
+    pub seed: u64,
+    pub delay: f64,
+    pub max_delay_ms: u64,
+    pub kill: f64,
+    pub truncate: f64,
+}
+
+impl Default for ChaosOptions {
+    fn default() -> Self {
+        ChaosOptions {
+            seed: 1,
+            delay: 0.0,
+            max_delay_ms: 1000,
+            kill: 0.0,
+            truncate: 0.0,
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for ChaosOptions {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut options = ChaosOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, Term<'a>)>>()? {
+            if key == seed() {
+                options.seed = value.decode()?;
+            } else if key == delay() {
+                options.delay = value.decode()?;
+            } else if key == max_delay_ms() {
+                options.max_delay_ms = value.decode()?;
+            } else if key == kill() {
+                options.kill = value.decode()?;
+            } else if key == truncate() {
+                options.truncate = value.decode()?;
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+struct Chaos {
+    options: ChaosOptions,
+    state: u64,
+}
+
+impl Chaos {
+    /// Next value in `[0, 1)` from a xorshift64* generator.
+    fn next(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let value = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn roll(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.next() < probability
+    }
+}
+
+static CHAOS: Mutex<Option<Chaos>> = Mutex::new(None);
+
+fn with_chaos<T>(f: impl FnOnce(&mut Chaos) -> T) -> Option<T> {
+    CHAOS.lock().ok()?.as_mut().map(f)
+}
+
+/// Whether to kill the child before the next command.
+pub fn should_kill() -> bool {
+    with_chaos(|chaos| chaos.roll(chaos.options.kill)).unwrap_or(false)
+}
+
+/// Sleep before a read, if the dice say so.
+pub fn delay_read() {
+    let delay = with_chaos(|chaos| {
+        chaos
+            .roll(chaos.options.delay)
+            .then(|| (chaos.next() * chaos.options.max_delay_ms as f64) as u64)
+    })
+    .flatten();
+
+    if let Some(ms) = delay {
+        std::thread::sleep(Duration::from_millis(ms));
+    }
+}
+
+/// Cut `output` short at a random character boundary, if the dice say so.
+pub fn truncate_output(output: &mut String) {
+    let cut = with_chaos(|chaos| {
+        chaos
+            .roll(chaos.options.truncate)
+            .then(|| (chaos.next() * output.len() as f64) as usize)
+    })
+    .flatten();
+
+    if let Some(mut at) = cut {
+        while !output.is_char_boundary(at) {
+            at -= 1;
+        }
+        output.truncate(at);
+    }
+}
+
+/// Enable fault injection for all processes, replacing any earlier setup.
+///
+/// # Arguments
+/// * `opts` - Keyword list of probabilities and seed; see [`ChaosOptions`]
+///
+/// # Returns
+/// * `Ok(:ok)` - Faults are now injected
+/// * `Err` - If a probability is outside `0.0..=1.0`
+#[rustler::nif]
+fn chaos_configure(opts: ChaosOptions) -> NifResult<Atom> {
+    let probabilities = [opts.delay, opts.kill, opts.truncate];
+    if probabilities.iter().any(|p| !(0.0..=1.0).contains(p)) {
+        return Err(error("probabilities must be between 0.0 and 1.0"));
+    }
+
+    // xorshift gets stuck at zero
+    let state = opts.seed.max(1);
+    *CHAOS
+        .lock()
+        .map_err(|e| error(format!("chaos lock failed: {}", e)))? = Some(Chaos {
+        options: opts,
+        state,
+    });
+
+    Ok(ok())
+}
+
+/// Disable fault injection.
+#[rustler::nif]
+fn chaos_disable() -> NifResult<Atom> {
+    *CHAOS
+        .lock()
+        .map_err(|e| error(format!("chaos lock failed: {}", e)))? = None;
+
+    Ok(ok())
+}
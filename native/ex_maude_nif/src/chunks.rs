@@ -0,0 +1,70 @@
+//! Output kept as the chunks it was read in.
+//!
+//! A response is normally read into one buffer that grows as Maude writes,
+//! then validated as UTF-8 and copied into a single binary. For output of
+//! hundreds of megabytes that last step alone holds the dirty scheduler for
+//! a noticeable time. With `format: :iodata` the reader instead moves what
+//! it has read into a new chunk every [`CHUNK`] bytes, and the NIF returns
+//! the chunks as a list of binaries - iodata that Elixir can write to a
+//! file or socket, or stream through, without ever joining it.
+//!
+//! The chunks are the bytes as Maude wrote them: they aren't checked for
+//! UTF-8, and a chunk boundary may fall inside a character or a line. Like
+//! a spilled response, chunked output is scanned for bookkeeping through a
+//! sample of its short lines, and it isn't cached. Output past the spill
+//! threshold still spills, chunks read so far included.
+//!
+//! Only the execute, `pool_execute`, and `execute_named` calls read in
+//! chunks; elsewhere `:iodata` gives the output as a single chunk.
+
+use crate::format::Trim;
+use crate::process::Response;
+
+/// Bytes read before they are moved into a chunk of their own.
+pub const CHUNK: usize = 64 * 1024;
+
+/// A response read in chunks.
+#[derive(Debug)]
+pub struct Chunks {
+    pub parts: Vec<Vec<u8>>,
+    /// The output's short lines, for scanning without joining the parts.
+    pub sample: String,
+}
+
+impl Chunks {
+    /// The parts joined into the response an unchunked read would give.
+    pub fn join(self) -> Response {
+        Response::from_bytes(self.parts.concat())
+    }
+
+    /// Remove whitespace from the ends of the output according to `trim`,
+    /// dropping parts left empty.
+    pub fn trim(mut self, trim: Trim) -> Chunks {
+        if trim == Trim::Both {
+            while let Some(first) = self.parts.first_mut() {
+                let start = first
+                    .iter()
+                    .position(|b| !b.is_ascii_whitespace())
+                    .unwrap_or(first.len());
+                first.drain(..start);
+                if !first.is_empty() {
+                    break;
+                }
+                self.parts.remove(0);
+            }
+        }
+
+        if trim != Trim::None {
+            while let Some(last) = self.parts.last_mut() {
+                let end = last.trim_ascii_end().len();
+                last.truncate(end);
+                if !last.is_empty() {
+                    break;
+                }
+                self.parts.pop();
+            }
+        }
+
+        self
+    }
+}
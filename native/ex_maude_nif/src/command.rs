@@ -0,0 +1,889 @@
+//! Validated construction of single Maude commands.
+//!
+//! `execute/2` writes whatever it is given straight to Maude's stdin, so a
+//! term taken from user input can end the command early and smuggle in
+//! another one (`0 . quit`), or leave it unfinished so Maude waits forever
+//! (an unclosed `(` or a `***` comment swallowing the final period).
+//! [`build`] assembles `<kind> in <module> : <term> .` and rejects any term
+//! that could be read as anything other than exactly that one command.
+//!
+//! [`MaudeCommand`] goes further for the commands it covers: Elixir names
+//! the command as a tuple such as `{:reduce, module, term}` or
+//! `{:show, :sorts, module}`, and [`MaudeCommand::serialize`] spells it out
+//! in Maude's syntax - keywords, `[bound, depth]` limits, arrows, and
+//! `such that` conditions included - validating every piece as [`build`]
+//! does.
+//!
+//! [`is_mutating`] classifies commands by whether they change interpreter
+//! state, for code that has to mirror or replay that state elsewhere.
+
+use crate::settings::Switch;
+use rustler::{Atom, Decoder, NifResult, Term};
+
+rustler::atoms! {
+    reduce,
+    rewrite,
+    frewrite,
+    erewrite,
+    parse,
+    search,
+    unify,
+    variant_unify,
+    match_ = "match",
+    xmatch,
+    check,
+    smt_search,
+    vu_narrow,
+    fvu_narrow,
+    show,
+    select,
+    set,
+    continue_ = "continue",
+    bound,
+    depth,
+    such_that,
+    one_step = "=>1",
+    one_or_more = "=>+",
+    any_steps = "=>*",
+    normal_form = "=>!",
+    modules,
+    views,
+    search_graph,
+    module,
+    all,
+    sorts,
+    ops,
+    vars,
+    mbs,
+    eqs,
+    rls,
+    strats,
+    sds,
+    summary,
+    kinds,
+    components,
+}
+
+/// Commands that take a module and a term.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandKind {
+    Reduce,
+    Rewrite,
+    Frewrite,
+    Erewrite,
+    Parse,
+    Search,
+    Unify,
+    VariantUnify,
+    Match,
+    Xmatch,
+    Check,
+    SmtSearch,
+    VuNarrow,
+    FvuNarrow,
+}
+
+impl CommandKind {
+    fn keyword(self) -> &'static str {
+        match self {
+            CommandKind::Reduce => "reduce",
+            CommandKind::Rewrite => "rewrite",
+            CommandKind::Frewrite => "frewrite",
+            CommandKind::Erewrite => "erewrite",
+            CommandKind::Parse => "parse",
+            CommandKind::Search => "search",
+            CommandKind::Unify => "unify",
+            CommandKind::VariantUnify => "variant unify",
+            CommandKind::Match => "match",
+            CommandKind::Xmatch => "xmatch",
+            CommandKind::Check => "check",
+            CommandKind::SmtSearch => "smt-search",
+            CommandKind::VuNarrow => "vu-narrow",
+            CommandKind::FvuNarrow => "fvu-narrow",
+        }
+    }
+
+    /// Whether the command takes a pattern to reach, after an arrow.
+    fn searches(self) -> bool {
+        matches!(
+            self,
+            CommandKind::Search
+                | CommandKind::SmtSearch
+                | CommandKind::VuNarrow
+                | CommandKind::FvuNarrow
+        )
+    }
+
+    /// Reject the limits Maude doesn't accept for the command: `reduce`
+    /// takes none, `rewrite` and the unify and match kinds only a bound.
+    fn check_limits(self, options: &CommandOptions) -> Result<(), String> {
+        let (bound, depth) = match self {
+            CommandKind::Reduce | CommandKind::Parse | CommandKind::Check => (false, false),
+            CommandKind::Rewrite
+            | CommandKind::Unify
+            | CommandKind::VariantUnify
+            | CommandKind::Match
+            | CommandKind::Xmatch => (true, false),
+            _ => (true, true),
+        };
+
+        if options.bound.is_some() && !bound {
+            return Err(format!("{} takes no bound", self.keyword()));
+        }
+        if options.depth.is_some() && !depth {
+            return Err(format!("{} takes no depth", self.keyword()));
+        }
+        if options.bound == Some(0) {
+            return Err("bound must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Decoder<'a> for CommandKind {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let kind = term.decode::<Atom>()?;
+
+        [
+            (reduce(), CommandKind::Reduce),
+            (rewrite(), CommandKind::Rewrite),
+            (frewrite(), CommandKind::Frewrite),
+            (erewrite(), CommandKind::Erewrite),
+            (parse(), CommandKind::Parse),
+            (search(), CommandKind::Search),
+            (unify(), CommandKind::Unify),
+            (variant_unify(), CommandKind::VariantUnify),
+            (match_(), CommandKind::Match),
+            (xmatch(), CommandKind::Xmatch),
+            (check(), CommandKind::Check),
+            (smt_search(), CommandKind::SmtSearch),
+            (vu_narrow(), CommandKind::VuNarrow),
+            (fvu_narrow(), CommandKind::FvuNarrow),
+        ]
+        .into_iter()
+        .find_map(|(atom, variant)| (atom == kind).then_some(variant))
+        .ok_or(rustler::Error::BadArg)
+    }
+}
+
+/// Build `<kind> in <module> : <term> .`, rejecting unsafe input.
+pub fn build(kind: CommandKind, module: &str, term: &str) -> Result<String, String> {
+    build_bounded(kind, None, module, term)
+}
+
+/// Build `<kind> [<bound>] in <module> : <term> .`, rejecting unsafe input.
+pub fn build_bounded(
+    kind: CommandKind,
+    bound: Option<u64>,
+    module: &str,
+    term: &str,
+) -> Result<String, String> {
+    build_limited(kind, &[], bound, None, module, term)
+}
+
+/// Build `<kind> {<flags>} [<bound>, <depth>] in <module> : <term> .`,
+/// rejecting unsafe input; the flags and either limit may be left out.
+pub fn build_limited(
+    kind: CommandKind,
+    flags: &[&str],
+    bound: Option<u64>,
+    depth: Option<u64>,
+    module: &str,
+    term: &str,
+) -> Result<String, String> {
+    check_module(module)?;
+    check_term(term)?;
+
+    let bound = match (bound, depth) {
+        (None, None) => String::new(),
+        (Some(n), None) => format!(" [{}]", n),
+        (n, Some(depth)) => format!(
+            " [{}, {}]",
+            n.map(|n| n.to_string()).unwrap_or_default(),
+            depth
+        ),
+    };
+    let flags = if flags.is_empty() {
+        String::new()
+    } else {
+        format!(" {{{}}}", flags.join(", "))
+    };
+    Ok(format!(
+        "{}{}{} in {} : {} .",
+        kind.keyword(),
+        flags,
+        bound,
+        module,
+        term.trim()
+    ))
+}
+
+/// Arrow between the initial term and the pattern of a search.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arrow {
+    /// `=>1`, exactly one step.
+    OneStep,
+    /// `=>+`, one or more steps.
+    OneOrMore,
+    /// `=>*`, any number of steps.
+    AnySteps,
+    /// `=>!`, to a state that can't be rewritten further.
+    NormalForm,
+}
+
+impl Arrow {
+    const ALL: [Arrow; 4] = [
+        Arrow::OneStep,
+        Arrow::OneOrMore,
+        Arrow::AnySteps,
+        Arrow::NormalForm,
+    ];
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Arrow::OneStep => "=>1",
+            Arrow::OneOrMore => "=>+",
+            Arrow::AnySteps => "=>*",
+            Arrow::NormalForm => "=>!",
+        }
+    }
+
+    fn atom(self) -> Atom {
+        match self {
+            Arrow::OneStep => one_step(),
+            Arrow::OneOrMore => one_or_more(),
+            Arrow::AnySteps => any_steps(),
+            Arrow::NormalForm => normal_form(),
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for Arrow {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let atom = term.decode::<Atom>()?;
+        Arrow::ALL
+            .into_iter()
+            .find(|arrow| arrow.atom() == atom)
+            .ok_or(rustler::Error::BadArg)
+    }
+}
+
+/// What a `show` command prints.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Show {
+    Modules,
+    Views,
+    SearchGraph,
+    Module,
+    All,
+    Sorts,
+    Ops,
+    Vars,
+    Mbs,
+    Eqs,
+    Rls,
+    Strats,
+    Sds,
+    Summary,
+    Kinds,
+    Components,
+}
+
+impl Show {
+    const ALL: [Show; 16] = [
+        Show::Modules,
+        Show::Views,
+        Show::SearchGraph,
+        Show::Module,
+        Show::All,
+        Show::Sorts,
+        Show::Ops,
+        Show::Vars,
+        Show::Mbs,
+        Show::Eqs,
+        Show::Rls,
+        Show::Strats,
+        Show::Sds,
+        Show::Summary,
+        Show::Kinds,
+        Show::Components,
+    ];
+
+    fn keyword(self) -> &'static str {
+        match self {
+            Show::Modules => "modules",
+            Show::Views => "views",
+            Show::SearchGraph => "search graph",
+            Show::Module => "module",
+            Show::All => "all",
+            Show::Sorts => "sorts",
+            Show::Ops => "ops",
+            Show::Vars => "vars",
+            Show::Mbs => "mbs",
+            Show::Eqs => "eqs",
+            Show::Rls => "rls",
+            Show::Strats => "strats",
+            Show::Sds => "sds",
+            Show::Summary => "summary",
+            Show::Kinds => "kinds",
+            Show::Components => "components",
+        }
+    }
+
+    fn atom(self) -> Atom {
+        match self {
+            Show::Modules => modules(),
+            Show::Views => views(),
+            Show::SearchGraph => search_graph(),
+            Show::Module => module(),
+            Show::All => all(),
+            Show::Sorts => sorts(),
+            Show::Ops => ops(),
+            Show::Vars => vars(),
+            Show::Mbs => mbs(),
+            Show::Eqs => eqs(),
+            Show::Rls => rls(),
+            Show::Strats => strats(),
+            Show::Sds => sds(),
+            Show::Summary => summary(),
+            Show::Kinds => kinds(),
+            Show::Components => components(),
+        }
+    }
+
+    /// Whether the command shows a module, the current one if none is given.
+    fn takes_module(self) -> bool {
+        !matches!(self, Show::Modules | Show::Views | Show::SearchGraph)
+    }
+}
+
+impl<'a> Decoder<'a> for Show {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let atom = term.decode::<Atom>()?;
+        Show::ALL
+            .into_iter()
+            .find(|show| show.atom() == atom)
+            .ok_or(rustler::Error::BadArg)
+    }
+}
+
+/// Limits and condition of a [`MaudeCommand`], decoded from a keyword
+/// list.
+///
+/// * `:bound` - Maximum number of results, or of rewrites for `rewrite`
+/// * `:depth` - Maximum depth of a search, or the gas of `frewrite`
+/// * `:such_that` - Condition on the solutions of a search
+#[derive(Debug, Default)]
+pub struct CommandOptions {
+    pub bound: Option<u64>,
+    pub depth: Option<u64>,
+    pub condition: Option<String>,
+}
+
+impl<'a> Decoder<'a> for CommandOptions {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut options = CommandOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, Term<'a>)>>()? {
+            if key == bound() {
+                options.bound = Some(value.decode()?);
+            } else if key == depth() {
+                options.depth = Some(value.decode()?);
+            } else if key == such_that() {
+                options.condition = Some(value.decode()?);
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// A Maude command decoded from an Elixir tuple:
+///
+/// * `{kind, module, term}` and `{kind, module, term, opts}`, for any
+///   [`CommandKind`], e.g. `{:reduce, "NAT", "1 + 1"}`
+/// * `{kind, module, from, arrow, to}` and `{kind, module, from, arrow,
+///   to, opts}` for the search and narrowing kinds, where `arrow` is one
+///   of `:"=>1"`, `:"=>+"`, `:"=>*"`, and `:"=>!"`
+/// * `{:show, what}` and `{:show, what, module}`, where `what` is
+///   `:modules`, `:views`, `:search_graph`, `:module`, `:all`, `:sorts`,
+///   `:ops`, `:vars`, `:mbs`, `:eqs`, `:rls`, `:strats`, `:sds`,
+///   `:summary`, `:kinds`, or `:components`
+/// * `{:select, module}`, `{:set, switch, boolean}` with a switch as for
+///   `set_option/3`, and `{:continue, n}`
+///
+/// `opts` are [`CommandOptions`].
+#[derive(Debug)]
+pub enum MaudeCommand {
+    Term {
+        kind: CommandKind,
+        module: String,
+        term: String,
+        options: CommandOptions,
+    },
+    Search {
+        kind: CommandKind,
+        module: String,
+        from: String,
+        arrow: Arrow,
+        to: String,
+        options: CommandOptions,
+    },
+    Show {
+        what: Show,
+        module: Option<String>,
+    },
+    Select(String),
+    Set(Switch, bool),
+    Continue(u64),
+}
+
+impl<'a> Decoder<'a> for MaudeCommand {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let items = rustler::types::tuple::get_tuple(term)?;
+        let (tag, args) = items.split_first().ok_or(rustler::Error::BadArg)?;
+        let tag = tag.decode::<Atom>()?;
+
+        if tag == show() {
+            return match args {
+                [what] => Ok(MaudeCommand::Show {
+                    what: what.decode()?,
+                    module: None,
+                }),
+                [what, module] => Ok(MaudeCommand::Show {
+                    what: what.decode()?,
+                    module: Some(module.decode()?),
+                }),
+                _ => Err(rustler::Error::BadArg),
+            };
+        }
+        if tag == select() {
+            return match args {
+                [module] => Ok(MaudeCommand::Select(module.decode()?)),
+                _ => Err(rustler::Error::BadArg),
+            };
+        }
+        if tag == set() {
+            return match args {
+                [switch, value] => Ok(MaudeCommand::Set(switch.decode()?, value.decode()?)),
+                _ => Err(rustler::Error::BadArg),
+            };
+        }
+        if tag == continue_() {
+            return match args {
+                [n] => Ok(MaudeCommand::Continue(n.decode()?)),
+                _ => Err(rustler::Error::BadArg),
+            };
+        }
+
+        let kind = items[0].decode::<CommandKind>()?;
+        match args {
+            [module, term] => Ok(MaudeCommand::Term {
+                kind,
+                module: module.decode()?,
+                term: term.decode()?,
+                options: CommandOptions::default(),
+            }),
+            [module, term, options] => Ok(MaudeCommand::Term {
+                kind,
+                module: module.decode()?,
+                term: term.decode()?,
+                options: options.decode()?,
+            }),
+            [module, from, arrow, to] => Ok(MaudeCommand::Search {
+                kind,
+                module: module.decode()?,
+                from: from.decode()?,
+                arrow: arrow.decode()?,
+                to: to.decode()?,
+                options: CommandOptions::default(),
+            }),
+            [module, from, arrow, to, options] => Ok(MaudeCommand::Search {
+                kind,
+                module: module.decode()?,
+                from: from.decode()?,
+                arrow: arrow.decode()?,
+                to: to.decode()?,
+                options: options.decode()?,
+            }),
+            _ => Err(rustler::Error::BadArg),
+        }
+    }
+}
+
+impl MaudeCommand {
+    /// The command in Maude's syntax, ending in ` .`; an error if a piece
+    /// is unsafe or doesn't go with the rest.
+    pub fn serialize(&self) -> Result<String, String> {
+        match self {
+            MaudeCommand::Term {
+                kind,
+                module,
+                term,
+                options,
+            } => {
+                if options.condition.is_some() {
+                    return Err(format!("{} takes no condition", kind.keyword()));
+                }
+                kind.check_limits(options)?;
+                build_limited(*kind, &[], options.bound, options.depth, module, term)
+            }
+            MaudeCommand::Search {
+                kind,
+                module,
+                from,
+                arrow,
+                to,
+                options,
+            } => {
+                if !kind.searches() {
+                    return Err(format!("{} takes no pattern", kind.keyword()));
+                }
+                kind.check_limits(options)?;
+                // Each piece on its own, so none can swallow the arrow
+                check_term(from)?;
+                check_term(to)?;
+
+                let mut query = format!("{} {} {}", from.trim(), arrow.symbol(), to.trim());
+                if let Some(condition) = &options.condition {
+                    check_term(condition)?;
+                    query.push_str(" such that ");
+                    query.push_str(condition.trim());
+                }
+                build_limited(*kind, &[], options.bound, options.depth, module, &query)
+            }
+            MaudeCommand::Show { what, module } => match module {
+                None => Ok(format!("show {} .", what.keyword())),
+                Some(module) if what.takes_module() => {
+                    check_module(module)?;
+                    Ok(format!("show {} {} .", what.keyword(), module))
+                }
+                Some(_) => Err(format!("show {} takes no module", what.keyword())),
+            },
+            MaudeCommand::Select(module) => {
+                check_module(module)?;
+                Ok(format!("select {} .", module))
+            }
+            MaudeCommand::Set(switch, value) => Ok(switch.command(*value)),
+            MaudeCommand::Continue(0) => Err("continue needs a positive count".to_string()),
+            MaudeCommand::Continue(n) => Ok(format!("continue {} .", n)),
+        }
+    }
+}
+
+pub fn check_module(module: &str) -> Result<(), String> {
+    let valid = !module.is_empty()
+        && !module.chars().any(|c| {
+            c.is_whitespace() || c.is_control() || matches!(c, '(' | ')' | ':' | '.' | ',')
+        });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid module: {:?}", module))
+    }
+}
+
+/// Check that a term stays inside a single command.
+///
+/// Outside string literals the term must have balanced brackets, no
+/// comment markers, and no token ending in `.` at the top level, since
+/// Maude treats that as the end of the command even without a space. A
+/// bracket or comma escaped with a backquote, as in `` `( ``, is part of a
+/// token rather than a bracket.
+fn check_term(term: &str) -> Result<(), String> {
+    if term.trim().is_empty() {
+        return Err("empty term".to_string());
+    }
+    if let Some(c) = term.chars().find(|c| c.is_control()) {
+        return Err(format!("control character {:?} in term", c));
+    }
+
+    let mut closers: Vec<char> = Vec::new();
+    let mut token = String::new();
+    let mut chars = term.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                check_top_level(&token, &closers)?;
+                token.clear();
+                skip_string(&mut chars)?;
+            }
+            '(' | '[' | '{' => {
+                check_top_level(&token, &closers)?;
+                token.clear();
+                closers.push(match c {
+                    '(' => ')',
+                    '[' => ']',
+                    _ => '}',
+                });
+            }
+            ')' | ']' | '}' => {
+                check_top_level(&token, &closers)?;
+                token.clear();
+                if closers.pop() != Some(c) {
+                    return Err(format!("unbalanced {:?} in term", c));
+                }
+            }
+            ',' => {
+                check_top_level(&token, &closers)?;
+                token.clear();
+            }
+            c if c.is_whitespace() => {
+                check_top_level(&token, &closers)?;
+                token.clear();
+            }
+            '`' => {
+                token.push(c);
+                let mut rest = chars.clone();
+                if let Some(next @ ('(' | ')' | '[' | ']' | '{' | '}' | ',')) = rest.next() {
+                    token.push(next);
+                    chars = rest;
+                }
+            }
+            c => {
+                token.push(c);
+                if token.contains("***") || token.contains("---") {
+                    return Err("comment in term".to_string());
+                }
+            }
+        }
+    }
+
+    check_top_level(&token, &closers)?;
+
+    match closers.last() {
+        Some(closer) => Err(format!("unclosed bracket in term, expected {:?}", closer)),
+        None => Ok(()),
+    }
+}
+
+fn check_top_level(token: &str, closers: &[char]) -> Result<(), String> {
+    if closers.is_empty() && token.ends_with('.') {
+        Err(format!("command terminator in term: {:?}", token))
+    } else {
+        Ok(())
+    }
+}
+
+/// Consume a string literal up to and including its closing quote.
+fn skip_string(chars: &mut impl Iterator<Item = char>) -> Result<(), String> {
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' => return Ok(()),
+            _ => {}
+        }
+    }
+
+    Err("unterminated string literal in term".to_string())
+}
+
+/// Commands that change interpreter state and must be replayed to rebuild it.
+pub const MUTATING: &[&str] = &[
+    "load", "sload", "in", "select", "set", "fmod", "mod", "fth", "th", "smod", "sth", "omod",
+    "oth", "view",
+];
+
+/// Whether `command` changes the interpreter's state (modules, selection,
+/// or settings), as opposed to only querying it.
+pub fn is_mutating(command: &str) -> bool {
+    let keyword = command.split_whitespace().next().unwrap_or("");
+    MUTATING.contains(&keyword)
+}
+
+/// Whether `command` reads a file, whose own commands may each end in a
+/// prompt.
+pub fn is_load(command: &str) -> bool {
+    let keyword = command.split_whitespace().next().unwrap_or("");
+    matches!(keyword, "load" | "sload" | "in")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(kind: CommandKind, module: &str, term: &str) -> MaudeCommand {
+        MaudeCommand::Term {
+            kind,
+            module: module.to_string(),
+            term: term.to_string(),
+            options: CommandOptions::default(),
+        }
+    }
+
+    #[test]
+    fn builds_commands() {
+        assert_eq!(
+            build(CommandKind::Reduce, "NAT", " 1 + 1 "),
+            Ok("reduce in NAT : 1 + 1 .".to_string())
+        );
+        assert_eq!(
+            build_limited(CommandKind::Frewrite, &[], None, Some(5), "M", "a"),
+            Ok("frewrite [, 5] in M : a .".to_string())
+        );
+        assert_eq!(
+            build(CommandKind::VariantUnify, "M", "X =? f(Y)"),
+            Ok("variant unify in M : X =? f(Y) .".to_string())
+        );
+    }
+
+    #[test]
+    fn accepts_terms_maude_reads_as_one() {
+        for term in [
+            "s(s(0))",
+            "f(a, g[b], {c})",
+            "0.5 + 1.5",
+            "\"end . quit\"",
+            "\"a \\\" . quit\"",
+            "< O : C | a : 1 >",
+            "a `( b",
+            "f(x.)",
+            "0 .quit",
+        ] {
+            assert_eq!(check_term(term), Ok(()), "{term}");
+        }
+    }
+
+    #[test]
+    fn refuses_terms_that_end_the_command() {
+        for term in [
+            "0 . quit",
+            "1 + 1.",
+            "f(0) . red 1",
+            "\"a\" . quit",
+            "`( . quit `)",
+            "a `, . quit",
+        ] {
+            assert!(check_term(term).is_err(), "{term}");
+        }
+    }
+
+    #[test]
+    fn refuses_terms_that_leave_the_command_open() {
+        for term in [
+            "",
+            "   ",
+            "f(0",
+            "f(0]",
+            "g(0))",
+            "\"open",
+            "\"ends in a backslash\\",
+            "0 *** .",
+            "0 --- .",
+            "0 ***( .",
+            "a***b",
+            "0\n. quit",
+            "0\r",
+            "0\u{0}",
+        ] {
+            assert!(check_term(term).is_err(), "{term:?}");
+        }
+    }
+
+    #[test]
+    fn checks_modules() {
+        for module in ["NAT", "MY-MOD", "LIST{Nat}", "'M", "A+B"] {
+            assert_eq!(check_module(module), Ok(()), "{module}");
+        }
+        for module in ["", "NAT . quit", "NAT.", "A:B", "L(X)", "A,B", "N\tAT"] {
+            assert!(check_module(module).is_err(), "{module:?}");
+        }
+    }
+
+    #[test]
+    fn serializes_commands() {
+        assert_eq!(
+            term(CommandKind::Reduce, "NAT", "1 + 1").serialize(),
+            Ok("reduce in NAT : 1 + 1 .".to_string())
+        );
+
+        let search = MaudeCommand::Search {
+            kind: CommandKind::Search,
+            module: "M".to_string(),
+            from: "a".to_string(),
+            arrow: Arrow::NormalForm,
+            to: "X".to_string(),
+            options: CommandOptions {
+                bound: Some(1),
+                depth: Some(10),
+                condition: Some("X =/= b".to_string()),
+            },
+        };
+        assert_eq!(
+            search.serialize(),
+            Ok("search [1, 10] in M : a =>! X such that X =/= b .".to_string())
+        );
+
+        assert_eq!(
+            MaudeCommand::Show {
+                what: Show::ALL[0],
+                module: None
+            }
+            .serialize()
+            .map(|command| command.starts_with("show ")),
+            Ok(true)
+        );
+        assert_eq!(
+            MaudeCommand::Continue(3).serialize(),
+            Ok("continue 3 .".to_string())
+        );
+    }
+
+    #[test]
+    fn refuses_unsafe_pieces() {
+        assert!(term(CommandKind::Reduce, "NAT", "0 . quit")
+            .serialize()
+            .is_err());
+        assert!(term(CommandKind::Reduce, "NAT . quit", "0")
+            .serialize()
+            .is_err());
+        assert!(MaudeCommand::Select("NAT . quit".to_string())
+            .serialize()
+            .is_err());
+        assert!(MaudeCommand::Continue(0).serialize().is_err());
+
+        let options = CommandOptions {
+            bound: Some(1),
+            ..CommandOptions::default()
+        };
+        let bounded = MaudeCommand::Term {
+            kind: CommandKind::Reduce,
+            module: "NAT".to_string(),
+            term: "0".to_string(),
+            options,
+        };
+        assert_eq!(
+            bounded.serialize(),
+            Err("reduce takes no bound".to_string())
+        );
+
+        let search = |from: &str, to: &str, condition: Option<&str>| MaudeCommand::Search {
+            kind: CommandKind::Search,
+            module: "M".to_string(),
+            from: from.to_string(),
+            arrow: Arrow::AnySteps,
+            to: to.to_string(),
+            options: CommandOptions {
+                condition: condition.map(str::to_string),
+                ..CommandOptions::default()
+            },
+        };
+        assert!(search("a (", ") b", None).serialize().is_err());
+        assert!(search("a", "X", Some("true . quit")).serialize().is_err());
+        assert!(search("a", "b . quit", None).serialize().is_err());
+    }
+
+    #[test]
+    fn classifies_commands() {
+        assert!(is_mutating("load foo.maude"));
+        assert!(is_mutating("  select NAT ."));
+        assert!(!is_mutating("red 1 + 1 ."));
+        assert!(!is_mutating("loader"));
+        assert!(is_load("sload x"));
+        assert!(!is_load("select NAT ."));
+    }
+}
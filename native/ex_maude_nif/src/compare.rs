@@ -0,0 +1,380 @@
+//! Diffing the results of one command across processes.
+//!
+//! `execute_compare/3` runs a command on two processes - two Maude
+//! versions, or two revisions of a theory - and `execute_expect/3` runs it
+//! on one and holds the output against one recorded earlier. Either way the
+//! outputs are parsed and compared by structure, not text:
+//!
+//! * Every `result Sort: term` line, and every binding of the solutions a
+//!   search prints, is an item, labelled `result 1`, `solution 2 X:Nat`,
+//!   and so on. Items are paired in order; one's terms are compared node by
+//!   node, and each node that differs in operator or arity (or, at the root,
+//!   in sort) is one difference, with its path of argument indices from the
+//!   root. Nodes below a difference aren't compared.
+//! * Output with no results at all, or whose terms don't parse, is
+//!   compared line by line instead, leaving out the `rewrites:` and
+//!   `states:` lines, whose timings differ from run to run.
+//!
+//! A difference holds both sides printed in prefix form, `nil` for a side
+//! that has no such item or line:
+//!
+//! ```text
+//! %{item: "result 1", path: [1], left: "s_(0)", right: "_+_(1, 1)"}
+//! ```
+
+use crate::diagnostics::Outcome;
+use crate::failure::Failure;
+use crate::input::Input;
+use crate::process::{MaudeProcess, Response};
+use crate::reply;
+use crate::search;
+use crate::term::{self, Term};
+use rustler::{Encoder, Env, NifMap, NifResult, ResourceArc};
+
+rustler::atoms! {
+    terms,
+    lines,
+}
+
+/// One place where two outputs differ.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Difference {
+    /// Label of the item, e.g. `"result 1"`, or `"line 3"` for a line.
+    pub item: String,
+    /// Argument indices from the item's root to the differing node.
+    pub path: Vec<usize>,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+/// How two outputs were compared, encoded as `:terms` or `:lines`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compared {
+    Terms,
+    Lines,
+}
+
+impl Encoder for Compared {
+    fn encode<'a>(&self, env: Env<'a>) -> rustler::Term<'a> {
+        match self {
+            Compared::Terms => terms(),
+            Compared::Lines => lines(),
+        }
+        .encode(env)
+    }
+}
+
+/// Result of `execute_compare/3` and `execute_expect/3`.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Comparison {
+    pub equal: bool,
+    /// How the outputs were compared: `:terms` or `:lines`.
+    pub compared: Compared,
+    pub differences: Vec<Difference>,
+    pub left_output: String,
+    pub right_output: String,
+}
+
+/// The results in `output`, labelled; `None` if it has none or one
+/// doesn't parse.
+fn results(output: &str) -> Option<Vec<(String, Term)>> {
+    let mut items = Vec::new();
+
+    for (n, line) in output
+        .lines()
+        .filter(|line| line.starts_with("result "))
+        .enumerate()
+    {
+        items.push((format!("result {}", n + 1), term::parse_result(line).ok()?));
+    }
+    if output.lines().any(|line| line.starts_with("Solution ")) {
+        for solution in search::parse_solutions(output).ok()? {
+            for (variable, value) in solution.substitution {
+                items.push((format!("solution {} {}", solution.number, variable), value));
+            }
+        }
+    }
+
+    (!items.is_empty()).then_some(items)
+}
+
+/// `term` in prefix form, e.g. `_+_(1, s_(0))`.
+fn prefix(term: &Term) -> String {
+    if term.args.is_empty() {
+        return term.op.clone();
+    }
+    let args: Vec<String> = term.args.iter().map(prefix).collect();
+    format!("{}({})", term.op, args.join(", "))
+}
+
+/// `term` as a difference shows it, with its sort if it is an item's root.
+fn show(term: &Term, root: bool) -> String {
+    match (&term.sort, root) {
+        (Some(sort), true) => format!("{}: {}", sort, prefix(term)),
+        _ => prefix(term),
+    }
+}
+
+/// Add the nodes where `left` and `right` differ to `out`.
+fn diff_terms(
+    item: &str,
+    path: &mut Vec<usize>,
+    left: &Term,
+    right: &Term,
+    out: &mut Vec<Difference>,
+) {
+    let root = path.is_empty();
+    if left.op != right.op
+        || left.args.len() != right.args.len()
+        || (root && left.sort != right.sort)
+    {
+        out.push(Difference {
+            item: item.to_string(),
+            path: path.clone(),
+            left: Some(show(left, root)),
+            right: Some(show(right, root)),
+        });
+        return;
+    }
+
+    for (i, (left, right)) in left.args.iter().zip(&right.args).enumerate() {
+        path.push(i);
+        diff_terms(item, path, left, right, out);
+        path.pop();
+    }
+}
+
+/// The differences between the items of two outputs, paired in order.
+fn diff_items(left: &[(String, Term)], right: &[(String, Term)]) -> Vec<Difference> {
+    let mut out = Vec::new();
+
+    for i in 0..left.len().max(right.len()) {
+        match (left.get(i), right.get(i)) {
+            (Some((label, left)), Some((other, right))) if label == other => {
+                diff_terms(label, &mut Vec::new(), left, right, &mut out);
+            }
+            (left, right) => {
+                // Differently labelled items are each missing on the other side
+                if let Some((label, term)) = left {
+                    out.push(Difference {
+                        item: label.clone(),
+                        path: Vec::new(),
+                        left: Some(show(term, true)),
+                        right: None,
+                    });
+                }
+                if let Some((label, term)) = right {
+                    out.push(Difference {
+                        item: label.clone(),
+                        path: Vec::new(),
+                        left: None,
+                        right: Some(show(term, true)),
+                    });
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// The lines of `output` compared when it has no results.
+fn compared_lines(output: &str) -> Vec<&str> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter(|line| !line.starts_with("rewrites: ") && !line.starts_with("states: "))
+        .collect()
+}
+
+fn diff_lines(left: &str, right: &str) -> Vec<Difference> {
+    let (left, right) = (compared_lines(left), compared_lines(right));
+
+    (0..left.len().max(right.len()))
+        .filter_map(|i| {
+            let (left, right) = (left.get(i), right.get(i));
+            (left != right).then(|| Difference {
+                item: format!("line {}", i + 1),
+                path: Vec::new(),
+                left: left.map(|line| line.to_string()),
+                right: right.map(|line| line.to_string()),
+            })
+        })
+        .collect()
+}
+
+/// Compare two outputs; see the module documentation.
+pub fn compare(left: String, right: String) -> Comparison {
+    let (compared, differences) = match (results(&left), results(&right)) {
+        (Some(l), Some(r)) => (Compared::Terms, diff_items(&l, &r)),
+        _ => (Compared::Lines, diff_lines(&left, &right)),
+    };
+
+    Comparison {
+        equal: differences.is_empty(),
+        compared,
+        differences,
+        left_output: left,
+        right_output: right,
+    }
+}
+
+/// Run `command` on `process` for the calling process, its output in
+/// memory.
+fn run(env: Env, process: &MaudeProcess, command: &Input) -> Result<String, Failure> {
+    let exchange = process.begin_by(env.pid())?;
+    let output = exchange
+        .execute_response(&command.parts())
+        .and_then(Response::into_text)?;
+    exchange.take_stderr()?;
+    Ok(output)
+}
+
+/// Run a command on two processes and diff the results.
+///
+/// The command runs on `left` first, then on `right`; each is a separate
+/// exchange, so other callers may run commands in between.
+///
+/// # Arguments
+/// * `left` - Handle to the first Maude process
+/// * `right` - Handle to the second
+/// * `command` - Maude command to execute on both
+///
+/// # Returns
+/// * `Ok(Comparison)` - `%{equal, compared, differences, left_output,
+///   right_output}`
+/// * `Err` - If I/O fails on either process, or an output spilled
+#[rustler::nif(schedule = "DirtyCpu")]
+fn execute_compare<'a>(
+    env: Env<'a>,
+    left: ResourceArc<MaudeProcess>,
+    right: ResourceArc<MaudeProcess>,
+    command: Input<'a>,
+) -> NifResult<Outcome<Comparison>> {
+    reply(run(env, &left, &command).and_then(|left| {
+        let right = run(env, &right, &command)?;
+        Ok(compare(left, right))
+    }))
+}
+
+/// Run a command and diff its results against an expected output, e.g.
+/// one recorded by an earlier run.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `command` - Maude command to execute
+/// * `expected` - Output to compare against, diffed as the right side
+///
+/// # Returns
+/// * `Ok(Comparison)` - As for `execute_compare/3`, the command's output
+///   as `left_output`
+/// * `Err` - If I/O fails, or the output spilled
+#[rustler::nif(schedule = "DirtyCpu")]
+fn execute_expect<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    command: Input<'a>,
+    expected: String,
+) -> NifResult<Outcome<Comparison>> {
+    reply(run(env, &process, &command).map(|output| compare(output, expected.trim().to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(result: &str) -> String {
+        format!(
+            "reduce in NAT : s 0 .\n\
+             rewrites: 1 in 0ms cpu (0ms real) (~ rewrites/second)\n\
+             result {}",
+            result
+        )
+    }
+
+    #[test]
+    fn finds_equal_results_equal() {
+        let left = output("NzNat: s_(0)");
+        let right = left.replace("0ms cpu (0ms real)", "3ms cpu (4ms real)");
+        let comparison = compare(left, right);
+
+        assert!(comparison.equal);
+        assert_eq!(comparison.compared, Compared::Terms);
+        assert!(comparison.differences.is_empty());
+    }
+
+    #[test]
+    fn finds_the_nodes_that_differ() {
+        let comparison = compare(
+            output("Nat: _+_(s_(0), s_(s_(0)))"),
+            output("Nat: _+_(s_(0), _*_(2, 1))"),
+        );
+
+        assert!(!comparison.equal);
+        assert_eq!(comparison.compared, Compared::Terms);
+        assert_eq!(comparison.differences.len(), 1);
+
+        let difference = &comparison.differences[0];
+        assert_eq!(difference.item, "result 1");
+        assert_eq!(difference.path, [1]);
+        assert_eq!(difference.left.as_deref(), Some("s_(s_(0))"));
+        assert_eq!(difference.right.as_deref(), Some("_*_(2, 1)"));
+    }
+
+    #[test]
+    fn finds_a_different_sort_at_the_root() {
+        let comparison = compare(output("NzNat: 1"), output("Nat: 1"));
+
+        assert_eq!(comparison.differences.len(), 1);
+        assert_eq!(comparison.differences[0].path, Vec::<usize>::new());
+        assert_eq!(comparison.differences[0].left.as_deref(), Some("NzNat: 1"));
+        assert_eq!(comparison.differences[0].right.as_deref(), Some("Nat: 1"));
+    }
+
+    #[test]
+    fn compares_search_solutions_by_binding() {
+        let search = |value: &str| {
+            format!(
+                "search in NAT : 0 =>* N:Nat .\n\n\
+                 Solution 1 (state 0)\n\
+                 states: 1  rewrites: 0 in 0ms cpu (0ms real) (~ rewrites/second)\n\
+                 N:Nat --> {}\n\n\
+                 No more solutions.",
+                value
+            )
+        };
+        let comparison = compare(search("0"), search("1"));
+
+        assert_eq!(comparison.compared, Compared::Terms);
+        assert_eq!(comparison.differences.len(), 1);
+        assert_eq!(comparison.differences[0].item, "solution 1 N:Nat");
+    }
+
+    #[test]
+    fn compares_an_error_line_by_line() {
+        let comparison = compare(output("NzNat: 2"), String::new());
+
+        assert!(!comparison.equal);
+        assert_eq!(comparison.compared, Compared::Lines);
+        assert_eq!(
+            comparison
+                .differences
+                .iter()
+                .map(|d| (d.item.as_str(), d.right.as_deref()))
+                .collect::<Vec<_>>(),
+            [("line 1", None), ("line 2", None)]
+        );
+        assert_eq!(
+            comparison.differences[1].left.as_deref(),
+            Some("result NzNat: 2")
+        );
+
+        let unparsed = "result Nat: )(";
+        let comparison = compare(unparsed.to_string(), unparsed.to_string());
+        assert!(comparison.equal);
+        assert_eq!(comparison.compared, Compared::Lines);
+    }
+}
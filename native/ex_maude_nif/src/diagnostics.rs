@@ -0,0 +1,362 @@
+//! Classification of the warnings and errors Maude writes to stderr.
+//!
+//! Maude reports a bad command on stderr and still answers with a prompt,
+//! so its stdout alone looks like success. The execute NIFs read stderr
+//! after each command and, if Maude complained, return
+//! `{:error, reason, raw}` in place of the output: `raw` is everything
+//! Maude wrote to stderr and `reason` describes its first complaint, which
+//! is usually the cause of the rest:
+//!
+//! ```text
+//! Warning: "nat.maude", line 5 (fmod FOO): bad token c.
+//! ```
+//!
+//! becomes `%{kind: :parse_error, severity: :warning, file: "nat.maude",
+//! line: 5, module: "FOO", message: "bad token c."}`. The kinds are:
+//!
+//! * `:parse_error` - A term, statement, or command that doesn't parse
+//! * `:no_such_module` - A module name Maude doesn't know
+//! * `:sort_error` - An undeclared or misused sort
+//! * `:file_not_found` - A file `load` or `in` can't open
+//! * `:unknown` - Anything else
+//!
+//! `file` is `"<standard input>"` for commands, and `file`, `line`, and
+//! `module` are `nil` when Maude doesn't give them. Advisories are not
+//! complaints and are ignored.
+
+use crate::format::Output;
+use rustler::{Encoder, Env, NifMap, Term};
+
+rustler::atoms! {
+    parse_error,
+    no_such_module,
+    sort_error,
+    file_not_found,
+    unknown,
+    warning,
+}
+
+/// Message starts that mark a parse failure.
+const PARSE_ERRORS: &[&str] = &[
+    "no parse for",
+    "bad token",
+    "didn't expect token",
+    "unexpected end of tokens",
+    "skipped unexpected token",
+    "missing space",
+    "syntax error",
+    "multiple distinct parses",
+    "ambiguous",
+];
+
+/// What a complaint is about; see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    ParseError,
+    NoSuchModule,
+    SortError,
+    FileNotFound,
+    Unknown,
+}
+
+impl Encoder for Kind {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            Kind::ParseError => parse_error(),
+            Kind::NoSuchModule => no_such_module(),
+            Kind::SortError => sort_error(),
+            Kind::FileNotFound => file_not_found(),
+            Kind::Unknown => unknown(),
+        }
+        .encode(env)
+    }
+}
+
+/// How Maude labelled a complaint.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Encoder for Severity {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            Severity::Warning => warning(),
+            Severity::Error => rustler::types::atom::error(),
+        }
+        .encode(env)
+    }
+}
+
+/// One complaint from Maude, encoded as a map.
+#[derive(Debug, Clone, NifMap)]
+#[rustler(encode)]
+pub struct Diagnostic {
+    pub kind: Kind,
+    /// `:warning` or `:error`, as Maude labelled it.
+    pub severity: Severity,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// Module being read when Maude complained, as in `line 5 (fmod FOO)`.
+    pub module: Option<String>,
+    /// The complaint, with any lines Maude continued it on.
+    pub message: String,
+}
+
+/// Result of an execute NIF: the output, or Maude's complaint about the
+/// command.
+pub enum Outcome<T = Output> {
+    Done(T),
+    Failed {
+        reason: Diagnostic,
+        raw: String,
+    },
+    /// A response that turned out not to be the command's own, encoded as
+    /// `{:error, :desync, details}`; see [`crate::sequence`].
+    Desync(String),
+}
+
+impl<T> Outcome<T> {
+    /// The failure for a command that left `stderr`, if it holds a warning
+    /// or error.
+    pub fn failed(stderr: &str) -> Option<Outcome<T>> {
+        let reason = parse(stderr).into_iter().next()?;
+        Some(Outcome::Failed {
+            reason,
+            raw: stderr.trim().to_string(),
+        })
+    }
+}
+
+impl<T: Encoder> Encoder for Outcome<T> {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            Outcome::Done(output) => output.encode(env),
+            Outcome::Failed { reason, raw } => {
+                (rustler::types::atom::error(), reason, raw).encode(env)
+            }
+            Outcome::Desync(details) => (
+                rustler::types::atom::error(),
+                crate::failure::desync(),
+                details,
+            )
+                .encode(env),
+        }
+    }
+}
+
+/// Split `stderr` into Maude's complaints, in the order it made them.
+///
+/// Each starts on a `Warning:` or `Error:` line and runs until the next
+/// labelled line, since Maude continues some messages on the following
+/// lines (e.g. the term with a `<---*HERE*` marker).
+pub fn parse(stderr: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut continuing = false;
+
+    for line in stderr.lines() {
+        if let Some(rest) = line.strip_prefix("Warning:") {
+            diagnostics.push(diagnostic(Severity::Warning, rest));
+            continuing = true;
+        } else if let Some(rest) = line.strip_prefix("Error:") {
+            diagnostics.push(diagnostic(Severity::Error, rest));
+            continuing = true;
+        } else if line.starts_with("Advisory:") {
+            continuing = false;
+        } else if let Some(last) = diagnostics.last_mut().filter(|_| continuing) {
+            if !line.trim().is_empty() {
+                last.message.push('\n');
+                last.message.push_str(line.trim_end());
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Build a diagnostic from the text after its `Warning:` or `Error:` label.
+fn diagnostic(severity: Severity, text: &str) -> Diagnostic {
+    let text = text.trim();
+    let (file, line, module, message) = match locate(text) {
+        Some((file, line, module, message)) => (Some(file), Some(line), module, message),
+        None => (None, None, None, text),
+    };
+
+    Diagnostic {
+        kind: classify(message),
+        severity,
+        file,
+        line,
+        module,
+        message: message.to_string(),
+    }
+}
+
+/// Split `<standard input>, line 3 (fmod FOO): message` into the file,
+/// line, module, and message; `None` if the text has no location.
+fn locate(text: &str) -> Option<(String, u32, Option<String>, &str)> {
+    let (file, rest) = match text.chars().next()? {
+        '<' => {
+            let end = text.find('>')?;
+            (&text[..=end], &text[end + 1..])
+        }
+        '"' => {
+            let end = text[1..].find('"')? + 1;
+            (&text[1..end], &text[end + 1..])
+        }
+        _ => return None,
+    };
+
+    let rest = rest.strip_prefix(", line ")?;
+    let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+    let line = rest[..digits].parse().ok()?;
+    let mut rest = rest[digits..].trim_start();
+
+    let mut module = None;
+    if let Some(inner) = rest.strip_prefix('(') {
+        let end = inner.find(')')?;
+        // `fmod FOO`: the keyword is dropped
+        let name = inner[..end].rsplit(' ').next().unwrap_or_default();
+        module = Some(name.to_string());
+        rest = inner[end + 1..].trim_start();
+    }
+
+    let message = rest.strip_prefix(':')?.trim_start();
+    Some((file.to_string(), line, module, message))
+}
+
+fn classify(message: &str) -> Kind {
+    let message = message.to_ascii_lowercase();
+
+    if PARSE_ERRORS.iter().any(|start| message.starts_with(start)) {
+        Kind::ParseError
+    } else if message.starts_with("no module") || message.starts_with("no last module") {
+        Kind::NoSuchModule
+    } else if message.contains("unable to locate file") || message.contains("unable to open file") {
+        Kind::FileNotFound
+    } else if message.contains("sort") || message.contains("kind") {
+        Kind::SortError
+    } else {
+        Kind::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_each_complaint() {
+        let cases = [
+            (
+                "Warning: <standard input>, line 1: bad token foo.",
+                Kind::ParseError,
+                Severity::Warning,
+                "bad token foo.",
+            ),
+            (
+                "Warning: <standard input>, line 1: no parse for term.",
+                Kind::ParseError,
+                Severity::Warning,
+                "no parse for term.",
+            ),
+            (
+                "Warning: <standard input>, line 1: no module FOO.",
+                Kind::NoSuchModule,
+                Severity::Warning,
+                "no module FOO.",
+            ),
+            (
+                "Warning: <standard input>, line 1: no last module.",
+                Kind::NoSuchModule,
+                Severity::Warning,
+                "no last module.",
+            ),
+            (
+                "Warning: <standard input>, line 2: undeclared sort Bar.",
+                Kind::SortError,
+                Severity::Warning,
+                "undeclared sort Bar.",
+            ),
+            (
+                "Error: <standard input>, line 1: unable to locate file: missing.maude",
+                Kind::FileNotFound,
+                Severity::Error,
+                "unable to locate file: missing.maude",
+            ),
+            (
+                "Warning: <standard input>, line 4 (mod FOO): variable X:Nat is used \
+                 before it is bound in rule.",
+                Kind::Unknown,
+                Severity::Warning,
+                "variable X:Nat is used before it is bound in rule.",
+            ),
+        ];
+
+        for (stderr, kind, severity, message) in cases {
+            let diagnostics = parse(stderr);
+            assert_eq!(diagnostics.len(), 1, "for {:?}", stderr);
+            assert_eq!(diagnostics[0].kind, kind, "for {:?}", stderr);
+            assert_eq!(diagnostics[0].severity, severity, "for {:?}", stderr);
+            assert_eq!(diagnostics[0].message, message, "for {:?}", stderr);
+            assert_eq!(diagnostics[0].file.as_deref(), Some("<standard input>"));
+        }
+    }
+
+    #[test]
+    fn reads_the_file_line_and_module() {
+        let diagnostics = parse("Warning: \"nat.maude\", line 5 (fmod FOO): bad token c.");
+
+        assert_eq!(diagnostics[0].file.as_deref(), Some("nat.maude"));
+        assert_eq!(diagnostics[0].line, Some(5));
+        assert_eq!(diagnostics[0].module.as_deref(), Some("FOO"));
+        assert_eq!(diagnostics[0].message, "bad token c.");
+
+        let diagnostics = parse("Warning: sort Nat has been imported from both NAT and FOO.");
+
+        assert_eq!(diagnostics[0].file, None);
+        assert_eq!(diagnostics[0].line, None);
+        assert_eq!(diagnostics[0].module, None);
+        assert_eq!(diagnostics[0].kind, Kind::SortError);
+    }
+
+    #[test]
+    fn continues_messages_and_skips_advisories() {
+        let stderr = "\
+Advisory: <standard input>, line 1 (fmod FOO): redefining module FOO.
+Warning: <standard input>, line 3: didn't expect token ):
+red 1 + ) <---*HERE*
+
+Warning: <standard input>, line 3: no parse for term.
+Advisory: this is ignored
+and so is this.";
+        let diagnostics = parse(stderr);
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0].message,
+            "didn't expect token ):\nred 1 + ) <---*HERE*"
+        );
+        assert_eq!(diagnostics[1].message, "no parse for term.");
+        assert!(diagnostics.iter().all(|d| d.kind == Kind::ParseError));
+    }
+
+    #[test]
+    fn ignores_output_that_merely_mentions_a_warning() {
+        let cases = [
+            "",
+            "result String: \"Warning: not a complaint\"",
+            "rewrites: 1 in 0ms cpu (0ms real) (~ rewrites/second)\n\
+             result Qid: 'Warning",
+            "Advisory: Warning: quoted in an advisory.",
+            "  Warning: indented, so not Maude's label",
+            "Warnings: 0",
+        ];
+
+        for output in cases {
+            assert!(parse(output).is_empty(), "for {:?}", output);
+            assert!(Outcome::<()>::failed(output).is_none(), "for {:?}", output);
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! Finding Maude installations.
+//!
+//! `discover_maude/0` looks for Maude executables where they are usually
+//! found, in this order:
+//!
+//! 1. `MAUDE_PATH` - an executable, or a directory holding one - and
+//!    `MAUDE_LIB`, the directory Maude itself reads its prelude from,
+//!    which for a release archive is also where the binary is
+//! 2. Every directory on `PATH`
+//! 3. Common install locations: `/usr/local/bin`, `/opt/homebrew/bin`,
+//!    `/opt/maude`, `~/.local/bin`, and the like
+//!
+//! A directory is checked for `maude`, the names release archives ship the
+//! binary under (`maude.linux64`, `maude.darwin64`), and those of the
+//! binaries bundled in `priv/maude/bin` (`maude-linux-x64`, ...); each file is
+//! listed once, under the first place it was found. Every candidate is run
+//! with `--version`, concurrently, and killed if it hasn't answered within
+//! two seconds, so one broken or hanging file can't stall discovery;
+//! candidates that didn't answer are still listed, with the reason.
+//!
+//! The bundled binaries in `priv/maude/bin` are for the Elixir layer to
+//! check; see `ExMaude.Binary`.
+
+use rustler::{Atom, NifMap};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+rustler::atoms! {
+    env,
+    path,
+    common,
+}
+
+/// How long a candidate has to print its version.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often a probe checks whether the candidate has exited.
+const PROBE_POLL: Duration = Duration::from_millis(10);
+
+/// File names a Maude executable goes by.
+#[cfg(not(windows))]
+const NAMES: &[&str] = &[
+    "maude",
+    "maude.linux64",
+    "maude.darwin64",
+    "maude-linux-x64",
+    "maude-darwin-arm64",
+    "maude-darwin-x64",
+];
+#[cfg(windows)]
+const NAMES: &[&str] = &["maude.exe"];
+
+/// Directories Maude is commonly installed in, `~` being the home
+/// directory.
+#[cfg(not(windows))]
+const COMMON: &[&str] = &[
+    "/usr/local/bin",
+    "/usr/bin",
+    "/opt/homebrew/bin",
+    "/opt/local/bin",
+    "/opt/maude",
+    "/opt/maude/bin",
+    "/usr/local/maude",
+    "~/.local/bin",
+    "~/bin",
+    "~/maude",
+];
+#[cfg(windows)]
+const COMMON: &[&str] = &["C:\\Program Files\\Maude", "C:\\Maude", "~\\maude"];
+
+/// One Maude executable found by `discover_maude/0`.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Candidate {
+    pub path: String,
+    /// Where it was found: `:env`, `:path`, or `:common`.
+    pub source: Atom,
+    /// Version printed by `maude --version`, if it answered.
+    pub version: Option<String>,
+    /// Why it didn't, if it didn't.
+    pub error: Option<String>,
+}
+
+/// Executable files in the places searched, in order, each once, with
+/// where it was found.
+fn candidates() -> Vec<(PathBuf, Atom)> {
+    let mut places: Vec<(PathBuf, Atom)> = Vec::new();
+
+    for var in ["MAUDE_PATH", "MAUDE_LIB"] {
+        if let Some(value) = std::env::var_os(var).filter(|value| !value.is_empty()) {
+            places.push((PathBuf::from(value), env()));
+        }
+    }
+    if let Some(value) = std::env::var_os("PATH") {
+        places.extend(std::env::split_paths(&value).map(|dir| (dir, path())));
+    }
+    places.extend(
+        COMMON
+            .iter()
+            .filter_map(|dir| Some((expand(dir)?, common()))),
+    );
+
+    let mut found: Vec<(PathBuf, Atom)> = Vec::new();
+    let mut seen: Vec<PathBuf> = Vec::new();
+    for (place, source) in places {
+        let files: Vec<PathBuf> = if place.is_dir() {
+            NAMES.iter().map(|name| place.join(name)).collect()
+        } else {
+            vec![place]
+        };
+
+        for file in files.into_iter().filter(|file| is_executable(file)) {
+            let canonical = file.canonicalize().unwrap_or_else(|_| file.clone());
+            if !seen.contains(&canonical) {
+                seen.push(canonical);
+                found.push((file, source));
+            }
+        }
+    }
+
+    found
+}
+
+/// `dir` with a leading `~` replaced by the home directory; `None` if
+/// there is none.
+fn expand(dir: &str) -> Option<PathBuf> {
+    let Some(rest) = dir.strip_prefix('~') else {
+        return Some(PathBuf::from(dir));
+    };
+    let home = std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" })?;
+    Some(PathBuf::from(home).join(rest.trim_start_matches(['/', '\\'])))
+}
+
+#[cfg(unix)]
+fn is_executable(file: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    file.metadata()
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(file: &Path) -> bool {
+    file.is_file()
+}
+
+/// Run `maude --version`, killing it if it takes longer than `timeout`.
+pub fn probe(file: &Path, timeout: Duration) -> Result<String, String> {
+    let mut child = Command::new(file)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("spawn failed: {}", e))?;
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(PROBE_POLL),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("no answer within {}ms", timeout.as_millis()));
+            }
+            Err(e) => return Err(format!("wait failed: {}", e)),
+        }
+    };
+
+    // The version is one short line, so it fit in the pipe as it exited
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        let _ = stdout.read_to_string(&mut output);
+    }
+    let version = output.trim();
+
+    if !status.success() {
+        return Err(format!("--version failed: {}", status));
+    }
+    if version.is_empty() {
+        return Err("--version printed nothing".to_string());
+    }
+    Ok(version.to_string())
+}
+
+/// List the Maude executables on this machine, with their versions.
+///
+/// # Returns
+/// * `[Candidate]` - `%{path, source, version, error}` per executable, in
+///   the order searched; empty if there is none. `error` says why
+///   `version` is `nil`.
+#[rustler::nif(schedule = "DirtyIo")]
+fn discover_maude() -> Vec<Candidate> {
+    let found = candidates();
+
+    let versions: Vec<Result<String, String>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = found
+            .iter()
+            .map(|(file, _)| {
+                std::thread::Builder::new()
+                    .name("ex_maude-probe".to_string())
+                    .spawn_scoped(scope, move || probe(file, PROBE_TIMEOUT))
+                    .map_err(|e| format!("probe failed to start: {}", e))
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle.and_then(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err("probe panicked".to_string()))
+                })
+            })
+            .collect()
+    });
+
+    found
+        .into_iter()
+        .zip(versions)
+        .map(|((file, source), version)| {
+            let (version, error) = match version {
+                Ok(version) => (Some(version), None),
+                Err(e) => (None, Some(e)),
+            };
+            Candidate {
+                path: file.to_string_lossy().into_owned(),
+                source,
+                version,
+                error,
+            }
+        })
+        .collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn script(name: &str, body: &str) -> PathBuf {
+        let file = std::env::temp_dir().join(format!("ex_maude_{}_{}", name, std::process::id()));
+        std::fs::write(&file, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o755)).unwrap();
+        file
+    }
+
+    #[test]
+    fn kills_a_candidate_that_hangs() {
+        let file = script("hangs", "sleep 30");
+        let start = Instant::now();
+        let probed = probe(&file, Duration::from_millis(200));
+        let _ = std::fs::remove_file(&file);
+
+        assert_eq!(probed, Err("no answer within 200ms".to_string()));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn keeps_a_hanging_reader_off_stdin() {
+        let file = script("reads", "read line; echo \"$line\"");
+        let probed = probe(&file, Duration::from_secs(5));
+        let _ = std::fs::remove_file(&file);
+
+        assert_eq!(probed, Err("--version printed nothing".to_string()));
+    }
+
+    #[test]
+    fn reads_the_version() {
+        let file = script("version", "echo 3.5.1");
+        let probed = probe(&file, PROBE_TIMEOUT);
+        let _ = std::fs::remove_file(&file);
+
+        assert_eq!(probed, Ok("3.5.1".to_string()));
+        assert!(probe(Path::new("/nonexistent/maude"), PROBE_TIMEOUT).is_err());
+    }
+}
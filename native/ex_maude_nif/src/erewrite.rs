@@ -0,0 +1,339 @@
+//! Object-based rewriting with `erewrite`.
+//!
+//! `erewrite` rewrites a configuration of objects and messages the way
+//! `frewrite` does, also exchanging messages with Maude's external
+//! objects - sockets, files, processes. A configuration of objects that
+//! keep talking to each other may never reach a normal form, so
+//! `erewrite/4` takes a bound on the rewrites, the gas per object, and a
+//! deadline. A command still running at its deadline is interrupted, and
+//! the configuration it had reached is returned with `timed_out: true`;
+//! see [`Exchange::execute_interruptible`].
+//!
+//! The result is printed in prefix form (`set print mixfix off .`, set for
+//! the call only), which parses exactly whatever the module's syntax, and
+//! split into its objects and messages:
+//!
+//! ```text
+//! __(to_say_(d, "hi"), <_:_|_>(c, Counter, _`,_(count`:_(1), name`:_("x"))))
+//! ```
+//!
+//! is one message, `to_say_(d, "hi")`, and one object `c` of class
+//! `Counter` with the attributes `count` and `name`.
+
+use crate::command::{self, CommandKind};
+use crate::diagnostics::Outcome;
+use crate::failure::Failure;
+use crate::process::{Exchange, MaudeProcess};
+use crate::reply;
+use crate::settings::Switch;
+use crate::term::{self, Term};
+use rustler::{Atom, Decoder, Env, LocalPid, NifMap, NifResult, ResourceArc};
+#[cfg(unix)]
+use std::time::{Duration, Instant};
+
+rustler::atoms! {
+    bound,
+    gas,
+    timeout,
+}
+
+/// Operator of an object, `< id : class | attributes >`.
+const OBJECT: &str = "<_:_|_>";
+
+/// Options for `erewrite/4`, decoded from a keyword list.
+///
+/// * `:bound` - Maximum number of rewrites (default: unbounded)
+/// * `:gas` - Maximum number of rewrites per object and message position
+///   in each pass over the configuration (default: unbounded)
+/// * `:timeout` - Milliseconds after which the command is interrupted
+///   (default: none; Unix only)
+#[derive(Debug, Default)]
+pub struct ErewriteOptions {
+    pub bound: Option<u64>,
+    pub gas: Option<u64>,
+    pub timeout: Option<u64>,
+}
+
+impl<'a> Decoder<'a> for ErewriteOptions {
+    fn decode(term: rustler::Term<'a>) -> NifResult<Self> {
+        let mut options = ErewriteOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, rustler::Term<'a>)>>()? {
+            if key == bound() {
+                options.bound = Some(value.decode()?);
+            } else if key == gas() {
+                options.gas = Some(value.decode()?);
+            } else if key == timeout() {
+                options.timeout = Some(value.decode()?);
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// An object of a configuration.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Object {
+    pub id: Term,
+    pub class: Term,
+    /// `{"name", value}` per attribute, in Maude's order.
+    pub attributes: Vec<(String, Term)>,
+}
+
+/// A configuration split into its objects and messages.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Configuration {
+    pub objects: Vec<Object>,
+    pub messages: Vec<Term>,
+}
+
+/// Result of `erewrite/4`.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Erewritten {
+    /// The final term, or the one reached when the command was interrupted.
+    pub result: Term,
+    /// `result` as objects and messages; `nil` if it isn't built from them.
+    pub configuration: Option<Configuration>,
+    /// Rewrites Maude reported; `nil` when interrupted.
+    pub rewrites: Option<u64>,
+    pub timed_out: bool,
+}
+
+/// Split a term of sort `Configuration` into its objects and messages.
+///
+/// `None` if an element of it that looks like an object isn't one, e.g.
+/// an attribute set that isn't built with `_,_`.
+pub fn configuration(term: &Term) -> Option<Configuration> {
+    let elements: &[Term] = match term.op.as_str() {
+        "__" => &term.args,
+        "none" => &[],
+        _ => std::slice::from_ref(term),
+    };
+
+    let mut objects = Vec::new();
+    let mut messages = Vec::new();
+    for element in elements {
+        if element.op == OBJECT {
+            objects.push(object(element)?);
+        } else {
+            messages.push(element.clone());
+        }
+    }
+
+    Some(Configuration { objects, messages })
+}
+
+fn object(term: &Term) -> Option<Object> {
+    let [id, class, attributes] = &term.args[..] else {
+        return None;
+    };
+    let attributes: &[Term] = match attributes.op.as_str() {
+        "_`,_" | "_,_" => &attributes.args,
+        "none" => &[],
+        _ => std::slice::from_ref(attributes),
+    };
+
+    let attributes = attributes
+        .iter()
+        .map(|attribute| {
+            // `count`:_(1)`, the name with its colon escaped
+            let name = attribute.op.strip_suffix("`:_")?;
+            let [value] = &attribute.args[..] else {
+                return None;
+            };
+            Some((name.to_string(), value.clone()))
+        })
+        .collect::<Option<_>>()?;
+
+    Some(Object {
+        id: id.clone(),
+        class: class.clone(),
+        attributes,
+    })
+}
+
+/// The count of a `rewrites: N in ...` line.
+fn rewrites(output: &str) -> Option<u64> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("rewrites: "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|count| count.parse().ok())
+}
+
+/// Set mixfix printing on or off; restored afterwards, so allowed on a
+/// read-only process.
+fn set_mixfix(exchange: &Exchange, value: bool) -> Result<(), Failure> {
+    let command = Switch::PrintMixfix.command(value);
+    exchange.trusted(|| exchange.execute(&command)).map(drop)
+}
+
+/// Run `command`, interrupting it at `timeout`; the output, and the
+/// current term if it was interrupted.
+fn run(
+    exchange: &Exchange,
+    command: &str,
+    timeout: Option<u64>,
+) -> Result<(String, Option<String>), Failure> {
+    let Some(timeout) = timeout else {
+        return exchange.execute(command).map(|output| (output, None));
+    };
+
+    #[cfg(unix)]
+    {
+        let deadline = Instant::now() + Duration::from_millis(timeout);
+        exchange.execute_interruptible(&[command.as_bytes()], deadline)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = timeout;
+        Err("erewrite timeouts are not supported on this platform".into())
+    }
+}
+
+fn rewrite(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    module: &str,
+    term: &str,
+    opts: &ErewriteOptions,
+) -> Result<Erewritten, Failure> {
+    if opts.bound == Some(0) {
+        return Err("bound must be positive".into());
+    }
+
+    let command = command::build_limited(
+        CommandKind::Erewrite,
+        &[],
+        opts.bound,
+        opts.gas,
+        module,
+        term,
+    )
+    .map_err(|e| format!("rejected command: {}", e))?;
+
+    let exchange = process.begin_by(caller)?;
+    let mixfix = process.settings().get(Switch::PrintMixfix);
+
+    set_mixfix(&exchange, false)?;
+    let result = run(&exchange, &command, opts.timeout);
+    let restored = set_mixfix(&exchange, mixfix);
+    let diagnostics = exchange.take_stderr();
+    drop(exchange);
+
+    let (output, current) = result?;
+    restored?;
+    let diagnostics = diagnostics?;
+
+    let timed_out = current.is_some();
+    let result = match &current {
+        Some(current) => term::parse(current),
+        None => term::parse_result(&output),
+    }
+    .map_err(|e| match diagnostics.trim() {
+        "" => Failure::from(format!("parse failed: {}", e)),
+        diagnostics => Failure::from(format!("erewrite failed: {}", diagnostics)),
+    })?;
+
+    Ok(Erewritten {
+        configuration: configuration(&result),
+        rewrites: if timed_out { None } else { rewrites(&output) },
+        result,
+        timed_out,
+    })
+}
+
+/// Rewrite a configuration with `erewrite in <module> : <term> .`.
+///
+/// The term is validated like that of `execute_term/4`, so it may be
+/// untrusted.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `module` - Module to rewrite in
+/// * `term` - Initial configuration
+/// * `opts` - Keyword list; see [`ErewriteOptions`]
+///
+/// # Returns
+/// * `Ok(Erewritten)` - `%{result, configuration, rewrites, timed_out}`
+/// * `Err` - If the input is rejected, the process is read-only (see
+///   [`crate::readonly`]), I/O fails, or the output has no parseable
+///   result (with Maude's complaint, if it made one)
+#[rustler::nif(schedule = "DirtyCpu")]
+fn erewrite(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    term: String,
+    opts: ErewriteOptions,
+) -> NifResult<Outcome<Erewritten>> {
+    reply(rewrite(&process, env.pid(), &module, &term, &opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: &str = "\
+erewrite in COUNTER : __(to_say_(d, \"hi\"), <_:_|_>(c, Counter, count`:_(0))) .
+rewrites: 3 in 0ms cpu (0ms real) (~ rewrites/second)
+result Configuration: __(to_say_(d, \"hi\"), <_:_|_>(c, Counter, _`,_(count`:_(1), name`:_(\"x\"))))";
+
+    fn parse(text: &str) -> Term {
+        term::parse(text).unwrap()
+    }
+
+    #[test]
+    fn splits_a_configuration_into_objects_and_messages() {
+        let result = term::parse_result(OUTPUT).unwrap();
+        let configuration = configuration(&result).unwrap();
+
+        assert_eq!(configuration.messages, [parse("to_say_(d, \"hi\")")]);
+        assert_eq!(configuration.objects.len(), 1);
+
+        let object = &configuration.objects[0];
+        assert_eq!(object.id, parse("c"));
+        assert_eq!(object.class, parse("Counter"));
+        assert_eq!(
+            object.attributes,
+            [
+                ("count".to_string(), parse("1")),
+                ("name".to_string(), parse("\"x\""))
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_the_rewrite_count() {
+        assert_eq!(rewrites(OUTPUT), Some(3));
+        assert_eq!(
+            rewrites("rewrites: 1000 in 12ms cpu (12ms real) (83333 rewrites/second)"),
+            Some(1000)
+        );
+        assert_eq!(rewrites("result Configuration: none"), None);
+    }
+
+    #[test]
+    fn splits_configurations_of_one_element_or_none() {
+        let empty = configuration(&parse("none")).unwrap();
+        assert!(empty.objects.is_empty() && empty.messages.is_empty());
+
+        let single = configuration(&parse("<_:_|_>(c, Counter, none)")).unwrap();
+        assert_eq!(single.objects.len(), 1);
+        assert!(single.objects[0].attributes.is_empty());
+        assert!(single.messages.is_empty());
+
+        let message = configuration(&parse("ping(c)")).unwrap();
+        assert_eq!(message.messages, [parse("ping(c)")]);
+    }
+
+    #[test]
+    fn refuses_malformed_objects() {
+        assert!(configuration(&parse("<_:_|_>(c, Counter)")).is_none());
+        assert!(configuration(&parse("<_:_|_>(c, Counter, f(1))")).is_none());
+    }
+}
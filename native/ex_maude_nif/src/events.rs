@@ -0,0 +1,388 @@
+//! A stream of lifecycle events for observability.
+//!
+//! `subscribe_events/2` has a pid sent
+//!
+//! ```elixir
+//! {:ex_maude_event, event, timestamp, details}
+//! ```
+//!
+//! for everything that happens to a process from then on, where
+//! `timestamp` is the system time in microseconds and `event` is one of:
+//!
+//! * `:spawned` - the process became ready; `%{os_pid, startup_us}`
+//! * `:command_started` - `%{command}`, the first 256 bytes of it
+//! * `:command_finished` - `%{command, duration_us, result}`, where
+//!   `result` is `:ok` or `{:error, reason}`
+//! * `:stderr_warning` - a line Maude wrote to stderr; `%{line}`
+//! * `:restarted` - a resilient process replaced its subprocess;
+//!   `%{restarts, os_pid}`
+//! * `:exited` - the child is gone, `%{reason}`: `"stopped"` if it was
+//!   stopped on purpose, else why its output ended
+//!
+//! The name is `subscribe_events/2` because `subscribe/2` is the
+//! heartbeat's, which sends one `{:maude_down, reason}` when the child
+//! dies; see [`crate::heartbeat`].
+//!
+//! Heartbeat pings and the NIF's own markers aren't reported. Only a
+//! process started with `start_async/2` can be subscribed to before it is
+//! `:spawned`.
+//!
+//! Events are sent in order by one `ex_maude-events` thread per process,
+//! started with the first subscription: the scheduler threads commands run
+//! on can't send without the calling process's environment. A process may
+//! have any number of subscribers; one is dropped once it has exited rather
+//! than monitored, or by `unsubscribe_events/2`. Without subscribers the
+//! events cost a flag check.
+
+use crate::error;
+use crate::failure::Failure;
+use crate::process::MaudeProcess;
+use crate::resilient::ResilientProcess;
+use crate::threads;
+use rustler::types::map::map_new;
+use rustler::{Atom, Encoder, Env, LocalPid, NifResult, OwnedEnv, ResourceArc, Term};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+rustler::atoms! {
+    ok,
+    ex_maude_event,
+    spawned,
+    command_started,
+    command_finished,
+    stderr_warning,
+    restarted,
+    exited,
+    error_atom = "error",
+    os_pid,
+    startup_us,
+    command,
+    duration_us,
+    result,
+    line,
+    restarts,
+    reason,
+}
+
+/// Longest stderr line sent whole; a longer one is sent in pieces.
+const LINE_LIMIT: usize = 4096;
+
+/// What happened, with its details.
+#[derive(Debug)]
+pub enum Event {
+    Spawned {
+        os_pid: Option<u32>,
+        startup_us: u64,
+    },
+    CommandStarted {
+        command: String,
+    },
+    CommandFinished {
+        command: String,
+        duration_us: u64,
+        error: Option<String>,
+    },
+    StderrWarning {
+        line: String,
+    },
+    Restarted {
+        restarts: u64,
+        os_pid: Option<u32>,
+    },
+    Exited {
+        reason: String,
+    },
+}
+
+impl Event {
+    /// The `{:ex_maude_event, event, timestamp, details}` message.
+    fn encode<'a>(&self, env: Env<'a>, timestamp: u64) -> Term<'a> {
+        let (event, details): (Atom, Vec<(Atom, Term<'a>)>) = match self {
+            Event::Spawned {
+                os_pid: pid,
+                startup_us: us,
+            } => (
+                spawned(),
+                vec![(os_pid(), pid.encode(env)), (startup_us(), us.encode(env))],
+            ),
+            Event::CommandStarted { command: text } => {
+                (command_started(), vec![(command(), text.encode(env))])
+            }
+            Event::CommandFinished {
+                command: text,
+                duration_us: us,
+                error,
+            } => {
+                let outcome = match error {
+                    None => ok().encode(env),
+                    Some(e) => (error_atom(), e).encode(env),
+                };
+                (
+                    command_finished(),
+                    vec![
+                        (command(), text.encode(env)),
+                        (duration_us(), us.encode(env)),
+                        (result(), outcome),
+                    ],
+                )
+            }
+            Event::StderrWarning { line: text } => {
+                (stderr_warning(), vec![(line(), text.encode(env))])
+            }
+            Event::Restarted {
+                restarts: count,
+                os_pid: pid,
+            } => (
+                restarted(),
+                vec![(restarts(), count.encode(env)), (os_pid(), pid.encode(env))],
+            ),
+            Event::Exited { reason: why } => (exited(), vec![(reason(), why.encode(env))]),
+        };
+
+        let details = details.into_iter().fold(map_new(env), |map, (key, value)| {
+            map.map_put(key, value).unwrap_or(map)
+        });
+        (ex_maude_event(), event, timestamp, details).encode(env)
+    }
+}
+
+/// A process's event subscribers, and the thread sending to them.
+#[derive(Default)]
+pub struct Events {
+    /// Shared with the sending thread, which drops pids that have exited.
+    subscribers: Arc<Mutex<Vec<LocalPid>>>,
+    /// Whether there are subscribers, checked before any event is made.
+    active: Arc<AtomicBool>,
+    /// Events for the sending thread, once one is started.
+    sender: OnceLock<Sender<(Event, u64)>>,
+    /// The stderr line being written, while there are subscribers. A leaf
+    /// lock.
+    line: Mutex<Vec<u8>>,
+    /// Set once `:exited` is sent, so it is sent once.
+    exited: AtomicBool,
+}
+
+impl Events {
+    fn lock(&self) -> MutexGuard<'_, Vec<LocalPid>> {
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Add `pid`, unless it is subscribed already; fails if the sending
+    /// thread can't be started.
+    pub fn subscribe(&self, pid: LocalPid) -> Result<(), String> {
+        self.start()?;
+        let mut subscribers = self.lock();
+        if !subscribers.contains(&pid) {
+            subscribers.push(pid);
+        }
+        self.active.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Remove `pid`, returning whether it was subscribed.
+    pub fn unsubscribe(&self, pid: LocalPid) -> bool {
+        let mut subscribers = self.lock();
+        let before = subscribers.len();
+        subscribers.retain(|subscriber| *subscriber != pid);
+        self.active
+            .store(!subscribers.is_empty(), Ordering::Relaxed);
+        subscribers.len() < before
+    }
+
+    /// Subscribe everyone subscribed to `other`, as when a process takes
+    /// over from it.
+    pub fn adopt(&self, other: &Events) -> Result<(), String> {
+        let pids = other.lock().clone();
+        pids.into_iter().try_for_each(|pid| self.subscribe(pid))
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Send `event` to every subscriber, if there are any.
+    pub fn emit(&self, event: impl FnOnce() -> Event) {
+        if !self.is_active() {
+            return;
+        }
+        let Some(sender) = self.sender.get() else {
+            return;
+        };
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(crate::process::micros)
+            .unwrap_or(0);
+        let _ = sender.send((event(), timestamp));
+    }
+
+    /// Run the command `text` names, with `:command_started` before and
+    /// `:command_finished` after.
+    pub fn command<T>(
+        &self,
+        text: impl FnOnce() -> String,
+        run: impl FnOnce() -> Result<T, Failure>,
+    ) -> Result<T, Failure> {
+        if !self.is_active() {
+            return run();
+        }
+
+        let text = text();
+        self.emit(|| Event::CommandStarted {
+            command: text.clone(),
+        });
+        let start = Instant::now();
+        let result = run();
+        self.emit(|| Event::CommandFinished {
+            command: text,
+            duration_us: crate::process::micros(start.elapsed()),
+            error: result.as_ref().err().map(Failure::to_string),
+        });
+        result
+    }
+
+    /// Send `:stderr_warning` for each line completed by `data`, read from
+    /// Maude's stderr.
+    ///
+    /// The warnings Maude gives for the NIF's load markers and sentinels,
+    /// naming modules starting `ex_maude_`, aren't sent.
+    pub fn stderr(&self, data: &[u8]) {
+        if !self.is_active() {
+            return;
+        }
+
+        let mut line = self.line.lock().unwrap_or_else(|e| e.into_inner());
+        line.extend_from_slice(data);
+        let mut lines = Vec::new();
+        while let Some(end) = line.iter().position(|&b| b == b'\n') {
+            lines.push(line.drain(..=end).collect::<Vec<u8>>());
+        }
+        if line.len() > LINE_LIMIT {
+            lines.push(std::mem::take(&mut *line));
+        }
+        drop(line);
+
+        for bytes in lines {
+            let text = String::from_utf8_lossy(&bytes).trim_end().to_string();
+            if text.trim().is_empty() || text.contains("no module ex_maude_") {
+                continue;
+            }
+            self.emit(|| Event::StderrWarning { line: text });
+        }
+    }
+
+    /// Send `:exited` with `reason`, unless it was sent already.
+    pub fn exit(&self, reason: &str) {
+        if !self.is_active() || self.exited.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        self.emit(|| Event::Exited {
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Start the sending thread, unless it is running.
+    ///
+    /// It stops once the process, and with it the sender, is dropped. Of
+    /// two started at once, the one whose sender isn't kept stops at once.
+    fn start(&self) -> Result<(), String> {
+        if self.sender.get().is_some() {
+            return Ok(());
+        }
+
+        let (sender, receiver) = mpsc::channel::<(Event, u64)>();
+        let subscribers = Arc::clone(&self.subscribers);
+        let active = Arc::clone(&self.active);
+
+        threads::spawn("ex_maude-events", move || {
+            let mut env = OwnedEnv::new();
+            for (event, timestamp) in receiver {
+                let pids = subscribers
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone();
+                let gone: Vec<LocalPid> = pids
+                    .into_iter()
+                    .filter(|pid| {
+                        env.send_and_clear(pid, |env| event.encode(env, timestamp))
+                            .is_err()
+                    })
+                    .collect();
+
+                if !gone.is_empty() {
+                    let mut subscribers = subscribers.lock().unwrap_or_else(|e| e.into_inner());
+                    subscribers.retain(|pid| !gone.contains(pid));
+                    active.store(!subscribers.is_empty(), Ordering::Relaxed);
+                }
+            }
+        })?;
+        let _ = self.sender.set(sender);
+        Ok(())
+    }
+}
+
+/// Have `pid` sent `{:ex_maude_event, event, timestamp, details}` for
+/// everything that happens to a process from now on; see
+/// [`crate::events`].
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `pid` - Process to send events to
+///
+/// # Returns
+/// * `Ok(:ok)` - `pid` is subscribed
+/// * `Err` - If `pid` is not alive or the sending thread can't be started
+#[rustler::nif]
+fn subscribe_events(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    pid: LocalPid,
+) -> NifResult<Atom> {
+    if !env.is_process_alive(pid) {
+        return Err(error("subscriber is not alive"));
+    }
+    process.events().subscribe(pid).map_err(error)?;
+    Ok(ok())
+}
+
+/// Stop sending events to `pid`.
+///
+/// # Returns
+/// * `true` - `pid` was subscribed
+/// * `false` - It wasn't
+#[rustler::nif]
+fn unsubscribe_events(process: ResourceArc<MaudeProcess>, pid: LocalPid) -> bool {
+    process.events().unsubscribe(pid)
+}
+
+/// `subscribe_events/2` for a resilient process, whose subscribers carry
+/// over to each subprocess replacing the last.
+///
+/// # Returns
+/// * As for `subscribe_events/2`
+#[rustler::nif(schedule = "DirtyCpu")]
+fn resilient_subscribe_events(
+    env: Env,
+    process: ResourceArc<ResilientProcess>,
+    pid: LocalPid,
+) -> NifResult<Atom> {
+    if !env.is_process_alive(pid) {
+        return Err(error("subscriber is not alive"));
+    }
+    process.subscribe_events(pid).map_err(error)?;
+    Ok(ok())
+}
+
+/// `unsubscribe_events/2` for a resilient process.
+///
+/// # Returns
+/// * As for `unsubscribe_events/2`
+#[rustler::nif(schedule = "DirtyCpu")]
+fn resilient_unsubscribe_events(
+    process: ResourceArc<ResilientProcess>,
+    pid: LocalPid,
+) -> NifResult<bool> {
+    process.unsubscribe_events(pid).map_err(error)
+}
@@ -0,0 +1,118 @@
+//! Why a command failed, as the NIFs report it.
+//!
+//! Most failures are messages, returned to Elixir as `{:error, message}`.
+//! A few are conditions callers match on, and travel as variants of their
+//! own until they are encoded, each as its own term:
+//!
+//! * [`Failure::ResourceLimit`] - `{:error, :resource_limit}`; see
+//!   [`crate::limits`]
+//! * [`Failure::Leased`] - `{:error, :leased}`; see [`crate::lease`]
+//! * [`Failure::Overloaded`] - `{:error, :overloaded}`; see
+//!   [`crate::backpressure`]
+//! * [`Failure::Awaiting`] - `{:error, :awaiting}`; see [`crate::sent`]
+//! * [`Failure::Desync`] - `{:error, :desync, details}`; see
+//!   [`crate::sequence`]
+//!
+//! A desync takes a tuple of its own, so the NIFs a command can desync
+//! hand their result back through [`crate::reply`] rather than
+//! [`crate::error`].
+//!
+//! A message converts into a `Failure`, so `?` passes one on from code that
+//! only ever fails with messages.
+
+use rustler::{Encoder, Env, Term};
+use std::fmt;
+
+rustler::atoms! {
+    resource_limit,
+    leased,
+    overloaded,
+    awaiting,
+    desync,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    Message(String),
+    ResourceLimit,
+    Leased,
+    Overloaded,
+    Awaiting,
+    /// What came in place of the sequence sentinel.
+    Desync(String),
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Failure {
+        Failure::Message(message)
+    }
+}
+
+impl From<&str> for Failure {
+    fn from(message: &str) -> Failure {
+        Failure::Message(message.to_string())
+    }
+}
+
+/// The message, for logs and events, and for callers that only report it.
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Message(message) => f.write_str(message),
+            Failure::ResourceLimit => f.write_str(crate::limits::EXCEEDED),
+            Failure::Leased => f.write_str(crate::lease::HELD),
+            Failure::Overloaded => f.write_str(crate::backpressure::OVERLOADED),
+            Failure::Awaiting => f.write_str(crate::sent::AWAITING),
+            Failure::Desync(details) => write!(f, "{}{}", crate::sequence::DESYNC, details),
+        }
+    }
+}
+
+/// The reason in `{:error, reason}`; a desync that still ends up there is
+/// `{:desync, details}`.
+impl Encoder for Failure {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            Failure::Message(message) => message.encode(env),
+            Failure::ResourceLimit => resource_limit().encode(env),
+            Failure::Leased => leased().encode(env),
+            Failure::Overloaded => overloaded().encode(env),
+            Failure::Awaiting => awaiting().encode(env),
+            Failure::Desync(details) => (desync(), details).encode(env),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn displays_the_message() {
+        assert_eq!(Failure::from("maude exited").to_string(), "maude exited");
+        assert_eq!(Failure::ResourceLimit.to_string(), crate::limits::EXCEEDED);
+        assert_eq!(
+            Failure::Desync("command 3".to_string()).to_string(),
+            "desync: command 3"
+        );
+    }
+
+    #[test]
+    fn replies_with_a_desync_of_its_own() {
+        let desync: Result<(), Failure> = Err(Failure::Desync("command 3".to_string()));
+        assert!(matches!(
+            crate::reply(desync),
+            Ok(crate::diagnostics::Outcome::Desync(details)) if details == "command 3"
+        ));
+        assert!(crate::reply(Err::<(), _>(Failure::Leased)).is_err());
+    }
+
+    #[test]
+    fn keeps_a_message_a_message() {
+        // Only the producers of a condition build its variant
+        assert_eq!(
+            Failure::from(crate::lease::HELD.to_string()),
+            Failure::Message(crate::lease::HELD.to_string())
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Keeping only the lines of a response a caller asks for.
+//!
+//! With `filter:` an execute call returns just the lines of Maude's output
+//! that start with one of the given prefixes, e.g. `filter: ["Solution",
+//! "result"]` for a search printing thousands of states in between. The
+//! reader applies the filter as the output arrives, so the rest is never
+//! held in memory, let alone copied into a binary.
+//!
+//! Lines are matched by their start only, the newline ending them kept.
+//! Stats and search bookkeeping still see the whole output, through a
+//! sample of its short lines as for chunked output; see [`crate::chunks`].
+//! A filtered call neither answers from the response cache nor adds to
+//! it; see [`crate::cache`].
+
+use rustler::{Decoder, NifResult, Term};
+
+/// The line prefixes to keep, from `filter:`: a binary or a list of them.
+#[derive(Debug, Clone)]
+pub struct LineFilter {
+    prefixes: Vec<Vec<u8>>,
+    /// Bytes of a line needed to tell whether it is kept.
+    longest: usize,
+}
+
+impl LineFilter {
+    pub fn new(prefixes: Vec<Vec<u8>>) -> LineFilter {
+        let longest = prefixes.iter().map(Vec::len).max().unwrap_or(0);
+        LineFilter { prefixes, longest }
+    }
+
+    fn keeps(&self, line: &[u8]) -> bool {
+        self.prefixes.iter().any(|prefix| line.starts_with(prefix))
+    }
+}
+
+impl<'a> Decoder<'a> for LineFilter {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let prefixes: Vec<String> = match term.decode::<String>() {
+            Ok(prefix) => vec![prefix],
+            Err(_) => term.decode()?,
+        };
+        Ok(LineFilter::new(
+            prefixes.into_iter().map(String::into_bytes).collect(),
+        ))
+    }
+}
+
+/// What is known of the line being read.
+#[derive(Debug)]
+enum Line {
+    /// Too little of it has arrived to tell; the bytes so far.
+    Undecided(Vec<u8>),
+    Kept,
+    Dropped,
+}
+
+/// A [`LineFilter`] applied to output fed to it piece by piece.
+#[derive(Debug)]
+pub struct Lines<'a> {
+    filter: &'a LineFilter,
+    line: Line,
+}
+
+impl<'a> Lines<'a> {
+    pub fn new(filter: &'a LineFilter) -> Lines<'a> {
+        Lines {
+            filter,
+            line: Line::Undecided(Vec::new()),
+        }
+    }
+
+    /// Append the kept part of `data` to `out`.
+    pub fn feed(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        for piece in data.split_inclusive(|&byte| byte == b'\n') {
+            let ended = piece.ends_with(b"\n");
+
+            match &mut self.line {
+                Line::Undecided(head) => {
+                    head.extend_from_slice(piece);
+                    if ended || head.len() >= self.filter.longest {
+                        if self.filter.keeps(head) {
+                            out.append(head);
+                            self.line = Line::Kept;
+                        } else {
+                            self.line = Line::Dropped;
+                        }
+                    }
+                }
+                Line::Kept => out.extend_from_slice(piece),
+                Line::Dropped => {}
+            }
+
+            if ended {
+                self.line = Line::Undecided(Vec::new());
+            }
+        }
+    }
+
+    /// End the output at the prompt `prompt`, which was fed last, leaving
+    /// it out of `out`.
+    pub fn finish(self, prompt: &[u8], out: &mut Vec<u8>) {
+        match self.line {
+            Line::Undecided(mut head) => {
+                // Only the prompt's own line can be left open
+                head.truncate(head.len().saturating_sub(prompt.len()));
+                if !head.is_empty() && self.filter.keeps(&head) {
+                    out.append(&mut head);
+                }
+            }
+            Line::Kept => out.truncate(out.len().saturating_sub(prompt.len())),
+            Line::Dropped => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROMPT: &[u8] = b"Maude> ";
+
+    fn filter(prefixes: &[&str]) -> LineFilter {
+        LineFilter::new(prefixes.iter().map(|p| p.as_bytes().to_vec()).collect())
+    }
+
+    /// What is kept of `pieces`, fed in turn and ending in the prompt.
+    fn filtered(filter: &LineFilter, pieces: &[&[u8]]) -> Vec<u8> {
+        let mut lines = Lines::new(filter);
+        let mut out = Vec::new();
+        for piece in pieces {
+            lines.feed(piece, &mut out);
+        }
+        lines.finish(PROMPT, &mut out);
+        out
+    }
+
+    /// Check what is kept of `output` and the prompt, split in two at every
+    /// point, and fed byte by byte.
+    fn every_split(filter: &LineFilter, output: &str, expected: &str) {
+        let whole = [output.as_bytes(), PROMPT].concat();
+        for at in 0..=whole.len() {
+            let (head, tail) = whole.split_at(at);
+            assert_eq!(
+                filtered(filter, &[head, tail]),
+                expected.as_bytes(),
+                "split at {}",
+                at
+            );
+        }
+        let bytes: Vec<&[u8]> = whole.chunks(1).collect();
+        assert_eq!(filtered(filter, &bytes), expected.as_bytes());
+    }
+
+    #[test]
+    fn keeps_lines_across_pieces() {
+        let search = filter(&["Solution", "result"]);
+        every_split(
+            &search,
+            "Solution 1 (state 0)\nstates: 1  rewrites: 2\nX --> 1\nresult Nat: 2\n",
+            "Solution 1 (state 0)\nresult Nat: 2\n",
+        );
+        every_split(&search, "states: 1\nSolutions\n", "Solutions\n");
+        every_split(&search, "", "");
+    }
+
+    #[test]
+    fn leaves_the_prompt_out() {
+        // A kept line the prompt follows without a newline
+        every_split(&filter(&["result"]), "result Nat: 2", "result Nat: 2");
+        every_split(&filter(&["result"]), "rewrites: 2", "");
+        // One still too short to tell when the prompt comes
+        every_split(&filter(&["a rather long prefix"]), "a r", "");
+        every_split(&filter(&["Maude"]), "", "");
+    }
+}
@@ -0,0 +1,501 @@
+//! Output format negotiation shared by the execute NIFs.
+//!
+//! The calls returning Maude's output - `execute`, `execute_io`,
+//! `execute_term`, `run`, `pool_execute`, `execute_named`, `collect`, and
+//! the resilient, hibernating, and shadowed execute calls - each have a
+//! variant taking a trailing keyword list with `:format`, and all of them
+//! route their output through [`render`], so the same choice of
+//! representation is available across them. The calls that parse the
+//! output into results of their own, such as `reduce_in`, `check`,
+//! `unify`, `match`, and `search_next`, take no `:format`:
+//!
+//! * `:raw` - Maude's output as printed, including the command echo and
+//!   stats line (the default)
+//! * `:clean` - Output with the command echo and stats lines removed
+//! * `:parsed` - The result term as `{op, sort, args}` tuples
+//! * `:json` - The result term as a JSON string with `op`, `sort`, and
+//!   `args` keys
+//! * `:transcript` - Everything Maude wrote for the command, untrimmed and
+//!   with the prompt it ended on, as a map of `raw` (a binary),
+//!   `response_start` and `response_end` (byte offsets of the output
+//!   without surrounding whitespace, the end exclusive), and `prompt_start`;
+//!   `:trim` is ignored and invalid UTF-8 is kept as is
+//! * `:iodata` - Maude's output as printed, as a list of binaries in the
+//!   chunks it was read in rather than one binary; see [`crate::chunks`].
+//!   `:decode` is ignored, as the bytes are never decoded
+//!
+//! Output that isn't valid UTF-8 is handled according to `:decode`:
+//!
+//! * `:replace` - Invalid sequences become U+FFFD and the output is
+//!   formatted as usual (the default); the process's `invalid_utf8` stat
+//!   counts these responses
+//! * `:raise` - The call fails with an error giving the byte offset
+//! * `:binary` - The bytes are returned exactly as Maude wrote them,
+//!   without formatting
+//!
+//! Valid output is unaffected by `:decode`.
+//!
+//! Surrounding whitespace is removed according to `:trim`, which
+//! overrides the process's own `:trim` spawn option:
+//!
+//! * `:both` - Leading and trailing whitespace (the default)
+//! * `:trailing` - Trailing whitespace only, keeping indentation
+//! * `:none` - Nothing; the output is exactly what preceded the prompt
+//!
+//! With `meta: true`, the output comes back as `{output, meta}`, where
+//! `meta` is `%{duration_us, bytes_read, cached, restarted}` as measured by
+//! the NIF; see [`Meta`]. The execute, `pool_execute`, `execute_named`, and
+//! `resilient_execute` calls measure it; the others ignore `:meta`.
+//!
+//! With `filter:`, a prefix or a list of them, only the lines of the output
+//! starting with one are kept, as it is read; see [`crate::filter`]. The
+//! calls measuring `:meta` filter; the others ignore `:filter`.
+//!
+//! A response that spilled to a file (see [`crate::spill`]) is returned as
+//! `{:spilled, path, bytes}` whatever the format, since converting it would
+//! mean reading it back into memory.
+
+use crate::filter::LineFilter;
+use crate::process::{Response, PROMPT};
+use crate::term::{self, Term};
+use rustler::{Atom, Binary, Decoder, Encoder, Env, NewBinary, NifMap, NifResult};
+
+rustler::atoms! {
+    format,
+    raw,
+    clean,
+    parsed,
+    json,
+    transcript,
+    iodata,
+    spilled,
+    decode,
+    replace,
+    raise,
+    binary,
+    trim,
+    both,
+    trailing,
+    none,
+    meta,
+    filter,
+}
+
+/// Representation requested with `format:`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Format {
+    #[default]
+    Raw,
+    Clean,
+    Parsed,
+    Json,
+    Transcript,
+    Iodata,
+}
+
+/// Handling of output that isn't valid UTF-8, requested with `decode:`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Decode {
+    #[default]
+    Replace,
+    Raise,
+    Binary,
+}
+
+/// Whitespace removed from around the output, requested with `trim:`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Trim {
+    #[default]
+    Both,
+    Trailing,
+    None,
+}
+
+impl Trim {
+    pub fn apply(self, output: &str) -> &str {
+        match self {
+            Trim::Both => output.trim(),
+            Trim::Trailing => output.trim_end(),
+            Trim::None => output,
+        }
+    }
+
+    pub fn apply_bytes(self, output: &[u8]) -> &[u8] {
+        match self {
+            Trim::Both => output.trim_ascii(),
+            Trim::Trailing => output.trim_ascii_end(),
+            Trim::None => output,
+        }
+    }
+}
+
+impl Encoder for Trim {
+    fn encode<'a>(&self, env: Env<'a>) -> rustler::Term<'a> {
+        match self {
+            Trim::Both => both(),
+            Trim::Trailing => trailing(),
+            Trim::None => none(),
+        }
+        .encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for Trim {
+    fn decode(term: rustler::Term<'a>) -> NifResult<Self> {
+        let value = term.decode::<Atom>()?;
+        [
+            (both(), Trim::Both),
+            (trailing(), Trim::Trailing),
+            (none(), Trim::None),
+        ]
+        .into_iter()
+        .find_map(|(atom, trim)| (atom == value).then_some(trim))
+        .ok_or(rustler::Error::BadArg)
+    }
+}
+
+/// Options accepted by the execute NIF variants, from a keyword list.
+///
+/// `trim` is `None` unless given, leaving the process's policy in effect.
+/// Unknown keys are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    pub format: Format,
+    pub decode: Decode,
+    pub trim: Option<Trim>,
+    /// Whether to return `{output, meta}`; see [`Meta`].
+    pub meta: bool,
+    /// Line prefixes to keep of the output; see [`crate::filter`].
+    pub filter: Option<LineFilter>,
+}
+
+impl<'a> Decoder<'a> for OutputOptions {
+    fn decode(term: rustler::Term<'a>) -> NifResult<Self> {
+        let mut options = OutputOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, rustler::Term<'a>)>>()? {
+            if key == format() {
+                let value = value.decode::<Atom>()?;
+                options.format = [
+                    (raw(), Format::Raw),
+                    (clean(), Format::Clean),
+                    (parsed(), Format::Parsed),
+                    (json(), Format::Json),
+                    (transcript(), Format::Transcript),
+                    (iodata(), Format::Iodata),
+                ]
+                .into_iter()
+                .find_map(|(atom, format)| (atom == value).then_some(format))
+                .ok_or(rustler::Error::BadArg)?;
+            } else if key == decode() {
+                let value = value.decode::<Atom>()?;
+                options.decode = [
+                    (replace(), Decode::Replace),
+                    (raise(), Decode::Raise),
+                    (binary(), Decode::Binary),
+                ]
+                .into_iter()
+                .find_map(|(atom, decode)| (atom == value).then_some(decode))
+                .ok_or(rustler::Error::BadArg)?;
+            } else if key == trim() {
+                options.trim = Some(value.decode()?);
+            } else if key == meta() {
+                options.meta = value.decode()?;
+            } else if key == filter() {
+                options.filter = Some(value.decode()?);
+            }
+        }
+
+        // Whitespace is part of the transcript
+        if options.format == Format::Transcript {
+            options.trim = Some(Trim::None);
+        }
+
+        Ok(options)
+    }
+}
+
+impl OutputOptions {
+    /// Whether the response should be read in chunks.
+    pub fn chunked(&self) -> bool {
+        self.format == Format::Iodata
+    }
+}
+
+/// Command output in the requested representation.
+#[derive(Debug)]
+pub enum Output {
+    Text(String),
+    Term(Term),
+    Bytes(Vec<u8>),
+    Chunks(Vec<Vec<u8>>),
+    Transcript(Transcript),
+    Spilled { path: String, bytes: u64 },
+    Metered(Box<Output>, Meta),
+}
+
+impl Output {
+    /// The output with `meta` attached if `options` asks for it.
+    pub fn metered(self, options: &OutputOptions, meta: Meta) -> Output {
+        if options.meta {
+            Output::Metered(Box::new(self), meta)
+        } else {
+            self
+        }
+    }
+}
+
+/// How one command went, measured in the NIF whatever Maude's `show
+/// timing` setting.
+#[derive(Debug, Clone, Copy, NifMap)]
+pub struct Meta {
+    /// Microseconds from writing the command to reading its prompt.
+    pub duration_us: u64,
+    /// Bytes Maude wrote in response, the prompt included.
+    pub bytes_read: u64,
+    /// Whether the response came from the cache; see [`crate::cache`].
+    pub cached: bool,
+    /// Whether the subprocess was replaced to answer; see
+    /// [`crate::resilient`].
+    pub restarted: bool,
+}
+
+impl Encoder for Output {
+    fn encode<'a>(&self, env: Env<'a>) -> rustler::Term<'a> {
+        match self {
+            Output::Text(text) => text.encode(env),
+            Output::Term(term) => term.encode(env),
+            Output::Bytes(bytes) => encode_binary(env, bytes),
+            Output::Chunks(parts) => parts
+                .iter()
+                .map(|part| encode_binary(env, part))
+                .collect::<Vec<_>>()
+                .encode(env),
+            Output::Transcript(transcript) => transcript.encode(env),
+            Output::Spilled { path, bytes } => (spilled(), path, bytes).encode(env),
+            Output::Metered(output, meta) => (output.as_ref(), meta).encode(env),
+        }
+    }
+}
+
+/// Output rendered with `format: :transcript`.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Transcript {
+    /// The output and the prompt after it, byte for byte.
+    pub raw: Bytes,
+    pub response_start: usize,
+    /// Exclusive.
+    pub response_end: usize,
+    pub prompt_start: usize,
+}
+
+impl Transcript {
+    /// The transcript of `output`, read up to the prompt and untrimmed.
+    fn new(output: &[u8]) -> Transcript {
+        let response_start = output
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .unwrap_or(output.len());
+        let response_end = response_start + output[response_start..].trim_ascii_end().len();
+
+        let mut raw = Vec::with_capacity(output.len() + PROMPT.len());
+        raw.extend_from_slice(output);
+        raw.extend_from_slice(PROMPT.as_bytes());
+
+        Transcript {
+            raw: Bytes(raw),
+            response_start,
+            response_end,
+            prompt_start: output.len(),
+        }
+    }
+}
+
+/// Bytes encoded as a binary rather than a list.
+#[derive(Debug)]
+pub struct Bytes(pub Vec<u8>);
+
+impl Encoder for Bytes {
+    fn encode<'a>(&self, env: Env<'a>) -> rustler::Term<'a> {
+        encode_binary(env, &self.0)
+    }
+}
+
+fn encode_binary<'a>(env: Env<'a>, bytes: &[u8]) -> rustler::Term<'a> {
+    let mut binary = NewBinary::new(env, bytes.len());
+    binary.as_mut_slice().copy_from_slice(bytes);
+    Binary::from(binary).encode(env)
+}
+
+/// Convert command output to the format given in `options`.
+pub fn render(output: String, options: &OutputOptions) -> Result<Output, String> {
+    match options.format {
+        Format::Raw => Ok(Output::Text(output)),
+        Format::Clean => Ok(Output::Text(strip_noise(&output))),
+        Format::Parsed => term::parse_result(&output)
+            .map(Output::Term)
+            .map_err(|e| format!("parse failed: {}", e)),
+        Format::Json => term::parse_result(&output)
+            .map(|term| Output::Text(to_json(&term)))
+            .map_err(|e| format!("parse failed: {}", e)),
+        Format::Transcript => Ok(Output::Transcript(Transcript::new(output.as_bytes()))),
+        Format::Iodata => Ok(Output::Chunks(vec![output.into_bytes()])),
+    }
+}
+
+/// [`render`] for a response that may have spilled to a file.
+pub fn render_response(response: Response, options: &OutputOptions) -> Result<Output, String> {
+    match response {
+        Response::Text(output) => render(output, options),
+        Response::Invalid { raw, .. } if options.format == Format::Transcript => {
+            Ok(Output::Transcript(Transcript::new(&raw)))
+        }
+        Response::Invalid { raw, .. } if options.format == Format::Iodata => {
+            Ok(Output::Chunks(vec![raw]))
+        }
+        Response::Chunks(chunks) if options.format == Format::Iodata => {
+            Ok(Output::Chunks(chunks.parts))
+        }
+        Response::Chunks(chunks) => render_response(chunks.join(), options),
+        Response::Invalid { raw, text } => match options.decode {
+            Decode::Replace => render(text, options),
+            Decode::Raise => {
+                let at = std::str::from_utf8(&raw)
+                    .err()
+                    .map_or(0, |e| e.valid_up_to());
+                Err(format!("invalid UTF-8 in output at byte {}", at))
+            }
+            Decode::Binary => Ok(Output::Bytes(raw)),
+        },
+        Response::Spilled(spill) => Ok(Output::Spilled {
+            path: spill.path.to_string_lossy().into_owned(),
+            bytes: spill.bytes,
+        }),
+    }
+}
+
+/// Drop the command echo and `rewrites:` stats lines Maude prints.
+fn strip_noise(output: &str) -> String {
+    let mut lines = output.lines().peekable();
+
+    // The echo is the first line and ends with the command's period
+    if lines.peek().is_some_and(|line| line.ends_with(" .")) {
+        lines.next();
+    }
+
+    lines
+        .filter(|line| !line.starts_with("rewrites: ") && !line.starts_with("states: "))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+fn to_json(term: &Term) -> String {
+    let sort = term
+        .sort
+        .as_deref()
+        .map_or_else(|| "null".to_string(), json_string);
+    let args: Vec<String> = term.args.iter().map(to_json).collect();
+
+    format!(
+        "{{\"op\":{},\"sort\":{},\"args\":[{}]}}",
+        json_string(&term.op),
+        sort,
+        args.join(",")
+    )
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_the_echo_and_stats_lines() {
+        let cases = [
+            (
+                "reduce in NAT : 1 + 1 .\n\
+                 rewrites: 1 in 0ms cpu (0ms real) (~ rewrites/second)\n\
+                 result NzNat: 2",
+                "result NzNat: 2",
+            ),
+            (
+                "search in PEANO : 0 =>* N:Nat .\n\n\
+                 Solution 1 (state 0)\n\
+                 states: 1  rewrites: 0 in 0ms cpu (0ms real) (~ rewrites/second)\n\
+                 N:Nat --> 0",
+                "Solution 1 (state 0)\nN:Nat --> 0",
+            ),
+            ("result Bool: true", "result Bool: true"),
+            ("", ""),
+        ];
+
+        for (output, expected) in cases {
+            assert_eq!(strip_noise(output), expected, "for {:?}", output);
+        }
+    }
+
+    #[test]
+    fn renders_terms_as_json() {
+        let cases = [
+            ("result NzNat: 2", r#"{"op":"2","sort":"NzNat","args":[]}"#),
+            (
+                "result Nat: s_(0)",
+                r#"{"op":"s_","sort":"Nat","args":[{"op":"0","sort":null,"args":[]}]}"#,
+            ),
+            (
+                "result String: \"say \\\"hi\\\"\"",
+                r#"{"op":"\"say \\\"hi\\\"\"","sort":"String","args":[]}"#,
+            ),
+        ];
+
+        for (output, expected) in cases {
+            let term = term::parse_result(output).unwrap();
+            assert_eq!(to_json(&term), expected, "for {:?}", output);
+        }
+    }
+
+    #[test]
+    fn escapes_control_characters_in_json() {
+        assert_eq!(json_string("a\tb\n\u{1}"), r#""a\tb\n\u0001""#);
+    }
+
+    #[test]
+    fn locates_the_response_and_prompt_in_a_transcript() {
+        let cases: [(&[u8], usize, usize); 3] = [
+            (b"result Bool: true\n", 0, 17),
+            (b"\n  result Bool: true  \n\n", 3, 20),
+            (b"\n", 1, 1),
+        ];
+
+        for (output, start, end) in cases {
+            let transcript = Transcript::new(output);
+            assert_eq!(transcript.response_start, start, "for {:?}", output);
+            assert_eq!(transcript.response_end, end, "for {:?}", output);
+            assert_eq!(transcript.prompt_start, output.len());
+            assert_eq!(&transcript.raw.0[output.len()..], PROMPT.as_bytes());
+            assert_eq!(&transcript.raw.0[..output.len()], output);
+        }
+    }
+}
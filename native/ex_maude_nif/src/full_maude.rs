@@ -0,0 +1,59 @@
+//! Running commands through Full Maude.
+//!
+//! Full Maude is written in Maude itself and runs in the interpreter's
+//! `loop` mode (see `loop_send/2`): once `full-maude.maude` is loaded,
+//! input in parentheses - `(omod ... endom)`, `(red ... .)` - goes to it
+//! rather than to the core interpreter, and it prints its own results,
+//! warnings included, on stdout. Maude still shows its prompt after each,
+//! so responses are framed as usual.
+//!
+//! With the `:full_maude` spawn option set to the path of
+//! `full-maude.maude`, a process loads it before the preload files - which
+//! may then hold object-oriented modules - and checks for the Full Maude
+//! banner. From then on the execute calls, pooled and session ones
+//! included, wrap each command in parentheses unless it already is, and
+//! `execute/2` reports a `Warning:` or `Error:` Full Maude printed as
+//! `{:error, reason, raw}`, like one on stderr.
+//!
+//! Full Maude keeps a module database of its own that the bookkeeping here
+//! can't follow, so such a process caches nothing, and the NIFs that build
+//! core commands themselves (`reduce_in/3`, `check/3`, ...) still talk to the
+//! core interpreter.
+
+use crate::failure::Failure;
+use crate::process::Exchange;
+
+/// Printed when Full Maude starts, e.g. `Full Maude 3.4 (February 14th, 2024)`.
+const BANNER: &str = "Full Maude";
+
+/// Load Full Maude from `path`, failing unless it announces itself.
+pub fn start(exchange: &Exchange, path: &str) -> Result<(), Failure> {
+    let output = exchange.execute(&format!("load {}", path))?;
+
+    let diagnostics = exchange.take_stderr()?;
+    if diagnostics.contains("Warning:") || diagnostics.contains("Error:") {
+        return Err(format!("full_maude failed: {}: {}", path, diagnostics.trim()).into());
+    }
+    if !output.contains(BANNER) {
+        return Err(format!("full_maude failed: {}: no Full Maude banner", path).into());
+    }
+    Ok(())
+}
+
+/// `command` in parentheses for Full Maude, or `None` if it already is.
+///
+/// The closing parenthesis goes on a line of its own so that a trailing
+/// `---` comment can't swallow it.
+pub fn wrap(command: &[&[u8]]) -> Option<Vec<u8>> {
+    let command = command.concat();
+    let command = command.trim_ascii();
+    if command.starts_with(b"(") && command.ends_with(b")") {
+        return None;
+    }
+
+    let mut wrapped = Vec::with_capacity(command.len() + 3);
+    wrapped.push(b'(');
+    wrapped.extend_from_slice(command);
+    wrapped.extend_from_slice(b"\n)");
+    Some(wrapped)
+}
@@ -0,0 +1,327 @@
+//! The state graph of the last `search`, as nodes and edges.
+//!
+//! After a search, Maude keeps the states it explored. `show search graph .`
+//! prints every state with the arcs leaving it, listing each rule that
+//! rewrites it into the target state:
+//!
+//! ```text
+//! state 0, S: c(1)
+//! arc 0 ===> state 1 (rl [inc] : c(N) => c(s N) .) (rl [dbl] : c(N) => c(N * 2) .)
+//! ```
+//!
+//! and `show path n .` prints the states from the initial one to state `n`
+//! with the rule applied between each:
+//!
+//! ```text
+//! state 0, S: c(1)
+//! ===[ rl [inc] : c(N) => c(s N) . ]===>
+//! state 1, S: c(2)
+//! ```
+//!
+//! `search_graph/1` and `search_path/2` parse these into
+//! `%{nodes: [...], edges: [...]}`, with one edge per rule, ready to hand
+//! to a graph library. Neither discards the search, so `search_next/2`
+//! still resumes it.
+
+use crate::diagnostics::Outcome;
+use crate::error;
+use crate::failure::Failure;
+use crate::process::MaudeProcess;
+use crate::reply;
+use crate::term::{self, Term};
+use rustler::{Env, LocalPid, NifMap, NifResult, ResourceArc};
+
+/// A state of the search graph.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Node {
+    pub id: u64,
+    /// The state's term, its root carrying the sort Maude printed.
+    pub term: Term,
+    /// The term as Maude printed it, for labelling.
+    pub text: String,
+}
+
+/// One rule rewriting state `from` into state `to`.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Edge {
+    pub from: u64,
+    pub to: u64,
+    /// The rule's label, or `nil` if it has none.
+    pub label: Option<String>,
+    /// The rule as Maude printed it.
+    pub rule: String,
+}
+
+/// Result of `search_graph/1` and `search_path/2`.
+#[derive(Debug, Default, NifMap)]
+#[rustler(encode)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+/// Parse the output of `show search graph .`.
+pub fn parse_graph(output: &str) -> Result<Graph, String> {
+    let mut graph = Graph::default();
+
+    for line in output.lines().map(str::trim) {
+        if let Some(state) = line.strip_prefix("state ") {
+            graph.nodes.push(parse_state(state)?);
+        } else if let Some(arc) = line.strip_prefix("arc ") {
+            let from = graph
+                .nodes
+                .last()
+                .map(|node| node.id)
+                .ok_or_else(|| format!("arc outside a state: {:?}", line))?;
+            parse_arc(from, arc, &mut graph.edges)?;
+        }
+    }
+
+    Ok(graph)
+}
+
+/// Parse the output of `show path n .`.
+pub fn parse_path(output: &str) -> Result<Graph, String> {
+    let mut graph = Graph::default();
+    let mut rule: Option<&str> = None;
+
+    for line in output.lines().map(str::trim) {
+        if let Some(state) = line.strip_prefix("state ") {
+            let node = parse_state(state)?;
+            if let Some(rule) = rule.take() {
+                // A rule line always follows a state
+                let from = graph.nodes.last().map(|node| node.id).unwrap_or_default();
+                graph.edges.push(edge(from, node.id, rule));
+            }
+            graph.nodes.push(node);
+        } else if let Some(step) = line.strip_prefix("===[") {
+            let step = step
+                .strip_suffix("]===>")
+                .ok_or_else(|| format!("invalid path step: {:?}", line))?;
+            if graph.nodes.is_empty() {
+                return Err(format!("path step before a state: {:?}", line));
+            }
+            rule = Some(step.trim());
+        }
+    }
+
+    Ok(graph)
+}
+
+/// Parse `N, Sort: term`.
+fn parse_state(state: &str) -> Result<Node, String> {
+    let invalid = || format!("invalid state: {:?}", state);
+
+    let (id, rest) = state.split_once(", ").ok_or_else(invalid)?;
+    let (sort, text) = rest.split_once(": ").ok_or_else(invalid)?;
+
+    let mut term = term::parse(text)?;
+    term.sort = Some(sort.trim().to_string());
+
+    Ok(Node {
+        id: id.parse().map_err(|_| invalid())?,
+        term,
+        text: text.to_string(),
+    })
+}
+
+/// Parse `K ===> state T (rule) (rule) ...` into one edge per rule.
+fn parse_arc(from: u64, arc: &str, edges: &mut Vec<Edge>) -> Result<(), String> {
+    let invalid = || format!("invalid arc: {:?}", arc);
+
+    let (_, rest) = arc.split_once(" ===> state ").ok_or_else(invalid)?;
+    let (to, mut rules) = rest.split_once(' ').ok_or_else(invalid)?;
+    let to = to.parse().map_err(|_| invalid())?;
+
+    // Each rule is parenthesized and may contain parentheses of its own
+    while let Some(inner) = rules.trim_start().strip_prefix('(') {
+        let mut depth = 1usize;
+        let end = inner
+            .char_indices()
+            .find(|&(_, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })
+            .map(|(i, _)| i)
+            .ok_or_else(invalid)?;
+
+        edges.push(edge(from, to, &inner[..end]));
+        rules = &inner[end + 1..];
+    }
+
+    if !rules.trim().is_empty() {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+fn edge(from: u64, to: u64, rule: &str) -> Edge {
+    Edge {
+        from,
+        to,
+        label: label(rule),
+        rule: rule.to_string(),
+    }
+}
+
+/// The label of a rule printed as `rl [inc] : ...` or `crl [inc] : ...`.
+fn label(rule: &str) -> Option<String> {
+    let rest = rule
+        .strip_prefix("rl [")
+        .or_else(|| rule.strip_prefix("crl ["))?;
+    let (label, rest) = rest.split_once(']')?;
+    rest.trim_start()
+        .starts_with(':')
+        .then(|| label.to_string())
+}
+
+/// Run `command` in one exchange, with the diagnostics it left.
+fn run(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    command: &str,
+) -> Result<(String, String), Failure> {
+    let exchange = process.begin_by(caller)?;
+    let output = exchange.execute(command)?;
+    let stderr = exchange.take_stderr()?;
+    Ok((output, stderr))
+}
+
+/// Run a `show` command and parse its output with `parse`.
+fn show(
+    env: Env,
+    process: &MaudeProcess,
+    command: &str,
+    parse: fn(&str) -> Result<Graph, String>,
+) -> NifResult<Outcome<Graph>> {
+    let (output, stderr) = match run(process, env.pid(), command) {
+        Ok(ran) => ran,
+        Err(failure) => return reply(Err(failure)),
+    };
+
+    if let Some(failed) = Outcome::failed(&stderr) {
+        return Ok(failed);
+    }
+    parse(&output)
+        .map(Outcome::Done)
+        .map_err(|e| error(format!("parse failed: {}", e)))
+}
+
+/// The whole state graph of the last search.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `Ok(Graph)` - Map of `nodes`, as `%{id, term, text}`, and `edges`, as
+///   `%{from, to, label, rule}`
+/// * `{:error, reason, raw}` - Maude complained, e.g. `"no state graph."`
+///   when no search has run
+/// * `Err` - If I/O fails or a state can't be parsed
+#[rustler::nif(schedule = "DirtyCpu")]
+fn search_graph(env: Env, process: ResourceArc<MaudeProcess>) -> NifResult<Outcome<Graph>> {
+    show(env, &process, "show search graph .", parse_graph)
+}
+
+/// The path from the initial state of the last search to `state`.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `state` - State number, as in a solution's `state`
+///
+/// # Returns
+/// * `Ok(Graph)` - The states along the path, in order, and the rule
+///   applied between each, as for `search_graph/1`
+/// * `{:error, reason, raw}` - Maude complained, e.g. `"bad state number."`
+/// * `Err` - If I/O fails or a state can't be parsed
+#[rustler::nif(schedule = "DirtyCpu")]
+fn search_path(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    state: u64,
+) -> NifResult<Outcome<Graph>> {
+    show(env, &process, &format!("show path {} .", state), parse_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arcs(graph: &Graph) -> Vec<(u64, u64, Option<&str>)> {
+        graph
+            .edges
+            .iter()
+            .map(|edge| (edge.from, edge.to, edge.label.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_a_search_graph() {
+        let output = "\
+state 0, Counter: c(1)
+arc 0 ===> state 1 (rl [inc] : c(N:Nat) => c(s N:Nat) .) (rl [dbl] : c(N:Nat) => c(N:Nat * 2) .)
+
+state 1, Counter: c(2)
+arc 0 ===> state 2 (rl [inc] : c(N:Nat) => c(s N:Nat) .)
+arc 1 ===> state 3 (rl [dbl] : c(N:Nat) => c(N:Nat * 2) .)
+
+state 2, Counter: c(3)
+
+state 3, Counter: c(4)
+arc 0 ===> state 0 (crl c(N:Nat) => c(1) if N:Nat > 3 .)";
+        let graph = parse_graph(output).unwrap();
+
+        assert_eq!(
+            graph.nodes.iter().map(|node| node.id).collect::<Vec<_>>(),
+            [0, 1, 2, 3]
+        );
+        assert_eq!(graph.nodes[0].text, "c(1)");
+        assert_eq!(graph.nodes[0].term.sort.as_deref(), Some("Counter"));
+        assert_eq!(graph.nodes[0].term.op, "c");
+
+        assert_eq!(
+            arcs(&graph),
+            [
+                (0, 1, Some("inc")),
+                (0, 1, Some("dbl")),
+                (1, 2, Some("inc")),
+                (1, 3, Some("dbl")),
+                (3, 0, None),
+            ]
+        );
+        assert_eq!(graph.edges[2].rule, "rl [inc] : c(N:Nat) => c(s N:Nat) .");
+        assert_eq!(graph.edges[4].rule, "crl c(N:Nat) => c(1) if N:Nat > 3 .");
+    }
+
+    #[test]
+    fn parses_a_search_path() {
+        let output = "\
+state 0, Counter: c(1)
+===[ rl [inc] : c(N:Nat) => c(s N:Nat) . ]===>
+state 1, Counter: c(2)
+===[ rl [dbl] : c(N:Nat) => c(N:Nat * 2) . ]===>
+state 3, Counter: c(4)";
+        let graph = parse_path(output).unwrap();
+
+        assert_eq!(
+            graph.nodes.iter().map(|node| node.id).collect::<Vec<_>>(),
+            [0, 1, 3]
+        );
+        assert_eq!(arcs(&graph), [(0, 1, Some("inc")), (1, 3, Some("dbl"))]);
+    }
+
+    #[test]
+    fn rejects_malformed_graphs() {
+        assert!(parse_graph("arc 0 ===> state 1 (rl a => b .)").is_err());
+        assert!(parse_graph("state 0, S: a\narc 0 ===> state 1 rl a => b .").is_err());
+        assert!(parse_graph("state 0, S: a\narc 0 ===> state 1 (rl a => b .").is_err());
+        assert!(parse_graph("state zero, S: a").is_err());
+        assert!(parse_path("===[ rl a => b . ]===>\nstate 1, S: b").is_err());
+    }
+}
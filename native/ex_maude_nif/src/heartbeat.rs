@@ -0,0 +1,333 @@
+//! Notification when a Maude child dies.
+//!
+//! A dead child is otherwise noticed only by the next command, which fails
+//! or comes back empty. `subscribe/2` registers a pid to hear about it
+//! instead: an `ex_maude-heartbeat` thread checks the child every
+//! `:interval` milliseconds (default: 1000) and sends the subscriber
+//!
+//! ```elixir
+//! {:maude_down, reason}
+//! ```
+//!
+//! once it has exited, where `reason` is `{:exit_status, code}`,
+//! `{:signal, signal}`, or `:closed` if Maude closed its output without
+//! exiting yet. With `ping: true` the thread also runs a `show modules`
+//! whenever the process is idle, which notices a child that can no longer
+//! answer before the OS reports it gone; a busy, leased, or paused
+//! process isn't pinged.
+//!
+//! A process has at most one subscriber and the message is sent once.
+//! Stopping the process on purpose sends nothing. The thread holds the
+//! handle until the subscriber unsubscribes, exits, or is notified.
+
+use crate::error;
+use crate::lifecycle::Lifecycle;
+use crate::process::{MaudeProcess, Response};
+use crate::threads;
+use rustler::{
+    Atom, Decoder, Encoder, Env, LocalPid, Monitor, NifResult, OwnedEnv, ResourceArc, Term,
+};
+use std::process::ExitStatus;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+rustler::atoms! {
+    ok,
+    interval,
+    ping,
+    maude_down,
+    exit_status,
+    signal,
+    closed,
+}
+
+/// Default time between checks.
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Options accepted by `subscribe/3`.
+#[derive(Debug, Clone, Copy)]
+pub struct HeartbeatOptions {
+    /// Time between checks of the child.
+    pub interval: Duration,
+    /// Whether to run a command while idle, not just ask the OS.
+    pub ping: bool,
+}
+
+impl Default for HeartbeatOptions {
+    fn default() -> Self {
+        HeartbeatOptions {
+            interval: DEFAULT_INTERVAL,
+            ping: false,
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for HeartbeatOptions {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut options = HeartbeatOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, Term<'a>)>>()? {
+            if key == interval() {
+                let ms: u64 = value.decode()?;
+                if ms == 0 {
+                    return Err(error("interval must be positive"));
+                }
+                options.interval = Duration::from_millis(ms);
+            } else if key == ping() {
+                options.ping = value.decode()?;
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Why the child is considered down.
+#[derive(Debug)]
+enum Down {
+    Exited(ExitStatus),
+    Closed,
+}
+
+impl Encoder for Down {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            Down::Exited(status) => {
+                #[cfg(unix)]
+                if let Some(number) = std::os::unix::process::ExitStatusExt::signal(status) {
+                    return (signal(), number).encode(env);
+                }
+                (exit_status(), status.code().unwrap_or(-1)).encode(env)
+            }
+            Down::Closed => closed().encode(env),
+        }
+    }
+}
+
+struct Subscriber {
+    pid: LocalPid,
+    monitor: Option<Monitor>,
+}
+
+#[derive(Default)]
+struct Beat {
+    subscriber: Option<Subscriber>,
+    /// Bumped whenever the subscriber changes, so a superseded thread exits.
+    generation: u64,
+}
+
+/// Subscription of one process. A leaf lock.
+#[derive(Default)]
+pub struct Heartbeat {
+    beat: Mutex<Beat>,
+    changed: Condvar,
+}
+
+impl Heartbeat {
+    fn lock(&self) -> MutexGuard<'_, Beat> {
+        self.beat.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Make `pid` the subscriber, returning the new generation and the
+    /// monitor of the one it replaces.
+    fn subscribe(&self, pid: LocalPid) -> (u64, Option<Monitor>) {
+        let mut beat = self.lock();
+        let replaced = beat.subscriber.replace(Subscriber { pid, monitor: None });
+        beat.generation += 1;
+        let generation = beat.generation;
+        drop(beat);
+
+        self.changed.notify_all();
+        (generation, replaced.and_then(|s| s.monitor))
+    }
+
+    /// Store the monitor watching the subscriber of `generation`; it is
+    /// handed back if the subscription has meanwhile changed.
+    fn attach_monitor(&self, generation: u64, monitor: Monitor) -> Option<Monitor> {
+        let mut beat = self.lock();
+        let current = beat.generation == generation;
+        match &mut beat.subscriber {
+            Some(subscriber) if current => {
+                subscriber.monitor = Some(monitor);
+                None
+            }
+            _ => Some(monitor),
+        }
+    }
+
+    /// Drop the subscriber - only if it is `pid`, when given - returning
+    /// its monitor.
+    pub fn unsubscribe(&self, pid: Option<LocalPid>) -> Option<Monitor> {
+        let mut beat = self.lock();
+        let current = beat.subscriber.as_ref()?.pid;
+        if pid.is_some_and(|pid| pid != current) {
+            return None;
+        }
+
+        let subscriber = beat.subscriber.take();
+        beat.generation += 1;
+        drop(beat);
+
+        self.changed.notify_all();
+        subscriber.and_then(|s| s.monitor)
+    }
+
+    /// Wait up to `interval`; `false` once `generation` is superseded.
+    fn wait(&self, generation: u64, interval: Duration) -> bool {
+        let beat = self.lock();
+        let (beat, _) = self
+            .changed
+            .wait_timeout_while(beat, interval, |beat| beat.generation == generation)
+            .unwrap_or_else(|e| e.into_inner());
+        beat.generation == generation
+    }
+
+    /// Take the subscriber of `generation` to notify it.
+    fn claim(&self, generation: u64) -> Option<Subscriber> {
+        let mut beat = self.lock();
+        if beat.generation != generation {
+            return None;
+        }
+        beat.generation += 1;
+        beat.subscriber.take()
+    }
+}
+
+/// Check the child of `process`, pinging it if asked and it is idle.
+fn check(process: &MaudeProcess, ping: bool) -> Option<Down> {
+    if let Some(status) = process.exit_status() {
+        return Some(Down::Exited(status));
+    }
+    // A paused child can't answer until it is resumed
+    if !ping || process.lifecycle() != Lifecycle::Ready || process.is_paused() {
+        return None;
+    }
+
+    let exchange = process.try_begin()?;
+    let pinged = exchange.execute_unrecorded(&[b"show modules ."], None);
+    drop(exchange);
+
+    match pinged {
+        Ok(response) => {
+            if let Response::Spilled(spill) = response {
+                let _ = std::fs::remove_file(&spill.path);
+            }
+            None
+        }
+        // Refusals, say in manual mode, leave the child alive
+        Err(_) => match process.exit_status() {
+            Some(status) => Some(Down::Exited(status)),
+            None if !process.is_alive() && process.lifecycle() != Lifecycle::Stopped => {
+                Some(Down::Closed)
+            }
+            None => None,
+        },
+    }
+}
+
+/// Check `process` until it is down or `generation` is superseded.
+fn patrol(process: ResourceArc<MaudeProcess>, generation: u64, options: HeartbeatOptions) {
+    let heartbeat = process.heartbeat();
+
+    loop {
+        if process.lifecycle() == Lifecycle::Stopped {
+            return;
+        }
+        if let Some(down) = check(&process, options.ping) {
+            let Some(subscriber) = heartbeat.claim(generation) else {
+                return;
+            };
+            if let Some(monitor) = subscriber.monitor {
+                process.demonitor(None, &monitor);
+            }
+            let _ = OwnedEnv::new()
+                .send_and_clear(&subscriber.pid, |env| (maude_down(), &down).encode(env));
+            return;
+        }
+        if !heartbeat.wait(generation, options.interval) {
+            return;
+        }
+    }
+}
+
+/// Have `pid` sent `{:maude_down, reason}` when the Maude child dies.
+///
+/// Replaces any earlier subscriber, which is sent nothing.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `pid` - Process to notify
+///
+/// # Returns
+/// * `Ok(:ok)` - `pid` is subscribed
+/// * `Err` - If `pid` is not alive
+#[rustler::nif]
+fn subscribe(env: Env, process: ResourceArc<MaudeProcess>, pid: LocalPid) -> NifResult<Atom> {
+    start(env, process, pid, HeartbeatOptions::default())
+}
+
+/// `subscribe/2` with options.
+///
+/// # Arguments
+/// * `opts` - Keyword list with `:interval` (milliseconds, default: 1000)
+///   and `:ping` (default: `false`)
+///
+/// # Returns
+/// * As for `subscribe/2`
+#[rustler::nif(name = "subscribe")]
+fn subscribe_with_opts(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    pid: LocalPid,
+    opts: HeartbeatOptions,
+) -> NifResult<Atom> {
+    start(env, process, pid, opts)
+}
+
+fn start(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    pid: LocalPid,
+    options: HeartbeatOptions,
+) -> NifResult<Atom> {
+    let heartbeat = process.heartbeat();
+    let (generation, replaced) = heartbeat.subscribe(pid);
+    if let Some(monitor) = replaced {
+        env.demonitor(&process, &monitor);
+    }
+
+    // A subscriber that dies is seen by `down`, which unsubscribes it
+    let Some(monitor) = env.monitor(&process, &pid) else {
+        heartbeat.unsubscribe(Some(pid));
+        return Err(error("subscriber is not alive"));
+    };
+    if let Some(stale) = heartbeat.attach_monitor(generation, monitor) {
+        env.demonitor(&process, &stale);
+    }
+
+    let watched = process.clone();
+    if let Err(e) = threads::spawn("ex_maude-heartbeat", move || {
+        patrol(watched, generation, options)
+    }) {
+        if let Some(monitor) = heartbeat.unsubscribe(Some(pid)) {
+            env.demonitor(&process, &monitor);
+        }
+        return Err(error(e));
+    }
+    Ok(ok())
+}
+
+/// Stop notifying the subscriber.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `:ok` - Nobody is subscribed (also if nobody was)
+#[rustler::nif]
+fn unsubscribe(env: Env, process: ResourceArc<MaudeProcess>) -> Atom {
+    if let Some(monitor) = process.heartbeat().unsubscribe(None) {
+        env.demonitor(&process, &monitor);
+    }
+    ok()
+}
@@ -0,0 +1,319 @@
+//! Maude processes that stop while idle and restart on demand.
+//!
+//! A hibernating process shuts its Maude subprocess down once no command
+//! has arrived for its idle timeout, and starts a new one on the next
+//! command. The new subprocess is brought back to the same state by
+//! replaying the journal of state-mutating commands (see
+//! [`command::is_mutating`]) after the preload files, so callers see only
+//! the extra latency of the first command after a nap.
+//!
+//! Idle sessions then cost only their journal instead of a Maude heap,
+//! which is what matters when most of many per-tenant sessions sit unused.
+//! One shared `ex_maude-watchdog` thread checks every hibernating process
+//! and hands idle subprocesses to the background pool to stop.
+
+use crate::command;
+use crate::diagnostics::Outcome;
+use crate::error;
+use crate::failure::Failure;
+use crate::format::{self, Output, OutputOptions, Trim};
+use crate::input::Input;
+use crate::options::SpawnOptions;
+use crate::process::{MaudeProcess, Response};
+use crate::reply;
+use crate::startup::SpawnError;
+use crate::threads;
+use rustler::{Atom, NifMap, NifResult, ResourceArc};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+
+rustler::atoms! {
+    ok,
+}
+
+struct Hibernating {
+    /// The running subprocess; `None` while hibernating.
+    process: Option<MaudeProcess>,
+    /// Every mutating command so far, replayed on wake.
+    journal: Vec<String>,
+    last_used: Instant,
+    hibernations: u64,
+    stopped: bool,
+}
+
+/// A hibernating process as seen by the watchdog.
+struct Watched {
+    /// Weak, so the watchdog drops the entry once the handle is collected.
+    state: Weak<Mutex<Hibernating>>,
+    timeout: Duration,
+}
+
+static WATCHED: Mutex<Vec<Watched>> = Mutex::new(Vec::new());
+static WATCHED_ADDED: Condvar = Condvar::new();
+/// Whether the watchdog thread is running.
+static WATCHDOG: Mutex<bool> = Mutex::new(false);
+
+/// Handle to a hibernating process shared with Elixir.
+pub struct HibernatingProcess {
+    maude_path: String,
+    options: SpawnOptions,
+    state: Arc<Mutex<Hibernating>>,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for HibernatingProcess {}
+
+/// Report returned by `hibernating_status/1`.
+#[derive(Debug, NifMap)]
+pub struct HibernationStatus {
+    /// Whether a Maude subprocess is running.
+    pub awake: bool,
+    /// Number of times the subprocess was stopped for being idle.
+    pub hibernations: u64,
+    /// Number of mutating commands in the journal.
+    pub journal: usize,
+    /// Milliseconds since the last command finished.
+    pub idle_ms: u64,
+}
+
+impl HibernatingProcess {
+    fn state(&self) -> Result<MutexGuard<'_, Hibernating>, String> {
+        self.state
+            .lock()
+            .map_err(|e| format!("hibernate lock failed: {}", e))
+    }
+
+    fn execute(&self, command: &str, trim: Option<Trim>) -> Result<String, Failure> {
+        let mut state = self.state()?;
+        if state.stopped {
+            return Err("process stopped".into());
+        }
+
+        let process = match state.process.take() {
+            Some(process) => process,
+            None => self.wake(&state.journal)?,
+        };
+        let result = process
+            .execute_trimmed(&[command.as_bytes()], trim)
+            .and_then(Response::into_text);
+        state.process = Some(process);
+
+        if result.is_ok() && command::is_mutating(command) {
+            state.journal.push(command.to_string());
+        }
+        state.last_used = Instant::now();
+
+        result
+    }
+
+    /// Start a subprocess and replay `journal` on it.
+    ///
+    /// Diagnostics from the replay were already reported when the commands
+    /// first ran, so they are discarded rather than failing the wake.
+    fn wake(&self, journal: &[String]) -> Result<MaudeProcess, String> {
+        let process = MaudeProcess::spawn(&self.maude_path, &self.options)
+            .map_err(|e| format!("wake failed: {}", e))?;
+
+        let exchange = process.begin();
+        let replayed = journal
+            .iter()
+            .try_for_each(|command| exchange.execute(command).map(drop))
+            .and_then(|()| exchange.take_stderr().map(drop));
+        drop(exchange);
+
+        if let Err(e) = replayed {
+            let _ = process.shutdown();
+            return Err(format!("wake failed: {}", e));
+        }
+
+        Ok(process)
+    }
+}
+
+/// Have the watchdog stop `state`'s subprocess after `timeout` idle; fails
+/// if the watchdog can't be started.
+fn watch(state: &Arc<Mutex<Hibernating>>, timeout: Duration) -> Result<(), String> {
+    WATCHED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(Watched {
+            state: Arc::downgrade(state),
+            timeout,
+        });
+    WATCHED_ADDED.notify_one();
+
+    // Started by the first call that manages to, so one that fails is retried
+    let mut started = WATCHDOG.lock().unwrap_or_else(|e| e.into_inner());
+    if !*started {
+        threads::spawn("ex_maude-watchdog", patrol)?;
+        *started = true;
+    }
+    Ok(())
+}
+
+/// Stop idle subprocesses, sleeping until the next one could be due.
+///
+/// A process busy with a command holds its state lock and is skipped, so
+/// a long command on one session never stalls the checks for the others.
+fn patrol() {
+    let mut watched = WATCHED.lock().unwrap_or_else(|e| e.into_inner());
+
+    loop {
+        let mut wait = Duration::from_secs(60);
+        let mut idle = Vec::new();
+
+        watched.retain(|entry| {
+            let Some(state) = entry.state.upgrade() else {
+                return false;
+            };
+            let Ok(mut state) = state.try_lock() else {
+                wait = wait.min(entry.timeout);
+                return true;
+            };
+            if state.stopped {
+                return false;
+            }
+
+            let since = state.last_used.elapsed();
+            match state.process.take_if(|_| since >= entry.timeout) {
+                Some(process) => {
+                    state.hibernations += 1;
+                    idle.push(process);
+                    wait = wait.min(entry.timeout);
+                }
+                None if state.process.is_some() => wait = wait.min(entry.timeout - since),
+                None => wait = wait.min(entry.timeout),
+            }
+            true
+        });
+
+        // Shut down off this thread so one slow quit doesn't delay the rest
+        for process in idle {
+            threads::run(move || {
+                let _ = process.shutdown();
+            });
+        }
+
+        watched = WATCHED_ADDED
+            .wait_timeout(watched, wait)
+            .unwrap_or_else(|e| e.into_inner())
+            .0;
+    }
+}
+
+/// Start a Maude process that hibernates after `idle_timeout` without
+/// commands.
+///
+/// # Arguments
+/// * `maude_path` - Path to the Maude executable
+/// * `idle_timeout` - Milliseconds without a command before the
+///   subprocess is stopped
+/// * `opts` - Keyword list of spawn options, as for `start_with_opts/2`;
+///   every subprocess started for the handle uses them
+///
+/// # Returns
+/// * `Ok(ResourceArc<HibernatingProcess>)` - Handle to the process
+/// * `Err` - If the timeout is zero, the first subprocess fails to start,
+///   or the watchdog thread can't be started
+#[rustler::nif(schedule = "DirtyCpu")]
+fn start_hibernating(
+    maude_path: String,
+    idle_timeout: u64,
+    opts: SpawnOptions,
+) -> NifResult<ResourceArc<HibernatingProcess>> {
+    if idle_timeout == 0 {
+        return Err(error("idle timeout must be positive"));
+    }
+
+    let process = MaudeProcess::spawn(&maude_path, &opts).map_err(SpawnError::into_nif_error)?;
+    let state = Arc::new(Mutex::new(Hibernating {
+        process: Some(process),
+        journal: Vec::new(),
+        last_used: Instant::now(),
+        hibernations: 0,
+        stopped: false,
+    }));
+    if let Err(e) = watch(&state, Duration::from_millis(idle_timeout)) {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(process) = state.process.take() {
+            let _ = process.shutdown();
+        }
+        return Err(error(e));
+    }
+
+    Ok(ResourceArc::new(HibernatingProcess {
+        maude_path,
+        options: opts,
+        state,
+    }))
+}
+
+/// Execute a command, first waking the process if it is hibernating.
+///
+/// Waking starts a new subprocess, loads the preload files, and replays
+/// every earlier mutating command. An iolist command is joined here, since
+/// mutating commands are kept in the journal.
+///
+/// # Arguments
+/// * `process` - Handle to the process
+/// * `command` - Maude command to execute
+///
+/// # Returns
+/// * `Ok(String)` - Command output (without the prompt)
+/// * `Err` - If I/O fails, waking fails, or the process was stopped
+#[rustler::nif(schedule = "DirtyCpu")]
+fn hibernating_execute<'a>(
+    process: ResourceArc<HibernatingProcess>,
+    command: Input<'a>,
+) -> NifResult<Outcome<String>> {
+    reply(process.execute(&command.to_string_lossy(), None))
+}
+
+/// `hibernating_execute/2` with output options; see [`format`] for `:format`.
+#[rustler::nif(schedule = "DirtyCpu", name = "hibernating_execute")]
+fn hibernating_execute_with_opts<'a>(
+    process: ResourceArc<HibernatingProcess>,
+    command: Input<'a>,
+    opts: OutputOptions,
+) -> NifResult<Outcome<Output>> {
+    reply(
+        process
+            .execute(&command.to_string_lossy(), opts.trim)
+            .and_then(|output| format::render(output, &opts).map_err(Failure::from)),
+    )
+}
+
+/// Report whether a hibernating process is awake and how often it slept.
+///
+/// # Arguments
+/// * `process` - Handle to the process
+#[rustler::nif(schedule = "DirtyCpu")]
+fn hibernating_status(process: ResourceArc<HibernatingProcess>) -> NifResult<HibernationStatus> {
+    let state = process.state().map_err(error)?;
+
+    Ok(HibernationStatus {
+        awake: state.process.is_some(),
+        hibernations: state.hibernations,
+        journal: state.journal.len(),
+        idle_ms: state.last_used.elapsed().as_millis() as u64,
+    })
+}
+
+/// Stop a hibernating process; the watchdog forgets it on its next check.
+///
+/// # Arguments
+/// * `process` - Handle to the process
+#[rustler::nif(schedule = "DirtyCpu")]
+fn hibernating_stop(process: ResourceArc<HibernatingProcess>) -> NifResult<Atom> {
+    let running = {
+        let mut state = process.state().map_err(error)?;
+        state.stopped = true;
+        state.process.take()
+    };
+
+    if let Some(running) = running {
+        running.shutdown().map_err(error)?;
+    }
+
+    Ok(ok())
+}
@@ -0,0 +1,140 @@
+//! The last commands a process ran, with their responses.
+//!
+//! With the `:history_size` spawn option set, a process keeps its last
+//! that many commands in a ring buffer: each with the output Maude gave
+//! and when it ran. `history/2` returns the latest of them, and
+//! `export_transcript/2` writes them to a file Maude can `load`, each
+//! command followed by its response as comments, to find out how a
+//! process got into the state it is in or to reproduce a bug.
+//!
+//! Every prompt-delimited command is kept, including those the NIFs send
+//! themselves (`select`, `show search graph`, ...), except the heartbeat's
+//! pings. Raw I/O isn't, and a pipelined command is kept by its first 256
+//! bytes. A response that spilled to a file is kept as a note naming it.
+
+use crate::error;
+use crate::process::{MaudeProcess, Response};
+use rustler::{Atom, NifMap, NifResult, ResourceArc};
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+rustler::atoms! {
+    ok,
+}
+
+/// One command as it ran.
+#[derive(Debug, Clone, NifMap)]
+pub struct Entry {
+    pub command: String,
+    pub response: String,
+    /// Milliseconds since the process started.
+    pub at: u64,
+}
+
+impl Entry {
+    pub fn new(command: String, response: &Response, at: u64) -> Entry {
+        let response = match response {
+            Response::Text(text) | Response::Invalid { text, .. } => text.clone(),
+            Response::Chunks(chunks) => {
+                String::from_utf8_lossy(&chunks.parts.concat()).into_owned()
+            }
+            Response::Spilled(spill) => format!(
+                "output spilled: {} bytes to {}",
+                spill.bytes,
+                spill.path.display()
+            ),
+        };
+        Entry {
+            command,
+            response,
+            at,
+        }
+    }
+}
+
+/// Ring buffer of the latest entries. A leaf lock.
+#[derive(Debug, Default)]
+pub struct History {
+    /// Entries kept; `0` turns recording off.
+    capacity: usize,
+    entries: VecDeque<Entry>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> History {
+        History {
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Whether entries are kept at all.
+    pub fn is_on(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Keep `entry`, dropping the oldest if the buffer is full.
+    pub fn push(&mut self, entry: Entry) {
+        if !self.is_on() {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// The latest `n` entries, oldest first.
+    pub fn last(&self, n: usize) -> Vec<Entry> {
+        let skip = self.entries.len().saturating_sub(n);
+        self.entries.iter().skip(skip).cloned().collect()
+    }
+}
+
+/// `entries` as a Maude file: each command as it was sent, then its
+/// response with every line commented out.
+pub fn transcript(entries: &[Entry]) -> String {
+    let mut out = format!("*** ex_maude transcript: {} commands\n", entries.len());
+
+    for entry in entries {
+        let _ = writeln!(out, "\n*** at {} ms", entry.at);
+        out.push_str(entry.command.trim_end());
+        out.push('\n');
+        for line in entry.response.lines() {
+            let _ = writeln!(out, "--- {}", line);
+        }
+    }
+
+    out
+}
+
+/// The latest commands the process ran.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `n` - Most entries to return
+///
+/// # Returns
+/// * `[Entry]` - Maps of `command`, `response`, and `at` (ms since the
+///   process started), oldest first; empty unless `:history_size` is set
+#[rustler::nif]
+fn history(process: ResourceArc<MaudeProcess>, n: usize) -> Vec<Entry> {
+    process.history(n)
+}
+
+/// Write the recorded history to `path` as a Maude file.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `path` - File to write, replaced if it exists
+///
+/// # Returns
+/// * `Ok(:ok)` - The transcript is written
+/// * `Err` - If the file can't be written
+#[rustler::nif(schedule = "DirtyIo")]
+fn export_transcript(process: ResourceArc<MaudeProcess>, path: String) -> NifResult<Atom> {
+    let entries = process.history(usize::MAX);
+    std::fs::write(&path, transcript(&entries))
+        .map_err(|e| error(format!("transcript write failed: {}: {}", path, e)))?;
+    Ok(ok())
+}
@@ -0,0 +1,80 @@
+//! Commands passed as iolists.
+//!
+//! The execute NIFs accept a command as any iolist - a binary, or a
+//! possibly nested, possibly improper list of binaries and bytes. It is
+//! never flattened: each binary is borrowed in place and the segments are
+//! written to Maude with one vectored write, so a command embedding a
+//! large generated term costs no copy on either side of the NIF boundary.
+
+use rustler::{Binary, Decoder, NifResult, Term};
+
+/// A command as the segments of an iolist, in order.
+pub struct Input<'a> {
+    segments: Vec<Segment<'a>>,
+}
+
+enum Segment<'a> {
+    /// A binary from the iolist, borrowed from the caller.
+    Binary(Binary<'a>),
+    /// A run of byte integers from the iolist.
+    Bytes(Vec<u8>),
+}
+
+impl Input<'_> {
+    /// The segments as byte slices, ready for a vectored write.
+    pub fn parts(&self) -> Vec<&[u8]> {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Binary(binary) => binary.as_slice(),
+                Segment::Bytes(bytes) => bytes.as_slice(),
+            })
+            .collect()
+    }
+
+    /// The whole command as text, for callers that need to keep it.
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.parts().concat()).into_owned()
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        match self.segments.last_mut() {
+            Some(Segment::Bytes(bytes)) => bytes.push(byte),
+            _ => self.segments.push(Segment::Bytes(vec![byte])),
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for Input<'a> {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut input = Input {
+            segments: Vec::new(),
+        };
+
+        // Iolists built by appending nest deeply, so walk them without recursion
+        let mut pending = vec![term];
+
+        while let Some(term) = pending.pop() {
+            if term.is_binary() {
+                let binary = Binary::from_term(term)?;
+                if !binary.is_empty() {
+                    input.segments.push(Segment::Binary(binary));
+                }
+                continue;
+            }
+            if term.is_empty_list() {
+                continue;
+            }
+
+            let (head, tail) = term.list_get_cell()?;
+            pending.push(tail);
+
+            match head.decode::<u8>() {
+                Ok(byte) => input.push_byte(byte),
+                Err(_) => pending.push(head),
+            }
+        }
+
+        Ok(input)
+    }
+}
@@ -0,0 +1,93 @@
+//! Dry-run validation of a Maude installation.
+//!
+//! `validate_install` spawns Maude exactly as `start_with_opts` would,
+//! checks it, and quits again, so health checks and deploy-time smoke tests
+//! can exercise the real startup path without leaving a process running.
+
+use crate::discover::{self, PROBE_TIMEOUT};
+use crate::failure::Failure;
+use crate::options::SpawnOptions;
+use crate::process::MaudeProcess;
+use rustler::NifMap;
+use std::path::Path;
+
+/// Report returned by `validate_install/2`.
+#[derive(Debug, Default, NifMap)]
+pub struct InstallReport {
+    /// `true` when Maude started, the prelude works, and every preload loaded.
+    pub ok: bool,
+    /// Version printed by `maude --version`.
+    pub version: Option<String>,
+    /// Whether the prelude is available (`BOOL` reduces).
+    pub prelude: bool,
+    /// Preload files that loaded cleanly.
+    pub loaded: Vec<String>,
+    /// Preload files that failed, with Maude's diagnostics.
+    pub failed: Vec<(String, String)>,
+    /// Why Maude couldn't be started or talked to, if it couldn't.
+    pub error: Option<String>,
+}
+
+/// Spawn Maude with the given options, check it, and quit.
+///
+/// Checks the version reported by `maude --version`, that the prelude is
+/// loaded, and loads each file in `:preload`. The process is always shut
+/// down before returning.
+///
+/// # Arguments
+/// * `maude_path` - Path to the Maude executable
+/// * `opts` - Keyword list of spawn options, as for `start_with_opts/2`
+///
+/// # Returns
+/// * `InstallReport` - Structured result; failures are reported, not raised
+#[rustler::nif(schedule = "DirtyCpu")]
+fn validate_install(maude_path: String, opts: SpawnOptions) -> InstallReport {
+    let mut report = InstallReport {
+        version: probe_version(&maude_path),
+        ..InstallReport::default()
+    };
+
+    let process = match MaudeProcess::spawn_bare(&maude_path, &opts) {
+        Ok(process) => process,
+        Err(e) => {
+            report.error = Some(e.to_string());
+            return report;
+        }
+    };
+
+    if let Err(e) = check(&process, &opts, &mut report) {
+        report.error = Some(e.to_string());
+    }
+
+    let _ = process.shutdown();
+
+    report.ok = report.error.is_none() && report.prelude && report.failed.is_empty();
+    report
+}
+
+fn check(
+    process: &MaudeProcess,
+    opts: &SpawnOptions,
+    report: &mut InstallReport,
+) -> Result<(), Failure> {
+    let exchange = process.begin();
+    exchange.write_command("reduce in BOOL : true .")?;
+    let output = exchange.read_until_prompt()?;
+    exchange.take_stderr()?;
+    report.prelude = output.contains("result Bool: true");
+
+    for path in &opts.preload {
+        match exchange.load(path)? {
+            Ok(()) => report.loaded.push(path.clone()),
+            Err(diagnostics) => report.failed.push((path.clone(), diagnostics)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Version string printed by `maude --version`, if it runs and answers
+/// within [`PROBE_TIMEOUT`], as `discover_maude/0` probes candidates.
+pub fn probe_version(maude_path: &str) -> Option<String> {
+    discover::probe(Path::new(maude_path), PROBE_TIMEOUT).ok()
+}
@@ -0,0 +1,529 @@
+//! Module descriptors built from Maude's `show` commands.
+//!
+//! `describe_module/2` runs `show sorts`, `show ops`, and `show summary` for
+//! a module in one exchange and parses them (the gap in a `show sorts` line
+//! is a tab):
+//!
+//! ```text
+//! sort Nat .    subsorts NzNat Zero < Nat < T .
+//! op _+_ : Nat Nat -> Nat [assoc comm prec 33 gather (e E) special (
+//!     id-hook ACU_NumberOpSymbol (+)
+//!     op-hook succSymbol (s_ : Nat ~> NzNat))] .
+//! ```
+//!
+//! Everything visible in the module is included, imported or not, since
+//! that is what autocomplete and browsing need. The statement counts come
+//! from `show summary` and likewise include imported statements.
+//!
+//! `list_modules/1` parses `show modules`, one `<keyword> <name>` line per
+//! module and theory in the database, prelude and instantiations included:
+//!
+//! ```text
+//! fmod NAT
+//! fth TRIV
+//! fmod LIST{Nat} * (sort NeList{Nat} to NeNatList, sort List{Nat} to NatList)
+//! ```
+
+use crate::command;
+use crate::diagnostics::Outcome;
+use crate::failure::Failure;
+use crate::process::{Exchange, MaudeProcess};
+use crate::reply;
+use crate::selection::MODULES;
+use rustler::{Atom, Env, LocalPid, NifMap, NifResult, ResourceArc};
+
+/// Attribute keywords that open a new entry in an operator's attributes.
+const ATTRIBUTES: &[&str] = &[
+    "assoc",
+    "comm",
+    "idem",
+    "iter",
+    "ctor",
+    "memo",
+    "object",
+    "msg",
+    "config",
+    "poly",
+    "prec",
+    "gather",
+    "format",
+    "strat",
+    "frozen",
+    "special",
+    "ditto",
+    "metadata",
+    "print",
+    "latex",
+    "id:",
+    "left-id:",
+    "right-id:",
+];
+
+/// Structure of a module, returned by `describe_module/2`.
+#[derive(Debug, Default, NifMap)]
+#[rustler(encode)]
+pub struct ModuleInfo {
+    pub name: String,
+    /// Every sort, in Maude's order.
+    pub sorts: Vec<String>,
+    /// `{sub, super}` pairs of the subsort relation, transitive ones
+    /// included.
+    pub subsorts: Vec<(String, String)>,
+    pub ops: Vec<OpInfo>,
+    pub equations: u64,
+    pub rules: u64,
+    pub memberships: u64,
+}
+
+/// One operator declaration.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct OpInfo {
+    /// Operator name in underscore notation, e.g. `_+_`.
+    pub name: String,
+    /// Argument sorts (or kinds, as `[Sort]`).
+    pub arity: Vec<String>,
+    /// Result sort or kind.
+    pub coarity: String,
+    /// Attributes with their arguments, e.g. `"prec 33"`, `"id: 0"`.
+    pub attributes: Vec<String>,
+}
+
+/// A module or theory in Maude's database, listed by `list_modules/1`.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct ModuleEntry {
+    /// Name as Maude prints it, with parameters and renamings.
+    pub name: String,
+    /// Keyword it was declared with: `:fmod`, `:mod`, `:fth`, `:th`,
+    /// `:smod`, `:sth`, `:omod`, or `:oth`.
+    pub kind: Atom,
+}
+
+/// Parse `show modules` output into `(keyword, name)` pairs, in Maude's
+/// order.
+pub fn parse_modules(output: &str) -> Vec<(&str, &str)> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .filter(|(keyword, _)| MODULES.contains(keyword))
+        .map(|(keyword, name)| (keyword, name.trim()))
+        .collect()
+}
+
+/// Whether `show modules` output lists `module`.
+fn is_listed(output: &str, module: &str) -> bool {
+    parse_modules(output)
+        .iter()
+        .any(|&(_, name)| name == module.trim())
+}
+
+/// Run `show modules .`, refusing output with a warning or error.
+fn show_modules(exchange: &Exchange) -> Result<String, Failure> {
+    let output = exchange.execute("show modules .")?;
+    let diagnostics = exchange.take_stderr()?;
+    if diagnostics.contains("Warning:") || diagnostics.contains("Error:") {
+        return Err(format!("show failed: {}", diagnostics.trim()).into());
+    }
+    Ok(output)
+}
+
+/// [`show_modules`] in an exchange of its own for `caller`.
+fn modules(process: &MaudeProcess, caller: LocalPid) -> Result<String, Failure> {
+    show_modules(&process.begin_by(caller)?)
+}
+
+/// Parse `show sorts` output into the sorts and subsort pairs.
+pub fn parse_sorts(output: &str) -> (Vec<String>, Vec<(String, String)>) {
+    let mut sorts = Vec::new();
+    let mut subsorts = Vec::new();
+
+    for line in output.lines() {
+        let (declaration, relation) = line.split_once('\t').unwrap_or((line, ""));
+        let Some(sort) = declaration
+            .trim()
+            .strip_prefix("sort ")
+            .and_then(|rest| rest.strip_suffix(" ."))
+        else {
+            continue;
+        };
+
+        // `subsorts A B < Sort < C D .` - only the sorts below this one are
+        // taken, since every sort above has a line of its own
+        let chain = relation
+            .trim()
+            .trim_start_matches("subsorts ")
+            .trim_start_matches("subsort ")
+            .trim_end_matches(" .");
+        let segments: Vec<&str> = chain.split(" < ").collect();
+        if let Some(at) = segments.iter().position(|segment| segment.trim() == sort) {
+            for lower in segments[..at].iter().flat_map(|s| s.split_whitespace()) {
+                subsorts.push((lower.to_string(), sort.to_string()));
+            }
+        }
+
+        sorts.push(sort.to_string());
+    }
+
+    (sorts, subsorts)
+}
+
+/// Parse `show ops` output; `special` hooks span several lines.
+pub fn parse_ops(output: &str) -> Result<Vec<OpInfo>, String> {
+    let mut declarations: Vec<String> = Vec::new();
+
+    for line in output.lines() {
+        if line.starts_with("op ") {
+            declarations.push(line.to_string());
+        } else if let Some(declaration) = declarations.last_mut() {
+            declaration.push(' ');
+            declaration.push_str(line.trim());
+        }
+    }
+
+    declarations.iter().map(|d| parse_op(d)).collect()
+}
+
+/// Parse `op <name> : <arity> -> <coarity> [<attributes>] .`.
+fn parse_op(declaration: &str) -> Result<OpInfo, String> {
+    let invalid = || format!("invalid op declaration: {:?}", declaration);
+
+    let rest = declaration
+        .strip_prefix("op ")
+        .and_then(|rest| rest.trim_end().strip_suffix('.'))
+        .ok_or_else(invalid)?;
+    let (name, rest) = rest.split_once(" : ").ok_or_else(invalid)?;
+
+    let mut tokens = rest.split_whitespace();
+    let mut arity = Vec::new();
+    loop {
+        match tokens.next().ok_or_else(invalid)? {
+            "->" | "~>" => break,
+            sort => arity.push(sort.to_string()),
+        }
+    }
+    let coarity = tokens.next().ok_or_else(invalid)?.to_string();
+
+    let attributes = tokens.collect::<Vec<_>>().join(" ");
+    let attributes = match attributes.strip_prefix('[') {
+        Some(inner) => split_attributes(inner.strip_suffix(']').ok_or_else(invalid)?),
+        None => Vec::new(),
+    };
+
+    Ok(OpInfo {
+        name: name.to_string(),
+        arity,
+        coarity,
+        attributes,
+    })
+}
+
+/// Split an attribute list at the keywords outside parentheses and strings.
+fn split_attributes(text: &str) -> Vec<String> {
+    let mut attributes: Vec<String> = Vec::new();
+    let mut depth = 0usize;
+    let mut quoted = false;
+
+    for token in text.split_whitespace() {
+        match attributes.last_mut() {
+            Some(attribute) if quoted || depth > 0 || !ATTRIBUTES.contains(&token) => {
+                attribute.push(' ');
+                attribute.push_str(token);
+            }
+            _ => attributes.push(token.to_string()),
+        }
+
+        let mut escaped = false;
+        for c in token.chars() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                '(' if !quoted => depth += 1,
+                ')' if !quoted => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+    }
+
+    attributes
+}
+
+/// Read a `<label>: N` line from `show summary` output.
+fn summary_count(output: &str, label: &str) -> u64 {
+    output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix(label)?.strip_prefix(": "))
+        .find_map(|count| count.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+fn describe(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    module: String,
+) -> Result<ModuleInfo, Failure> {
+    command::check_module(&module).map_err(|e| format!("rejected command: {}", e))?;
+
+    let exchange = process.begin_by(caller)?;
+    let mut outputs = Vec::new();
+    for show in ["sorts", "ops", "summary"] {
+        outputs.push(exchange.execute(&format!("show {} {} .", show, module))?);
+
+        let diagnostics = exchange.take_stderr()?;
+        if diagnostics.contains("Warning:") || diagnostics.contains("Error:") {
+            return Err(format!("show failed: {}", diagnostics.trim()).into());
+        }
+    }
+    drop(exchange);
+
+    let (sorts, subsorts) = parse_sorts(&outputs[0]);
+    let ops = parse_ops(&outputs[1]).map_err(|e| format!("parse failed: {}", e))?;
+    let summary = &outputs[2];
+
+    Ok(ModuleInfo {
+        name: module,
+        sorts,
+        subsorts,
+        ops,
+        equations: summary_count(summary, "equations"),
+        rules: summary_count(summary, "rules"),
+        memberships: summary_count(summary, "membership axioms"),
+    })
+}
+
+/// Describe a module's sorts, subsorts, operators, and statement counts.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `module` - Name of a loaded module
+///
+/// # Returns
+/// * `Ok(ModuleInfo)` - `%{name, sorts, subsorts, ops, equations, rules,
+///   memberships}`, each op a `%{name, arity, coarity, attributes}` map
+/// * `Err` - If the module is unknown, I/O fails, or an op can't be parsed
+#[rustler::nif(schedule = "DirtyCpu")]
+fn describe_module(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+) -> NifResult<Outcome<ModuleInfo>> {
+    reply(describe(&process, env.pid(), module))
+}
+
+/// List the modules and theories Maude has loaded.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `Ok([ModuleEntry])` - `%{name, kind}` per module, in Maude's order
+/// * `Err` - If I/O fails
+#[rustler::nif(schedule = "DirtyCpu")]
+fn list_modules(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+) -> NifResult<Outcome<Vec<ModuleEntry>>> {
+    let output = match modules(&process, env.pid()) {
+        Ok(output) => output,
+        Err(failure) => return reply(Err(failure)),
+    };
+
+    parse_modules(&output)
+        .into_iter()
+        .map(|(keyword, name)| {
+            Ok(ModuleEntry {
+                name: name.to_string(),
+                kind: Atom::from_str(env, keyword)?,
+            })
+        })
+        .collect::<NifResult<_>>()
+        .map(Outcome::Done)
+}
+
+/// Whether a module or theory named `module` is loaded, by the name
+/// `list_modules/1` lists it under.
+///
+/// Lets a supervisor check that the theories it needs loaded before
+/// serving traffic, without parsing load output.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `module` - Module name, e.g. `"NAT"` or `"LIST{Nat}"`
+///
+/// # Returns
+/// * `Ok(bool)` - Whether it is loaded
+/// * `Err` - If I/O fails
+#[rustler::nif(schedule = "DirtyCpu", name = "module_exists?")]
+fn module_exists(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+) -> NifResult<Outcome<bool>> {
+    reply(modules(&process, env.pid()).map(|output| is_listed(&output, &module)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|&(sub, sup)| (sub.to_string(), sup.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_show_sorts() {
+        let output = "\
+sort Bool .
+sort Zero .\tsubsort Zero < Nat .
+sort NzNat .\tsubsort NzNat < Nat .
+sort Nat .\tsubsorts NzNat Zero < Nat .";
+        let (sorts, subsorts) = parse_sorts(output);
+
+        assert_eq!(sorts, ["Bool", "Zero", "NzNat", "Nat"]);
+        assert_eq!(subsorts, pairs(&[("NzNat", "Nat"), ("Zero", "Nat")]));
+    }
+
+    #[test]
+    fn takes_only_the_sorts_below_each_sort() {
+        let output = "\
+sort A .\tsubsorts A < B < C .
+sort B .\tsubsorts A < B < C .
+sort C .\tsubsorts A B < C .";
+        let (sorts, subsorts) = parse_sorts(output);
+
+        assert_eq!(sorts, ["A", "B", "C"]);
+        assert_eq!(subsorts, pairs(&[("A", "B"), ("A", "C"), ("B", "C")]));
+    }
+
+    #[test]
+    fn parses_show_ops() {
+        let output = "\
+op true : -> Bool [ctor special (id-hook SystemTrue)] .
+op s_ : Nat -> NzNat [ctor iter special (
+    id-hook SuccSymbol
+    term-hook zeroTerm (0))] .
+op _+_ : Nat Nat -> Nat [assoc comm prec 33 gather (e E) special (
+    id-hook ACU_NumberOpSymbol (+)
+    op-hook succSymbol (s_ : Nat ~> NzNat))] .
+op _quo_ : Nat NzNat -> Nat [prec 31 gather (E e) format (d d s d)] .
+op error : -> [Nat] .";
+        let ops = parse_ops(output).unwrap();
+
+        assert_eq!(ops.len(), 5);
+
+        assert_eq!(ops[0].name, "true");
+        assert!(ops[0].arity.is_empty());
+        assert_eq!(ops[0].coarity, "Bool");
+        assert_eq!(ops[0].attributes, ["ctor", "special (id-hook SystemTrue)"]);
+
+        assert_eq!(ops[1].name, "s_");
+        assert_eq!(ops[1].arity, ["Nat"]);
+        assert_eq!(
+            ops[1].attributes,
+            [
+                "ctor",
+                "iter",
+                "special ( id-hook SuccSymbol term-hook zeroTerm (0))"
+            ]
+        );
+
+        assert_eq!(ops[2].name, "_+_");
+        assert_eq!(ops[2].arity, ["Nat", "Nat"]);
+        assert_eq!(ops[2].coarity, "Nat");
+        assert_eq!(
+            ops[2].attributes,
+            [
+                "assoc",
+                "comm",
+                "prec 33",
+                "gather (e E)",
+                "special ( id-hook ACU_NumberOpSymbol (+) \
+                 op-hook succSymbol (s_ : Nat ~> NzNat))"
+            ]
+        );
+
+        assert_eq!(
+            ops[3].attributes,
+            ["prec 31", "gather (E e)", "format (d d s d)"]
+        );
+
+        assert_eq!(ops[4].name, "error");
+        assert_eq!(ops[4].coarity, "[Nat]");
+        assert!(ops[4].attributes.is_empty());
+    }
+
+    #[test]
+    fn keeps_keywords_inside_strings_in_attributes() {
+        let ops = parse_ops("op f : Nat -> Nat [metadata \"assoc \\\" comm\" memo] .").unwrap();
+
+        assert_eq!(ops[0].attributes, ["metadata \"assoc \\\" comm\"", "memo"]);
+    }
+
+    #[test]
+    fn rejects_malformed_ops() {
+        assert!(parse_ops("op f Nat -> Nat .").is_err());
+        assert!(parse_ops("op f : Nat Nat .").is_err());
+        assert!(parse_ops("op f : Nat -> Nat [assoc .").is_err());
+    }
+
+    #[test]
+    fn reads_show_summary_counts() {
+        let output = "\
+Module: NAT
+sorts: 4
+kinds: 2
+operators: 23
+equations: 0
+rules: 0
+membership axioms: 0
+strategies: 0";
+
+        assert_eq!(summary_count(output, "sorts"), 4);
+        assert_eq!(summary_count(output, "operators"), 23);
+        assert_eq!(summary_count(output, "membership axioms"), 0);
+        assert_eq!(summary_count(output, "strategy definitions"), 0);
+    }
+
+    #[test]
+    fn parses_show_modules() {
+        let output = "\
+fmod TRUTH-VALUE
+fmod BOOL
+fth TRIV
+mod CONFIGURATION
+smod STRATEGY
+fmod LIST{Nat} * (sort NeList{Nat} to NeNatList, sort List{Nat} to NatList)
+view Nat";
+
+        assert_eq!(
+            parse_modules(output),
+            [
+                ("fmod", "TRUTH-VALUE"),
+                ("fmod", "BOOL"),
+                ("fth", "TRIV"),
+                ("mod", "CONFIGURATION"),
+                ("smod", "STRATEGY"),
+                (
+                    "fmod",
+                    "LIST{Nat} * (sort NeList{Nat} to NeNatList, sort List{Nat} to NatList)"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_modules_by_their_listed_name() {
+        let output = "fmod NAT\nfth TRIV\nfmod LIST{Nat}\n";
+
+        assert!(is_listed(output, "NAT"));
+        assert!(is_listed(output, " TRIV "));
+        assert!(is_listed(output, "LIST{Nat}"));
+        assert!(!is_listed(output, "LIST"));
+        assert!(!is_listed(output, "fmod"));
+        assert!(!is_listed("", "NAT"));
+    }
+}
@@ -0,0 +1,140 @@
+//! Exclusive use of a Maude process for a multi-command conversation.
+//!
+//! Each command already runs whole, but a conversation such as
+//! `select M .` followed by several `reduce`s spans many commands, and
+//! another caller's `select` in between changes the module under it.
+//! `lease/2` gives one Elixir process the Maude process to itself until
+//! it calls `release/1`: everyone else's commands fail with
+//! `{:error, :leased}`, or with `queue: true` wait for the release
+//! instead; their `release/1` fails with `:leased` either way. Commands
+//! queued before the lease finish first, and the lease ends by itself if
+//! the holder exits.
+
+use crate::error;
+use crate::failure::Failure;
+use crate::process::MaudeProcess;
+use rustler::{Atom, Decoder, Env, LocalPid, Monitor, NifResult, ResourceArc, Term};
+
+rustler::atoms! {
+    ok,
+    queue,
+}
+
+/// Message for a command refused because another process holds the lease;
+/// the NIFs return the `:leased` atom instead.
+pub const HELD: &str = "process is leased";
+
+/// A process's claim on a Maude process.
+pub struct Lease {
+    pub holder: LocalPid,
+    /// Whether other callers wait for the release instead of failing.
+    pub queue: bool,
+    /// Watches `holder`, so the lease ends when it exits.
+    pub monitor: Option<Monitor>,
+}
+
+/// Options for `lease/3`, decoded from a keyword list.
+///
+/// * `:queue` - Make other callers wait for the release instead of failing
+///   with `{:error, :leased}` (default: `false`)
+#[derive(Debug, Default)]
+pub struct LeaseOptions {
+    pub queue: bool,
+}
+
+impl<'a> Decoder<'a> for LeaseOptions {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut options = LeaseOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, Term<'a>)>>()? {
+            if key == queue() {
+                options.queue = value.decode()?;
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Give `pid` exclusive use of the process until `release/1`.
+///
+/// Returns once commands queued before the lease have finished. Leasing to
+/// the current holder again is a no-op.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `pid` - Process that may use it meanwhile
+///
+/// # Returns
+/// * `Ok(:ok)` - `pid` holds the lease
+/// * `Err(:leased)` - Another process holds it
+/// * `Err` - If `pid` is not alive
+#[rustler::nif(schedule = "DirtyIo")]
+fn lease(env: Env, process: ResourceArc<MaudeProcess>, pid: LocalPid) -> NifResult<Atom> {
+    acquire(env, &process, pid, &LeaseOptions::default())
+}
+
+/// `lease/2` with options.
+///
+/// # Arguments
+/// * `opts` - Keyword list; see [`LeaseOptions`]
+///
+/// # Returns
+/// * As for `lease/2`; with a queueing lease held by another process, this
+///   waits for its release instead of failing
+#[rustler::nif(schedule = "DirtyIo", name = "lease")]
+fn lease_with_opts(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    pid: LocalPid,
+    opts: LeaseOptions,
+) -> NifResult<Atom> {
+    acquire(env, &process, pid, &opts)
+}
+
+fn acquire(
+    env: Env,
+    process: &ResourceArc<MaudeProcess>,
+    pid: LocalPid,
+    opts: &LeaseOptions,
+) -> NifResult<Atom> {
+    if !process.acquire_lease(pid, opts.queue).map_err(error)? {
+        return Ok(ok());
+    }
+
+    // Monitored only once leased: a holder that dies after this is seen
+    // by `down`, one that died before makes the monitor fail
+    let Some(monitor) = env.monitor(process, &pid) else {
+        process.release_lease(Some(pid));
+        return Err(error("lease holder is not alive"));
+    };
+    if let Some(stale) = process.attach_lease_monitor(pid, monitor) {
+        env.demonitor(process, &stale);
+    }
+
+    Ok(ok())
+}
+
+/// End the calling process's lease on the process.
+///
+/// Only the holder can release; a lease whose holder exits ends by itself.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `Ok(:ok)` - The process is no longer leased (also if it wasn't)
+/// * `Err(:leased)` - Another process holds the lease
+#[rustler::nif]
+fn release(env: Env, process: ResourceArc<MaudeProcess>) -> NifResult<Atom> {
+    match process.release_lease(Some(env.pid())) {
+        Some(lease) => {
+            if let Some(monitor) = lease.monitor {
+                env.demonitor(&process, &monitor);
+            }
+            Ok(ok())
+        }
+        None if process.is_leased() => Err(error(Failure::Leased)),
+        None => Ok(ok()),
+    }
+}
@@ -11,20 +11,179 @@
 //! Use the `:port` backend (default) for production unless profiling shows
 //! the latency improvement from NIF is necessary.
 
-use rustler::{NifResult, ResourceArc};
-use std::io::{BufRead, BufReader, Write};
-use std::process::{Child, Command, Stdio};
-use std::sync::Mutex;
+mod backpressure;
+mod bench;
+mod builder;
+mod cache;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod chunks;
+mod command;
+mod compare;
+mod diagnostics;
+mod discover;
+mod erewrite;
+mod events;
+mod failure;
+mod filter;
+mod format;
+mod full_maude;
+mod graph;
+mod heartbeat;
+mod hibernate;
+mod history;
+mod input;
+mod install;
+mod introspect;
+mod lease;
+mod lifecycle;
+mod limits;
+mod locks;
+mod manual;
+mod matching;
+mod mock;
+mod narrowing;
+mod options;
+mod orphan;
+mod os;
+mod pipeline;
+mod pool;
+mod process;
+mod profile;
+mod readonly;
+mod resilient;
+mod sandbox;
+mod search;
+mod selection;
+mod sent;
+mod sequence;
+mod session;
+mod settings;
+mod shadow;
+mod smt;
+mod source;
+mod spill;
+mod startup;
+mod stats;
+mod suspend;
+mod term;
+mod threads;
+mod trace;
+mod unify;
+#[cfg(all(test, unix))]
+mod unloaded;
+mod usage;
+mod version;
+#[cfg(windows)]
+mod windows;
+mod wire;
+mod writer;
 
-/// Wrapper around the Maude subprocess with synchronized I/O handles.
-pub struct MaudeProcess {
-    child: Mutex<Child>,
-    stdin: Mutex<std::process::ChildStdin>,
-    stdout: Mutex<BufReader<std::process::ChildStdout>>,
+use command::{CommandKind, MaudeCommand};
+use diagnostics::Outcome;
+use failure::Failure;
+use format::{Meta, OutputOptions};
+use input::Input;
+use options::SpawnOptions;
+use process::{micros, Exchange, MaudeProcess, Response};
+use rustler::{Encoder, Env, LocalPid, NifResult, ResourceArc};
+use startup::SpawnError;
+use std::time::Instant;
+
+rustler::atoms! {
+    not_bool,
+    undecided,
+}
+
+/// Build the error returned by the NIFs: `{:error, message}`, or the term
+/// standing for a failure callers match on; see [`failure`].
+pub(crate) fn error(failure: impl Into<Failure>) -> rustler::Error {
+    rustler::Error::Term(Box::new(failure.into()))
 }
 
-#[rustler::resource_impl]
-impl rustler::Resource for MaudeProcess {}
+/// Hand a command's result back to Elixir: a desync as
+/// `{:error, :desync, details}`, any other failure as by [`error`].
+pub(crate) fn reply<T>(result: Result<T, Failure>) -> NifResult<Outcome<T>> {
+    match result {
+        Ok(value) => Ok(Outcome::Done(value)),
+        Err(Failure::Desync(details)) => Ok(Outcome::Desync(details)),
+        Err(failure) => Err(error(failure)),
+    }
+}
+
+/// Run `command` for `caller` and render its output, passing spilled
+/// responses through; see [`diagnostics`] for a command Maude complains
+/// about, and [`cache`] for one answered from memory.
+fn run(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    command: &[&[u8]],
+    opts: &OutputOptions,
+) -> NifResult<Outcome> {
+    let exchange = process.begin_by(caller).map_err(error)?;
+    answer(process, exchange, command, opts).map_err(error)
+}
+
+/// The body of [`run`], for a command whose exchange is already begun, as
+/// for a pool's worker. A desync comes back as [`Outcome::Desync`].
+pub(crate) fn answer(
+    process: &MaudeProcess,
+    exchange: Exchange<'_>,
+    command: &[&[u8]],
+    opts: &OutputOptions,
+) -> Result<Outcome, Failure> {
+    match respond(process, exchange, command, opts) {
+        Err(Failure::Desync(details)) => Ok(Outcome::Desync(details)),
+        result => result,
+    }
+}
+
+fn respond(
+    process: &MaudeProcess,
+    exchange: Exchange<'_>,
+    command: &[&[u8]],
+    opts: &OutputOptions,
+) -> Result<Outcome, Failure> {
+    let key = opts
+        .filter
+        .is_none()
+        .then(|| exchange.cache_key(command, opts.trim))
+        .flatten();
+    let start = Instant::now();
+    if let Some(response) = key.as_ref().and_then(|key| exchange.cached(key)) {
+        drop(exchange);
+        let meta = Meta {
+            duration_us: micros(start.elapsed()),
+            bytes_read: 0,
+            cached: true,
+            restarted: false,
+        };
+        return Ok(format::render_response(response, opts)
+            .map(|output| Outcome::Done(output.metered(opts, meta)))?);
+    }
+
+    let (response, meta) = exchange.execute_metered(command, opts)?;
+    let stderr = exchange.take_stderr()?;
+
+    if let Some(failed) = Outcome::failed(&stderr) {
+        return Ok(failed);
+    }
+    // Full Maude reports its complaints on stdout
+    if process.is_full_maude() {
+        if let Some(failed) = Outcome::failed(response.scan()) {
+            return Ok(failed);
+        }
+    }
+    // Still in the exchange, so no other command can have made it stale
+    if let Some(key) = key {
+        exchange.remember(key, &response);
+    }
+    drop(exchange);
+
+    format::render_response(response, opts)
+        .map(|output| Outcome::Done(output.metered(opts, meta)))
+        .map_err(Failure::from)
+}
 
 /// Start a new Maude subprocess.
 ///
@@ -36,34 +195,84 @@ impl rustler::Resource for MaudeProcess {}
 /// * `Err` - If spawning fails
 #[rustler::nif]
 fn start(maude_path: String) -> NifResult<ResourceArc<MaudeProcess>> {
-    let mut child = Command::new(&maude_path)
-        .args(["-no-banner", "-no-wrap", "-no-advise", "-interactive"])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| rustler::Error::Term(Box::new(format!("spawn failed: {}", e))))?;
-
-    let stdin = child
-        .stdin
-        .take()
-        .ok_or_else(|| rustler::Error::Term(Box::new("failed to get stdin".to_string())))?;
-
-    let stdout = child
-        .stdout
-        .take()
-        .ok_or_else(|| rustler::Error::Term(Box::new("failed to get stdout".to_string())))?;
-
-    let process = MaudeProcess {
-        child: Mutex::new(child),
-        stdin: Mutex::new(stdin),
-        stdout: Mutex::new(BufReader::new(stdout)),
-    };
+    MaudeProcess::spawn(&maude_path, &SpawnOptions::default())
+        .map(ResourceArc::new)
+        .map_err(SpawnError::into_nif_error)
+}
 
-    // Read until first prompt to ensure Maude is ready
-    read_until_prompt(&process)?;
+/// Start a new Maude subprocess with options.
+///
+/// # Arguments
+/// * `maude_path` - Path to the Maude executable
+/// * `opts` - Keyword list of spawn options (`:args`, `:preload`)
+///
+/// # Returns
+/// * `Ok(ResourceArc<MaudeProcess>)` - Handle to the running process
+/// * `Err` - If spawning or preloading fails
+#[rustler::nif(schedule = "DirtyCpu")]
+fn start_with_opts(maude_path: String, opts: SpawnOptions) -> NifResult<ResourceArc<MaudeProcess>> {
+    MaudeProcess::spawn(&maude_path, &opts)
+        .map(ResourceArc::new)
+        .map_err(SpawnError::into_nif_error)
+}
+
+/// Start a Maude subprocess without waiting for it to become ready.
+///
+/// The handle is returned as soon as the executable is running, in the
+/// `:starting` state; the first prompt and the preload files are awaited
+/// on a background thread. Commands fail until `await_ready/2` reports the
+/// process ready.
+///
+/// # Arguments
+/// * `maude_path` - Path to the Maude executable
+/// * `opts` - Keyword list of spawn options, as for `start_with_opts/2`
+///
+/// # Returns
+/// * `Ok(ResourceArc<MaudeProcess>)` - Handle to the starting process
+/// * `Err` - If the executable can't be started
+#[rustler::nif(schedule = "DirtyCpu")]
+fn start_async(maude_path: String, opts: SpawnOptions) -> NifResult<ResourceArc<MaudeProcess>> {
+    let process = MaudeProcess::launch(&maude_path, &opts)
+        .map(ResourceArc::new)
+        .map_err(SpawnError::into_nif_error)?;
+
+    let starting = process.clone();
+    threads::run(move || {
+        // A failure leaves the process broken with the reason, for await_ready
+        let _ = starting.initialize(&opts, &opts.preload, opts.select.as_deref());
+    });
 
-    Ok(ResourceArc::new(process))
+    Ok(process)
+}
+
+/// Wait for a process to finish starting.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `timeout` - Milliseconds to wait
+///
+/// # Returns
+/// * `Ok(:ok)` - The process is ready
+/// * `Err` - `"timeout"` if it is still starting, or the reason it can't
+///   take commands: it failed to start, is draining, or has stopped
+#[rustler::nif(schedule = "DirtyCpu")]
+fn await_ready(process: ResourceArc<MaudeProcess>, timeout: u64) -> NifResult<rustler::Atom> {
+    process
+        .await_ready(std::time::Duration::from_millis(timeout))
+        .map_err(error)?;
+    Ok(rustler::types::atom::ok())
+}
+
+/// Report where a process is in its lifecycle.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `:starting`, `:ready`, `:draining`, `:stopped`, or `:broken`
+#[rustler::nif]
+fn lifecycle(process: ResourceArc<MaudeProcess>) -> lifecycle::Lifecycle {
+    process.lifecycle()
 }
 
 /// Execute a Maude command and return the output.
@@ -77,53 +286,467 @@ fn start(maude_path: String) -> NifResult<ResourceArc<MaudeProcess>> {
 ///
 /// # Returns
 /// * `Ok(String)` - Command output (without the prompt)
+/// * `Ok({:spilled, path, bytes})` - Output too large to hold in memory was
+///   written to `path`; see `:spill_threshold` in `start_with_opts/2`
+/// * `{:error, reason, raw}` - Maude reported a warning or error; see
+///   [`diagnostics`]
+/// * `Err` - If I/O fails
+#[rustler::nif(schedule = "DirtyCpu")]
+fn execute<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    command: Input<'a>,
+) -> NifResult<Outcome> {
+    run(
+        &process,
+        env.pid(),
+        &command.parts(),
+        &OutputOptions::default(),
+    )
+}
+
+/// `execute/2` with output options; see [`format`] for `:format`.
+#[rustler::nif(schedule = "DirtyCpu", name = "execute")]
+fn execute_with_opts<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    command: Input<'a>,
+    opts: OutputOptions,
+) -> NifResult<Outcome> {
+    run(&process, env.pid(), &command.parts(), &opts)
+}
+
+/// Execute a Maude command on a dirty I/O scheduler.
+///
+/// Identical to `execute/2`, but the calling scheduler thread only waits on
+/// the pipe while Maude computes in its own OS process, so it belongs with
+/// the I/O-bound dirty schedulers. Many concurrent long-running commands
+/// then don't starve the dirty CPU schedulers that real CPU work needs.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `command` - Maude command to execute
+///
+/// # Returns
+/// * `Ok(String)` - Command output (without the prompt)
+/// * `Ok({:spilled, path, bytes})` - As for `execute/2`
+/// * `{:error, reason, raw}` - As for `execute/2`
 /// * `Err` - If I/O fails
+#[rustler::nif(schedule = "DirtyIo")]
+fn execute_io<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    command: Input<'a>,
+) -> NifResult<Outcome> {
+    run(
+        &process,
+        env.pid(),
+        &command.parts(),
+        &OutputOptions::default(),
+    )
+}
+
+/// `execute_io/2` with output options; see [`format`] for `:format`.
+#[rustler::nif(schedule = "DirtyIo", name = "execute_io")]
+fn execute_io_with_opts<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    command: Input<'a>,
+    opts: OutputOptions,
+) -> NifResult<Outcome> {
+    run(&process, env.pid(), &command.parts(), &opts)
+}
+
+/// Execute a single validated `<kind> in <module> : <term> .` command.
+///
+/// Unlike `execute/2`, the command is assembled here and the term is
+/// rejected if it contains control characters, comments, unbalanced
+/// brackets, or a top-level `.`, so untrusted input can't end the command
+/// early, inject a second one, or leave Maude waiting for more input.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `kind` - `:reduce`, `:rewrite`, `:frewrite`, `:erewrite`, `:parse`, `:search`,
+///   `:unify`, `:variant_unify`, `:match`, `:xmatch`, `:check`, `:smt_search`,
+///   `:vu_narrow`, or `:fvu_narrow`
+/// * `module` - Module to run the command in
+/// * `term` - Term (or, for the search and narrowing kinds, the unify kinds,
+///   and the match kinds, the `term =>* pattern`, `t1 =? t2`, or
+///   `pattern <=? subject` text)
+///
+/// # Returns
+/// * `Ok(String)` - Command output (without the prompt)
+/// * `Ok({:spilled, path, bytes})` - As for `execute/2`
+/// * `{:error, reason, raw}` - As for `execute/2`
+/// * `Err` - If the input is rejected or I/O fails
+#[rustler::nif(schedule = "DirtyCpu")]
+fn execute_term(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    kind: CommandKind,
+    module: String,
+    term: String,
+) -> NifResult<Outcome> {
+    let command = command::build(kind, &module, &term)
+        .map_err(|e| error(format!("rejected command: {}", e)))?;
+
+    run(
+        &process,
+        env.pid(),
+        &[command.as_bytes()],
+        &OutputOptions::default(),
+    )
+}
+
+/// `execute_term/4` with output options; see [`format`] for `:format`.
+#[rustler::nif(schedule = "DirtyCpu", name = "execute_term")]
+fn execute_term_with_opts(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    kind: CommandKind,
+    module: String,
+    term: String,
+    opts: OutputOptions,
+) -> NifResult<Outcome> {
+    let command = command::build(kind, &module, &term)
+        .map_err(|e| error(format!("rejected command: {}", e)))?;
+
+    run(&process, env.pid(), &[command.as_bytes()], &opts)
+}
+
+/// Execute a command given as a tuple, e.g. `{:reduce, "NAT", "1 + 1"}` or
+/// `{:search, "M", "a", :"=>*", "X", bound: 1}`; see
+/// [`command::MaudeCommand`] for the forms.
+///
+/// The command is serialized here, with every module and term validated as
+/// for `execute_term/4`, so callers never format Maude syntax themselves.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `command` - Command tuple
+///
+/// # Returns
+/// * As for `execute_term/4`; `Err` also if the tuple's pieces don't go
+///   together, say a bound for `reduce`
+#[rustler::nif(schedule = "DirtyCpu", name = "run")]
+fn run_command(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    command: MaudeCommand,
+) -> NifResult<Outcome> {
+    run_typed(&process, env.pid(), &command, &OutputOptions::default())
+}
+
+/// `run/2` with output options; see [`format`] for `:format`.
+#[rustler::nif(schedule = "DirtyCpu", name = "run")]
+fn run_command_with_opts(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    command: MaudeCommand,
+    opts: OutputOptions,
+) -> NifResult<Outcome> {
+    run_typed(&process, env.pid(), &command, &opts)
+}
+
+fn run_typed(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    command: &MaudeCommand,
+    opts: &OutputOptions,
+) -> NifResult<Outcome> {
+    let command = command
+        .serialize()
+        .map_err(|e| error(format!("rejected command: {}", e)))?;
+
+    run(process, caller, &[command.as_bytes()], opts)
+}
+
+/// Execute a reduce/rewrite command and return the result as a parsed term.
+///
+/// The result term is returned as nested `{op, sort, [args]}` tuples, with
+/// the root sort taken from Maude's `result Sort:` line. Prefix output
+/// (`set print mixfix off .`) parses exactly; mixfix output is parsed
+/// using the prelude operator precedences.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `command` - A `reduce`, `rewrite`, or similar command producing a result
+///
+/// # Returns
+/// * `Ok(Term)` - The parsed result term
+/// * `Err` - If I/O fails or the output has no parseable result
+#[rustler::nif(schedule = "DirtyCpu")]
+fn execute_parsed<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    command: Input<'a>,
+) -> NifResult<Outcome<term::Term>> {
+    reply(parsed(env, &process, &command))
+}
+
+/// `execute_parsed/2` with options.
+///
+/// # Arguments
+/// * `opts` - Keyword list with `:native` (default: `true`); see
+///   [`term::ParsedOptions`]
+///
+/// # Returns
+/// * `Ok(value)` - A `true`, `false`, integer, float, binary, or atom for a
+///   constant of a builtin sort with `native: true`, otherwise the parsed
+///   result term
+/// * `Err` - As for `execute_parsed/2`
+#[rustler::nif(schedule = "DirtyCpu", name = "execute_parsed")]
+fn execute_parsed_with_opts<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    command: Input<'a>,
+    opts: term::ParsedOptions,
+) -> NifResult<Outcome<rustler::Term<'a>>> {
+    reply(parsed(env, &process, &command).map(|term| {
+        if opts.native {
+            term::Native(term).encode(env)
+        } else {
+            term.encode(env)
+        }
+    }))
+}
+
+fn parsed(env: Env, process: &MaudeProcess, command: &Input) -> Result<term::Term, Failure> {
+    let output = process
+        .begin_by(env.pid())
+        .and_then(|exchange| exchange.execute_response(&command.parts()))
+        .and_then(Response::into_text)?;
+
+    term::parse_result(&output).map_err(|e| format!("parse failed: {}", e).into())
+}
+
+/// Reduce `term` in `module` and return the parsed result.
+///
+/// The fast path for the most common command: it is assembled and
+/// validated as by `execute_term/4`, and only the `result Sort: term` line
+/// of the output is converted, so nothing else crosses the NIF boundary.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `module` - Module to reduce in
+/// * `term` - Term to reduce
+///
+/// # Returns
+/// * `Ok(Term)` - The result as nested `{op, sort, [args]}` tuples, with the
+///   root sort from the `result` line
+/// * `Err` - If the input is rejected, Maude reports a warning, or I/O fails
 #[rustler::nif(schedule = "DirtyCpu")]
-fn execute(process: ResourceArc<MaudeProcess>, command: String) -> NifResult<String> {
-    // Write command to Maude stdin
-    {
-        let mut stdin = process
-            .stdin
-            .lock()
-            .map_err(|e| rustler::Error::Term(Box::new(format!("stdin lock failed: {}", e))))?;
-
-        writeln!(stdin, "{}", command)
-            .map_err(|e| rustler::Error::Term(Box::new(format!("write failed: {}", e))))?;
-
-        stdin
-            .flush()
-            .map_err(|e| rustler::Error::Term(Box::new(format!("flush failed: {}", e))))?;
+fn reduce_in(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    term: String,
+) -> NifResult<Outcome<term::Term>> {
+    reply(reduce(&process, env.pid(), &module, &term))
+}
+
+fn reduce(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    module: &str,
+    term: &str,
+) -> Result<term::Term, Failure> {
+    let command = command::build(CommandKind::Reduce, module, term)
+        .map_err(|e| format!("rejected command: {}", e))?;
+
+    let exchange = process.begin_by(caller)?;
+    let output = exchange.execute(&command)?;
+    let diagnostics = exchange.take_stderr()?;
+    drop(exchange);
+
+    if diagnostics.contains("Warning:") || diagnostics.contains("Error:") {
+        return Err(format!("reduce failed: {}", diagnostics.trim()).into());
     }
 
-    // Read response until we see the prompt
-    read_until_prompt(&process)
+    term::parse_result(&output).map_err(|e| format!("parse failed: {}", e).into())
 }
 
-/// Stop the Maude subprocess.
+/// Reduce a `Bool` term in `module` and return its truth value.
+///
+/// Most application queries are predicates, so this saves matching on the
+/// result term. The term is validated as by `execute_term/4`.
 ///
 /// # Arguments
 /// * `process` - Handle to the Maude process
-#[rustler::nif]
-fn stop(process: ResourceArc<MaudeProcess>) -> NifResult<()> {
-    let mut child = process
-        .child
-        .lock()
-        .map_err(|e| rustler::Error::Term(Box::new(format!("child lock failed: {}", e))))?;
-
-    // Send quit command first for graceful shutdown
-    if let Ok(mut stdin) = process.stdin.lock() {
-        let _ = writeln!(stdin, "quit");
-        let _ = stdin.flush();
+/// * `module` - Module to reduce in
+/// * `term` - Term expected to reduce to `true` or `false`
+///
+/// # Returns
+/// * `Ok(bool)` - The result was `true` or `false`
+/// * `Err({:not_bool, sort})` - The result has another sort, or a kind such
+///   as `[Bool]` for an error term
+/// * `Err({:undecided, term})` - The result is a `Bool` that didn't reduce
+///   to a constant, e.g. because it contains a variable
+/// * `Err` - As for `reduce_in/3`
+#[rustler::nif(schedule = "DirtyCpu")]
+fn check(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    term: String,
+) -> NifResult<Outcome<bool>> {
+    let result = match reduce(&process, env.pid(), &module, &term) {
+        Ok(result) => result,
+        Err(failure) => return reply(Err(failure)),
+    };
+
+    match (result.sort.as_deref(), result.op.as_str()) {
+        (Some("Bool"), "true") => Ok(Outcome::Done(true)),
+        (Some("Bool"), "false") => Ok(Outcome::Done(false)),
+        (Some("Bool"), _) => Err(rustler::Error::Term(Box::new((undecided(), result)))),
+        (sort, _) => Err(rustler::Error::Term(Box::new((
+            not_bool(),
+            sort.unwrap_or_default().to_string(),
+        )))),
+    }
+}
+
+/// Fetch the next solutions of a bounded search with `continue n .`.
+///
+/// Start the search with a bound, e.g. `search [1] in M : t =>* X:S .`,
+/// through `execute/2` or `execute_term/4`, then call this repeatedly to
+/// enumerate further solutions lazily. The process remembers whether a
+/// search is pending, so no command is sent once it is exhausted.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `n` - Maximum number of further solutions to return
+///
+/// # Returns
+/// * `Ok(Page)` - A list of `%{number, state, substitution}` maps, or
+///   `:exhausted` when there are no solutions left
+/// * `Err` - If I/O fails or a solution can't be parsed
+#[rustler::nif(schedule = "DirtyCpu")]
+fn search_next(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    n: u64,
+) -> NifResult<Outcome<search::Page>> {
+    reply(continue_search(&process, env.pid(), n))
+}
+
+fn continue_search(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    n: u64,
+) -> Result<search::Page, Failure> {
+    if n == 0 {
+        return Err("n must be positive".into());
+    }
+
+    // Check and continue in one exchange so no other command drops the search
+    let exchange = process.begin_by(caller)?;
+    if !process.search_active() {
+        return Ok(search::Page::Exhausted);
+    }
+
+    let output = exchange.execute(&format!("continue {} .", n))?;
+    exchange.take_stderr()?;
+    drop(exchange);
+
+    let solutions = search::parse_solutions(&output).map_err(|e| format!("parse failed: {}", e))?;
+
+    if solutions.is_empty() {
+        Ok(search::Page::Exhausted)
+    } else {
+        Ok(search::Page::Solutions(solutions))
     }
+}
 
-    // Give it a moment to exit gracefully
-    std::thread::sleep(std::time::Duration::from_millis(100));
+/// Render an Elixir AST as a correctly escaped Maude term string.
+///
+/// Accepts the `{op, sort, [args]}` tuples returned by `execute_parsed/2`
+/// as well as `{op, [args]}`, `{:string, text}`, `{:qid, name}`,
+/// `{:var, name, sort}`, numbers, booleans, and constant binaries.
+///
+/// # Arguments
+/// * `ast` - Term AST to render
+///
+/// # Returns
+/// * `Ok(String)` - Maude term, safe to splice into a command
+/// * `Err` - If a token is invalid (whitespace, control characters, `.`)
+#[rustler::nif]
+fn build_term(ast: builder::Ast) -> NifResult<String> {
+    builder::render(&ast).map_err(|e| error(format!("build failed: {}", e)))
+}
+
+/// Send input to the loop started by `loop init .` (LOOP-MODE interfaces).
+///
+/// The input is wrapped in parentheses unless it already is, which is how
+/// Maude routes a line to the active loop instead of the command parser.
+/// Loop output is the loop's printed QidList rather than a command result,
+/// so only the trailing newline is removed and inner whitespace is kept.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `input` - Input for the loop, with or without surrounding parentheses
+///
+/// # Returns
+/// * `Ok(String)` - Text printed by the loop in response
+/// * `Err` - If I/O fails
+#[rustler::nif(schedule = "DirtyCpu")]
+fn loop_send(env: Env, process: ResourceArc<MaudeProcess>, input: String) -> NifResult<String> {
+    let input = input.trim();
+    let input = if input.starts_with('(') && input.ends_with(')') {
+        input.to_string()
+    } else {
+        format!("({})", input)
+    };
 
-    // Force kill if still running
-    let _ = child.kill();
-    let _ = child.wait();
+    let exchange = process.begin_by(env.pid()).map_err(error)?;
+    exchange.write_command(&input).map_err(error)?;
+    let output = exchange.read_raw_until_prompt().map_err(error)?;
+    drop(exchange);
 
-    Ok(())
+    Ok(output.strip_suffix('\n').unwrap_or(&output).to_string())
+}
+
+/// How a Maude process was launched, captured at spawn time.
+///
+/// Every option is reported with its default filled in, so the map can be
+/// attached to a support ticket or telemetry event as it is.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `EffectiveConfig` - Map of `maude_path`, `args`, `preload`, `env`,
+///   `os_pid`, `prompt`, `trim`, `startup_timeout` and
+///   `lock_wait_threshold` (ms), `spill_threshold`, `spill_dir`,
+///   `max_memory`, and `max_cpu_seconds`
+#[rustler::nif]
+fn config(process: ResourceArc<MaudeProcess>) -> options::EffectiveConfig {
+    process.config().clone()
+}
+
+/// Cumulative rewrite statistics for a Maude process.
+///
+/// Totals are summed from the `rewrites: ... cpu (... real)` lines of every
+/// command the process has run, including files it loaded.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `ProcessStats` - Map of `commands`, `rewrites`, `cpu_ms`, `real_ms`,
+///   and `rewrites_per_second`
+#[rustler::nif]
+fn stats(process: ResourceArc<MaudeProcess>) -> stats::ProcessStats {
+    process.stats()
+}
+
+/// Stop the Maude subprocess.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+#[rustler::nif]
+fn stop(process: ResourceArc<MaudeProcess>) -> NifResult<()> {
+    process.shutdown().map_err(error)
 }
 
 /// Check if the Maude subprocess is still running.
@@ -136,56 +759,13 @@ fn stop(process: ResourceArc<MaudeProcess>) -> NifResult<()> {
 /// * `false` if the process has exited
 #[rustler::nif]
 fn alive(process: ResourceArc<MaudeProcess>) -> bool {
-    match process.child.lock() {
-        Ok(mut child) => match child.try_wait() {
-            Ok(None) => true,     // Still running
-            Ok(Some(_)) => false, // Exited
-            Err(_) => false,      // Error checking status
-        },
-        Err(_) => false, // Lock failed
-    }
+    process.is_alive()
 }
 
-/// Read from Maude stdout until we see the "Maude>" prompt.
-fn read_until_prompt(process: &MaudeProcess) -> NifResult<String> {
-    let mut stdout = process
-        .stdout
-        .lock()
-        .map_err(|e| rustler::Error::Term(Box::new(format!("stdout lock failed: {}", e))))?;
-
-    let mut output = String::new();
-    let mut line = String::new();
-
-    loop {
-        line.clear();
-        match stdout.read_line(&mut line) {
-            Ok(0) => {
-                // EOF - process likely exited
-                break;
-            }
-            Ok(_) => {
-                // Check if this line contains the prompt
-                if line.contains("Maude>") {
-                    // Don't include the prompt in output
-                    if let Some(before_prompt) = line.split("Maude>").next() {
-                        if !before_prompt.is_empty() {
-                            output.push_str(before_prompt);
-                        }
-                    }
-                    break;
-                }
-                output.push_str(&line);
-            }
-            Err(e) => {
-                return Err(rustler::Error::Term(Box::new(format!(
-                    "read failed: {}",
-                    e
-                ))))
-            }
-        }
-    }
-
-    Ok(output.trim().to_string())
+/// Set up process-wide state when the library loads.
+fn load(_env: Env, _info: rustler::Term) -> bool {
+    orphan::kill_on_exit();
+    true
 }
 
-rustler::init!("Elixir.ExMaude.Backend.NIF.Native");
+rustler::init!("Elixir.ExMaude.Backend.NIF.Native", load = load);
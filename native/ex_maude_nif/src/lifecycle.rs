@@ -0,0 +1,186 @@
+//! Lifecycle states of a Maude process.
+//!
+//! Every process moves through these states:
+//!
+//! ```text
+//! starting -> ready -> draining -> stopped
+//!     \          \         \
+//!      +----------+---------+--> broken
+//! ```
+//!
+//! A process is `starting` until its first prompt arrives and its preload
+//! files are loaded; only `start_async/2` hands out a handle that early, and
+//! `await_ready/2` waits for it to leave that state. `draining` refuses new
+//! commands while those queued before the drain finish, then the process
+//! stops. A process that fails to start, or whose Maude exits, is `broken`.
+//!
+//! Commands check the state before any I/O, so a call issued at the wrong
+//! time fails with an error naming the state rather than a pipe error. The
+//! state mutex is a leaf: nothing else is locked while it is held.
+
+use crate::failure::Failure;
+use rustler::{Encoder, Env, Term};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+rustler::atoms! {
+    starting,
+    ready,
+    draining,
+    stopped,
+    broken,
+}
+
+/// Where a process is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lifecycle {
+    Starting,
+    Ready,
+    Draining,
+    Stopped,
+    Broken,
+}
+
+impl Encoder for Lifecycle {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            Lifecycle::Starting => starting(),
+            Lifecycle::Ready => ready(),
+            Lifecycle::Draining => draining(),
+            Lifecycle::Stopped => stopped(),
+            Lifecycle::Broken => broken(),
+        }
+        .encode(env)
+    }
+}
+
+struct Current {
+    lifecycle: Lifecycle,
+    /// While draining, the first exchange ticket that is refused.
+    drain_from: u64,
+    /// Why the process broke, for `await_ready/2`.
+    reason: Option<Failure>,
+}
+
+/// A process's lifecycle state, with waiting for it to change.
+pub struct State {
+    current: Mutex<Current>,
+    changed: Condvar,
+}
+
+impl State {
+    pub fn new() -> State {
+        State {
+            current: Mutex::new(Current {
+                lifecycle: Lifecycle::Starting,
+                drain_from: 0,
+                reason: None,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Current> {
+        self.current.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The current state.
+    pub fn get(&self) -> Lifecycle {
+        self.lock().lifecycle
+    }
+
+    /// Move from `starting` to `ready`.
+    pub fn ready(&self) {
+        self.update(|current| {
+            if current.lifecycle == Lifecycle::Starting {
+                current.lifecycle = Lifecycle::Ready;
+            }
+        });
+    }
+
+    /// Refuse exchanges from ticket `from` on, unless already stopped or
+    /// broken.
+    pub fn drain(&self, from: u64) {
+        self.update(|current| {
+            if matches!(current.lifecycle, Lifecycle::Starting | Lifecycle::Ready) {
+                current.lifecycle = Lifecycle::Draining;
+                current.drain_from = from;
+            }
+        });
+    }
+
+    /// Mark the process stopped; a broken process stays broken.
+    pub fn stop(&self) {
+        self.update(|current| {
+            if current.lifecycle != Lifecycle::Broken {
+                current.lifecycle = Lifecycle::Stopped;
+            }
+        });
+    }
+
+    /// Mark the process broken for `reason`, unless it was stopped first.
+    pub fn break_with(&self, reason: &Failure) {
+        self.update(|current| {
+            if current.lifecycle != Lifecycle::Stopped && current.lifecycle != Lifecycle::Broken {
+                current.lifecycle = Lifecycle::Broken;
+                current.reason = Some(reason.clone());
+            }
+        });
+    }
+
+    /// Mark the process broken by a startup failure, whose reason replaces
+    /// any seen while starting.
+    pub fn fail(&self, reason: &str) {
+        self.update(|current| {
+            current.lifecycle = Lifecycle::Broken;
+            current.reason = Some(reason.into());
+        });
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Current)) {
+        change(&mut self.lock());
+        self.changed.notify_all();
+    }
+
+    /// Whether the exchange holding `ticket` may do I/O; `setup` exchanges
+    /// are the ones that bring a starting process up.
+    pub fn check(&self, ticket: u64, setup: bool) -> Result<(), Failure> {
+        let current = self.lock();
+        match current.lifecycle {
+            Lifecycle::Ready => Ok(()),
+            Lifecycle::Starting if setup => Ok(()),
+            Lifecycle::Starting => Err("process is starting; call await_ready/2 first".into()),
+            Lifecycle::Draining if ticket < current.drain_from => Ok(()),
+            Lifecycle::Draining => Err("process is draining".into()),
+            Lifecycle::Stopped => Err("process stopped".into()),
+            Lifecycle::Broken => Err(current
+                .reason
+                .clone()
+                .unwrap_or_else(|| "maude exited".into())),
+        }
+    }
+
+    /// Wait up to `timeout` for the process to leave `starting`.
+    ///
+    /// Returns `Ok` once it is ready, or the error a command would get in
+    /// the state it reached instead; `"timeout"` if it is still starting.
+    pub fn await_ready(&self, timeout: Duration) -> Result<(), Failure> {
+        let deadline = Instant::now() + timeout;
+        let mut current = self.lock();
+
+        while current.lifecycle == Lifecycle::Starting {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err("timeout".into());
+            }
+            current = self
+                .changed
+                .wait_timeout(current, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        drop(current);
+
+        self.check(u64::MAX, false)
+    }
+}
@@ -0,0 +1,109 @@
+//! Resource limits for the Maude subprocess.
+//!
+//! `:max_memory` and `:max_cpu_seconds` are applied to the child with
+//! `setrlimit` between fork and exec, so an untrusted Maude program can't
+//! exhaust the host. Maude reacts to each limit in its own way:
+//!
+//! * `RLIMIT_AS` - allocation fails and Maude aborts after printing
+//!   `std::bad_alloc` to stderr
+//! * `RLIMIT_CPU` - the kernel sends `SIGXCPU` at the limit and `SIGKILL`
+//!   a second later
+//!
+//! CPU time is counted over the subprocess's whole life, not per command.
+//! When Maude dies one of these ways, the command in flight and every later
+//! one fail with `{:error, :resource_limit}` instead of `"maude exited"`.
+
+use std::process::{Command, ExitStatus};
+
+/// Message for a command cut short by a limit; the NIFs return the
+/// `:resource_limit` atom instead.
+pub const EXCEEDED: &str = "resource limit exceeded";
+
+/// Limits applied to a spawned process; `None` leaves the inherited one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// Bytes of address space.
+    pub memory: Option<u64>,
+    /// Seconds of CPU time.
+    pub cpu_seconds: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_none() && self.cpu_seconds.is_none()
+    }
+
+    /// Arrange for `command`'s child to start under these limits.
+    #[cfg(unix)]
+    pub fn apply(&self, command: &mut Command) -> Result<(), String> {
+        use std::os::unix::process::CommandExt;
+
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let limits = *self;
+        // SAFETY: the hook runs between fork and exec and only calls
+        // setrlimit, which is async-signal-safe; it allocates nothing.
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(bytes) = limits.memory {
+                    check(libc::setrlimit(libc::RLIMIT_AS, &rlimit(bytes, bytes)))?;
+                }
+                if let Some(seconds) = limits.cpu_seconds {
+                    // A soft limit below the hard one gets SIGXCPU first
+                    check(libc::setrlimit(
+                        libc::RLIMIT_CPU,
+                        &rlimit(seconds, seconds + 1),
+                    ))?;
+                }
+                Ok(())
+            });
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self, _command: &mut Command) -> Result<(), String> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err("resource limits are only supported on Unix".to_string())
+        }
+    }
+
+    /// Whether a child that exited with `status`, having written `stderr`,
+    /// was stopped by one of these limits.
+    #[cfg(unix)]
+    pub fn exceeded(&self, status: ExitStatus, stderr: &str) -> bool {
+        use std::os::unix::process::ExitStatusExt;
+
+        let out_of_memory = stderr.contains("bad_alloc") || stderr.contains("out of memory");
+        match status.signal() {
+            Some(libc::SIGXCPU) | Some(libc::SIGKILL) => self.cpu_seconds.is_some(),
+            _ => self.memory.is_some() && out_of_memory,
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn exceeded(&self, _status: ExitStatus, _stderr: &str) -> bool {
+        false
+    }
+}
+
+#[cfg(unix)]
+fn rlimit(soft: u64, hard: u64) -> libc::rlimit {
+    libc::rlimit {
+        rlim_cur: soft as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    }
+}
+
+#[cfg(unix)]
+fn check(result: libc::c_int) -> std::io::Result<()> {
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,271 @@
+//! Lock ordering for the handles of a Maude process.
+//!
+//! A process guards its child, pipes, and bookkeeping with separate
+//! mutexes. Code that holds more than one at a time takes them in [`Rank`]
+//! order:
+//!
+//! ```text
+//! child -> stdin -> pending -> stdout -> stderr -> settings
+//! ```
+//!
+//! The exchange queue sits outside this order: its ticket mutex is never
+//! held while another lock is taken, and every other lock is either taken
+//! inside an exchange or, for `shutdown`, `is_alive`, and `exit_status`,
+//! limited to `child` plus a `try_lock` of `stdin` that can't block. The
+//! lifecycle state ([`crate::lifecycle`]) is a leaf: it may be taken while
+//! any of these is held, and nothing is locked under it.
+//!
+//! Debug builds check the order on every acquisition and panic on a
+//! violation naming both locks, so an inversion fails loudly in tests
+//! instead of showing up as an occasional hang in production.
+//!
+//! With the `lock-watch` feature, each lock also times contended waits.
+//! Waits longer than the process's `:lock_wait_threshold` are counted and
+//! the longest is kept, and the oldest wait still in progress can be read
+//! at any time, so `stats/1` called from another Elixir process shows a
+//! stuck call while it is still stuck.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{LockResult, Mutex, MutexGuard, PoisonError, TryLockError};
+use std::time::Duration;
+
+#[cfg(feature = "lock-watch")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "lock-watch")]
+use std::time::Instant;
+
+/// Position of a lock in the acquisition order; lower ranks come first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rank {
+    Child,
+    Stdin,
+    Pending,
+    Stdout,
+    Stderr,
+    Settings,
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    /// Ranks of the locks this thread holds, in acquisition order.
+    static HELD: std::cell::RefCell<Vec<Rank>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Lock wait statistics, summed over a process's locks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LockWaits {
+    /// Completed waits longer than the threshold.
+    pub slow: u64,
+    /// Longest completed wait, in milliseconds.
+    pub longest_ms: u64,
+    /// Age of the oldest wait still in progress, in milliseconds.
+    pub waiting_ms: u64,
+}
+
+impl LockWaits {
+    pub fn merge(self, other: LockWaits) -> LockWaits {
+        LockWaits {
+            slow: self.slow + other.slow,
+            longest_ms: self.longest_ms.max(other.longest_ms),
+            waiting_ms: self.waiting_ms.max(other.waiting_ms),
+        }
+    }
+}
+
+/// A mutex with a place in the lock order.
+pub struct Ordered<T> {
+    rank: Rank,
+    mutex: Mutex<T>,
+    #[cfg(feature = "lock-watch")]
+    watch: Watch,
+}
+
+/// Guard for an [`Ordered`] lock; releases its rank when dropped.
+pub struct Guard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    #[cfg_attr(not(debug_assertions), allow(dead_code))]
+    rank: Rank,
+}
+
+impl<T> Ordered<T> {
+    /// A lock at `rank`; waits longer than `threshold` count as slow.
+    #[cfg_attr(not(feature = "lock-watch"), allow(unused_variables))]
+    pub fn new(rank: Rank, value: T, threshold: Duration) -> Ordered<T> {
+        Ordered {
+            rank,
+            mutex: Mutex::new(value),
+            #[cfg(feature = "lock-watch")]
+            watch: Watch::new(threshold),
+        }
+    }
+
+    /// Acquire the lock, checking the order in debug builds.
+    pub fn lock(&self) -> LockResult<Guard<'_, T>> {
+        check_order(self.rank);
+
+        let result = match self.mutex.try_lock() {
+            Ok(guard) => Ok(guard),
+            Err(TryLockError::Poisoned(e)) => Err(e),
+            Err(TryLockError::WouldBlock) => self.wait(),
+        };
+
+        match result {
+            Ok(guard) => Ok(self.hold(guard)),
+            Err(e) => Err(PoisonError::new(self.hold(e.into_inner()))),
+        }
+    }
+
+    /// Acquire the lock only if it is free; a poisoned lock is still taken.
+    ///
+    /// This can't deadlock, so it may be used out of order.
+    pub fn try_lock(&self) -> Option<Guard<'_, T>> {
+        match self.mutex.try_lock() {
+            Ok(guard) => Some(self.hold(guard)),
+            Err(TryLockError::Poisoned(e)) => Some(self.hold(e.into_inner())),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    /// The value, through exclusive access; no locking is needed.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.mutex.get_mut()
+    }
+
+    /// Wait statistics for this lock; all zero without `lock-watch`.
+    pub fn waits(&self) -> LockWaits {
+        #[cfg(feature = "lock-watch")]
+        return self.watch.snapshot();
+
+        #[cfg(not(feature = "lock-watch"))]
+        LockWaits::default()
+    }
+
+    #[cfg(feature = "lock-watch")]
+    fn wait(&self) -> LockResult<MutexGuard<'_, T>> {
+        let started = self.watch.begin();
+        let result = self.mutex.lock();
+        self.watch.end(started);
+        result
+    }
+
+    #[cfg(not(feature = "lock-watch"))]
+    fn wait(&self) -> LockResult<MutexGuard<'_, T>> {
+        self.mutex.lock()
+    }
+
+    fn hold<'a>(&self, guard: MutexGuard<'a, T>) -> Guard<'a, T> {
+        #[cfg(debug_assertions)]
+        HELD.with(|held| held.borrow_mut().push(self.rank));
+
+        Guard {
+            guard,
+            rank: self.rank,
+        }
+    }
+}
+
+impl<T> Deref for Guard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        // Guards can be dropped in any order, so remove this one's entry
+        #[cfg(debug_assertions)]
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(at) = held.iter().rposition(|rank| *rank == self.rank) {
+                held.remove(at);
+            }
+        });
+    }
+}
+
+/// Panic if this thread already holds a lock ranked at or after `rank`.
+#[cfg(debug_assertions)]
+fn check_order(rank: Rank) {
+    HELD.with(|held| {
+        if let Some(holding) = held.borrow().iter().find(|held| **held >= rank) {
+            panic!(
+                "lock order violation: acquiring {:?} while holding {:?}",
+                rank, holding
+            );
+        }
+    });
+}
+
+#[cfg(not(debug_assertions))]
+fn check_order(_rank: Rank) {}
+
+/// Contended wait timing for one lock.
+#[cfg(feature = "lock-watch")]
+struct Watch {
+    threshold: Duration,
+    slow: AtomicU64,
+    longest_ms: AtomicU64,
+    /// Start of each wait in progress; a leaf lock held only briefly.
+    waiting: Mutex<Vec<Instant>>,
+}
+
+#[cfg(feature = "lock-watch")]
+impl Watch {
+    fn new(threshold: Duration) -> Watch {
+        Watch {
+            threshold,
+            slow: AtomicU64::new(0),
+            longest_ms: AtomicU64::new(0),
+            waiting: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn begin(&self) -> Instant {
+        let started = Instant::now();
+        self.waiting
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(started);
+        started
+    }
+
+    fn end(&self, started: Instant) {
+        {
+            let mut waiting = self.waiting.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(at) = waiting.iter().position(|start| *start == started) {
+                waiting.swap_remove(at);
+            }
+        }
+
+        let waited = started.elapsed();
+        if waited > self.threshold {
+            self.slow.fetch_add(1, Ordering::Relaxed);
+            self.longest_ms
+                .fetch_max(waited.as_millis() as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn snapshot(&self) -> LockWaits {
+        let oldest = self
+            .waiting
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .min()
+            .map(|start| start.elapsed().as_millis() as u64);
+
+        LockWaits {
+            slow: self.slow.load(Ordering::Relaxed),
+            longest_ms: self.longest_ms.load(Ordering::Relaxed),
+            waiting_ms: oldest.unwrap_or(0),
+        }
+    }
+}
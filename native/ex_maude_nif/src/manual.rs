@@ -0,0 +1,96 @@
+//! Raw I/O escape hatch for unusual Maude front-ends.
+//!
+//! `send_bytes/2` and `recv_until/3` bypass the prompt-delimited protocol
+//! entirely, for front-ends that don't answer with `Maude> ` (or answer
+//! with more than one). Either call puts the process in manual mode, where
+//! every structured call (`execute/2` and friends) is refused, since the
+//! reader can no longer tell which output belongs to which command.
+//! `resync/2` brings Maude back to a known prompt and leaves manual mode.
+
+use crate::error;
+use crate::process::MaudeProcess;
+use rustler::{Atom, Binary, Env, NifResult, OwnedBinary, ResourceArc};
+use std::time::Duration;
+
+rustler::atoms! {
+    ok,
+}
+
+/// Write raw bytes to Maude's stdin, switching to manual mode.
+///
+/// Nothing is added: include the trailing newline if Maude should see one.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `data` - Bytes to write
+///
+/// # Returns
+/// * `Ok(:ok)` - The bytes were written and flushed
+/// * `Err` - If I/O fails
+#[rustler::nif(schedule = "DirtyIo")]
+fn send_bytes(env: Env, process: ResourceArc<MaudeProcess>, data: Binary) -> NifResult<Atom> {
+    process
+        .begin_by(env.pid())
+        .and_then(|exchange| exchange.send_bytes(&data))
+        .map_err(error)?;
+    Ok(ok())
+}
+
+/// Read Maude's stdout up to and including `pattern`, switching to manual
+/// mode.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `pattern` - Bytes to wait for; must not be empty
+/// * `timeout` - Milliseconds to wait for the pattern
+///
+/// # Returns
+/// * `Ok(binary)` - Everything read up to the end of the pattern; later
+///   bytes are kept for the next call
+/// * `Err` - `"timeout"` if the pattern didn't arrive in time (the bytes
+///   read so far are kept for the next call), or if I/O fails
+#[rustler::nif(schedule = "DirtyIo")]
+fn recv_until<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    pattern: Binary,
+    timeout: u64,
+) -> NifResult<Binary<'a>> {
+    if pattern.is_empty() {
+        return Err(error("pattern must not be empty"));
+    }
+
+    let bytes = process
+        .begin_by(env.pid())
+        .and_then(|exchange| exchange.recv_until(&pattern, Duration::from_millis(timeout)))
+        .map_err(error)?
+        .ok_or_else(|| error("timeout"))?;
+
+    let mut binary =
+        OwnedBinary::new(bytes.len()).ok_or_else(|| error("binary allocation failed"))?;
+    binary.as_mut_slice().copy_from_slice(&bytes);
+
+    Ok(binary.release(env))
+}
+
+/// Leave manual mode once Maude is back at its prompt.
+///
+/// A marker command is sent and everything up to its result is discarded,
+/// including raw output nobody received.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `timeout` - Milliseconds to wait for the marker's result
+///
+/// # Returns
+/// * `Ok(:ok)` - Structured calls are accepted again
+/// * `Err` - If the marker didn't come back in time (the process stays in
+///   manual mode), or if I/O fails
+#[rustler::nif(schedule = "DirtyIo")]
+fn resync(env: Env, process: ResourceArc<MaudeProcess>, timeout: u64) -> NifResult<Atom> {
+    process
+        .begin_by(env.pid())
+        .and_then(|exchange| exchange.resync(Duration::from_millis(timeout)))
+        .map_err(error)?;
+    Ok(ok())
+}
@@ -0,0 +1,300 @@
+//! Parsed results of `match` and `xmatch`.
+//!
+//! Both commands print one block per matcher. `xmatch` matches with
+//! extension, so for operators with `assoc` or `comm` the pattern may match
+//! only part of the subject, which Maude reports before the substitution:
+//!
+//! ```text
+//! Matcher 1
+//! Matched portion = h(a, b)
+//! X --> a
+//! Y --> b
+//! ```
+//!
+//! `Matched portion = (whole)` means the entire subject matched. Maude
+//! prints `No match.` when there is none and nothing after the last
+//! matcher, so as with unification a bounded command that returns exactly
+//! `n` matchers is reported as incomplete.
+
+use crate::command::{self, CommandKind};
+use crate::diagnostics::Outcome;
+use crate::failure::Failure;
+use crate::process::MaudeProcess;
+use crate::reply;
+use crate::term::{self, Term};
+use rustler::{Atom, Decoder, Encoder, Env, LocalPid, NifMap, NifResult, ResourceArc};
+
+rustler::atoms! {
+    bound,
+    extension,
+    whole,
+}
+
+/// Printed when an incomplete unification algorithm was used.
+const MISSED: &str = "may have been missed";
+
+/// Options for `match/5`, decoded from a keyword list.
+///
+/// * `:bound` - Maximum number of matchers to compute (default: all)
+/// * `:extension` - Match with extension (`xmatch`), reporting the part of
+///   the subject each matcher covers (default: `false`)
+#[derive(Debug, Default)]
+pub struct MatchOptions {
+    pub bound: Option<u64>,
+    pub extension: bool,
+}
+
+impl<'a> Decoder<'a> for MatchOptions {
+    fn decode(term: rustler::Term<'a>) -> NifResult<Self> {
+        let mut options = MatchOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, rustler::Term<'a>)>>()? {
+            if key == bound() {
+                options.bound = Some(value.decode()?);
+            } else if key == extension() {
+                options.extension = value.decode()?;
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Part of the subject an `xmatch` matcher covers.
+#[derive(Debug)]
+pub enum Portion {
+    Whole,
+    Term(Term),
+}
+
+impl Encoder for Portion {
+    fn encode<'a>(&self, env: Env<'a>) -> rustler::Term<'a> {
+        match self {
+            Portion::Whole => whole().encode(env),
+            Portion::Term(term) => term.encode(env),
+        }
+    }
+}
+
+/// One matcher of a pattern against a subject.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Matcher {
+    /// Matcher number, counted from 1.
+    pub number: u64,
+    /// Variable bindings as `{"X", term}`, in Maude's order.
+    pub substitution: Vec<(String, Term)>,
+    /// For `xmatch`, `:whole` or the matched subterm; `nil` for `match`.
+    pub context: Option<Portion>,
+}
+
+/// Result of `match/4`.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Matchers {
+    pub matches: Vec<Matcher>,
+    /// Whether these are all the matchers, rather than a set cut short by
+    /// the bound or by an incomplete unification algorithm.
+    pub complete: bool,
+}
+
+/// Parse every `Matcher N` block in a `match` or `xmatch` output.
+pub fn parse_matchers(output: &str) -> Result<Vec<Matcher>, String> {
+    let mut matchers: Vec<Matcher> = Vec::new();
+
+    for line in output.lines().map(str::trim) {
+        if let Some(number) = line.strip_prefix("Matcher ") {
+            matchers.push(Matcher {
+                number: number
+                    .parse()
+                    .map_err(|_| format!("invalid matcher header: {:?}", line))?,
+                substitution: Vec::new(),
+                context: None,
+            });
+        } else if let Some(portion) = line.strip_prefix("Matched portion = ") {
+            let matcher = matchers
+                .last_mut()
+                .ok_or_else(|| format!("portion outside a matcher: {:?}", line))?;
+            matcher.context = Some(match portion {
+                "(whole)" => Portion::Whole,
+                portion => Portion::Term(term::parse(portion)?),
+            });
+        } else if let Some((variable, value)) = line.split_once(" --> ") {
+            let matcher = matchers
+                .last_mut()
+                .ok_or_else(|| format!("binding outside a matcher: {:?}", line))?;
+            matcher
+                .substitution
+                .push((variable.to_string(), term::parse(value)?));
+        }
+    }
+
+    Ok(matchers)
+}
+
+fn run(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    module: &str,
+    pattern: &str,
+    subject: &str,
+    options: &MatchOptions,
+) -> Result<Matchers, Failure> {
+    if options.bound == Some(0) {
+        return Err("bound must be positive".into());
+    }
+
+    let kind = if options.extension {
+        CommandKind::Xmatch
+    } else {
+        CommandKind::Match
+    };
+    let problem = format!("{} <=? {}", pattern.trim(), subject.trim());
+    let command = command::build_bounded(kind, options.bound, module, &problem)
+        .map_err(|e| format!("rejected command: {}", e))?;
+
+    let exchange = process.begin_by(caller)?;
+    let output = exchange.execute(&command)?;
+    let diagnostics = exchange.take_stderr()?;
+    drop(exchange);
+
+    let matches = parse_matchers(&output).map_err(|e| format!("parse failed: {}", e))?;
+    let complete = !output.contains(MISSED)
+        && !diagnostics.contains(MISSED)
+        && options.bound.is_none_or(|n| (matches.len() as u64) < n);
+
+    Ok(Matchers { matches, complete })
+}
+
+/// Run `match in <module> : <pattern> <=? <subject> .` and parse the matchers.
+///
+/// The pattern and subject are validated like the term of
+/// `execute_term/4`, so they may be untrusted. A condition can follow the
+/// subject as `such that <condition>`.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `module` - Module to match in
+/// * `pattern` - Pattern term, e.g. `f(X, Y)`
+/// * `subject` - Subject term, e.g. `f(a, b)`
+///
+/// # Returns
+/// * `Ok(Matchers)` - `%{matches: [%{number, substitution, context}], complete}`
+/// * `Err` - If the input is rejected, I/O fails, or a matcher can't be parsed
+#[rustler::nif(schedule = "DirtyCpu", name = "match")]
+fn match_term(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    pattern: String,
+    subject: String,
+) -> NifResult<Outcome<Matchers>> {
+    reply(run(
+        &process,
+        env.pid(),
+        &module,
+        &pattern,
+        &subject,
+        &MatchOptions::default(),
+    ))
+}
+
+/// `match/4` with options; see [`MatchOptions`].
+#[rustler::nif(schedule = "DirtyCpu", name = "match")]
+fn match_term_with_opts(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    pattern: String,
+    subject: String,
+    opts: MatchOptions,
+) -> NifResult<Outcome<Matchers>> {
+    reply(run(&process, env.pid(), &module, &pattern, &subject, &opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(variable: &str, value: &str) -> (String, Term) {
+        (variable.to_string(), term::parse(value).unwrap())
+    }
+
+    #[test]
+    fn parses_every_matcher() {
+        let output = "\
+match in NAT : N:Nat + M:Nat <=? 1 + 2 .
+Decision time: 0ms cpu (0ms real)
+
+Matcher 1
+N:Nat --> 1
+M:Nat --> 2
+
+Matcher 2
+N:Nat --> 2
+M:Nat --> 1";
+        let matchers = parse_matchers(output).unwrap();
+
+        assert_eq!(matchers.len(), 2);
+        assert_eq!(matchers[0].number, 1);
+        assert_eq!(
+            matchers[0].substitution,
+            vec![binding("N:Nat", "1"), binding("M:Nat", "2")]
+        );
+        assert_eq!(matchers[1].number, 2);
+        assert_eq!(
+            matchers[1].substitution,
+            vec![binding("N:Nat", "2"), binding("M:Nat", "1")]
+        );
+        assert!(matchers.iter().all(|matcher| matcher.context.is_none()));
+    }
+
+    #[test]
+    fn parses_no_match() {
+        let output = "\
+match in NAT : s(N:Nat) <=? 0 .
+Decision time: 0ms cpu (0ms real)
+
+No match.";
+
+        assert!(parse_matchers(output).unwrap().is_empty());
+    }
+
+    #[test]
+    fn parses_the_matched_portion_of_an_xmatch() {
+        let output = "\
+xmatch in NAT : N:Nat + M:Nat <=? 1 + 2 + 3 .
+Decision time: 0ms cpu (0ms real)
+
+Matcher 1
+Matched portion = (whole)
+N:Nat --> 1
+M:Nat --> 2 + 3
+
+Matcher 2
+Matched portion = 1 + 2
+N:Nat --> 1
+M:Nat --> 2";
+        let matchers = parse_matchers(output).unwrap();
+
+        assert_eq!(matchers.len(), 2);
+        assert!(matches!(matchers[0].context, Some(Portion::Whole)));
+        assert_eq!(
+            matchers[0].substitution,
+            vec![binding("N:Nat", "1"), binding("M:Nat", "2 + 3")]
+        );
+        match &matchers[1].context {
+            Some(Portion::Term(portion)) => {
+                assert_eq!(portion, &term::parse("1 + 2").unwrap())
+            }
+            other => panic!("expected a portion, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_matchers() {
+        assert!(parse_matchers("Matcher one\nN:Nat --> 1").is_err());
+        assert!(parse_matchers("Matched portion = (whole)").is_err());
+        assert!(parse_matchers("N:Nat --> 1").is_err());
+    }
+}
@@ -0,0 +1,649 @@
+//! Processes answering from a transcript instead of running Maude.
+//!
+//! `start_mock/1` returns a handle like `start/1` does, but no Maude runs
+//! behind it: a thread reads the commands written to it and answers each
+//! from a transcript of canned responses, printing the prompt after each
+//! just as Maude would. Every NIF that talks to a process works on it the
+//! same way, so code built on ExMaude can be tested without Maude
+//! installed.
+//!
+//! A transcript maps requests to responses, as a map or as a list of
+//! `{request, response}` pairs:
+//!
+//! ```elixir
+//! [
+//!   {"reduce in NAT : 1 + 1 .", "reduce in NAT : 1 + 1 .\nresult NzNat: 2"},
+//!   {"load missing.maude", {"", "Warning: couldn't open file missing.maude."}}
+//! ]
+//! ```
+//!
+//! A response is what Maude prints to stdout, or a pair of that and what
+//! it prints to stderr. Requests are matched with whitespace collapsed and
+//! the final period left out, so `"red 1 + 1"` matches `red 1 + 1 .`. A
+//! request listed several times is answered with each response in
+//! turn, the last one repeating.
+//!
+//! Requests are split much as Maude splits its input: a module or view
+//! definition ends with its `end...` keyword, `load`, `cd`, `quit` and
+//! the like with their line, and anything else with a line ending in `.`.
+//! Module definitions that aren't in the transcript are accepted silently,
+//! as Maude would, and `quit` ends the process. `select` of a module that
+//! isn't answers with Maude's "no module" warning, which load markers and
+//! `sequence_check` rely on. Any other request not found gets a warning
+//! instead, on stderr, naming it.
+//!
+//! The mock has no OS process: `os_pid/1` and signals fail on it. It needs
+//! pipes with `poll`, and so isn't available on Windows.
+
+use crate::error;
+use crate::process::MaudeProcess;
+#[cfg(unix)]
+use crate::process::PROMPT;
+use rustler::types::map::MapIterator;
+use rustler::{Decoder, NifResult, ResourceArc, Term};
+use std::collections::{HashMap, VecDeque};
+#[cfg(unix)]
+use std::io::{ErrorKind, PipeReader, PipeWriter, Read, Write};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, OwnedFd};
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, ExitStatus};
+#[cfg(unix)]
+use std::process::{ChildStderr, ChildStdin, ChildStdout};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::sync::Arc;
+#[cfg(unix)]
+use std::thread::JoinHandle;
+
+/// Keywords starting a module or view definition.
+#[cfg(unix)]
+const DEFINITIONS: &[&str] = &[
+    "fmod", "mod", "fth", "th", "smod", "sth", "omod", "oth", "view",
+];
+
+/// Commands that end with their line rather than with a period.
+#[cfg(unix)]
+const ONE_LINE: &[&str] = &[
+    "load", "sload", "in", "cd", "pwd", "ls", "push", "pop", "quit", "q", "eof",
+];
+
+/// One canned response.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(unix), allow(dead_code))]
+struct Reply {
+    output: String,
+    diagnostics: String,
+}
+
+impl<'a> Decoder<'a> for Reply {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        if let Ok(output) = term.decode::<String>() {
+            return Ok(Reply {
+                output,
+                diagnostics: String::new(),
+            });
+        }
+        let (output, diagnostics) = term.decode::<(String, String)>()?;
+        Ok(Reply {
+            output,
+            diagnostics,
+        })
+    }
+}
+
+/// Requests and their responses, in the order each is given.
+#[derive(Debug, Default)]
+pub struct Transcript {
+    replies: HashMap<String, VecDeque<Reply>>,
+}
+
+impl Transcript {
+    fn add(&mut self, request: &str, reply: Reply) {
+        self.replies
+            .entry(key(request))
+            .or_default()
+            .push_back(reply);
+    }
+
+    /// The response to `request`, using it up unless it's the last one.
+    #[cfg(unix)]
+    fn answer(&mut self, request: &str) -> Option<Reply> {
+        let replies = self.replies.get_mut(&key(request))?;
+        if replies.len() > 1 {
+            replies.pop_front()
+        } else {
+            replies.front().cloned()
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for Transcript {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut transcript = Transcript::default();
+
+        if let Some(entries) = MapIterator::new(term) {
+            for (request, reply) in entries {
+                transcript.add(&request.decode::<String>()?, reply.decode()?);
+            }
+        } else {
+            for (request, reply) in term.decode::<Vec<(String, Reply)>>()? {
+                transcript.add(&request, reply);
+            }
+        }
+
+        Ok(transcript)
+    }
+}
+
+/// `request` as it is looked up: whitespace collapsed, the final period
+/// dropped.
+fn key(request: &str) -> String {
+    let words: Vec<&str> = request.split_whitespace().collect();
+    let joined = words.join(" ");
+    joined.trim_end_matches('.').trim_end().to_string()
+}
+
+/// Whether the lines of `request` so far make a whole request.
+#[cfg(unix)]
+fn complete(request: &str) -> bool {
+    let Some(keyword) = request.split_whitespace().next() else {
+        return false;
+    };
+
+    if DEFINITIONS.contains(&keyword) {
+        request
+            .split_whitespace()
+            .last()
+            .is_some_and(|word| word.starts_with("end"))
+    } else {
+        ONE_LINE.contains(&keyword) || request.trim_end().ends_with('.')
+    }
+}
+
+/// What stands behind a process: a Maude child, or a mock responder.
+pub enum Host {
+    Child(Child),
+    #[cfg(unix)]
+    Mock(Responder),
+}
+
+impl Host {
+    pub fn try_wait(&mut self) -> std::io::Result<Option<ExitStatus>> {
+        match self {
+            Host::Child(child) => child.try_wait(),
+            #[cfg(unix)]
+            Host::Mock(mock) => Ok(mock.try_wait()),
+        }
+    }
+
+    pub fn kill(&mut self) -> std::io::Result<()> {
+        match self {
+            Host::Child(child) => child.kill(),
+            #[cfg(unix)]
+            Host::Mock(mock) => {
+                mock.kill();
+                Ok(())
+            }
+        }
+    }
+
+    pub fn wait(&mut self) -> std::io::Result<ExitStatus> {
+        match self {
+            Host::Child(child) => child.wait(),
+            #[cfg(unix)]
+            Host::Mock(mock) => Ok(mock.wait()),
+        }
+    }
+
+    /// The OS process id; `None` for a mock, which has none.
+    pub fn id(&self) -> Option<u32> {
+        match self {
+            Host::Child(child) => Some(child.id()),
+            #[cfg(unix)]
+            Host::Mock(_) => None,
+        }
+    }
+}
+
+#[cfg(unix)]
+/// How long the responder waits on a pipe before checking whether it
+/// was killed.
+const POLL_MS: libc::c_int = 50;
+
+#[cfg(unix)]
+/// Handle to a running responder.
+pub struct Responder {
+    thread: Option<JoinHandle<ExitStatus>>,
+    status: Option<ExitStatus>,
+    killed: Arc<AtomicBool>,
+}
+
+#[cfg(unix)]
+/// The pipes a process reads and writes, as if to a child.
+pub struct Pipes {
+    pub stdin: ChildStdin,
+    pub stdout: ChildStdout,
+    pub stderr: ChildStderr,
+}
+
+#[cfg(unix)]
+impl Responder {
+    /// Start answering from `transcript` on a thread of its own.
+    pub fn start(transcript: Transcript) -> std::io::Result<(Responder, Pipes)> {
+        let (requests, stdin) = std::io::pipe()?;
+        let (stdout, output) = std::io::pipe()?;
+        let (stderr, diagnostics) = std::io::pipe()?;
+        set_nonblocking(&output)?;
+        set_nonblocking(&diagnostics)?;
+
+        let killed = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&killed);
+        let thread = std::thread::Builder::new()
+            .name("ex_maude-mock".to_string())
+            .spawn(move || {
+                let mut session = Session {
+                    transcript,
+                    requests,
+                    output,
+                    diagnostics,
+                    killed: flag,
+                    line: 0,
+                };
+                session.run()
+            })?;
+
+        let pipes = Pipes {
+            stdin: OwnedFd::from(stdin).into(),
+            stdout: OwnedFd::from(stdout).into(),
+            stderr: OwnedFd::from(stderr).into(),
+        };
+        let responder = Responder {
+            thread: Some(thread),
+            status: None,
+            killed,
+        };
+        Ok((responder, pipes))
+    }
+
+    pub fn try_wait(&mut self) -> Option<ExitStatus> {
+        if self.thread.as_ref().is_some_and(JoinHandle::is_finished) {
+            return Some(self.wait());
+        }
+        self.status
+    }
+
+    pub fn kill(&mut self) {
+        self.killed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn wait(&mut self) -> ExitStatus {
+        if let Some(thread) = self.thread.take() {
+            let status = thread
+                .join()
+                .unwrap_or_else(|_| ExitStatus::from_raw(libc::SIGABRT));
+            self.status = Some(status);
+        }
+        self.status.unwrap_or_else(|| ExitStatus::from_raw(0))
+    }
+}
+
+#[cfg(unix)]
+/// One responder's state.
+struct Session {
+    transcript: Transcript,
+    requests: PipeReader,
+    output: PipeWriter,
+    diagnostics: PipeWriter,
+    killed: Arc<AtomicBool>,
+    /// Input lines read so far, for warnings.
+    line: usize,
+}
+
+#[cfg(unix)]
+/// Why the responder stopped.
+enum End {
+    Quit,
+    Killed,
+    /// The process closed its end of a pipe.
+    Closed,
+}
+
+#[cfg(unix)]
+impl Session {
+    fn run(&mut self) -> ExitStatus {
+        let end = self.serve();
+        match end {
+            End::Killed => ExitStatus::from_raw(libc::SIGKILL),
+            End::Quit | End::Closed => ExitStatus::from_raw(0),
+        }
+    }
+
+    fn serve(&mut self) -> End {
+        if let Err(end) = self.write(PROMPT.as_bytes(), false) {
+            return end;
+        }
+
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut request = String::new();
+        let mut start = 1;
+        loop {
+            while let Some(newline) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=newline).collect();
+                self.line += 1;
+                let line = String::from_utf8_lossy(&line);
+
+                let trimmed = line.trim_start();
+                if request.is_empty() {
+                    if trimmed.is_empty()
+                        || trimmed.starts_with("***")
+                        || trimmed.starts_with("---")
+                    {
+                        continue;
+                    }
+                    start = self.line;
+                }
+                request.push_str(&line);
+
+                if complete(&request) {
+                    let taken = std::mem::take(&mut request);
+                    if let Err(end) = self.answer(&taken, start) {
+                        return end;
+                    }
+                }
+            }
+
+            match self.read(&mut buffer) {
+                Ok(()) => {}
+                Err(end) => return end,
+            }
+        }
+    }
+
+    /// Answer one whole request, which began on input line `line`.
+    fn answer(&mut self, request: &str, line: usize) -> Result<(), End> {
+        let keyword = request.split_whitespace().next().unwrap_or("");
+        let reply = match self.transcript.answer(request) {
+            Some(reply) => reply,
+            None if matches!(keyword, "quit" | "q" | "eof") => return Err(End::Quit),
+            None if DEFINITIONS.contains(&keyword) => Reply {
+                output: String::new(),
+                diagnostics: String::new(),
+            },
+            None if keyword == "select" => Reply {
+                output: String::new(),
+                diagnostics: format!(
+                    "Warning: <standard input>, line {}: no module {}.\n",
+                    line,
+                    key(request).trim_start_matches("select").trim()
+                ),
+            },
+            None => Reply {
+                output: String::new(),
+                diagnostics: format!(
+                    "Warning: <standard input>, line {}: no response recorded for {:?}.\n",
+                    line,
+                    key(request)
+                ),
+            },
+        };
+
+        // Maude writes a command's warnings before its prompt
+        if !reply.diagnostics.is_empty() {
+            let mut diagnostics = reply.diagnostics;
+            if !diagnostics.ends_with('\n') {
+                diagnostics.push('\n');
+            }
+            self.write(diagnostics.as_bytes(), true)?;
+        }
+        let mut output = reply.output;
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        output.push_str(PROMPT);
+        self.write(output.as_bytes(), false)
+    }
+
+    /// Wait for input and append it to `buffer`.
+    fn read(&mut self, buffer: &mut Vec<u8>) -> Result<(), End> {
+        await_fd(&self.requests, libc::POLLIN, &self.killed)?;
+
+        let mut chunk = [0u8; 8192];
+        match self.requests.read(&mut chunk) {
+            Ok(0) => Err(End::Closed),
+            Ok(n) => {
+                buffer.extend_from_slice(&chunk[..n]);
+                Ok(())
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => Ok(()),
+            Err(_) => Err(End::Closed),
+        }
+    }
+
+    /// Write all of `bytes` to stdout, or to stderr if `diagnostics`,
+    /// giving up if the responder is killed meanwhile.
+    fn write(&mut self, bytes: &[u8], diagnostics: bool) -> Result<(), End> {
+        let pipe = if diagnostics {
+            &mut self.diagnostics
+        } else {
+            &mut self.output
+        };
+
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            match pipe.write(rest) {
+                Ok(0) => return Err(End::Closed),
+                Ok(n) => rest = &rest[n..],
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    await_fd(pipe, libc::POLLOUT, &self.killed)?
+                }
+                Err(_) => return Err(End::Closed),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+/// Wait until `pipe` is ready for `events`, checking `killed` every
+/// [`POLL_MS`].
+fn await_fd(pipe: &impl AsRawFd, events: libc::c_short, killed: &AtomicBool) -> Result<(), End> {
+    let mut fd = libc::pollfd {
+        fd: pipe.as_raw_fd(),
+        events,
+        revents: 0,
+    };
+
+    loop {
+        if killed.load(Ordering::Relaxed) {
+            return Err(End::Killed);
+        }
+        // SAFETY: `fd` points to one valid pollfd for the call.
+        let ready = unsafe { libc::poll(&mut fd, 1, POLL_MS) };
+        if ready > 0 {
+            return Ok(());
+        }
+        if ready < 0 && std::io::Error::last_os_error().kind() != ErrorKind::Interrupted {
+            return Err(End::Closed);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn set_nonblocking(pipe: &impl AsRawFd) -> std::io::Result<()> {
+    let fd = pipe.as_raw_fd();
+
+    // SAFETY: `fd` is an open descriptor owned by `pipe` for this call.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Start a mock process answering from a transcript instead of Maude.
+///
+/// # Arguments
+/// * `transcript` - Map of requests to responses, or a list of
+///   `{request, response}` pairs; a response is a binary of stdout, or
+///   `{stdout, stderr}`
+///
+/// # Returns
+/// * `Ok(ResourceArc<MaudeProcess>)` - Handle to the ready mock process
+/// * `Err` - If the responder can't be started, or on Windows
+#[rustler::nif(schedule = "DirtyIo")]
+fn start_mock(transcript: Transcript) -> NifResult<ResourceArc<MaudeProcess>> {
+    #[cfg(unix)]
+    {
+        MaudeProcess::mock(transcript)
+            .map(ResourceArc::new)
+            .map_err(error)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = transcript;
+        Err(error(
+            "mock processes are not supported on this platform".to_string(),
+        ))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn reply(output: &str, diagnostics: &str) -> Reply {
+        Reply {
+            output: output.to_string(),
+            diagnostics: diagnostics.to_string(),
+        }
+    }
+
+    /// A running responder and its pipes, past the first prompt.
+    fn start(entries: &[(&str, Reply)]) -> (Responder, Pipes) {
+        let mut transcript = Transcript::default();
+        for (request, reply) in entries {
+            transcript.add(request, reply.clone());
+        }
+        let (responder, mut pipes) = Responder::start(transcript).unwrap();
+        assert_eq!(read_to_prompt(&mut pipes), "");
+        (responder, pipes)
+    }
+
+    /// Write `input` and read stdout up to the next prompt.
+    fn send(pipes: &mut Pipes, input: &str) -> String {
+        pipes.stdin.write_all(input.as_bytes()).unwrap();
+        read_to_prompt(pipes)
+    }
+
+    fn read_to_prompt(pipes: &mut Pipes) -> String {
+        let mut output = Vec::new();
+        let mut chunk = [0u8; 1024];
+        while !output.ends_with(PROMPT.as_bytes()) {
+            let n = pipes.stdout.read(&mut chunk).unwrap();
+            assert!(n > 0, "mock closed stdout");
+            output.extend_from_slice(&chunk[..n]);
+        }
+        output.truncate(output.len() - PROMPT.len());
+        String::from_utf8(output).unwrap()
+    }
+
+    /// Read one line of stderr, which the mock writes before the prompt.
+    fn read_diagnostic(pipes: &mut Pipes) -> String {
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+        while !line.ends_with(b"\n") {
+            assert_eq!(pipes.stderr.read(&mut byte).unwrap(), 1);
+            line.push(byte[0]);
+        }
+        String::from_utf8(line).unwrap()
+    }
+
+    #[test]
+    fn looks_up_requests_without_spacing_or_the_final_period() {
+        assert_eq!(key("red   1 +\n 1 ."), "red 1 + 1");
+        assert_eq!(key("red 1 + 1"), "red 1 + 1");
+        assert_eq!(key("  load nat.maude\n"), "load nat.maude");
+    }
+
+    #[test]
+    fn splits_requests_as_maude_does() {
+        assert!(complete("red 1 + 1 .\n"));
+        assert!(!complete("red 1 +\n"));
+        assert!(complete("load nat.maude\n"));
+        assert!(!complete("fmod FOO is\n  sort Foo .\n"));
+        assert!(complete("fmod FOO is\n  sort Foo .\nendfm\n"));
+        assert!(!complete("\n"));
+    }
+
+    #[test]
+    fn answers_from_the_transcript_in_turn() {
+        let (mut responder, mut pipes) = start(&[
+            ("red 1 + 1 .", reply("result NzNat: 2", "")),
+            ("rew c .", reply("result C: d", "")),
+            ("rew c .", reply("result C: e", "")),
+        ]);
+
+        assert_eq!(send(&mut pipes, "red 1 +\n  1 .\n"), "result NzNat: 2\n");
+        assert_eq!(send(&mut pipes, "rew c .\n"), "result C: d\n");
+        assert_eq!(send(&mut pipes, "rew c .\n"), "result C: e\n");
+        // The last response repeats
+        assert_eq!(send(&mut pipes, "rew c .\n"), "result C: e\n");
+
+        responder.kill();
+        assert_eq!(responder.wait().signal(), Some(libc::SIGKILL));
+    }
+
+    #[test]
+    fn writes_recorded_diagnostics_to_stderr() {
+        let (mut responder, mut pipes) = start(&[(
+            "load missing.maude",
+            reply("", "Warning: couldn't open file missing.maude."),
+        )]);
+
+        assert_eq!(send(&mut pipes, "load missing.maude\n"), "");
+        assert_eq!(
+            read_diagnostic(&mut pipes),
+            "Warning: couldn't open file missing.maude.\n"
+        );
+
+        responder.kill();
+        responder.wait();
+    }
+
+    #[test]
+    fn falls_back_for_unrecorded_requests() {
+        let (mut responder, mut pipes) = start(&[]);
+
+        // Definitions are accepted silently, comments skipped
+        assert_eq!(
+            send(
+                &mut pipes,
+                "*** a comment\nfmod FOO is\n  sort Foo .\nendfm\n"
+            ),
+            ""
+        );
+
+        assert_eq!(send(&mut pipes, "select BAR .\n"), "");
+        assert_eq!(
+            read_diagnostic(&mut pipes),
+            "Warning: <standard input>, line 5: no module BAR.\n"
+        );
+
+        assert_eq!(send(&mut pipes, "red 2 * 3 .\n"), "");
+        assert_eq!(
+            read_diagnostic(&mut pipes),
+            "Warning: <standard input>, line 6: no response recorded for \"red 2 * 3\".\n"
+        );
+
+        pipes.stdin.write_all(b"quit\n").unwrap();
+        assert!(responder.wait().success());
+        assert!(responder.try_wait().is_some_and(|status| status.success()));
+    }
+}
@@ -0,0 +1,484 @@
+//! Parsed results of `vu-narrow` and `fvu-narrow`.
+//!
+//! Narrowing-based reachability finds the instances of a term that reach a
+//! pattern with rules marked `[narrowing]`, modulo the module's equational
+//! theory. Each solution shows the state reached, the substitution
+//! accumulated on the initial term's variables, and the variant unifier
+//! that made the state match the pattern:
+//!
+//! ```text
+//! Solution 1
+//! rewrites: 1 in 0ms cpu (0ms real) (~ rewrites/second)
+//! state: c
+//! accumulated substitution:
+//! X --> a
+//! variant unifier:
+//! Y:S --> c
+//! ```
+//!
+//! With tracing on, Maude prints every narrowing step as it takes it, the
+//! ones since the previous solution right before the next `Solution`
+//! header:
+//!
+//! ```text
+//! *********** narrowing step
+//! rl f(a) => c [narrowing] .
+//! Rule variable bindings:
+//! empty substitution
+//! Subject variable bindings:
+//! #1:S --> a
+//! f(#1:S)
+//! --->
+//! c
+//! ```
+//!
+//! Those are all the steps Maude explored, dead ends included, not only
+//! the path to the solution they precede.
+
+use crate::command::{self, CommandKind};
+use crate::diagnostics::Outcome;
+use crate::failure::Failure;
+use crate::process::MaudeProcess;
+use crate::reply;
+use crate::term::{self, Term};
+use crate::trace::{self, TraceOptions, HEADER};
+use rustler::{Atom, Decoder, Env, LocalPid, NifMap, NifResult, ResourceArc};
+
+rustler::atoms! {
+    bound,
+    depth,
+    delay,
+    filter,
+    trace_ = "trace",
+}
+
+/// Options for `vu_narrow/4` and `fvu_narrow/4`, decoded from a keyword
+/// list.
+///
+/// * `:bound` - Maximum number of solutions (default: all)
+/// * `:depth` - Maximum number of narrowing steps (default: unbounded)
+/// * `:delay`, `:filter` - Maude's `{delay, filter}` flags, for
+///   `vu_narrow/4` only (default: `false`)
+/// * `:trace` - Return the narrowing steps with each solution (default:
+///   `false`)
+#[derive(Debug, Default)]
+pub struct NarrowOptions {
+    pub bound: Option<u64>,
+    pub depth: Option<u64>,
+    pub delay: bool,
+    pub filter: bool,
+    pub trace: bool,
+}
+
+impl NarrowOptions {
+    fn flags(&self) -> Vec<&'static str> {
+        [(self.delay, "delay"), (self.filter, "filter")]
+            .into_iter()
+            .filter_map(|(set, flag)| set.then_some(flag))
+            .collect()
+    }
+}
+
+impl<'a> Decoder<'a> for NarrowOptions {
+    fn decode(term: rustler::Term<'a>) -> NifResult<Self> {
+        let mut options = NarrowOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, rustler::Term<'a>)>>()? {
+            if key == bound() {
+                options.bound = Some(value.decode()?);
+            } else if key == depth() {
+                options.depth = Some(value.decode()?);
+            } else if key == delay() {
+                options.delay = value.decode()?;
+            } else if key == filter() {
+                options.filter = value.decode()?;
+            } else if key == trace_() {
+                options.trace = value.decode()?;
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// One narrowing step from the trace.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Step {
+    /// The rule applied, e.g. `"rl f(a) => c [narrowing] ."`.
+    pub rule: String,
+    /// Rule label, if it has one.
+    pub label: Option<String>,
+    /// Bindings of the rule's variables as `{"X", term}` pairs.
+    pub rule_substitution: Vec<(String, String)>,
+    /// Bindings of the narrowed term's variables.
+    pub subject_substitution: Vec<(String, String)>,
+    /// Subterm that was narrowed.
+    pub redex: String,
+    /// What the redex was narrowed to.
+    pub contractum: String,
+}
+
+/// One solution of a narrowing search.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Solution {
+    /// Solution number, counted from 1.
+    pub number: u64,
+    /// The state that unified with the pattern.
+    pub state: Term,
+    /// Accumulated substitution as `{"X", term}`, in Maude's order.
+    pub substitution: Vec<(String, Term)>,
+    /// Variant unifier of the state and the pattern.
+    pub unifier: Vec<(String, Term)>,
+    /// Steps taken since the previous solution; empty unless traced.
+    pub steps: Vec<Step>,
+}
+
+/// Which binding list the lines of a solution go to.
+enum Section {
+    Preamble,
+    Substitution,
+    Unifier,
+}
+
+/// Parse every `Solution N` block in a narrowing output, collecting the
+/// traced steps before each.
+pub fn parse_solutions(output: &str) -> Result<Vec<Solution>, String> {
+    let mut solutions: Vec<Solution> = Vec::new();
+    let mut steps: Vec<Step> = Vec::new();
+    // Number of the solution whose `state:` line is still to come
+    let mut pending: Option<u64> = None;
+    let mut section = Section::Preamble;
+    let mut lines = output.lines().map(str::trim).peekable();
+
+    while let Some(line) = lines.next() {
+        if let Some(header) = line.strip_prefix(HEADER) {
+            // Block body runs until the next header or the end of the steps
+            let mut body = Vec::new();
+            while let Some(next) = lines.peek() {
+                if next.is_empty() || next.starts_with(HEADER) || next.starts_with("Solution ") {
+                    break;
+                }
+                body.extend(lines.next());
+            }
+            if header == "narrowing step" {
+                steps.extend(parse_step(&body));
+            }
+        } else if let Some(number) = line.strip_prefix("Solution ") {
+            if let Some(number) = pending {
+                return Err(format!("no state in solution {}", number));
+            }
+            pending = Some(
+                number
+                    .parse()
+                    .map_err(|_| format!("invalid solution header: {:?}", line))?,
+            );
+            section = Section::Preamble;
+        } else if let Some(state) = line.strip_prefix("state: ") {
+            let number = pending
+                .take()
+                .ok_or_else(|| format!("state outside a solution: {:?}", line))?;
+            solutions.push(Solution {
+                number,
+                state: term::parse(state)?,
+                substitution: Vec::new(),
+                unifier: Vec::new(),
+                steps: std::mem::take(&mut steps),
+            });
+        } else if line == "accumulated substitution:" {
+            section = Section::Substitution;
+        } else if line == "variant unifier:" {
+            section = Section::Unifier;
+        } else if let Some((variable, value)) = line.split_once(" --> ") {
+            let binding = (variable.to_string(), term::parse(value)?);
+            match (&section, solutions.last_mut()) {
+                (Section::Substitution, Some(solution)) => solution.substitution.push(binding),
+                (Section::Unifier, Some(solution)) => solution.unifier.push(binding),
+                _ => return Err(format!("binding outside a solution: {:?}", line)),
+            }
+        }
+    }
+
+    match pending {
+        Some(number) => Err(format!("no state in solution {}", number)),
+        None => Ok(solutions),
+    }
+}
+
+fn parse_step(body: &[&str]) -> Option<Step> {
+    let (rule, lines) = body.split_first()?;
+    let arrow = lines.iter().position(|line| *line == "--->")?;
+    let (redex, head) = lines[..arrow].split_last()?;
+
+    // Rule bindings come first, then the subject's
+    let subject = head
+        .iter()
+        .position(|line| *line == "Subject variable bindings:")?;
+    let bindings = |lines: &[&str]| {
+        lines
+            .iter()
+            .filter_map(|line| line.split_once(" --> "))
+            .map(|(variable, value)| (variable.to_string(), value.to_string()))
+            .collect()
+    };
+
+    Some(Step {
+        rule: rule.to_string(),
+        label: trace::statement_label(rule),
+        rule_substitution: bindings(&head[..subject]),
+        subject_substitution: bindings(&head[subject + 1..]),
+        redex: redex.to_string(),
+        contractum: lines.get(arrow + 1)?.to_string(),
+    })
+}
+
+fn run(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    kind: CommandKind,
+    module: &str,
+    query: &str,
+    options: &NarrowOptions,
+) -> Result<Vec<Solution>, Failure> {
+    if options.bound == Some(0) {
+        return Err("bound must be positive".into());
+    }
+
+    let command = command::build_limited(
+        kind,
+        &options.flags(),
+        options.bound,
+        options.depth,
+        module,
+        query,
+    )
+    .map_err(|e| format!("rejected command: {}", e))?;
+
+    // One exchange, so the diagnostics are this command's
+    let exchange = process.begin_by(caller)?;
+    let output = if options.trace {
+        trace::execute_raw(&exchange, &[command.as_bytes()], &TraceOptions::default())
+    } else {
+        exchange.execute(&command)
+    }?;
+    let diagnostics = exchange.take_stderr()?;
+    drop(exchange);
+
+    if diagnostics.contains("Warning:") || diagnostics.contains("Error:") {
+        return Err(format!("narrowing failed: {}", diagnostics.trim()).into());
+    }
+
+    parse_solutions(&output).map_err(|e| format!("parse failed: {}", e).into())
+}
+
+/// Run `vu-narrow in <module> : <query> .` and parse the solutions.
+///
+/// The query is validated like the term of `execute_term/4`, e.g.
+/// `f(X) =>* Y:S`, with any of the arrows `=>1`, `=>+`, `=>*`, and `=>!`.
+/// Narrowing need not terminate; use `vu_narrow/4` with `:bound` or
+/// `:depth` unless the search space is known to be finite.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `module` - Module to narrow in
+/// * `query` - Initial term, arrow, and pattern
+///
+/// # Returns
+/// * `Ok([Solution])` - `%{number, state, substitution, unifier, steps}`
+///   maps, empty if there is no solution
+/// * `Err` - If the input is rejected, Maude reports a warning, I/O fails,
+///   or a solution can't be parsed
+#[rustler::nif(schedule = "DirtyCpu")]
+fn vu_narrow(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    query: String,
+) -> NifResult<Outcome<Vec<Solution>>> {
+    reply(run(
+        &process,
+        env.pid(),
+        CommandKind::VuNarrow,
+        &module,
+        &query,
+        &NarrowOptions::default(),
+    ))
+}
+
+/// `vu_narrow/3` with options; see [`NarrowOptions`].
+#[rustler::nif(schedule = "DirtyCpu", name = "vu_narrow")]
+fn vu_narrow_with_opts(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    query: String,
+    opts: NarrowOptions,
+) -> NifResult<Outcome<Vec<Solution>>> {
+    reply(run(
+        &process,
+        env.pid(),
+        CommandKind::VuNarrow,
+        &module,
+        &query,
+        &opts,
+    ))
+}
+
+/// Run `fvu-narrow in <module> : <query> .` and parse the solutions.
+///
+/// Like `vu_narrow/3`, but Maude folds states subsumed by earlier ones, so
+/// the search terminates on more theories.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `module` - Module to narrow in
+/// * `query` - Initial term, arrow, and pattern
+///
+/// # Returns
+/// * As for `vu_narrow/3`
+#[rustler::nif(schedule = "DirtyCpu")]
+fn fvu_narrow(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    query: String,
+) -> NifResult<Outcome<Vec<Solution>>> {
+    reply(run(
+        &process,
+        env.pid(),
+        CommandKind::FvuNarrow,
+        &module,
+        &query,
+        &NarrowOptions::default(),
+    ))
+}
+
+/// `fvu_narrow/3` with options; see [`NarrowOptions`].
+#[rustler::nif(schedule = "DirtyCpu", name = "fvu_narrow")]
+fn fvu_narrow_with_opts(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    query: String,
+    opts: NarrowOptions,
+) -> NifResult<Outcome<Vec<Solution>>> {
+    reply(run(
+        &process,
+        env.pid(),
+        CommandKind::FvuNarrow,
+        &module,
+        &query,
+        &opts,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(variable: &str, value: &str) -> (String, Term) {
+        (variable.to_string(), term::parse(value).unwrap())
+    }
+
+    #[test]
+    fn parses_every_solution() {
+        let output = "\
+vu-narrow in EX : f(X:S) =>* Y:S .
+
+Solution 1
+rewrites: 1 in 0ms cpu (0ms real) (~ rewrites/second)
+state: f(#1:S)
+accumulated substitution:
+X:S --> #1:S
+variant unifier:
+Y:S --> f(%1:S)
+#1:S --> %1:S
+
+Solution 2
+rewrites: 2 in 0ms cpu (0ms real) (~ rewrites/second)
+state: c
+accumulated substitution:
+X:S --> a
+variant unifier:
+Y:S --> c
+
+No more solutions.
+rewrites: 2 in 0ms cpu (0ms real) (~ rewrites/second)";
+        let solutions = parse_solutions(output).unwrap();
+
+        assert_eq!(solutions.len(), 2);
+
+        assert_eq!(solutions[0].number, 1);
+        assert_eq!(solutions[0].state, term::parse("f(#1:S)").unwrap());
+        assert_eq!(solutions[0].substitution, [binding("X:S", "#1:S")]);
+        assert_eq!(
+            solutions[0].unifier,
+            [binding("Y:S", "f(%1:S)"), binding("#1:S", "%1:S")]
+        );
+
+        assert_eq!(solutions[1].number, 2);
+        assert_eq!(solutions[1].state, term::parse("c").unwrap());
+        assert_eq!(solutions[1].substitution, [binding("X:S", "a")]);
+        assert_eq!(solutions[1].unifier, [binding("Y:S", "c")]);
+        assert!(solutions.iter().all(|solution| solution.steps.is_empty()));
+    }
+
+    #[test]
+    fn parses_no_solution() {
+        let output = "\
+vu-narrow in EX : c =>+ Y:S .
+
+No solution.
+rewrites: 0 in 0ms cpu (0ms real) (~ rewrites/second)";
+
+        assert!(parse_solutions(output).unwrap().is_empty());
+    }
+
+    #[test]
+    fn collects_the_steps_before_each_solution() {
+        let output = "\
+vu-narrow [1] in EX : f(X:S) =>1 Y:S .
+*********** narrowing step
+rl [r1] : f(a) => c [narrowing] .
+Rule variable bindings:
+empty substitution
+Subject variable bindings:
+X:S --> a
+f(X:S)
+--->
+c
+
+Solution 1
+rewrites: 1 in 0ms cpu (0ms real) (~ rewrites/second)
+state: c
+accumulated substitution:
+X:S --> a
+variant unifier:
+Y:S --> c";
+        let solutions = parse_solutions(output).unwrap();
+
+        assert_eq!(solutions.len(), 1);
+        let step = &solutions[0].steps[0];
+        assert_eq!(solutions[0].steps.len(), 1);
+        assert_eq!(step.rule, "rl [r1] : f(a) => c [narrowing] .");
+        assert_eq!(step.label.as_deref(), Some("r1"));
+        assert!(step.rule_substitution.is_empty());
+        assert_eq!(
+            step.subject_substitution,
+            [("X:S".to_string(), "a".to_string())]
+        );
+        assert_eq!(
+            (step.redex.as_str(), step.contractum.as_str()),
+            ("f(X:S)", "c")
+        );
+    }
+
+    #[test]
+    fn rejects_solutions_without_a_state() {
+        assert!(parse_solutions("Solution 1\nSolution 2\nstate: c").is_err());
+        assert!(parse_solutions("Solution 1\naccumulated substitution:").is_err());
+        assert!(parse_solutions("state: c").is_err());
+        assert!(parse_solutions("variant unifier:\nY:S --> c").is_err());
+    }
+}
@@ -0,0 +1,303 @@
+//! Options accepted when spawning a Maude subprocess.
+
+use crate::error;
+use crate::format::Trim;
+use crate::limits::Limits;
+use rustler::{Atom, Decoder, NifMap, NifResult, Term};
+use std::path::PathBuf;
+use std::time::Duration;
+
+rustler::atoms! {
+    args,
+    preload,
+    spill_threshold,
+    spill_dir,
+    startup_timeout,
+    trim,
+    lock_wait_threshold,
+    max_memory,
+    max_cpu_seconds,
+    select,
+    pipeline_depth,
+    cache_size,
+    full_maude,
+    history_size,
+    read_buffer_size,
+    max_line_length,
+    read_only,
+    max_queue,
+    sequence_check,
+    wire_log,
+    sandbox,
+    inherit,
+}
+
+/// Flags used when the caller doesn't pass `:args`.
+const DEFAULT_ARGS: &[&str] = &["-no-banner", "-no-wrap", "-no-advise"];
+
+/// Output size beyond which a response is written to a file (64 MiB).
+const DEFAULT_SPILL_THRESHOLD: usize = 64 * 1024 * 1024;
+
+/// Capacity of the buffer stdout is read through (8 KiB, as `BufReader`'s).
+const DEFAULT_READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Length of a line beyond which the rest of a response is read in chunks
+/// (1 MiB).
+const DEFAULT_MAX_LINE_LENGTH: usize = 1024 * 1024;
+
+/// How long a new process may take to show its first prompt.
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Lock waits longer than this count as slow under `lock-watch`.
+const DEFAULT_LOCK_WAIT_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Spawn options, decoded from an Elixir keyword list.
+///
+/// * `:args` - Command-line flags for Maude (default: `-no-banner -no-wrap
+///   -no-advise`). `-interactive` is always added since prompt detection
+///   depends on it.
+/// * `:preload` - Maude files to `load` once the first prompt appears.
+/// * `:select` - Module to select once the preload files are loaded; see
+///   [`crate::selection`]
+/// * `:spill_threshold` - Bytes of output to hold in memory before the rest
+///   of a response is written to a file instead (default: 64 MiB)
+/// * `:spill_dir` - Directory for spilled responses (default: the system
+///   temp directory)
+/// * `:startup_timeout` - Milliseconds to wait for the first prompt before
+///   giving up with `:banner_timeout` (default: `10000`)
+/// * `:trim` - Whitespace removed from around each response: `:both`,
+///   `:trailing`, or `:none` (default: `:both`); execute calls can
+///   override it with their own `:trim` option
+/// * `:lock_wait_threshold` - Milliseconds after which a wait for one of
+///   the process's locks counts as slow in `stats/1` (default: `1000`);
+///   only measured when the NIF is built with the `lock-watch` feature
+/// * `:max_memory` - Bytes of address space Maude may use (Unix only;
+///   default: unlimited); see [`crate::limits`]
+/// * `:max_cpu_seconds` - Seconds of CPU time Maude may use over its whole
+///   life (Unix only; default: unlimited)
+/// * `:pipeline_depth` - Commands `submit/2` may have outstanding at once
+///   (default: `0`, which leaves pipelining off); see [`crate::pipeline`]
+/// * `:cache_size` - Responses to `reduce` and `search` the execute calls
+///   keep for repeats (default: `0`, which leaves caching off); see
+///   [`crate::cache`]
+/// * `:full_maude` - Path of `full-maude.maude`, loaded before the preload
+///   files to run the execute calls through Full Maude (default: none); see
+///   [`crate::full_maude`]
+/// * `:history_size` - Commands kept, with their responses, for `history/2`
+///   and `export_transcript/2` (default: `0`, which keeps none); see
+///   [`crate::history`]
+/// * `:read_buffer_size` - Bytes read from Maude's stdout at a time
+///   (default: 8 KiB); larger buffers take fewer reads on big outputs
+/// * `:max_line_length` - Bytes a line may run to before the rest of the
+///   response is read in chunks, joined once it is complete, rather than
+///   into one growing buffer (default: 1 MiB). Huge terms printed with
+///   `-no-wrap` come out as a single line of tens of megabytes, and
+///   regrowing the buffer for one means copying it over and over.
+/// * `:read_only` - Refuse commands that would change the modules,
+///   selection, or settings once the process is set up (default: `false`);
+///   see [`crate::readonly`]
+/// * `:max_queue` - Callers that may wait for their turn while another
+///   has it; beyond that execute calls fail with `{:error, :overloaded}`
+///   (default: unbounded); see [`crate::backpressure`]
+/// * `:sequence_check` - Tag each command with a sentinel and return
+///   `{:error, :desync, details}` when a response turns out not to be
+///   its own, at the cost of a second prompt per command (default:
+///   `false`); see [`crate::sequence`]
+/// * `:wire_log` - File every byte written to and read from Maude is
+///   appended to, with timestamps (default: none); see [`crate::wire`]
+/// * `:sandbox` - Directory Maude runs in and `load` is confined to once
+///   the process is set up (default: none); see [`crate::sandbox`]
+///
+/// Unknown keys are ignored.
+#[derive(Debug, Clone)]
+pub struct SpawnOptions {
+    pub args: Vec<String>,
+    pub preload: Vec<String>,
+    pub select: Option<String>,
+    pub spill_threshold: usize,
+    pub spill_dir: PathBuf,
+    pub startup_timeout: Duration,
+    pub trim: Trim,
+    pub lock_wait_threshold: Duration,
+    pub limits: Limits,
+    pub pipeline_depth: usize,
+    pub cache_size: usize,
+    pub full_maude: Option<String>,
+    pub history_size: usize,
+    pub read_buffer_size: usize,
+    pub max_line_length: usize,
+    pub read_only: bool,
+    pub max_queue: Option<u64>,
+    pub sequence_check: bool,
+    pub wire_log: Option<PathBuf>,
+    pub sandbox: Option<PathBuf>,
+}
+
+impl Default for SpawnOptions {
+    fn default() -> Self {
+        SpawnOptions {
+            args: DEFAULT_ARGS.iter().map(|arg| arg.to_string()).collect(),
+            preload: Vec::new(),
+            select: None,
+            spill_threshold: DEFAULT_SPILL_THRESHOLD,
+            spill_dir: std::env::temp_dir(),
+            startup_timeout: DEFAULT_STARTUP_TIMEOUT,
+            trim: Trim::default(),
+            lock_wait_threshold: DEFAULT_LOCK_WAIT_THRESHOLD,
+            limits: Limits::default(),
+            pipeline_depth: 0,
+            cache_size: 0,
+            full_maude: None,
+            history_size: 0,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            read_only: false,
+            max_queue: None,
+            sequence_check: false,
+            wire_log: None,
+            sandbox: None,
+        }
+    }
+}
+
+/// Report returned by `config/1`: how a process was launched, with every
+/// default filled in.
+#[derive(Debug, Clone, NifMap)]
+pub struct EffectiveConfig {
+    pub maude_path: String,
+    /// Arguments exactly as passed to the executable, `-interactive`
+    /// included.
+    pub args: Vec<String>,
+    pub preload: Vec<String>,
+    pub select: Option<String>,
+    /// How the child's environment was set up: always `:inherit`, the
+    /// BEAM's environment unchanged.
+    pub env: Atom,
+    pub os_pid: u32,
+    pub prompt: String,
+    pub trim: Trim,
+    pub startup_timeout: u64,
+    pub lock_wait_threshold: u64,
+    pub spill_threshold: usize,
+    pub spill_dir: String,
+    /// `nil` when unlimited.
+    pub max_memory: Option<u64>,
+    pub max_cpu_seconds: Option<u64>,
+    pub pipeline_depth: usize,
+    pub cache_size: usize,
+    pub full_maude: Option<String>,
+    pub history_size: usize,
+    pub read_buffer_size: usize,
+    pub max_line_length: usize,
+    pub read_only: bool,
+    pub max_queue: Option<u64>,
+    pub sequence_check: bool,
+    pub wire_log: Option<String>,
+    pub sandbox: Option<String>,
+}
+
+impl SpawnOptions {
+    /// The configuration a child launched from `maude_path` with these
+    /// options runs under.
+    pub fn effective(&self, maude_path: &str, os_pid: u32) -> EffectiveConfig {
+        EffectiveConfig {
+            maude_path: maude_path.to_string(),
+            args: self.command_args(),
+            preload: self.preload.clone(),
+            select: self.select.clone(),
+            env: inherit(),
+            os_pid,
+            prompt: crate::process::PROMPT.to_string(),
+            trim: self.trim,
+            startup_timeout: self.startup_timeout.as_millis() as u64,
+            lock_wait_threshold: self.lock_wait_threshold.as_millis() as u64,
+            spill_threshold: self.spill_threshold,
+            spill_dir: self.spill_dir.to_string_lossy().into_owned(),
+            max_memory: self.limits.memory,
+            max_cpu_seconds: self.limits.cpu_seconds,
+            pipeline_depth: self.pipeline_depth,
+            cache_size: self.cache_size,
+            full_maude: self.full_maude.clone(),
+            history_size: self.history_size,
+            read_buffer_size: self.read_buffer_size,
+            max_line_length: self.max_line_length,
+            read_only: self.read_only,
+            max_queue: self.max_queue,
+            sequence_check: self.sequence_check,
+            wire_log: self
+                .wire_log
+                .as_ref()
+                .map(|path| path.to_string_lossy().into_owned()),
+            sandbox: self
+                .sandbox
+                .as_ref()
+                .map(|dir| dir.to_string_lossy().into_owned()),
+        }
+    }
+
+    /// Full argument list passed to the Maude executable.
+    pub fn command_args(&self) -> Vec<String> {
+        let mut command_args = self.args.clone();
+        if !command_args.iter().any(|arg| arg == "-interactive") {
+            command_args.push("-interactive".to_string());
+        }
+        command_args
+    }
+}
+
+impl<'a> Decoder<'a> for SpawnOptions {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut options = SpawnOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, Term<'a>)>>()? {
+            if key == args() {
+                options.args = value.decode()?;
+            } else if key == preload() {
+                options.preload = value.decode()?;
+            } else if key == select() {
+                options.select = Some(value.decode()?);
+            } else if key == spill_threshold() {
+                options.spill_threshold = value.decode()?;
+            } else if key == spill_dir() {
+                options.spill_dir = PathBuf::from(value.decode::<String>()?);
+            } else if key == startup_timeout() {
+                options.startup_timeout = Duration::from_millis(value.decode()?);
+            } else if key == trim() {
+                options.trim = value.decode()?;
+            } else if key == lock_wait_threshold() {
+                options.lock_wait_threshold = Duration::from_millis(value.decode()?);
+            } else if key == max_memory() {
+                options.limits.memory = Some(value.decode()?);
+            } else if key == max_cpu_seconds() {
+                options.limits.cpu_seconds = Some(value.decode()?);
+            } else if key == pipeline_depth() {
+                options.pipeline_depth = value.decode()?;
+            } else if key == cache_size() {
+                options.cache_size = value.decode()?;
+            } else if key == full_maude() {
+                options.full_maude = Some(value.decode()?);
+            } else if key == history_size() {
+                options.history_size = value.decode()?;
+            } else if key == read_buffer_size() {
+                options.read_buffer_size = value.decode()?;
+                if options.read_buffer_size == 0 {
+                    return Err(error("read_buffer_size must be positive"));
+                }
+            } else if key == max_line_length() {
+                options.max_line_length = value.decode()?;
+            } else if key == read_only() {
+                options.read_only = value.decode()?;
+            } else if key == max_queue() {
+                options.max_queue = Some(value.decode()?);
+            } else if key == sequence_check() {
+                options.sequence_check = value.decode()?;
+            } else if key == wire_log() {
+                options.wire_log = Some(PathBuf::from(value.decode::<String>()?));
+            } else if key == sandbox() {
+                options.sandbox = Some(PathBuf::from(value.decode::<String>()?));
+            }
+        }
+
+        Ok(options)
+    }
+}
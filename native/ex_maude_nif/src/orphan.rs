@@ -0,0 +1,269 @@
+//! Detection of leaked Maude processes.
+//!
+//! Every Maude child is registered here from spawn until it is shut down
+//! or its handle is dropped, which kills and reaps it. A Maude process
+//! whose parent is this OS process but which isn't registered has leaked -
+//! one that survived its handle, or was started outside of one - and
+//! `orphan_check/0` reports it.
+//!
+//! Children are found through `/proc` on Linux and `ps` on other Unix
+//! systems. Windows isn't scanned: each child runs in a kill-on-close job
+//! object (see `windows.rs`), so a dropped handle takes anything its Maude
+//! started with it too.
+//!
+//! Handles are only dropped when the VM collects them, which a halting VM
+//! never gets to: `System.halt/0`, `q()`, or aborting from the break menu
+//! after Ctrl-C in `iex` would leave every running Maude behind. So on Unix
+//! the library registers an `atexit` handler when it loads that kills the
+//! tracked children. A handler registered from a shared library also runs
+//! when the library is unloaded, which Rustler has no callback for. It
+//! reads the pids from a fixed table of atomics too, since a thread halted
+//! while holding the set's lock would otherwise hide them all.
+
+use crate::error;
+use rustler::{Atom, Decoder, NifResult, Term};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{LazyLock, Mutex, MutexGuard};
+
+rustler::atoms! {
+    kill,
+}
+
+static TRACKED: LazyLock<Mutex<HashSet<u32>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// Number of children the exit handler can find without [`TRACKED`].
+const SLOTS: usize = 1024;
+
+/// The tracked pids again, or `0` for a free slot, for the exit handler:
+/// another thread may hold [`TRACKED`] as the VM goes down. A child tracked
+/// while every slot is taken is only in the set.
+static SLOTTED: [AtomicU32; SLOTS] = [const { AtomicU32::new(0) }; SLOTS];
+
+fn tracked() -> MutexGuard<'static, HashSet<u32>> {
+    // A set of pids can't be left inconsistent by a panicking holder
+    TRACKED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Register a Maude child as owned by a live handle.
+pub fn track(pid: u32) {
+    if tracked().insert(pid) {
+        let _ = SLOTTED.iter().find(|slot| {
+            slot.compare_exchange(0, pid, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        });
+    }
+}
+
+/// Forget a Maude child that was shut down or whose handle is gone.
+pub fn untrack(pid: u32) {
+    if tracked().remove(&pid) {
+        let _ = SLOTTED.iter().find(|slot| {
+            slot.compare_exchange(pid, 0, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        });
+    }
+}
+
+/// Kill the tracked children when the OS process exits or the library is
+/// unloaded; registers the handler once, however often the library loads.
+pub fn kill_on_exit() {
+    #[cfg(unix)]
+    {
+        static REGISTER: std::sync::Once = std::sync::Once::new();
+        // SAFETY: registers a plain function that touches only statics.
+        REGISTER.call_once(|| unsafe {
+            libc::atexit(kill_tracked);
+        });
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn kill_tracked() {
+    // SAFETY: a plain syscall on a pid; one that is gone just fails.
+    let kill = |pid: u32| unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGKILL);
+    };
+
+    SLOTTED
+        .iter()
+        .map(|slot| slot.load(Ordering::SeqCst))
+        .filter(|&pid| pid != 0)
+        .for_each(kill);
+
+    // Those beyond the slots, unless another thread holds the lock
+    match TRACKED.try_lock() {
+        Ok(tracked) => tracked.iter().copied().for_each(kill),
+        Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner().iter().copied().for_each(kill),
+        Err(std::sync::TryLockError::WouldBlock) => {}
+    }
+}
+
+/// Options for `orphan_check/1`, decoded from a keyword list.
+///
+/// * `:kill` - Kill (and reap) the orphans found (default: `false`)
+#[derive(Debug, Default)]
+pub struct OrphanOptions {
+    pub kill: bool,
+}
+
+impl<'a> Decoder<'a> for OrphanOptions {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut options = OrphanOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, Term<'a>)>>()? {
+            if key == kill() {
+                options.kill = value.decode()?;
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Untracked Maude children of this OS process, by pid.
+pub fn find() -> Result<Vec<u32>, String> {
+    let tracked = tracked().clone();
+
+    let mut orphans: Vec<u32> = children()?
+        .into_iter()
+        .filter(|(pid, name)| name.to_lowercase().contains("maude") && !tracked.contains(pid))
+        .map(|(pid, _)| pid)
+        .collect();
+
+    orphans.sort_unstable();
+    Ok(orphans)
+}
+
+/// `(pid, command name)` of each child of this OS process.
+#[cfg(target_os = "linux")]
+fn children() -> Result<Vec<(u32, String)>, String> {
+    let me = std::process::id();
+    let entries = std::fs::read_dir("/proc").map_err(|e| format!("process scan failed: {}", e))?;
+
+    let mut children = Vec::new();
+    for entry in entries.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        else {
+            continue;
+        };
+
+        // Processes can exit mid-scan
+        let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+
+        // `pid (comm) state ppid ...`; comm may itself contain parentheses
+        let (Some(open), Some(close)) = (stat.find('('), stat.rfind(')')) else {
+            continue;
+        };
+        let ppid = stat[close + 1..]
+            .split_whitespace()
+            .nth(1)
+            .and_then(|ppid| ppid.parse::<u32>().ok());
+
+        if ppid == Some(me) {
+            children.push((pid, stat[open + 1..close].to_string()));
+        }
+    }
+
+    Ok(children)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn children() -> Result<Vec<(u32, String)>, String> {
+    let me = std::process::id();
+    let output = std::process::Command::new("ps")
+        .args(["-A", "-o", "pid=,ppid=,comm="])
+        .output()
+        .map_err(|e| format!("process scan failed: {}", e))?;
+
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let children = listing
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let pid = fields.next()?.parse().ok()?;
+            let ppid: u32 = fields.next()?.parse().ok()?;
+            let name = fields.collect::<Vec<_>>().join(" ");
+            (ppid == me).then_some((pid, name))
+        })
+        .collect();
+
+    Ok(children)
+}
+
+#[cfg(not(unix))]
+fn children() -> Result<Vec<(u32, String)>, String> {
+    Err("orphan detection is not supported on this platform".to_string())
+}
+
+/// Kill an orphan and reap it so it doesn't linger as a zombie.
+#[cfg(unix)]
+fn terminate(pid: u32) {
+    // SAFETY: plain syscalls on a pid; a pid that is gone just fails.
+    unsafe {
+        if libc::kill(pid as libc::pid_t, libc::SIGKILL) == 0 {
+            libc::waitpid(pid as libc::pid_t, std::ptr::null_mut(), 0);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate(_pid: u32) {}
+
+/// Find Maude processes started by this OS process that no handle owns.
+///
+/// # Returns
+/// * `Ok([pid])` - Orphaned Maude pids, sorted
+/// * `Err` - If the process table can't be read
+#[rustler::nif(schedule = "DirtyIo")]
+fn orphan_check() -> NifResult<Vec<u32>> {
+    find().map_err(error)
+}
+
+/// `orphan_check/0` with options.
+///
+/// # Arguments
+/// * `opts` - Keyword list; see [`OrphanOptions`]
+///
+/// # Returns
+/// * `Ok([pid])` - Orphaned Maude pids found (and killed, with `kill: true`)
+/// * `Err` - If the process table can't be read
+#[rustler::nif(schedule = "DirtyIo", name = "orphan_check")]
+fn orphan_check_with_opts(opts: OrphanOptions) -> NifResult<Vec<u32>> {
+    let orphans = find().map_err(error)?;
+
+    if opts.kill {
+        orphans.iter().copied().for_each(terminate);
+    }
+
+    Ok(orphans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slotted(pid: u32) -> usize {
+        SLOTTED
+            .iter()
+            .filter(|slot| slot.load(Ordering::SeqCst) == pid)
+            .count()
+    }
+
+    #[test]
+    fn slots_follow_the_set() {
+        let pid = u32::MAX - 1;
+        track(pid);
+        track(pid);
+        assert_eq!(slotted(pid), 1);
+
+        untrack(pid);
+        assert_eq!(slotted(pid), 0);
+        assert!(!tracked().contains(&pid));
+    }
+}
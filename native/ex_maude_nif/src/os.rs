@@ -0,0 +1,133 @@
+//! The Maude child as an OS process.
+//!
+//! `os_pid/1` gives the child's process id, for tools that work on the OS
+//! process rather than on the handle: attaching `perf` or `dtrace`,
+//! moving it into a cgroup, `renice`. `signal/2` sends it a signal by name,
+//! such as `:sigterm` or `:sigusr1`, or by number.
+//!
+//! The handle keeps track of nothing a signal does, except that `:sigstop`
+//! and `:sigcont` count as `pause/1` and `resume/1`. A child killed this way
+//! is noticed like one that crashed; one stopped by `:sigtstp` or a signal
+//! the handle doesn't know holds up every command until it is continued.
+//! Windows has no signals, and there `signal/2` returns an error.
+
+use crate::error;
+use crate::failure::Failure;
+use crate::process::MaudeProcess;
+use rustler::{Atom, Decoder, NifResult, ResourceArc, Term};
+
+rustler::atoms! {
+    ok,
+    sighup,
+    sigint,
+    sigquit,
+    sigabrt,
+    sigkill,
+    sigusr1,
+    sigusr2,
+    sigalrm,
+    sigterm,
+    sigcont,
+    sigstop,
+    sigtstp,
+    sigprof,
+    sigwinch,
+}
+
+/// A signal given as an atom such as `:sigterm` or as its number.
+#[derive(Debug, Clone, Copy)]
+pub struct Signal(i32);
+
+#[cfg(unix)]
+impl Signal {
+    fn named() -> [(Atom, libc::c_int); 14] {
+        [
+            (sighup(), libc::SIGHUP),
+            (sigint(), libc::SIGINT),
+            (sigquit(), libc::SIGQUIT),
+            (sigabrt(), libc::SIGABRT),
+            (sigkill(), libc::SIGKILL),
+            (sigusr1(), libc::SIGUSR1),
+            (sigusr2(), libc::SIGUSR2),
+            (sigalrm(), libc::SIGALRM),
+            (sigterm(), libc::SIGTERM),
+            (sigcont(), libc::SIGCONT),
+            (sigstop(), libc::SIGSTOP),
+            (sigtstp(), libc::SIGTSTP),
+            (sigprof(), libc::SIGPROF),
+            (sigwinch(), libc::SIGWINCH),
+        ]
+    }
+}
+
+impl<'a> Decoder<'a> for Signal {
+    #[cfg(unix)]
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        if let Ok(number) = term.decode::<i32>() {
+            return match number {
+                1..=64 => Ok(Signal(number)),
+                _ => Err(error(format!("invalid signal: {}", number))),
+            };
+        }
+
+        let name = term.decode::<Atom>()?;
+        Signal::named()
+            .into_iter()
+            .find_map(|(atom, number)| (atom == name).then_some(Signal(number)))
+            .ok_or(rustler::Error::BadArg)
+    }
+
+    #[cfg(not(unix))]
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        term.decode::<i32>()
+            .or_else(|_| term.decode::<Atom>().map(|_| 0))
+            .map(Signal)
+    }
+}
+
+#[cfg(unix)]
+fn send(process: &MaudeProcess, Signal(number): Signal) -> Result<(), Failure> {
+    let paused = match number {
+        libc::SIGSTOP => true,
+        libc::SIGCONT => false,
+        _ => process.is_paused(),
+    };
+    process.signal(number, paused)
+}
+
+#[cfg(not(unix))]
+fn send(_process: &MaudeProcess, _signal: Signal) -> Result<(), Failure> {
+    Err("signals are not supported on this platform".into())
+}
+
+/// The OS process id of the Maude child.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `Ok(pid)` - The child's process id
+/// * `Err` - If it has exited, as its id may already belong to another
+///   process
+#[rustler::nif]
+fn os_pid(process: ResourceArc<MaudeProcess>) -> NifResult<u32> {
+    process.os_pid().map_err(error)
+}
+
+/// Send a signal to the Maude child.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `sig` - `:sighup`, `:sigint`, `:sigquit`, `:sigabrt`, `:sigkill`,
+///   `:sigusr1`, `:sigusr2`, `:sigalrm`, `:sigterm`, `:sigcont`, `:sigstop`,
+///   `:sigtstp`, `:sigprof`, `:sigwinch`, or a signal number
+///
+/// # Returns
+/// * `Ok(:ok)` - The signal was sent
+/// * `Err` - If the child has exited, the signal is invalid, or the
+///   platform has no signals
+#[rustler::nif]
+fn signal(process: ResourceArc<MaudeProcess>, sig: Signal) -> NifResult<Atom> {
+    send(&process, sig).map_err(error)?;
+    Ok(ok())
+}
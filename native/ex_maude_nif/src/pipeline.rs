@@ -0,0 +1,174 @@
+//! Pipelined commands, written ahead of their responses.
+//!
+//! `execute/2` waits for each response before the next command goes out,
+//! so a burst of small commands spends most of its time on round trips.
+//! With the `:pipeline_depth` spawn option set, `submit/2` writes a command
+//! and returns a ticket straight away, and `collect/2` waits for that
+//! ticket's response. Maude answers in the order commands were written, so
+//! responses are matched to tickets by counting prompts; those read on the
+//! way to a later ticket are kept until collected.
+//!
+//! At most `:pipeline_depth` tickets may be outstanding - submitted and not
+//! yet collected - and `submit/2` fails with `"pipeline is full"` beyond
+//! that. Any other command first reads the responses still pending, so it
+//! is answered with its own.
+//!
+//! Two things are weaker than for `execute/2`. Maude runs ahead of the
+//! reader, so a warning on stderr may be reported with an earlier ticket
+//! than its own. And a pipelined response ends at the first prompt in it,
+//! so output that itself contains `Maude> ` is cut short.
+
+use crate::diagnostics::Outcome;
+use crate::error;
+use crate::failure::Failure;
+use crate::format::{self, OutputOptions};
+use crate::input::Input;
+use crate::process::{MaudeProcess, Response};
+use rustler::{Env, NifResult, ResourceArc};
+use std::collections::{HashMap, VecDeque};
+
+/// A ticket whose command was written but whose response hasn't been read.
+pub struct Unread {
+    pub ticket: u64,
+    /// Start of the command, for the bookkeeping done as it is answered.
+    pub head: String,
+    pub whole: bool,
+    /// Whether it empties the cache once answered.
+    pub stale: bool,
+}
+
+/// A response read for a ticket: the output with the stderr read after it,
+/// or why it couldn't be read.
+pub type Answer = Result<(Response, String), Failure>;
+
+/// Pipelined commands of one process.
+#[derive(Default)]
+pub struct Pipeline {
+    /// Outstanding tickets allowed; `0` turns pipelining off.
+    depth: usize,
+    next: u64,
+    /// Oldest first, the order Maude answers in.
+    unread: VecDeque<Unread>,
+    ready: HashMap<u64, Answer>,
+}
+
+impl Pipeline {
+    pub fn new(depth: usize) -> Pipeline {
+        Pipeline {
+            depth,
+            ..Pipeline::default()
+        }
+    }
+
+    /// Check that another command may be submitted.
+    pub fn admit(&self) -> Result<(), String> {
+        if self.depth == 0 {
+            return Err("pipelining is off; set :pipeline_depth".to_string());
+        }
+        if self.unread.len() + self.ready.len() >= self.depth {
+            return Err("pipeline is full".to_string());
+        }
+        Ok(())
+    }
+
+    /// Record a written command and hand out its ticket.
+    pub fn push(&mut self, head: String, whole: bool, stale: bool) -> u64 {
+        let ticket = self.next;
+        self.next += 1;
+        self.unread.push_back(Unread {
+            ticket,
+            head,
+            whole,
+            stale,
+        });
+        ticket
+    }
+
+    /// The next ticket to read a response for.
+    pub fn next_unread(&mut self) -> Option<Unread> {
+        self.unread.pop_front()
+    }
+
+    /// Whether `ticket` is outstanding.
+    pub fn holds(&self, ticket: u64) -> bool {
+        self.ready.contains_key(&ticket) || self.unread.iter().any(|u| u.ticket == ticket)
+    }
+
+    /// Store the answer for `ticket`.
+    pub fn answer(&mut self, ticket: u64, answer: Answer) {
+        self.ready.insert(ticket, answer);
+    }
+
+    /// Fail every unread ticket with `reason`; after a failed read nothing
+    /// more will arrive for them.
+    pub fn abandon(&mut self, reason: &Failure) {
+        while let Some(unread) = self.unread.pop_front() {
+            self.ready.insert(unread.ticket, Err(reason.clone()));
+        }
+    }
+
+    /// Take the answer for `ticket`, if it has been read.
+    pub fn take(&mut self, ticket: u64) -> Option<Answer> {
+        self.ready.remove(&ticket)
+    }
+}
+
+/// Write a command without waiting for its response.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process, spawned with `:pipeline_depth`
+/// * `command` - Maude command to execute
+///
+/// # Returns
+/// * `Ok(ticket)` - Ticket to pass to `collect/2`
+/// * `Err` - If pipelining is off, the pipeline is full, or I/O fails
+#[rustler::nif(schedule = "DirtyIo")]
+fn submit<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    command: Input<'a>,
+) -> NifResult<u64> {
+    process
+        .begin_pipelined(env.pid())
+        .and_then(|exchange| exchange.submit(&command.parts()))
+        .map_err(error)
+}
+
+/// Wait for the response to a submitted command.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `ticket` - Ticket returned by `submit/2`
+///
+/// # Returns
+/// * As for `execute/2`
+/// * `Err` - Also if the ticket is unknown or was already collected
+#[rustler::nif(schedule = "DirtyCpu")]
+fn collect(env: Env, process: ResourceArc<MaudeProcess>, ticket: u64) -> NifResult<Outcome> {
+    run(env, &process, ticket, &OutputOptions::default())
+}
+
+/// `collect/2` with output options; see [`format`] for `:format`.
+#[rustler::nif(schedule = "DirtyCpu", name = "collect")]
+fn collect_with_opts(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    ticket: u64,
+    opts: OutputOptions,
+) -> NifResult<Outcome> {
+    run(env, &process, ticket, &opts)
+}
+
+fn run(env: Env, process: &MaudeProcess, ticket: u64, opts: &OutputOptions) -> NifResult<Outcome> {
+    let (response, stderr) = process
+        .begin_pipelined(env.pid())
+        .and_then(|exchange| exchange.collect(ticket, opts.trim))
+        .map_err(error)?;
+
+    if let Some(failed) = Outcome::failed(&stderr) {
+        return Ok(failed);
+    }
+    format::render_response(response, opts)
+        .map(Outcome::Done)
+        .map_err(error)
+}
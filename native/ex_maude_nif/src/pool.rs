@@ -0,0 +1,903 @@
+//! Pool of Maude subprocesses managed from Rust.
+//!
+//! A pool owns a generation of identically configured workers and routes
+//! each command to one of them. Reloading builds a complete replacement
+//! generation next to the old one and swaps routing over in one step, so
+//! callers never see a half-upgraded pool.
+//!
+//! Workers are initialized by replaying the pool's journal - the `load`
+//! commands for its preload files - pipelined, without waiting for a
+//! prompt per file. A few fully initialized spare workers (`:spares`,
+//! default 1) are kept off the routing table, so growing the pool or
+//! replacing a worker hands one out immediately while its replacement
+//! starts in the background.
+//!
+//! A worker that has run `:max_worker_commands` commands, or served for
+//! `:max_worker_age` milliseconds, is recycled: swapped for a spare, or for
+//! a freshly started worker once it is up, and stopped after its in-flight
+//! commands. That bounds what a long-lived worker accumulates - memo
+//! tables, leaked state from one caller - without any caller waiting for
+//! the theories to load. The policy is checked after each command.
+//!
+//! `pool_execute_batch/3` fans a list of commands out over the workers
+//! under one shared `:budget`. Items still waiting to start when the budget
+//! runs out return `{:error, :budget_exhausted}` without being sent; an
+//! item already running is allowed to finish, since Maude can't abandon a
+//! command without the worker being killed.
+
+use crate::diagnostics::Outcome;
+use crate::error;
+use crate::failure::Failure;
+use crate::format::OutputOptions;
+use crate::input::Input;
+use crate::lifecycle::Lifecycle;
+use crate::options::SpawnOptions;
+use crate::process::MaudeProcess;
+use crate::threads;
+use rustler::{Atom, Decoder, Encoder, Env, LocalPid, NifMap, NifResult, ResourceArc, Term};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+rustler::atoms! {
+    ok,
+    strategy,
+    blue_green,
+    budget,
+    budget_exhausted,
+    spares,
+    max_worker_commands,
+    max_worker_age,
+}
+
+/// A pooled Maude process.
+///
+/// Commands are served in arrival order by the process's own request
+/// queue, so a busy worker just queues callers rather than racing them.
+pub struct Worker {
+    pub process: MaudeProcess,
+    /// Commands run, for the recycling policy.
+    commands: AtomicU64,
+    /// Set once the worker is being recycled.
+    retiring: AtomicBool,
+}
+
+impl Worker {
+    /// Start a worker initialized by replaying the journal for `options`.
+    pub fn spawn(maude_path: &str, options: &SpawnOptions) -> Result<Worker, Failure> {
+        let process = MaudeProcess::spawn_bare(maude_path, options)?;
+
+        if let Err(e) = process.replay(&journal(options)) {
+            let _ = process.shutdown();
+            return Err(e);
+        }
+
+        Ok(Worker {
+            process,
+            commands: AtomicU64::new(0),
+            retiring: AtomicBool::new(false),
+        })
+    }
+
+    /// Run one command once every earlier caller has been served and
+    /// render its output, as `execute/2` does for a process of its own:
+    /// trimmed with `opts.trim` or the worker's policy, measured, and
+    /// failed if Maude complains about it.
+    pub fn execute(&self, command: &[&[u8]], opts: &OutputOptions) -> Result<Outcome, Failure> {
+        self.commands.fetch_add(1, Ordering::Relaxed);
+        crate::answer(&self.process, self.process.begin(), command, opts)
+    }
+
+    /// [`Worker::execute`] on behalf of `caller`, honouring a lease and
+    /// `:max_queue` as [`MaudeProcess::begin_by`] does, for workers callers
+    /// address directly.
+    pub fn execute_by(
+        &self,
+        caller: LocalPid,
+        command: &[&[u8]],
+        opts: &OutputOptions,
+    ) -> Result<Outcome, Failure> {
+        let exchange = self.process.begin_by(caller)?;
+        self.commands.fetch_add(1, Ordering::Relaxed);
+        crate::answer(&self.process, exchange, command, opts)
+    }
+
+    /// Whether `policy` says the worker should be replaced.
+    fn due(&self, policy: &PoolPolicy) -> bool {
+        policy
+            .max_commands
+            .is_some_and(|max| self.commands.load(Ordering::Relaxed) >= max)
+            || policy
+                .max_age
+                .is_some_and(|max| self.process.uptime() >= max)
+    }
+
+    /// Wait for queued commands to finish, then stop the process.
+    pub fn shutdown(&self) -> Result<(), Failure> {
+        self.process.drain()
+    }
+}
+
+/// Workers of one configuration; replaced wholesale on reload.
+type Generation = Arc<Vec<Arc<Worker>>>;
+
+/// Commands that bring a fresh worker to the pool's configured state.
+fn journal(options: &SpawnOptions) -> Vec<String> {
+    options
+        .preload
+        .iter()
+        .map(|path| format!("load {}", path))
+        .chain(
+            options
+                .select
+                .iter()
+                .map(|module| format!("select {} .", module)),
+        )
+        .collect()
+}
+
+/// How a pool keeps its workers warm, decoded from the keyword list given
+/// to `pool_start/3` alongside the spawn options.
+///
+/// * `:spares` - Initialized workers kept off the routing table, ready to
+///   join it (default: `1`)
+/// * `:max_worker_commands` - Commands a worker runs before it is recycled
+///   (default: unlimited)
+/// * `:max_worker_age` - Milliseconds a worker serves before it is
+///   recycled (default: unlimited)
+#[derive(Debug, Clone, Copy)]
+pub struct PoolPolicy {
+    pub spares: usize,
+    pub max_commands: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+impl Default for PoolPolicy {
+    fn default() -> Self {
+        PoolPolicy {
+            spares: 1,
+            max_commands: None,
+            max_age: None,
+        }
+    }
+}
+
+/// Spawn options and [`PoolPolicy`], from one keyword list.
+#[derive(Debug, Default)]
+pub struct PoolOptions {
+    pub spawn: SpawnOptions,
+    pub policy: PoolPolicy,
+}
+
+impl<'a> Decoder<'a> for PoolOptions {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut options = PoolOptions {
+            spawn: term.decode()?,
+            policy: PoolPolicy::default(),
+        };
+
+        for (key, value) in term.decode::<Vec<(Atom, Term<'a>)>>()? {
+            if key == spares() {
+                options.policy.spares = value.decode()?;
+            } else if key == max_worker_commands() {
+                let max = value.decode()?;
+                if max == 0 {
+                    return Err(error("max_worker_commands must be positive"));
+                }
+                options.policy.max_commands = Some(max);
+            } else if key == max_worker_age() {
+                options.policy.max_age = Some(Duration::from_millis(value.decode()?));
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Initialized workers off the routing table, rebuilt in the background
+/// after each use.
+#[derive(Default)]
+struct Spares {
+    /// Bumped on reload and stop so stale background spawns are discarded.
+    epoch: u64,
+    workers: Vec<Worker>,
+    /// Background spawns under way for this epoch.
+    pending: usize,
+}
+
+/// Pool handle shared with Elixir.
+pub struct MaudePool {
+    maude_path: String,
+    options: Mutex<SpawnOptions>,
+    policy: PoolPolicy,
+    workers: Arc<RwLock<Generation>>,
+    next: AtomicUsize,
+    spares: Arc<Mutex<Spares>>,
+    /// Workers replaced under the policy so far.
+    recycled: Arc<AtomicU64>,
+}
+
+/// Report returned by `pool_status/1`.
+#[derive(Debug, NifMap)]
+pub struct PoolStatus {
+    /// Workers on the routing table.
+    pub size: usize,
+    pub ready: usize,
+    /// Workers running a command or with callers queued.
+    pub busy: usize,
+    /// Spare workers ready to join, and spares still starting.
+    pub spares: usize,
+    pub spares_pending: usize,
+    pub recycled: u64,
+    /// Commands each worker has run, in routing order.
+    pub commands: Vec<u64>,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for MaudePool {}
+
+impl Drop for MaudePool {
+    fn drop(&mut self) {
+        // A pool dropped without `pool_stop/1` stops its workers and spares;
+        // spares still starting, and the replacements of workers being
+        // recycled, find them gone and stop themselves
+        self.retire_spares();
+        let retired = match self.workers.write() {
+            Ok(mut workers) => std::mem::take(&mut *workers),
+            Err(e) => std::mem::take(&mut *e.into_inner()),
+        };
+
+        for worker in retired.iter() {
+            let _ = worker.shutdown();
+        }
+    }
+}
+
+impl MaudePool {
+    /// Current generation of workers.
+    pub fn generation(&self) -> Result<Generation, Failure> {
+        self.workers
+            .read()
+            .map(|workers| workers.clone())
+            .map_err(|e| format!("pool lock failed: {}", e).into())
+    }
+
+    /// Pick a ready worker, preferring an idle one after the round-robin
+    /// cursor.
+    pub fn checkout(&self) -> Result<Arc<Worker>, Failure> {
+        let workers = self.generation()?;
+        if workers.is_empty() {
+            return Err("pool has no workers".into());
+        }
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let ready: Vec<&Arc<Worker>> = (0..workers.len())
+            .map(|offset| &workers[(start + offset) % workers.len()])
+            .filter(|worker| worker.process.lifecycle() == Lifecycle::Ready)
+            .collect();
+
+        let worker = ready
+            .iter()
+            .find(|worker| !worker.process.is_busy())
+            .or(ready.first())
+            .ok_or_else(|| "pool has no ready workers".to_string())?;
+
+        Ok((*worker).clone())
+    }
+
+    /// Take up to `n` ready spare workers.
+    fn take_spares(&self, n: usize) -> Vec<Worker> {
+        let Ok(mut spares) = self.spares.lock() else {
+            return Vec::new();
+        };
+        let keep = spares.workers.len().saturating_sub(n);
+        spares.workers.split_off(keep)
+    }
+
+    /// Start spare workers for `options` in the background until the
+    /// policy's number are ready or starting.
+    fn refill_spares(&self, options: &SpawnOptions) {
+        let Ok(mut spares) = self.spares.lock() else {
+            return;
+        };
+        let missing = self
+            .policy
+            .spares
+            .saturating_sub(spares.workers.len() + spares.pending);
+        spares.pending += missing;
+        let epoch = spares.epoch;
+        drop(spares);
+
+        for _ in 0..missing {
+            let slot = self.spares.clone();
+            let maude_path = self.maude_path.clone();
+            let options = options.clone();
+
+            threads::run(move || {
+                let worker = Worker::spawn(&maude_path, &options);
+
+                let Ok(mut spares) = slot.lock() else {
+                    return;
+                };
+                if spares.epoch != epoch {
+                    drop(spares);
+                    if let Ok(worker) = worker {
+                        let _ = worker.process.shutdown();
+                    }
+                    return;
+                }
+                spares.pending -= 1;
+                // A failed spawn leaves the slot for the next refill
+                if let Ok(worker) = worker {
+                    spares.workers.push(worker);
+                }
+            });
+        }
+    }
+
+    /// Discard the spares, e.g. because the configuration changed.
+    fn retire_spares(&self) {
+        let retired = self
+            .spares
+            .lock()
+            .map(|mut spares| {
+                spares.epoch += 1;
+                spares.pending = 0;
+                std::mem::take(&mut spares.workers)
+            })
+            .unwrap_or_default();
+
+        for worker in retired {
+            let _ = worker.process.shutdown();
+        }
+    }
+
+    /// Recycle `worker` if the policy says it is due.
+    ///
+    /// A spare takes its place at once; without one, a new worker is
+    /// started in the background and the old one keeps serving until it
+    /// is up. Skipped while a reload holds the options, since the reload
+    /// replaces every worker anyway.
+    fn recycle_if_due(&self, worker: &Arc<Worker>) {
+        if !worker.due(&self.policy) || worker.retiring.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let Ok(options) = self.options.try_lock() else {
+            worker.retiring.store(false, Ordering::Relaxed);
+            return;
+        };
+
+        if let Some(spare) = self.take_spares(1).pop() {
+            replace(&self.workers, &self.recycled, worker, spare);
+            self.refill_spares(&options);
+            return;
+        }
+
+        let workers = self.workers.clone();
+        let recycled = self.recycled.clone();
+        let maude_path = self.maude_path.clone();
+        let options = options.clone();
+        let worker = worker.clone();
+
+        threads::run(move || match Worker::spawn(&maude_path, &options) {
+            Ok(fresh) => replace(&workers, &recycled, &worker, fresh),
+            // Try again after its next command
+            Err(_) => worker.retiring.store(false, Ordering::Relaxed),
+        });
+    }
+
+    /// Replace the current generation with `f` of it, returning the old one.
+    fn update(
+        &self,
+        f: impl FnOnce(&[Arc<Worker>]) -> Vec<Arc<Worker>>,
+    ) -> Result<Generation, Failure> {
+        let mut workers = self
+            .workers
+            .write()
+            .map_err(|e| format!("pool lock failed: {}", e))?;
+
+        let updated = Arc::new(f(&workers));
+        Ok(std::mem::replace(&mut *workers, updated))
+    }
+
+    /// Replace the current generation, returning the old one.
+    fn swap(&self, generation: Generation) -> Result<Generation, Failure> {
+        let mut workers = self
+            .workers
+            .write()
+            .map_err(|e| format!("pool lock failed: {}", e))?;
+
+        Ok(std::mem::replace(&mut *workers, generation))
+    }
+}
+
+/// Put `fresh` in the place of `old` in the current generation and drain
+/// `old`; `fresh` is shut down instead if a reload or resize already
+/// removed `old`.
+fn replace(workers: &RwLock<Generation>, recycled: &AtomicU64, old: &Arc<Worker>, fresh: Worker) {
+    let Ok(mut generation) = workers.write() else {
+        let _ = fresh.process.shutdown();
+        return;
+    };
+    let Some(at) = generation
+        .iter()
+        .position(|worker| Arc::ptr_eq(worker, old))
+    else {
+        drop(generation);
+        let _ = fresh.process.shutdown();
+        return;
+    };
+
+    let mut replaced: Vec<Arc<Worker>> = generation.iter().cloned().collect();
+    replaced[at] = Arc::new(fresh);
+    *generation = Arc::new(replaced);
+    drop(generation);
+
+    recycled.fetch_add(1, Ordering::Relaxed);
+    drain(Arc::new(vec![old.clone()]));
+}
+
+/// Spawn `size` workers in parallel, shutting all of them down if any fails.
+fn spawn_generation(
+    maude_path: &str,
+    options: &SpawnOptions,
+    size: usize,
+) -> Result<Generation, Failure> {
+    let results: Vec<Result<Worker, Failure>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..size)
+            .map(|_| {
+                std::thread::Builder::new()
+                    .name("ex_maude-spawn".to_string())
+                    .spawn_scoped(scope, || Worker::spawn(maude_path, options))
+                    .map_err(|e| format!("worker spawn failed to start: {}", e).into())
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle.and_then(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err("worker spawn panicked".into()))
+                })
+            })
+            .collect()
+    });
+
+    let mut workers = Vec::with_capacity(size);
+    let mut failure = None;
+
+    for result in results {
+        match result {
+            Ok(worker) => workers.push(Arc::new(worker)),
+            Err(e) => failure = failure.or(Some(e)),
+        }
+    }
+
+    if let Some(e) = failure {
+        drain(Arc::new(workers));
+        return Err(e);
+    }
+
+    if let Some(dead) = workers.iter().position(|worker| !worker.process.is_alive()) {
+        drain(Arc::new(workers));
+        return Err(format!("worker {} exited during startup", dead).into());
+    }
+
+    Ok(Arc::new(workers))
+}
+
+/// Shut down a retired generation once in-flight commands have finished.
+fn drain(generation: Generation) {
+    threads::run(move || {
+        for worker in generation.iter() {
+            let _ = worker.shutdown();
+        }
+    });
+}
+
+/// Start a pool of Maude processes.
+///
+/// # Arguments
+/// * `maude_path` - Path to the Maude executable
+/// * `size` - Number of workers
+/// * `opts` - Keyword list of spawn options, as for `start_with_opts/2`,
+///   and of the pool's policy; see [`PoolPolicy`]
+///
+/// # Returns
+/// * `Ok(ResourceArc<MaudePool>)` - Handle to the pool
+/// * `Err` - If any worker fails to start
+#[rustler::nif(schedule = "DirtyCpu")]
+fn pool_start(
+    maude_path: String,
+    size: usize,
+    opts: PoolOptions,
+) -> NifResult<ResourceArc<MaudePool>> {
+    if size == 0 {
+        return Err(error("pool size must be positive"));
+    }
+
+    let workers = spawn_generation(&maude_path, &opts.spawn, size).map_err(error)?;
+
+    let pool = ResourceArc::new(MaudePool {
+        maude_path,
+        options: Mutex::new(opts.spawn.clone()),
+        policy: opts.policy,
+        workers: Arc::new(RwLock::new(workers)),
+        next: AtomicUsize::new(0),
+        spares: Arc::new(Mutex::new(Spares::default())),
+        recycled: Arc::new(AtomicU64::new(0)),
+    });
+    pool.refill_spares(&opts.spawn);
+
+    Ok(pool)
+}
+
+/// Execute a command on one of the pool's workers.
+///
+/// # Arguments
+/// * `pool` - Handle to the pool
+/// * `command` - Maude command to execute
+///
+/// # Returns
+/// * As for `execute/2`, including `{:error, reason, raw}` for a command
+///   Maude complains about
+#[rustler::nif(schedule = "DirtyCpu")]
+fn pool_execute<'a>(pool: ResourceArc<MaudePool>, command: Input<'a>) -> NifResult<Outcome> {
+    pool_run(&pool, &command, &OutputOptions::default())
+}
+
+/// `pool_execute/2` with output options; see [`format`] for `:format`.
+#[rustler::nif(schedule = "DirtyCpu", name = "pool_execute")]
+fn pool_execute_with_opts<'a>(
+    pool: ResourceArc<MaudePool>,
+    command: Input<'a>,
+    opts: OutputOptions,
+) -> NifResult<Outcome> {
+    pool_run(&pool, &command, &opts)
+}
+
+fn pool_run(pool: &MaudePool, command: &Input, opts: &OutputOptions) -> NifResult<Outcome> {
+    let worker = pool.checkout().map_err(error)?;
+    let result = worker.execute(&command.parts(), opts);
+    pool.recycle_if_due(&worker);
+    result.map_err(error)
+}
+
+/// Options for `pool_execute_batch/3`, decoded from a keyword list.
+///
+/// * `:budget` - Milliseconds shared by the whole batch (default: none)
+/// * Output options (`:format`, `:decode`, `:trim`) apply to every item
+#[derive(Debug, Default)]
+pub struct BatchOptions {
+    pub budget: Option<Duration>,
+    pub output: OutputOptions,
+}
+
+impl<'a> Decoder<'a> for BatchOptions {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut options = BatchOptions {
+            budget: None,
+            output: term.decode()?,
+        };
+
+        for (key, value) in term.decode::<Vec<(Atom, Term<'a>)>>()? {
+            if key == budget() {
+                options.budget = Some(Duration::from_millis(value.decode()?));
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Outcome of one command in a batch.
+pub enum BatchItem {
+    /// Output, or the diagnostic of a command Maude complained about.
+    Done(Outcome),
+    Failed(Failure),
+    /// Not started before the batch's budget ran out.
+    Exhausted,
+}
+
+impl Encoder for BatchItem {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            BatchItem::Done(output) => output.encode(env),
+            BatchItem::Failed(failure) => (rustler::types::atom::error(), failure).encode(env),
+            BatchItem::Exhausted => (rustler::types::atom::error(), budget_exhausted()).encode(env),
+        }
+    }
+}
+
+/// Run one batch item unless `deadline` has already passed.
+fn batch_item(
+    pool: &MaudePool,
+    command: &[&[u8]],
+    opts: &OutputOptions,
+    deadline: Option<Instant>,
+) -> BatchItem {
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        return BatchItem::Exhausted;
+    }
+
+    let result = pool.checkout().and_then(|worker| {
+        let result = worker.execute(command, opts);
+        pool.recycle_if_due(&worker);
+        result
+    });
+
+    match result {
+        Ok(outcome) => BatchItem::Done(outcome),
+        Err(e) => BatchItem::Failed(e),
+    }
+}
+
+/// Execute a list of commands across the pool under one time budget.
+///
+/// Up to one command per worker runs at a time, taken in list order. Once
+/// `:budget` milliseconds have passed since the call began, commands not
+/// yet started are skipped; commands already running still complete, so
+/// the call can overrun the budget by at most the slowest of those.
+///
+/// # Arguments
+/// * `pool` - Handle to the pool
+/// * `commands` - Maude commands to execute
+/// * `opts` - Keyword list; see [`BatchOptions`]
+///
+/// # Returns
+/// * `Ok(Vec<BatchItem>)` - One result per command, in order: the output as
+///   for `pool_execute/3`, `{:error, reason, raw}` for a command Maude
+///   complains about, `{:error, reason}`, or
+///   `{:error, :budget_exhausted}`
+/// * `Err` - If the pool has no workers
+#[rustler::nif(schedule = "DirtyCpu")]
+fn pool_execute_batch<'a>(
+    pool: ResourceArc<MaudePool>,
+    commands: Vec<Input<'a>>,
+    opts: BatchOptions,
+) -> NifResult<Vec<BatchItem>> {
+    let deadline = opts.budget.map(|budget| Instant::now() + budget);
+    let workers = pool.generation().map_err(error)?.len();
+    if workers == 0 {
+        return Err(error("pool has no workers"));
+    }
+
+    let commands: Vec<Vec<&[u8]>> = commands.iter().map(Input::parts).collect();
+    let next = AtomicUsize::new(0);
+    let mut items: Vec<Option<BatchItem>> = (0..commands.len()).map(|_| None).collect();
+
+    let finished = std::thread::scope(|scope| {
+        let mut refused = None;
+        let handles: Vec<_> = (0..workers.min(commands.len()))
+            .filter_map(|_| {
+                std::thread::Builder::new()
+                    .name("ex_maude-batch".to_string())
+                    .spawn_scoped(scope, || {
+                        let mut done = Vec::new();
+                        loop {
+                            let at = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(command) = commands.get(at) else {
+                                return done;
+                            };
+                            done.push((at, batch_item(&pool, command, &opts.output, deadline)));
+                        }
+                    })
+                    .map_err(|e| refused = Some(e))
+                    .ok()
+            })
+            .collect();
+
+        // Each thread takes commands until none are left, so fewer threads
+        // only run the batch with less parallelism
+        if handles.is_empty() && !commands.is_empty() {
+            if let Some(e) = refused {
+                return Err(format!("batch failed to start: {}", e));
+            }
+        }
+
+        Ok(handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_default())
+            .collect::<Vec<Vec<(usize, BatchItem)>>>())
+    })
+    .map_err(error)?;
+
+    for (at, item) in finished.into_iter().flatten() {
+        items[at] = Some(item);
+    }
+
+    Ok(items
+        .into_iter()
+        .map(|item| item.unwrap_or_else(|| BatchItem::Failed("batch item panicked".into())))
+        .collect())
+}
+
+/// Atomically replace the pool's workers with ones preloading `files`.
+///
+/// With the `:blue_green` strategy (the only one supported, and the
+/// default), a full replacement generation is started and checked while
+/// the old one keeps serving. Routing switches in a single step once every
+/// new worker is healthy, and the old workers are shut down after their
+/// in-flight commands complete. If any new worker fails, the pool is left
+/// untouched.
+///
+/// # Arguments
+/// * `pool` - Handle to the pool
+/// * `files` - Maude files the new workers preload, replacing `:preload`
+/// * `opts` - Keyword list; `strategy: :blue_green`
+///
+/// # Returns
+/// * `Ok(:ok)` - Routing now uses the new workers
+/// * `Err` - If the strategy is unknown or a new worker failed to start
+#[rustler::nif(schedule = "DirtyCpu")]
+fn pool_reload<'a>(
+    pool: ResourceArc<MaudePool>,
+    files: Vec<String>,
+    opts: Vec<(Atom, Term<'a>)>,
+) -> NifResult<Atom> {
+    for (key, value) in &opts {
+        if *key == strategy() && value.decode::<Atom>()? != blue_green() {
+            return Err(error("unsupported reload strategy"));
+        }
+    }
+
+    let mut options = pool
+        .options
+        .lock()
+        .map_err(|e| error(format!("pool lock failed: {}", e)))?;
+
+    let size = pool.generation().map_err(error)?.len();
+    let mut next_options = options.clone();
+    next_options.preload = files;
+
+    let replacement = spawn_generation(&pool.maude_path, &next_options, size).map_err(error)?;
+    let retired = pool.swap(replacement).map_err(error)?;
+
+    pool.retire_spares();
+    pool.refill_spares(&next_options);
+    *options = next_options;
+
+    drain(retired);
+    Ok(ok())
+}
+
+/// Add `n` workers to the current generation, spares first, the rest
+/// started in parallel; on failure the spares taken are put back.
+fn grow(pool: &MaudePool, options: &SpawnOptions, n: usize) -> Result<usize, Failure> {
+    let spares = pool.take_spares(n);
+    let spawned = n - spares.len();
+
+    let fresh = match spawn_generation(&pool.maude_path, options, spawned) {
+        Ok(fresh) => fresh,
+        Err(e) => {
+            // Put the spares back rather than losing ready workers
+            if let Ok(mut slot) = pool.spares.lock() {
+                slot.workers.extend(spares);
+            }
+            return Err(e);
+        }
+    };
+
+    let mut size = 0;
+    pool.update(|current| {
+        let mut grown: Vec<Arc<Worker>> = current.to_vec();
+        grown.extend(spares.into_iter().map(Arc::new));
+        grown.extend(fresh.iter().cloned());
+        size = grown.len();
+        grown
+    })?;
+    pool.refill_spares(options);
+
+    Ok(size)
+}
+
+/// Add `n` workers to the pool, starting from the spare workers.
+///
+/// Spares that are ready join the pool at once and the rest are started
+/// in parallel by pipelined journal replay. New spares are then built in
+/// the background.
+///
+/// # Arguments
+/// * `pool` - Handle to the pool
+/// * `n` - Number of workers to add
+///
+/// # Returns
+/// * `Ok(usize)` - Pool size after growing
+/// * `Err` - If a new worker failed to start; the pool is left unchanged
+#[rustler::nif(schedule = "DirtyCpu")]
+fn pool_prewarm(pool: ResourceArc<MaudePool>, n: usize) -> NifResult<usize> {
+    let options = pool
+        .options
+        .lock()
+        .map_err(|e| error(format!("pool lock failed: {}", e)))?;
+
+    grow(&pool, &options, n).map_err(error)
+}
+
+/// Grow or shrink the pool to `size` workers.
+///
+/// Growing works as `pool_prewarm/2` does. Shrinking takes workers off the
+/// routing table at once, from the end, and stops them after their
+/// in-flight commands.
+///
+/// # Arguments
+/// * `pool` - Handle to the pool
+/// * `size` - Number of workers wanted
+///
+/// # Returns
+/// * `Ok(usize)` - Pool size afterwards
+/// * `Err` - If `size` is 0 or a new worker failed to start
+#[rustler::nif(schedule = "DirtyCpu")]
+fn pool_resize(pool: ResourceArc<MaudePool>, size: usize) -> NifResult<usize> {
+    if size == 0 {
+        return Err(error("pool size must be positive"));
+    }
+
+    let options = pool
+        .options
+        .lock()
+        .map_err(|e| error(format!("pool lock failed: {}", e)))?;
+
+    let current = pool.generation().map_err(error)?.len();
+    if size > current {
+        return grow(&pool, &options, size - current).map_err(error);
+    }
+
+    let old = pool
+        .update(|current| current[..size.min(current.len())].to_vec())
+        .map_err(error)?;
+    drain(Arc::new(old.get(size..).unwrap_or_default().to_vec()));
+
+    Ok(size.min(old.len()))
+}
+
+/// Report the pool's workers, spares, and recycling so far.
+///
+/// # Arguments
+/// * `pool` - Handle to the pool
+#[rustler::nif]
+fn pool_status(pool: ResourceArc<MaudePool>) -> NifResult<PoolStatus> {
+    let workers = pool.generation().map_err(error)?;
+    let (spares, spares_pending) = pool
+        .spares
+        .lock()
+        .map(|spares| (spares.workers.len(), spares.pending))
+        .map_err(|e| error(format!("pool lock failed: {}", e)))?;
+
+    Ok(PoolStatus {
+        size: workers.len(),
+        ready: workers
+            .iter()
+            .filter(|worker| worker.process.lifecycle() == Lifecycle::Ready)
+            .count(),
+        busy: workers
+            .iter()
+            .filter(|worker| worker.process.is_busy())
+            .count(),
+        spares,
+        spares_pending,
+        recycled: pool.recycled.load(Ordering::Relaxed),
+        commands: workers
+            .iter()
+            .map(|worker| worker.commands.load(Ordering::Relaxed))
+            .collect(),
+    })
+}
+
+/// Stop every worker in the pool.
+///
+/// # Arguments
+/// * `pool` - Handle to the pool
+#[rustler::nif(schedule = "DirtyCpu")]
+fn pool_stop(pool: ResourceArc<MaudePool>) -> NifResult<Atom> {
+    pool.retire_spares();
+    let retired = pool.swap(Arc::new(Vec::new())).map_err(error)?;
+
+    for worker in retired.iter() {
+        let _ = worker.shutdown();
+    }
+
+    Ok(ok())
+}
@@ -0,0 +1,2569 @@
+//! Maude subprocess management and prompt-delimited I/O.
+
+use crate::backpressure;
+use crate::cache::{self, Cache};
+use crate::chunks::{Chunks, CHUNK};
+use crate::command;
+use crate::events::{Event, Events};
+use crate::failure::Failure;
+use crate::filter::{LineFilter, Lines};
+use crate::format::{Meta, OutputOptions, Trim};
+use crate::full_maude;
+use crate::heartbeat::Heartbeat;
+use crate::history::{Entry, History};
+use crate::lease::Lease;
+use crate::lifecycle::{Lifecycle, State};
+use crate::limits::Limits;
+use crate::locks::{LockWaits, Ordered, Rank};
+use crate::mock::Host;
+#[cfg(unix)]
+use crate::mock::{Responder, Transcript};
+use crate::options::{EffectiveConfig, SpawnOptions};
+use crate::pipeline::{Answer, Pipeline, Unread};
+use crate::readonly;
+use crate::sandbox::Sandbox;
+use crate::selection;
+use crate::sent::Sent;
+use crate::sequence::Sentinel;
+use crate::settings::{Settings, Switch};
+use crate::spill::{Sample, Spill, Spiller};
+use crate::startup::{SpawnError, StartupFailure};
+use crate::stats::{Counters, ProcessStats};
+use crate::version::Fingerprint;
+use crate::wire::{Direction, WireLog};
+use crate::writer::{self, Progress, WriteProgress};
+use rustler::{Env, LocalPid, Monitor};
+use std::cell::{Cell, RefCell};
+use std::io::{BufRead, BufReader, IoSlice, Read, Write};
+use std::path::PathBuf;
+use std::process::{ChildStderr, ChildStdin, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Prompt printed by Maude in interactive mode when it is ready for input.
+pub const PROMPT: &str = "Maude> ";
+
+/// Prompt of Maude's debugger, entered when a command is interrupted.
+const DEBUG_PROMPT: &str = "Debug(1)> ";
+
+/// How long to wait for Maude to settle after a command finished just as
+/// it was interrupted.
+#[cfg(unix)]
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bytes of a command inspected for search and settings bookkeeping.
+const COMMAND_HEAD: usize = 256;
+
+/// Most stderr kept between two `take_stderr` calls; the rest is dropped.
+const STDERR_LIMIT: usize = 1024 * 1024;
+
+/// Wrapper around the Maude subprocess with synchronized I/O handles.
+///
+/// The handles are locked in the order given by [`Rank`]; see
+/// [`crate::locks`].
+pub struct MaudeProcess {
+    child: Ordered<Host>,
+    stdin: Ordered<ChildStdin>,
+    stdout: Ordered<BufReader<ChildStdout>>,
+    stderr: Ordered<ChildStderr>,
+    stats: Counters,
+    /// Whether the last search has solutions left for `continue`.
+    search_active: AtomicBool,
+    /// Set once stdout reaches EOF, which happens before the child is reaped.
+    closed: AtomicBool,
+    /// Set by raw I/O; prompt-delimited commands are refused until `resync`.
+    manual: AtomicBool,
+    /// Set while the child is stopped by `pause/1`; see [`crate::suspend`].
+    paused: AtomicBool,
+    /// Whether execute calls go through Full Maude; see
+    /// [`crate::full_maude`].
+    full_maude: bool,
+    /// Diagnostics drained while waiting for stdout or looking for a load's
+    /// marker, for the next `take_stderr`. A leaf lock.
+    held_stderr: Mutex<Held>,
+    /// Bytes read in manual mode but not yet returned by `recv_until`.
+    pending: Ordered<Vec<u8>>,
+    /// Current module, while known; see [`crate::selection`]. A leaf lock.
+    selection: Mutex<Option<String>>,
+    /// Commands written ahead of their responses; see [`crate::pipeline`].
+    /// A leaf lock.
+    pipeline: Mutex<Pipeline>,
+    /// A command sent and not yet awaited; see [`crate::sent`]. A leaf
+    /// lock.
+    sent: Mutex<Option<Sent>>,
+    /// Who to tell when the child dies; see [`crate::heartbeat`].
+    heartbeat: Heartbeat,
+    /// Who to tell about everything else; see [`crate::events`].
+    events: Events,
+    /// Responses to repeated commands; see [`crate::cache`]. A leaf lock.
+    cache: Mutex<Cache>,
+    /// The last commands run; see [`crate::history`]. A leaf lock.
+    history: Mutex<History>,
+    /// Version and capabilities, once probed; see [`crate::version`].
+    fingerprint: OnceLock<Fingerprint>,
+    /// Runtime switches as last set; Maude has no command to query them.
+    settings: Ordered<Settings>,
+    /// Output held in memory before a response spills to `spill_dir`.
+    spill_threshold: usize,
+    spill_dir: PathBuf,
+    /// Line length past which a response is read in chunks.
+    max_line_length: usize,
+    /// Whether commands that change state are refused; see
+    /// [`crate::readonly`].
+    read_only: bool,
+    /// Callers that may wait for a turn; see [`crate::backpressure`].
+    max_queue: Option<u64>,
+    /// Directory file commands are confined to; see [`crate::sandbox`].
+    sandbox: Option<Sandbox>,
+    /// Whether each command is tagged with a sentinel, and the number of
+    /// the next; see [`crate::sequence`].
+    sequence_check: bool,
+    sequence: AtomicU64,
+    /// Raw I/O as it goes over the pipes; see [`crate::wire`].
+    wire_log: WireLog,
+    /// Progress of the command being streamed; see [`crate::writer`].
+    writing: Progress,
+    /// Whitespace removed from responses unless a call overrides it.
+    trim: Trim,
+    /// Resource limits the child was started under.
+    limits: Limits,
+    /// How the child was launched, for `config/1`.
+    config: EffectiveConfig,
+    /// When the child was launched.
+    started: Instant,
+    /// Job object the child runs in; closing it kills the child.
+    #[cfg(windows)]
+    job: crate::windows::Job,
+    lifecycle: State,
+    queue: Queue,
+}
+
+/// Where [`Exchange::read_stop`] stopped reading.
+#[derive(Debug, PartialEq)]
+enum Stop {
+    Prompt,
+    Debugger,
+    Deadline,
+}
+
+/// Output of one command.
+#[derive(Debug)]
+pub enum Response {
+    Text(String),
+    /// Output that wasn't valid UTF-8: the bytes as read, and a decoding
+    /// with each invalid sequence replaced by U+FFFD.
+    Invalid {
+        raw: Vec<u8>,
+        text: String,
+    },
+    /// The output outgrew the spill threshold and was written to a file.
+    Spilled(Spill),
+    /// The output as read in chunks; see [`crate::chunks`].
+    Chunks(Chunks),
+}
+
+impl Response {
+    /// `bytes` as text, or as an invalid response if they aren't UTF-8.
+    pub fn from_bytes(bytes: Vec<u8>) -> Response {
+        match String::from_utf8(bytes) {
+            Ok(text) => Response::Text(text),
+            Err(e) => {
+                let raw = e.into_bytes();
+                let text = String::from_utf8_lossy(&raw).into_owned();
+                Response::Invalid { raw, text }
+            }
+        }
+    }
+
+    /// The output as (lossily decoded) text; a spilled response is an error
+    /// naming its file.
+    pub fn into_text(self) -> Result<String, Failure> {
+        match self {
+            Response::Text(text) | Response::Invalid { text, .. } => Ok(text),
+            Response::Chunks(chunks) => chunks.join().into_text(),
+            Response::Spilled(spill) => Err(format!(
+                "output too large: {} bytes spilled to {}",
+                spill.bytes,
+                spill.path.display()
+            )
+            .into()),
+        }
+    }
+
+    /// Text to scan for bookkeeping: all of it, or a spill's sample.
+    pub fn scan(&self) -> &str {
+        match self {
+            Response::Text(text) | Response::Invalid { text, .. } => text,
+            Response::Spilled(spill) => &spill.sample,
+            Response::Chunks(chunks) => &chunks.sample,
+        }
+    }
+}
+
+/// FIFO ticket queue giving callers their turn with the process in order.
+///
+/// A plain mutex around the pipes gives no ordering among waiters and lets
+/// one caller's write slip in between another caller's write and read.
+/// Callers instead take a ticket and wait until it is served, so each
+/// exchange runs whole and in arrival order.
+#[derive(Default)]
+struct Queue {
+    tickets: Mutex<Tickets>,
+    turn: Condvar,
+}
+
+#[derive(Default)]
+struct Tickets {
+    /// Next ticket to hand out.
+    next: u64,
+    /// Ticket being served.
+    serving: u64,
+    /// Caller with exclusive use of the process; see [`crate::lease`].
+    lease: Option<Lease>,
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, Tickets> {
+        // The counters stay consistent even if a holder panicked
+        self.tickets.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A caller's turn with the process; the next caller goes when it drops.
+///
+/// All prompt-delimited I/O happens through an exchange, so a sequence of
+/// commands run through one is never interleaved with anyone else's.
+pub struct Exchange<'a> {
+    process: &'a MaudeProcess,
+    ticket: u64,
+    /// Whether this exchange is bringing a starting process up.
+    setup: bool,
+    /// Whether responses are read in chunks, for the duration of an
+    /// [`Exchange::execute_metered`] asking for it.
+    chunked: Cell<bool>,
+    /// Lines kept of a response, likewise; see [`crate::filter`].
+    filter: RefCell<Option<LineFilter>>,
+    /// Whether the commands written are the NIF's own, which a read-only
+    /// process lets through.
+    trusted: Cell<bool>,
+}
+
+impl Drop for Exchange<'_> {
+    fn drop(&mut self) {
+        let queue = &self.process.queue;
+        queue.lock().serving += 1;
+        queue.turn.notify_all();
+    }
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for MaudeProcess {
+    const IMPLEMENTS_DOWN: bool = true;
+
+    /// A lease holder exited without releasing, or a subscriber exited;
+    /// free the process.
+    fn down<'a>(&'a self, _env: Env<'a>, pid: LocalPid, _monitor: Monitor) {
+        self.release_lease(Some(pid));
+        self.heartbeat.unsubscribe(Some(pid));
+    }
+}
+
+impl MaudeProcess {
+    /// Spawn Maude, wait for the first prompt, and load the preload files.
+    pub fn spawn(maude_path: &str, options: &SpawnOptions) -> Result<MaudeProcess, SpawnError> {
+        let process = Self::launch(maude_path, options)?;
+        process.initialize(options, &options.preload, options.select.as_deref())?;
+        Ok(process)
+    }
+
+    /// Spawn Maude and wait for the first prompt without preloading.
+    ///
+    /// Fails with a [`SpawnError::Startup`] if the program can't be executed
+    /// or doesn't show a prompt within `options.startup_timeout`.
+    pub fn spawn_bare(
+        maude_path: &str,
+        options: &SpawnOptions,
+    ) -> Result<MaudeProcess, SpawnError> {
+        let process = Self::launch(maude_path, options)?;
+        process.initialize(options, &[], None)?;
+        Ok(process)
+    }
+
+    /// Start the Maude executable, leaving the process `starting`.
+    ///
+    /// Nothing has been read yet; [`MaudeProcess::initialize`] brings the
+    /// process up.
+    pub fn launch(maude_path: &str, options: &SpawnOptions) -> Result<MaudeProcess, SpawnError> {
+        let mut command = Command::new(maude_path);
+        command
+            .args(options.command_args())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        options.limits.apply(&mut command)?;
+        let sandbox = options.sandbox.as_deref().map(Sandbox::open).transpose()?;
+        if let Some(sandbox) = &sandbox {
+            command.current_dir(sandbox.root());
+        }
+        let wire_log = match &options.wire_log {
+            Some(path) => WireLog::open(path)?,
+            None => WireLog::default(),
+        };
+
+        let mut child = command.spawn().map_err(|e| spawn_failure(maude_path, e))?;
+
+        #[cfg(windows)]
+        let job = match crate::windows::Job::assign(&child) {
+            Ok(job) => job,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("job setup failed: {}", e).into());
+            }
+        };
+
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| "failed to get stdin".to_string())?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| "failed to get stdout".to_string())?;
+
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| "failed to get stderr".to_string())?;
+
+        set_nonblocking(&stderr).map_err(|e| format!("stderr setup failed: {}", e))?;
+
+        let child_id = child.id();
+        crate::orphan::track(child_id);
+        wire_log.record(
+            Direction::Note,
+            &[format!(
+                "launched {} {:?}, pid {}",
+                maude_path,
+                options.command_args(),
+                child_id
+            )
+            .as_bytes()],
+        );
+
+        Ok(Self::assemble(
+            Host::Child(child),
+            (stdin, stdout, stderr),
+            options,
+            sandbox,
+            wire_log,
+            options.effective(maude_path, child_id),
+            #[cfg(windows)]
+            job,
+        ))
+    }
+
+    /// Start a mock answering from `transcript`, ready for commands; see
+    /// [`crate::mock`].
+    #[cfg(unix)]
+    pub fn mock(transcript: Transcript) -> Result<MaudeProcess, Failure> {
+        let options = SpawnOptions::default();
+        let (responder, pipes) =
+            Responder::start(transcript).map_err(|e| format!("mock setup failed: {}", e))?;
+        set_nonblocking(&pipes.stderr).map_err(|e| format!("stderr setup failed: {}", e))?;
+
+        let process = Self::assemble(
+            Host::Mock(responder),
+            (pipes.stdin, pipes.stdout, pipes.stderr),
+            &options,
+            None,
+            WireLog::default(),
+            options.effective("mock", 0),
+        );
+        process
+            .initialize(&options, &[], None)
+            .map_err(|e| e.to_string())?;
+        Ok(process)
+    }
+
+    /// A process on `host`, talking to it through its pipes, leaving it
+    /// `starting`.
+    fn assemble(
+        host: Host,
+        (stdin, stdout, stderr): (ChildStdin, ChildStdout, ChildStderr),
+        options: &SpawnOptions,
+        sandbox: Option<Sandbox>,
+        wire_log: WireLog,
+        config: EffectiveConfig,
+        #[cfg(windows)] job: crate::windows::Job,
+    ) -> MaudeProcess {
+        let threshold = options.lock_wait_threshold;
+        MaudeProcess {
+            child: Ordered::new(Rank::Child, host, threshold),
+            stdin: Ordered::new(Rank::Stdin, stdin, threshold),
+            stdout: Ordered::new(
+                Rank::Stdout,
+                BufReader::with_capacity(options.read_buffer_size, stdout),
+                threshold,
+            ),
+            stderr: Ordered::new(Rank::Stderr, stderr, threshold),
+            stats: Counters::default(),
+            search_active: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            manual: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            full_maude: options.full_maude.is_some(),
+            held_stderr: Mutex::new(Held::default()),
+            pending: Ordered::new(Rank::Pending, Vec::new(), threshold),
+            selection: Mutex::new(None),
+            pipeline: Mutex::new(Pipeline::new(options.pipeline_depth)),
+            sent: Mutex::new(None),
+            heartbeat: Heartbeat::default(),
+            events: Events::default(),
+            cache: Mutex::new(Cache::new(options.cache_size)),
+            history: Mutex::new(History::new(options.history_size)),
+            fingerprint: OnceLock::new(),
+            settings: Ordered::new(Rank::Settings, Settings::new(options), threshold),
+            spill_threshold: options.spill_threshold,
+            spill_dir: options.spill_dir.clone(),
+            max_line_length: options.max_line_length,
+            read_only: options.read_only,
+            max_queue: options.max_queue,
+            sandbox,
+            sequence_check: options.sequence_check,
+            sequence: AtomicU64::new(0),
+            wire_log,
+            writing: Progress::default(),
+            trim: options.trim,
+            limits: options.limits,
+            config,
+            started: Instant::now(),
+            #[cfg(windows)]
+            job,
+            lifecycle: State::new(),
+            queue: Queue::default(),
+        }
+    }
+
+    /// Wait for the first prompt and load `preload`, making a launched
+    /// process `ready`.
+    ///
+    /// On failure the process is shut down and left `broken`.
+    pub fn initialize(
+        &self,
+        options: &SpawnOptions,
+        preload: &[String],
+        select: Option<&str>,
+    ) -> Result<(), SpawnError> {
+        let exchange = self.turn(self.ticket(), true);
+
+        // Read until first prompt to ensure Maude is ready
+        let mut ready = exchange
+            .await_first_prompt(options.startup_timeout)
+            .and_then(|()| exchange.take_stderr().map(drop).map_err(SpawnError::from));
+
+        if let (Ok(()), Some(path)) = (&ready, &options.full_maude) {
+            ready = full_maude::start(&exchange, path).map_err(SpawnError::from);
+        }
+
+        for path in preload {
+            if ready.is_err() {
+                break;
+            }
+            ready = match exchange.load(path) {
+                Ok(Ok(())) => Ok(()),
+                Ok(Err(e)) => Err(format!("preload failed: {}: {}", path, e).into()),
+                Err(e) => Err(e.into()),
+            };
+        }
+        if let (Ok(()), Some(module)) = (&ready, select) {
+            ready = selection::select(&exchange, module).map_err(SpawnError::from);
+        }
+        drop(exchange);
+
+        match ready {
+            Ok(()) => {
+                self.lifecycle.ready();
+                self.events.emit(|| Event::Spawned {
+                    os_pid: self.os_pid().ok(),
+                    startup_us: micros(self.uptime()),
+                });
+                Ok(())
+            }
+            Err(e) => {
+                self.lifecycle.fail(&e.to_string());
+                let _ = self.shutdown();
+                Err(e)
+            }
+        }
+    }
+
+    /// Wait for this caller's turn with the process, in arrival order.
+    ///
+    /// Leases are ignored: this is for processes the NIF owns itself, such
+    /// as pool workers, which are never handed out to lease.
+    pub fn begin(&self) -> Exchange<'_> {
+        let exchange = self.turn(self.ticket(), false);
+        exchange.settle();
+        exchange
+    }
+
+    /// Take a turn on behalf of `caller`, honouring a lease.
+    ///
+    /// While another process holds the lease this fails with
+    /// [`Failure::Leased`], or waits for the release if the lease queues other
+    /// callers. The holder itself, and callers of [`MaudeProcess::begin`],
+    /// are never held up by it. Fails with [`Failure::Overloaded`] if
+    /// `:max_queue` callers are already waiting.
+    pub fn begin_by(&self, caller: LocalPid) -> Result<Exchange<'_>, Failure> {
+        let exchange = self.begin_pipelined(caller)?;
+        exchange.settle();
+        Ok(exchange)
+    }
+
+    /// Take a turn only if nobody holds or waits for one and the process
+    /// isn't leased, for work that can be skipped while it is busy.
+    pub fn try_begin(&self) -> Option<Exchange<'_>> {
+        let tickets = self.queue.lock();
+        if tickets.lease.is_some() || tickets.next != tickets.serving {
+            return None;
+        }
+
+        let exchange = self.turn(Self::take_ticket(tickets), false);
+        exchange.settle();
+        Some(exchange)
+    }
+
+    /// [`MaudeProcess::begin_by`] leaving pipelined responses unread, for
+    /// `submit/2` and `collect/2`.
+    pub fn begin_pipelined(&self, caller: LocalPid) -> Result<Exchange<'_>, Failure> {
+        let tickets = self.await_lease(caller)?;
+        if self
+            .max_queue
+            .is_some_and(|max| backpressure::depth(tickets.next, tickets.serving) >= max)
+        {
+            self.stats.record_overloaded();
+            return Err(Failure::Overloaded);
+        }
+        let ticket = Self::take_ticket(tickets);
+        Ok(self.turn(ticket, false))
+    }
+
+    /// Lease the process to `holder`, once every exchange already queued
+    /// has finished.
+    ///
+    /// Returns `false` if `holder` already held it. Another holder's lease
+    /// fails with [`Failure::Leased`], or is waited out if it queues callers.
+    pub fn acquire_lease(&self, holder: LocalPid, queue: bool) -> Result<bool, Failure> {
+        let mut tickets = self.await_lease(holder)?;
+        if tickets.lease.is_some() {
+            return Ok(false);
+        }
+        tickets.lease = Some(Lease {
+            holder,
+            queue,
+            monitor: None,
+        });
+
+        // Earlier callers finish before the holder has the process alone
+        drop(self.turn(Self::take_ticket(tickets), false));
+        Ok(true)
+    }
+
+    /// Store the monitor watching `holder`; it is handed back if the lease
+    /// has meanwhile been released, so the caller can remove it.
+    pub fn attach_lease_monitor(&self, holder: LocalPid, monitor: Monitor) -> Option<Monitor> {
+        match &mut self.queue.lock().lease {
+            Some(lease) if lease.holder == holder && lease.monitor.is_none() => {
+                lease.monitor = Some(monitor);
+                None
+            }
+            _ => Some(monitor),
+        }
+    }
+
+    /// End the lease - only if `holder` has it, when given - and let
+    /// waiting callers in.
+    pub fn release_lease(&self, holder: Option<LocalPid>) -> Option<Lease> {
+        let mut tickets = self.queue.lock();
+        let held = tickets.lease.as_ref()?.holder;
+        if holder.is_some_and(|holder| holder != held) {
+            return None;
+        }
+
+        let lease = tickets.lease.take();
+        drop(tickets);
+        self.queue.turn.notify_all();
+        lease
+    }
+
+    /// Whether some process holds the lease.
+    pub fn is_leased(&self) -> bool {
+        self.queue.lock().lease.is_some()
+    }
+
+    /// Wait until `caller` may queue: the process isn't leased, or is
+    /// leased to `caller`.
+    fn await_lease(&self, caller: LocalPid) -> Result<MutexGuard<'_, Tickets>, Failure> {
+        let mut tickets = self.queue.lock();
+
+        while let Some(lease) = &tickets.lease {
+            if lease.holder == caller {
+                break;
+            }
+            if !lease.queue {
+                return Err(Failure::Leased);
+            }
+            tickets = self
+                .queue
+                .turn
+                .wait(tickets)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+
+        Ok(tickets)
+    }
+
+    /// Take the next place in the queue.
+    fn ticket(&self) -> u64 {
+        Self::take_ticket(self.queue.lock())
+    }
+
+    fn take_ticket(mut tickets: MutexGuard<'_, Tickets>) -> u64 {
+        tickets.next += 1;
+        tickets.next - 1
+    }
+
+    /// Wait until `ticket` is served.
+    fn turn(&self, ticket: u64, setup: bool) -> Exchange<'_> {
+        let mut tickets = self.queue.lock();
+        while tickets.serving != ticket {
+            tickets = self
+                .queue
+                .turn
+                .wait(tickets)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+
+        Exchange {
+            process: self,
+            ticket,
+            setup,
+            chunked: Cell::new(false),
+            filter: RefCell::new(None),
+            trusted: Cell::new(false),
+        }
+    }
+
+    /// How the process was launched.
+    pub fn config(&self) -> &EffectiveConfig {
+        &self.config
+    }
+
+    /// Subscription to the child's death; see [`crate::heartbeat`].
+    pub fn heartbeat(&self) -> &Heartbeat {
+        &self.heartbeat
+    }
+
+    /// Event subscribers; see [`crate::events`].
+    pub fn events(&self) -> &Events {
+        &self.events
+    }
+
+    /// Time since the child was launched.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// The current module, if known without asking Maude.
+    pub fn selected_module(&self) -> Option<String> {
+        self.selection
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Where the process is in its lifecycle; see [`crate::lifecycle`].
+    pub fn lifecycle(&self) -> Lifecycle {
+        self.lifecycle.get()
+    }
+
+    /// Wait up to `timeout` for a starting process to become ready.
+    pub fn await_ready(&self, timeout: Duration) -> Result<(), Failure> {
+        self.lifecycle.await_ready(timeout)
+    }
+
+    /// Refuse new commands, let the queued ones finish, then stop.
+    pub fn drain(&self) -> Result<(), Failure> {
+        let ticket = self.ticket();
+        self.lifecycle.drain(ticket);
+
+        let _turn = self.turn(ticket, false);
+        self.shutdown()
+    }
+
+    /// Record that stdout reached EOF: Maude has exited, for `reason`.
+    fn close(&self, reason: &Failure) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.lifecycle.break_with(reason);
+        self.events.exit(&reason.to_string());
+    }
+
+    /// Send `signal` to the child, provided it is still running, and record
+    /// whether it is now paused.
+    ///
+    /// The child stays locked meanwhile, so it can't be reaped and its pid
+    /// reused before the signal arrives.
+    #[cfg(unix)]
+    pub fn signal(&self, signal: libc::c_int, paused: bool) -> Result<(), Failure> {
+        let mut child = self
+            .child
+            .lock()
+            .map_err(|e| format!("child lock failed: {}", e))?;
+        if self.closed.load(Ordering::Relaxed) || !matches!(child.try_wait(), Ok(None)) {
+            return Err("maude exited".into());
+        }
+
+        let pid = child
+            .id()
+            .ok_or_else(|| "mock process can't be signalled".to_string())?;
+
+        // SAFETY: a plain syscall on the pid of a child not yet reaped.
+        if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(format!("signal failed: {}", e).into());
+        }
+        self.paused.store(paused, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The child's OS process id, provided it is still running.
+    pub fn os_pid(&self) -> Result<u32, Failure> {
+        let mut child = self
+            .child
+            .lock()
+            .map_err(|e| format!("child lock failed: {}", e))?;
+        if self.closed.load(Ordering::Relaxed) || !matches!(child.try_wait(), Ok(None)) {
+            return Err("maude exited".into());
+        }
+        child
+            .id()
+            .ok_or_else(|| "mock process has no OS process".into())
+    }
+
+    /// Version and capabilities, if already probed.
+    pub fn fingerprint(&self) -> Option<Fingerprint> {
+        self.fingerprint.get().cloned()
+    }
+
+    /// Keep `fingerprint` unless another caller probed first, returning the
+    /// one kept.
+    pub fn remember_fingerprint(&self, fingerprint: Fingerprint) -> Fingerprint {
+        self.fingerprint.get_or_init(|| fingerprint).clone()
+    }
+
+    /// The latest `n` commands recorded; see [`crate::history`].
+    pub fn history(&self, n: usize) -> Vec<Entry> {
+        self.history
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .last(n)
+    }
+
+    /// Whether execute calls go through Full Maude.
+    pub fn is_full_maude(&self) -> bool {
+        self.full_maude
+    }
+
+    /// Whether the child is stopped by `pause/1`.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Whether an exchange is running or waiting.
+    pub fn is_busy(&self) -> bool {
+        let tickets = self.queue.lock();
+        tickets.next != tickets.serving
+    }
+
+    /// Run one command in its own exchange; see [`Exchange::execute`].
+    pub fn execute(&self, command: &str) -> Result<String, Failure> {
+        self.begin().execute(command)
+    }
+
+    /// Run one command in its own exchange; see [`Exchange::execute_trimmed`].
+    pub fn execute_trimmed(
+        &self,
+        parts: &[&[u8]],
+        trim: Option<Trim>,
+    ) -> Result<Response, Failure> {
+        self.begin().execute_trimmed(parts, trim)
+    }
+
+    /// Replay commands in one exchange; see [`Exchange::replay`].
+    ///
+    /// They bring a process to its configured state, so a read-only process
+    /// runs them too.
+    pub fn replay(&self, commands: &[String]) -> Result<(), Failure> {
+        let exchange = self.begin();
+        exchange.trusted(|| exchange.replay(commands))
+    }
+
+    /// Whether a search can be resumed with `continue`.
+    pub fn search_active(&self) -> bool {
+        self.search_active.load(Ordering::Relaxed)
+    }
+
+    /// Cached values of Maude's runtime switches.
+    pub fn settings(&self) -> Settings {
+        self.settings
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Cumulative rewrite counters for everything this process has run,
+    /// with its lock wait statistics.
+    pub fn stats(&self) -> ProcessStats {
+        let tickets = self.queue.lock();
+        let depth = backpressure::depth(tickets.next, tickets.serving);
+        drop(tickets);
+        self.stats.snapshot(self.lock_waits(), depth)
+    }
+
+    /// Wait statistics over every handle lock; zero without `lock-watch`.
+    fn lock_waits(&self) -> LockWaits {
+        [
+            self.child.waits(),
+            self.stdin.waits(),
+            self.pending.waits(),
+            self.stdout.waits(),
+            self.stderr.waits(),
+            self.settings.waits(),
+        ]
+        .into_iter()
+        .fold(LockWaits::default(), LockWaits::merge)
+    }
+
+    /// Directory file commands are confined to, if any; see
+    /// [`crate::sandbox`].
+    pub fn sandbox(&self) -> Option<&Sandbox> {
+        self.sandbox.as_ref()
+    }
+
+    /// How far the command being streamed to Maude has got; see
+    /// [`crate::writer`].
+    pub fn write_progress(&self) -> Option<WriteProgress> {
+        self.writing.get()
+    }
+
+    /// Write `parts` as one command line, then the line `after`, and flush
+    /// them, in pieces of at most [`writer::PIECE`] bytes, counting each in
+    /// the write's progress.
+    ///
+    /// For a writer thread streaming a command while its caller reads,
+    /// which has checked it may be written.
+    fn write_streamed(&self, parts: &[&[u8]], after: &str) -> Result<(), Failure> {
+        let mut stdin = self
+            .stdin
+            .lock()
+            .map_err(|e| format!("stdin lock failed: {}", e))?;
+
+        let tail = format!("\n{}\n", after);
+        let total = parts.iter().map(|part| part.len()).sum::<usize>() + tail.len();
+        self.writing.start(total);
+        let written = parts
+            .iter()
+            .copied()
+            .chain([tail.as_bytes()])
+            .flat_map(|part| part.chunks(writer::PIECE))
+            .try_for_each(|piece| {
+                stdin
+                    .write_all(piece)
+                    .map_err(|e| format!("write failed: {}", e))?;
+                self.writing.advance(piece.len());
+                Ok::<(), String>(())
+            })
+            .and_then(|()| stdin.flush().map_err(|e| format!("flush failed: {}", e)))
+            .map_err(Failure::from);
+        self.writing.finish();
+
+        if written.is_ok() {
+            self.wire_log.record_line(Direction::Stdin, parts);
+            self.wire_log
+                .record_line(Direction::Stdin, &[after.as_bytes()]);
+        }
+        written
+    }
+
+    /// Ask Maude to quit, then make sure the child is gone.
+    pub fn shutdown(&self) -> Result<(), Failure> {
+        let mut child = self
+            .child
+            .lock()
+            .map_err(|e| format!("child lock failed: {}", e))?;
+
+        // Send quit command first for graceful shutdown. A writer blocked on
+        // a full pipe holds stdin, and waiting for it would stop the kill
+        if let Some(mut stdin) = self.stdin.try_lock() {
+            if writeln!(stdin, "quit").is_ok() {
+                self.wire_log.record(Direction::Stdin, &[b"quit\n"]);
+            }
+            let _ = stdin.flush();
+        }
+
+        // Give it a moment to exit gracefully
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        // Force kill if still running, along with anything it started
+        #[cfg(windows)]
+        self.job.terminate();
+        let _ = child.kill();
+        let _ = child.wait();
+        if let Some(pid) = child.id() {
+            crate::orphan::untrack(pid);
+        }
+        self.lifecycle.stop();
+        self.events.exit("stopped");
+        // Callers waiting out a lease now fail with "process stopped"
+        self.release_lease(None);
+
+        Ok(())
+    }
+
+    /// How the child exited, if it has and wasn't stopped by
+    /// [`MaudeProcess::shutdown`].
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        let mut child = self.child.lock().ok()?;
+        let status = child.try_wait().ok()??;
+
+        // `shutdown` keeps the child locked until the process is stopped
+        (self.lifecycle() != Lifecycle::Stopped).then_some(status)
+    }
+
+    /// Whether the child process is still running.
+    pub fn is_alive(&self) -> bool {
+        if self.closed.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        match self.child.lock() {
+            Ok(mut child) => match child.try_wait() {
+                Ok(None) => true,     // Still running
+                Ok(Some(_)) => false, // Exited
+                Err(_) => false,      // Error checking status
+            },
+            Err(_) => false, // Lock failed
+        }
+    }
+}
+
+impl Exchange<'_> {
+    /// Load a Maude file, returning the warnings Maude reported if any.
+    pub fn load(&self, path: &str) -> Result<Result<(), String>, Failure> {
+        self.execute(&format!("load {}", path))?;
+
+        let diagnostics = self.take_stderr()?;
+        if diagnostics.contains("Warning:") || diagnostics.contains("Error:") {
+            Ok(Err(diagnostics.trim().to_string()))
+        } else {
+            Ok(Ok(()))
+        }
+    }
+
+    /// Send `commands` back to back, then wait for all of their output.
+    ///
+    /// Pipelining saves a round trip per command when rebuilding state, at
+    /// the cost of per-command diagnostics: any warning or error Maude
+    /// reports fails the whole replay. The output is discarded.
+    pub fn replay(&self, commands: &[String]) -> Result<(), Failure> {
+        for command in commands {
+            self.write_command(command)?;
+        }
+
+        for segment in self.read_marked()? {
+            if let Response::Spilled(spill) = segment {
+                let _ = std::fs::remove_file(&spill.path);
+            }
+        }
+
+        self.process.search_active.store(false, Ordering::Relaxed);
+
+        let diagnostics = self.take_stderr()?;
+        if diagnostics.contains("Warning:") || diagnostics.contains("Error:") {
+            return Err(format!("replay failed: {}", diagnostics.trim()).into());
+        }
+
+        Ok(())
+    }
+
+    /// Run one command and return its trimmed output.
+    ///
+    /// Output that spilled to a file is an error; see [`Response::into_text`].
+    pub fn execute(&self, command: &str) -> Result<String, Failure> {
+        self.execute_response(&[command.as_bytes()])?.into_text()
+    }
+
+    /// Run one command, given as consecutive parts, and return its output,
+    /// trimmed by the process's policy unless it spilled.
+    pub fn execute_response(&self, parts: &[&[u8]]) -> Result<Response, Failure> {
+        self.execute_trimmed(parts, None)
+    }
+
+    /// [`Exchange::execute_response`] with `trim` in place of the process's
+    /// policy when given.
+    ///
+    /// Also tracks whether a search is left that `continue` can resume:
+    /// Maude keeps it across `set` and `show` commands, but any other
+    /// command discards it.
+    pub fn execute_trimmed(
+        &self,
+        parts: &[&[u8]],
+        trim: Option<Trim>,
+    ) -> Result<Response, Failure> {
+        let response = self.process.events.command(
+            || command_head(parts).0.trim().to_string(),
+            || self.execute_unrecorded(parts, trim),
+        )?;
+        self.record(
+            || String::from_utf8_lossy(&parts.concat()).into_owned(),
+            &response,
+        );
+        Ok(response)
+    }
+
+    /// [`Exchange::execute_trimmed`] leaving the command out of the
+    /// history, for the heartbeat's pings, which would crowd everything
+    /// else out.
+    pub fn execute_unrecorded(
+        &self,
+        parts: &[&[u8]],
+        trim: Option<Trim>,
+    ) -> Result<Response, Failure> {
+        #[cfg(feature = "chaos")]
+        if crate::chaos::should_kill() {
+            if let Ok(mut child) = self.process.child.lock() {
+                let _ = child.kill();
+            }
+        }
+
+        // Refused before the sentinel goes out, or its answer goes unread
+        self.check_writable(parts)?;
+        let sentinel = self.write_sentinel()?;
+        // Only the start matters, and a command may be one huge term
+        let (head, whole) = command_head(parts);
+
+        // A streamed command is marked by its writer, since the input may
+        // hold more commands still being written as the first is answered
+        let streamed = writer::streams(parts).then(next_marker);
+        let load = command::is_load(head.trim_start());
+        let read = || match &streamed {
+            Some(marker) => join(self.read_to_marker(marker)?),
+            None if load => join(self.read_marked()?),
+            None => self.read_response(),
+        };
+        let respond = || {
+            #[cfg(feature = "chaos")]
+            crate::chaos::delay_read();
+
+            if let Some(sentinel) = sentinel {
+                if let Err(desync) = self.read_sentinel(sentinel) {
+                    // The command's own response is still to come
+                    if let Ok(Response::Spilled(spill)) = read() {
+                        let _ = std::fs::remove_file(&spill.path);
+                    }
+                    return Err(desync);
+                }
+            }
+            read()
+        };
+
+        let response = if let Some(marker) = &streamed {
+            let after = marker_command(marker);
+            writer::alongside(|| self.process.write_streamed(parts, &after), respond)?
+        } else {
+            self.write_parts(parts)?;
+            respond()?
+        };
+        let response = self.trimmed(response, trim);
+
+        self.observe(&head, whole, self.stales_cache(parts), &response);
+        Ok(response)
+    }
+
+    /// [`Exchange::execute_trimmed`] with the trim of `opts`, also measuring
+    /// how long Maude took to answer and how much it wrote, and reading the
+    /// response in chunks or through a filter if `opts` asks for it.
+    pub fn execute_metered(
+        &self,
+        parts: &[&[u8]],
+        opts: &OutputOptions,
+    ) -> Result<(Response, Meta), Failure> {
+        let wrapped = if self.process.full_maude {
+            full_maude::wrap(parts)
+        } else {
+            None
+        };
+        let wrapped_parts;
+        let parts = match &wrapped {
+            Some(wrapped) => {
+                wrapped_parts = [&wrapped[..]];
+                &wrapped_parts[..]
+            }
+            None => parts,
+        };
+
+        let before = self.process.stats.bytes_read();
+        let start = Instant::now();
+        self.chunked.set(opts.chunked());
+        self.filter.replace(opts.filter.clone());
+        let response = self.execute_trimmed(parts, opts.trim);
+        self.chunked.set(false);
+        self.filter.replace(None);
+        let response = response?;
+
+        let meta = Meta {
+            duration_us: micros(start.elapsed()),
+            bytes_read: self.process.stats.bytes_read() - before,
+            cached: false,
+            restarted: false,
+        };
+        Ok((response, meta))
+    }
+
+    /// Run one command, interrupting it if it is still running at
+    /// `deadline`.
+    ///
+    /// Maude answers SIGINT by suspending the command in its debugger,
+    /// where `where .` shows the term rewriting had reached before `abort .`
+    /// returns to the top level. Returns the output, and that term if the
+    /// command was interrupted. A command that finished just as the signal
+    /// went out leaves Maude printing a prompt for it, which is discarded
+    /// by [`Exchange::resync`]. The output is read into memory and trimmed.
+    #[cfg(unix)]
+    pub fn execute_interruptible(
+        &self,
+        parts: &[&[u8]],
+        deadline: Instant,
+    ) -> Result<(String, Option<String>), Failure> {
+        self.write_parts(parts)?;
+
+        let mut output = Vec::new();
+        let mut current = None;
+        if self.read_stop(&mut output, None, Some(deadline), false)? == Stop::Deadline {
+            let paused = self.process.is_paused();
+            self.process.signal(libc::SIGINT, paused)?;
+
+            if self.read_stop(&mut output, None, None, true)? == Stop::Debugger {
+                current = Some(self.abort()?);
+            } else {
+                self.resync(SETTLE_TIMEOUT)?;
+            }
+        }
+
+        let output = String::from_utf8_lossy(&output).trim().to_string();
+        let response = Response::Text(output);
+        self.process.stats.record(response.scan());
+        let (head, whole) = command_head(parts);
+        self.observe(&head, whole, self.stales_cache(parts), &response);
+        self.record(
+            || String::from_utf8_lossy(&parts.concat()).into_owned(),
+            &response,
+        );
+
+        response.into_text().map(|output| (output, current))
+    }
+
+    /// Leave the debugger for the top level, returning the current term.
+    #[cfg(unix)]
+    fn abort(&self) -> Result<String, Failure> {
+        let mut reply = Vec::new();
+        self.trusted(|| self.write_command("where ."))?;
+        if self.read_stop(&mut reply, None, None, true)? != Stop::Debugger {
+            return Err("interrupt failed: no debugger prompt".into());
+        }
+
+        let mut rest = Vec::new();
+        self.trusted(|| self.write_command("abort ."))?;
+        self.read_stop(&mut rest, None, None, false)?;
+
+        let reply = String::from_utf8_lossy(&reply);
+        let current = reply
+            .split_once("Current term is:")
+            .map(|(_, rest)| rest)
+            .and_then(|rest| rest.split_once("which arose while"))
+            .map(|(term, _)| term.trim().to_string());
+        current.ok_or_else(|| {
+            format!("interrupt failed: unexpected debugger output: {}", reply).into()
+        })
+    }
+
+    /// `response` trimmed by `trim`, or the process's policy if not given.
+    fn trimmed(&self, response: Response, trim: Option<Trim>) -> Response {
+        let trim = trim.unwrap_or(self.process.trim);
+
+        match response {
+            #[cfg_attr(not(feature = "chaos"), allow(unused_mut))]
+            Response::Text(output) => {
+                let mut output = trim.apply(&output).to_string();
+
+                #[cfg(feature = "chaos")]
+                crate::chaos::truncate_output(&mut output);
+
+                Response::Text(output)
+            }
+            Response::Invalid { raw, text } => Response::Invalid {
+                raw: trim.apply_bytes(&raw).to_vec(),
+                text: trim.apply(&text).to_string(),
+            },
+            Response::Chunks(chunks) => Response::Chunks(chunks.trim(trim)),
+            spilled => spilled,
+        }
+    }
+
+    /// Update the search, selection, settings, and cache state for
+    /// `command`, given its start, whether that is all of it, and whether
+    /// it stales the cache, once it is answered.
+    fn observe(&self, head: &str, whole: bool, stale: bool, response: &Response) {
+        let command = head.trim_start();
+        if command.starts_with("search") || command.starts_with("continue") {
+            let output = response.scan();
+            let more = output.contains("Solution ") && !output.contains("No more solutions.");
+            self.process.search_active.store(more, Ordering::Relaxed);
+        } else if !command.starts_with("set ") && !command.starts_with("show ") {
+            self.process.search_active.store(false, Ordering::Relaxed);
+        }
+
+        if selection::may_change(command) {
+            self.record_selection(None);
+        }
+        if stale {
+            self.cache().clear();
+        }
+
+        if whole {
+            self.process
+                .settings
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .observe(command);
+        }
+    }
+
+    /// Write a command without waiting for its response, returning its
+    /// ticket; see [`crate::pipeline`].
+    pub fn submit(&self, parts: &[&[u8]]) -> Result<u64, Failure> {
+        self.pipeline().admit()?;
+        self.write_parts(parts)?;
+
+        let (head, whole) = command_head(parts);
+        let stale = self.stales_cache(parts);
+        Ok(self.pipeline().push(head, whole, stale))
+    }
+
+    /// Wait for the response to a submitted command, reading the ones
+    /// ahead of it on the way.
+    pub fn collect(&self, ticket: u64, trim: Option<Trim>) -> Result<(Response, String), Failure> {
+        if !self.pipeline().holds(ticket) {
+            return Err(format!("unknown ticket {}", ticket).into());
+        }
+
+        loop {
+            if let Some(answer) = self.pipeline().take(ticket) {
+                return answer.map(|(response, stderr)| (self.trimmed(response, trim), stderr));
+            }
+            self.read_pipelined();
+        }
+    }
+
+    /// Write a command without reading its output, which
+    /// [`Exchange::await_output`] does; see [`crate::sent`].
+    pub fn send_command(&self, parts: &[&[u8]]) -> Result<(), Failure> {
+        if writer::streams(parts) {
+            return Err(
+                format!("command too large to send: over {} bytes", writer::STREAMED).into(),
+            );
+        }
+        self.write_parts(parts)?;
+
+        let (head, whole) = command_head(parts);
+        self.process.events.emit(|| Event::CommandStarted {
+            command: head.trim().to_string(),
+        });
+        *self.sent() = Some(Sent::new(head, whole, self.stales_cache(parts)));
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for the output of the command
+    /// [`Exchange::send_command`] wrote, with the stderr read after it.
+    ///
+    /// On a timeout the command stays sent, with what was read so far.
+    pub fn await_output(&self, timeout: Duration) -> Result<(Response, String), Failure> {
+        let mut sent = self
+            .sent()
+            .take()
+            .ok_or_else(|| "no command was sent".to_string())?;
+
+        let deadline = Some(Instant::now() + timeout);
+        let stopped =
+            match self.read_stop(&mut sent.output, Some(&mut sent.spiller), deadline, true) {
+                Ok(Stop::Deadline) => {
+                    *self.sent() = Some(sent);
+                    return Err("timeout".into());
+                }
+                Ok(Stop::Debugger) => self.leave_debugger().and(Err("interrupted".into())),
+                Ok(Stop::Prompt) => Ok(()),
+                Err(e) => Err(e),
+            };
+
+        let answered = stopped.and_then(|()| {
+            let output = std::mem::take(&mut sent.output);
+            let response = match sent.spiller.take() {
+                Some(mut spiller) => {
+                    spiller.write(&output)?;
+                    Response::Spilled(spiller.finish()?)
+                }
+                None => self.trimmed(self.decoded(output), None),
+            };
+            self.process.stats.record(response.scan());
+            self.observe(&sent.head, sent.whole, sent.stale, &response);
+            self.record(|| sent.head.clone(), &response);
+            Ok((response, self.take_stderr()?))
+        });
+
+        if let Some(spiller) = sent.spiller.take() {
+            spiller.discard();
+        }
+        self.process.events.emit(|| Event::CommandFinished {
+            command: sent.head.trim().to_string(),
+            duration_us: micros(sent.started.elapsed()),
+            error: answered.as_ref().err().map(Failure::to_string),
+        });
+        answered
+    }
+
+    /// Return from the debugger an interrupted command stopped in.
+    #[cfg(unix)]
+    fn leave_debugger(&self) -> Result<(), Failure> {
+        self.abort().map(drop)
+    }
+
+    /// Commands are only interrupted on Unix, so none stops in the
+    /// debugger here.
+    #[cfg(not(unix))]
+    fn leave_debugger(&self) -> Result<(), Failure> {
+        Ok(())
+    }
+
+    /// Read every pipelined response still pending, so the next command
+    /// written is answered with its own.
+    fn settle(&self) {
+        while self.read_pipelined() {}
+    }
+
+    /// Read the response to the oldest unread ticket and keep it for
+    /// `collect`; `false` if there was none.
+    fn read_pipelined(&self) -> bool {
+        let Some(Unread {
+            ticket,
+            head,
+            whole,
+            stale,
+        }) = self.pipeline().next_unread()
+        else {
+            return false;
+        };
+
+        let answer: Answer = self.read_to_prompt(true).and_then(|response| {
+            self.observe(&head, whole, stale, &response);
+            self.record(|| head.clone(), &response);
+            Ok((response, self.take_stderr()?))
+        });
+
+        let mut pipeline = self.pipeline();
+        if let Err(e) = &answer {
+            pipeline.abandon(e);
+        }
+        pipeline.answer(ticket, answer);
+        true
+    }
+
+    /// Add the command `command` gives, answered by `response`, to the
+    /// history if it is kept.
+    fn record(&self, command: impl FnOnce() -> String, response: &Response) {
+        let mut history = self
+            .process
+            .history
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if history.is_on() {
+            let at = self.process.started.elapsed().as_millis() as u64;
+            history.push(Entry::new(command(), response, at));
+        }
+    }
+
+    fn cache(&self) -> MutexGuard<'_, Cache> {
+        self.process.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether any command in `parts` changes interpreter state, so that
+    /// cached responses go stale once it is answered. The input is only
+    /// split into commands while there is a cache to empty.
+    fn stales_cache(&self, parts: &[&[u8]]) -> bool {
+        self.cache().is_on()
+            && readonly::keywords(parts)
+                .iter()
+                .any(|keyword| command::is_mutating(keyword))
+    }
+
+    /// The cache key for `parts` with `trim` in place of the process's
+    /// policy when given, if its response may be cached.
+    pub fn cache_key(&self, parts: &[&[u8]], trim: Option<Trim>) -> Option<cache::Key> {
+        // Full Maude's own state isn't tracked, so nothing is known fresh
+        if self.process.full_maude {
+            return None;
+        }
+        self.cache().key(parts, trim.unwrap_or(self.process.trim))
+    }
+
+    /// The cached response for `key`, counted as a cache hit or miss.
+    pub fn cached(&self, key: &cache::Key) -> Option<Response> {
+        let response = self.cache().get(key);
+        self.process.stats.record_cache(response.is_some());
+
+        // Maude's last search is some other one
+        if response.is_some() && key.1.trim_ascii_start().starts_with(b"search") {
+            self.process.search_active.store(false, Ordering::Relaxed);
+        }
+        response
+    }
+
+    /// Cache `response` for `key`, which Maude answered without complaint.
+    pub fn remember(&self, key: cache::Key, response: &Response) {
+        self.cache().put(key, response);
+    }
+
+    fn pipeline(&self) -> MutexGuard<'_, Pipeline> {
+        self.process
+            .pipeline
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn sent(&self) -> MutexGuard<'_, Option<Sent>> {
+        self.process.sent.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record the current module, or `None` when it is no longer known.
+    pub fn record_selection(&self, module: Option<String>) {
+        *self
+            .process
+            .selection
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = module;
+    }
+
+    /// Overwrite the cached value of a switch, e.g. after Maude rejected a `set`.
+    pub fn record_setting(&self, switch: Switch, value: bool) {
+        self.process
+            .settings
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .set(switch, value);
+    }
+
+    /// Write a single command line to Maude stdin and flush it.
+    pub fn write_command(&self, command: &str) -> Result<(), Failure> {
+        self.write_parts(&[command.as_bytes()])
+    }
+
+    /// Write `parts` back to back as one command line and flush it.
+    ///
+    /// The parts go out in vectored writes rather than being joined first.
+    pub fn write_parts(&self, parts: &[&[u8]]) -> Result<(), Failure> {
+        self.check_writable(parts)?;
+
+        let mut stdin = self
+            .process
+            .stdin
+            .lock()
+            .map_err(|e| format!("stdin lock failed: {}", e))?;
+
+        let mut slices: Vec<IoSlice> = parts
+            .iter()
+            .chain([&b"\n"[..]].iter())
+            .map(|part| IoSlice::new(part))
+            .collect();
+        let mut slices = &mut slices[..];
+
+        while !slices.is_empty() {
+            match stdin.write_vectored(slices) {
+                Ok(0) => return Err("write failed: pipe closed".into()),
+                Ok(n) => IoSlice::advance_slices(&mut slices, n),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("write failed: {}", e).into()),
+            }
+        }
+        self.process.wire_log.record_line(Direction::Stdin, parts);
+
+        stdin
+            .flush()
+            .map_err(|e| format!("flush failed: {}", e).into())
+    }
+
+    /// Read from Maude stdout until we see the "Maude>" prompt, trimming the result.
+    pub fn read_until_prompt(&self) -> Result<String, Failure> {
+        self.read_raw_until_prompt()
+            .map(|output| output.trim().to_string())
+    }
+
+    /// Read from Maude stdout until the output ends with the prompt.
+    ///
+    /// Output that spilled to a file is an error; see [`Response::into_text`].
+    pub fn read_raw_until_prompt(&self) -> Result<String, Failure> {
+        self.read_response()?.into_text()
+    }
+
+    /// Read from Maude stdout until the output ends with the prompt.
+    ///
+    /// Maude prints its prompt without a trailing newline, so the reader works
+    /// on raw chunks instead of lines. The prompt itself is stripped but the
+    /// rest of the output is returned untouched. Once more than the spill
+    /// threshold has arrived, the output so far and everything after it go
+    /// to a file instead of memory.
+    pub fn read_response(&self) -> Result<Response, Failure> {
+        self.read_to_prompt(false)
+    }
+
+    /// Read everything Maude prints for the commands written so far,
+    /// however many prompts that takes, one segment per prompt.
+    ///
+    /// A loaded file may leave Maude printing prompts of its own - an
+    /// unterminated command at its end, say - and output may even contain
+    /// the prompt's text, so prompts alone can't tell where the commands'
+    /// output ends. A marker is written after them instead: `select` of a
+    /// module that doesn't exist, which changes nothing and whose warning
+    /// names the marker. The segment that brings that warning is the
+    /// marker's and ends the read; the warning itself is dropped from the
+    /// diagnostics the next [`Exchange::take_stderr`] returns.
+    fn read_marked(&self) -> Result<Vec<Response>, Failure> {
+        let marker = next_marker();
+        self.trusted(|| self.write_command(&marker_command(&marker)))?;
+        self.read_to_marker(&marker)
+    }
+
+    /// The segments [`Exchange::read_marked`] reads, for a `marker` already
+    /// written.
+    fn read_to_marker(&self, marker: &str) -> Result<Vec<Response>, Failure> {
+        let warning = format!("no module {}.", marker);
+        let mut segments = Vec::new();
+        loop {
+            let segment = self.read_to_prompt(true)?;
+
+            // Maude writes a command's warnings before its prompt
+            let diagnostics = self.take_stderr()?;
+            let (marked, rest): (Vec<&str>, Vec<&str>) = diagnostics
+                .split_inclusive('\n')
+                .partition(|line| line.contains(&warning));
+            self.hold_stderr(&rest.concat());
+
+            if !marked.is_empty() {
+                return Ok(segments);
+            }
+            segments.push(segment);
+        }
+    }
+
+    /// Write the sentinel tagging the command about to be written, if the
+    /// process checks its sequence; see [`crate::sequence`].
+    fn write_sentinel(&self) -> Result<Option<Sentinel>, Failure> {
+        if !self.process.sequence_check {
+            return Ok(None);
+        }
+
+        let sentinel = Sentinel::new(self.process.sequence.fetch_add(1, Ordering::Relaxed));
+        self.trusted(|| self.write_command(&sentinel.command()))?;
+        Ok(Some(sentinel))
+    }
+
+    /// Read the response to `sentinel`, which must come first and be
+    /// empty; otherwise read on to it and fail with what came instead.
+    ///
+    /// The sentinel's warning is dropped from the diagnostics the next
+    /// [`Exchange::take_stderr`] returns, like a load marker's.
+    fn read_sentinel(&self, sentinel: Sentinel) -> Result<(), Failure> {
+        let warning = sentinel.warning();
+        let mut prompts = 0;
+        let mut stray = String::new();
+        loop {
+            let segment = self.read_to_prompt(true)?;
+
+            let diagnostics = self.take_stderr()?;
+            let (marked, rest): (Vec<&str>, Vec<&str>) = diagnostics
+                .split_inclusive('\n')
+                .partition(|line| line.trim_end().ends_with(&warning));
+            self.hold_stderr(&rest.concat());
+
+            if let Response::Spilled(spill) = &segment {
+                let _ = std::fs::remove_file(&spill.path);
+            }
+            stray.push_str(segment.scan());
+
+            if !marked.is_empty() {
+                if prompts == 0 && stray.trim().is_empty() {
+                    return Ok(());
+                }
+                return Err(sentinel.desync(prompts, &stray));
+            }
+            prompts += 1;
+        }
+    }
+
+    /// Keep `diagnostics` for the next [`Exchange::take_stderr`].
+    fn hold_stderr(&self, diagnostics: &str) {
+        self.held().push(diagnostics.as_bytes());
+    }
+
+    fn held(&self) -> MutexGuard<'_, Held> {
+        self.process
+            .held_stderr
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Wait up to `timeout`, or for as long as it takes if `None`, for
+    /// stdout to become readable, `false` if it didn't.
+    ///
+    /// Maude blocks once a pipe it writes to is full, so stderr is drained
+    /// into the held diagnostics meanwhile: a command printing more
+    /// warnings than the pipe holds would otherwise never get to its
+    /// prompt, with this side waiting for it.
+    fn await_stdout(
+        &self,
+        stdout: &ChildStdout,
+        timeout: Option<Duration>,
+    ) -> Result<bool, Failure> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut stderr = self
+            .process
+            .stderr
+            .lock()
+            .map_err(|e| format!("stderr lock failed: {}", e))?;
+
+        loop {
+            let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            let ready = wait_either(stdout, &stderr, remaining)
+                .map_err(|e| format!("poll failed: {}", e))?;
+
+            if ready.stdout {
+                return Ok(true);
+            }
+            if !ready.stderr {
+                return Ok(false);
+            }
+            if !self.drain_stderr(&mut stderr)? {
+                // Closed, so it can't fill up any more
+                drop(stderr);
+                return match remaining {
+                    Some(remaining) => wait_readable(stdout, remaining)
+                        .map_err(|e| format!("poll failed: {}", e).into()),
+                    None => Ok(true),
+                };
+            }
+        }
+    }
+
+    /// Move everything Maude has written to stderr so far into the held
+    /// diagnostics without waiting, `false` once stderr is closed.
+    fn drain_stderr(&self, stderr: &mut ChildStderr) -> Result<bool, Failure> {
+        let mut chunk = [0u8; 4096];
+
+        loop {
+            if !stderr_ready(stderr).map_err(|e| format!("stderr read failed: {}", e))? {
+                return Ok(true);
+            }
+
+            match stderr.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    self.process
+                        .wire_log
+                        .record(Direction::Stderr, &[&chunk[..n]]);
+                    self.held().push(&chunk[..n]);
+                    self.process.events.stderr(&chunk[..n]);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(true),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("stderr read failed: {}", e).into()),
+            }
+        }
+    }
+
+    /// [`Exchange::read_response`], ending at the first prompt instead of
+    /// at a chunk that ends with one if `first` is set, as when further
+    /// responses may follow in the same chunk.
+    fn read_to_prompt(&self, first: bool) -> Result<Response, Failure> {
+        self.check_structured()?;
+
+        let mut stdout = self
+            .process
+            .stdout
+            .lock()
+            .map_err(|e| format!("stdout lock failed: {}", e))?;
+
+        let mut output: Vec<u8> = Vec::new();
+        let mut spiller: Option<Spiller> = None;
+        let mut read = 0;
+        // Only a whole response is read in chunks, not one of several
+        let chunked = !first && self.chunked.get();
+        let filter = self.filter.borrow();
+        let mut lines = filter.as_ref().filter(|_| !first).map(Lines::new);
+        let filtering = lines.is_some();
+        // The prompt is dropped from filtered output, so it is looked for
+        // in the last bytes read, and nothing is held back for it
+        let mut last: Vec<u8> = Vec::new();
+        let held = if filtering { 0 } else { PROMPT.len() - 1 };
+        let mut parts: Vec<Vec<u8>> = Vec::new();
+        let mut parted = 0;
+        let mut sample = Sample::default();
+        // Length of the line being read, until one runs past the limit
+        let mut line = 0;
+        let mut long_line = false;
+
+        loop {
+            if stdout.buffer().is_empty() {
+                self.await_stdout(stdout.get_ref(), None)?;
+            }
+            let chunk = stdout
+                .fill_buf()
+                .map_err(|e| format!("read failed: {}", e))?;
+
+            if chunk.is_empty() {
+                // EOF - process likely exited
+                drop(stdout);
+                let reason = self.exit_reason()?;
+                self.process.close(&reason);
+                if reason == Failure::ResourceLimit || read == 0 {
+                    return Err(reason);
+                }
+                break;
+            }
+
+            let len = if first {
+                prompt_end(&output, chunk).unwrap_or(chunk.len())
+            } else {
+                chunk.len()
+            };
+            if !long_line {
+                line = match chunk[..len].iter().rposition(|&b| b == b'\n') {
+                    Some(newline) => len - newline - 1,
+                    None => line + len,
+                };
+                long_line = line > self.process.max_line_length;
+            }
+            self.process
+                .wire_log
+                .record(Direction::Stdout, &[&chunk[..len]]);
+            match &mut lines {
+                Some(lines) => {
+                    sample.feed(&chunk[..len]);
+                    lines.feed(&chunk[..len], &mut output);
+                    append(&mut last, &chunk[..len]);
+                    last.drain(..last.len().saturating_sub(PROMPT.len()));
+                }
+                None => append(&mut output, &chunk[..len]),
+            }
+            stdout.consume(len);
+            read += len;
+
+            let prompted = if filtering { &last } else { &output };
+            if prompted.ends_with(PROMPT.as_bytes()) {
+                // Don't include the prompt in output
+                match lines.take() {
+                    Some(lines) => lines.finish(PROMPT.as_bytes(), &mut output),
+                    None => output.truncate(output.len() - PROMPT.len()),
+                }
+                break;
+            }
+
+            if spiller.is_some() || parted + output.len() > self.process.spill_threshold {
+                let spiller = match &mut spiller {
+                    Some(spiller) => spiller,
+                    None => spiller.insert(Spiller::create(&self.process.spill_dir)?),
+                };
+                for part in parts.drain(..) {
+                    spiller.write(&part)?;
+                }
+
+                // Hold back enough to spot a prompt split across chunks
+                let keep = output.len().saturating_sub(held);
+                spiller.write(&output[..keep])?;
+                output.drain(..keep);
+            } else if (chunked || long_line) && output.len() >= CHUNK {
+                // Moved rather than copied, holding back as for a spill
+                let tail = output.split_off(output.len() - held);
+                let part = std::mem::replace(&mut output, tail);
+                if chunked && !filtering {
+                    sample.feed(&part);
+                }
+                parted += part.len();
+                parts.push(part);
+            }
+        }
+
+        let response = match spiller {
+            None if chunked || filtering => {
+                if !filtering {
+                    sample.feed(&output);
+                }
+                if !output.is_empty() {
+                    parts.push(output);
+                }
+                Response::Chunks(Chunks {
+                    parts,
+                    sample: sample.finish(),
+                })
+            }
+            None => {
+                // Parts of a long line are copied once, into a buffer of
+                // the right size
+                if !parts.is_empty() {
+                    parts.push(output);
+                    output = parts.concat();
+                }
+                self.decoded(output)
+            }
+            Some(mut spiller) => {
+                spiller.write(&output)?;
+                Response::Spilled(spiller.finish()?)
+            }
+        };
+
+        self.process.stats.record(response.scan());
+        self.process.stats.record_read(read);
+        Ok(response)
+    }
+
+    /// Output read whole as a response, [`Response::Invalid`] if it isn't
+    /// valid UTF-8.
+    fn decoded(&self, output: Vec<u8>) -> Response {
+        match String::from_utf8(output) {
+            Ok(text) => Response::Text(text),
+            Err(e) => {
+                self.process.stats.record_invalid();
+                let raw = e.into_bytes();
+                let text = String::from_utf8_lossy(&raw).into_owned();
+                Response::Invalid { raw, text }
+            }
+        }
+    }
+
+    /// Read stdout into `output` until it ends with the prompt, or with the
+    /// debugger's if `debugger` is set, and drop that prompt.
+    ///
+    /// Given a `spiller`, output past the spill threshold goes to a file
+    /// as [`Exchange::read_response`] sends it, all but the last bytes,
+    /// which may hold part of a prompt, staying in `output`.
+    /// [`Stop::Deadline`] once `deadline` passes with Maude still silent;
+    /// what was read stays in `output` and `spiller` for the next call.
+    fn read_stop(
+        &self,
+        output: &mut Vec<u8>,
+        mut spiller: Option<&mut Option<Spiller>>,
+        deadline: Option<Instant>,
+        debugger: bool,
+    ) -> Result<Stop, Failure> {
+        self.check_structured()?;
+
+        let mut stdout = self
+            .process
+            .stdout
+            .lock()
+            .map_err(|e| format!("stdout lock failed: {}", e))?;
+
+        loop {
+            if output.ends_with(PROMPT.as_bytes()) {
+                output.truncate(output.len() - PROMPT.len());
+                return Ok(Stop::Prompt);
+            }
+            if debugger && output.ends_with(DEBUG_PROMPT.as_bytes()) {
+                output.truncate(output.len() - DEBUG_PROMPT.len());
+                return Ok(Stop::Debugger);
+            }
+
+            if stdout.buffer().is_empty() {
+                let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+                if !self.await_stdout(stdout.get_ref(), remaining)? {
+                    return Ok(Stop::Deadline);
+                }
+            }
+
+            let chunk = stdout
+                .fill_buf()
+                .map_err(|e| format!("read failed: {}", e))?;
+
+            if chunk.is_empty() {
+                drop(stdout);
+                let reason = self.exit_reason()?;
+                self.process.close(&reason);
+                return Err(reason);
+            }
+
+            let len = chunk.len();
+            self.process.wire_log.record(Direction::Stdout, &[chunk]);
+            append(output, chunk);
+            stdout.consume(len);
+            self.process.stats.record_read(len);
+
+            if let Some(spilled) = spiller.as_deref_mut() {
+                if spilled.is_some() || output.len() > self.process.spill_threshold {
+                    let spilled = match spilled {
+                        Some(spilled) => spilled,
+                        None => spilled.insert(Spiller::create(&self.process.spill_dir)?),
+                    };
+                    // Hold back enough to spot either prompt split across
+                    // chunks
+                    let keep = output.len().saturating_sub(DEBUG_PROMPT.len() - 1);
+                    spilled.write(&output[..keep])?;
+                    output.drain(..keep);
+                }
+            }
+        }
+    }
+
+    /// Wait for the prompt a fresh process shows once it's ready.
+    ///
+    /// Anything else - the program exiting, printing output that isn't
+    /// Maude's, or printing nothing for `timeout` - is a startup failure.
+    fn await_first_prompt(&self, timeout: Duration) -> Result<(), SpawnError> {
+        let deadline = Instant::now() + timeout;
+        let mut stdout = self
+            .process
+            .stdout
+            .lock()
+            .map_err(|e| format!("stdout lock failed: {}", e))?;
+
+        let mut output: Vec<u8> = Vec::new();
+
+        loop {
+            if stdout.buffer().is_empty() {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if !self.await_stdout(stdout.get_ref(), Some(remaining))? {
+                    let output = String::from_utf8_lossy(&output);
+                    let failure = if output.is_empty() || output.contains("Maude") {
+                        StartupFailure::BannerTimeout
+                    } else {
+                        StartupFailure::NotMaude
+                    };
+                    return Err(SpawnError::startup(failure, &output, &self.take_stderr()?));
+                }
+            }
+
+            let chunk = stdout
+                .fill_buf()
+                .map_err(|e| format!("read failed: {}", e))?;
+
+            if chunk.is_empty() {
+                self.process.close(&"maude exited".into());
+                let output = String::from_utf8_lossy(&output);
+                return Err(SpawnError::startup(
+                    StartupFailure::NotMaude,
+                    &output,
+                    &self.take_stderr()?,
+                ));
+            }
+
+            let len = chunk.len();
+            self.process.wire_log.record(Direction::Stdout, &[chunk]);
+            append(&mut output, chunk);
+            stdout.consume(len);
+
+            if output.ends_with(PROMPT.as_bytes()) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Why Maude's stdout closed: `"maude exited"`, or
+    /// [`Failure::ResourceLimit`] if it was stopped by a resource limit.
+    ///
+    /// With limits set, the exit status and stderr are checked; stderr is
+    /// consumed, since nothing can follow on a process that has exited.
+    fn exit_reason(&self) -> Result<Failure, Failure> {
+        let exited = Failure::from("maude exited");
+        if self.process.limits.is_empty() {
+            return Ok(exited);
+        }
+
+        // The child closes stdout as it exits but may not be reaped yet
+        let status = {
+            let mut child = self
+                .process
+                .child
+                .lock()
+                .map_err(|e| format!("child lock failed: {}", e))?;
+            let deadline = Instant::now() + Duration::from_millis(500);
+            loop {
+                match child.try_wait() {
+                    Ok(Some(status)) => break Some(status),
+                    Ok(None) if Instant::now() < deadline => {
+                        std::thread::sleep(Duration::from_millis(10))
+                    }
+                    _ => break None,
+                }
+            }
+        };
+
+        let stderr = self.take_stderr()?;
+        match status {
+            Some(status) if self.process.limits.exceeded(status, &stderr) => {
+                Ok(Failure::ResourceLimit)
+            }
+            _ => Ok(exited),
+        }
+    }
+
+    /// Fail unless `parts` may be written as a command now.
+    fn check_writable(&self, parts: &[&[u8]]) -> Result<(), Failure> {
+        self.check_structured()?;
+        if self.sent().is_some() {
+            return Err(Failure::Awaiting);
+        }
+        if self.guarded() {
+            readonly::check(parts)?;
+        }
+        if let Some(sandbox) = self.confinement() {
+            sandbox.check(parts)?;
+        }
+        Ok(())
+    }
+
+    fn check_structured(&self) -> Result<(), Failure> {
+        self.check_lifecycle()?;
+        if self.process.manual.load(Ordering::Relaxed) {
+            return Err("process is in manual mode; call resync/2 first".into());
+        }
+        Ok(())
+    }
+
+    /// Run `f` with the commands it writes let through a read-only
+    /// process, for the NIF's own: markers and trace switches, which leave
+    /// the state as they found it.
+    pub fn trusted<T>(&self, f: impl FnOnce() -> T) -> T {
+        let outer = self.trusted.replace(true);
+        let result = f();
+        self.trusted.set(outer);
+        result
+    }
+
+    /// Whether what is written must pass [`readonly::check`]: on a
+    /// read-only process, past setup, unless trusted.
+    fn guarded(&self) -> bool {
+        self.process.read_only && !self.setup && !self.trusted.get()
+    }
+
+    /// The sandbox what is written must stay in: that of a sandboxed
+    /// process, past setup, unless trusted.
+    fn confinement(&self) -> Option<&Sandbox> {
+        let confined = !self.setup && !self.trusted.get();
+        self.process.sandbox.as_ref().filter(|_| confined)
+    }
+
+    fn check_lifecycle(&self) -> Result<(), Failure> {
+        self.process.lifecycle.check(self.ticket, self.setup)
+    }
+
+    /// Write raw bytes to Maude stdin, switching the process to manual mode.
+    pub fn send_bytes(&self, data: &[u8]) -> Result<(), Failure> {
+        self.check_lifecycle()?;
+        if self.guarded() {
+            return Err("read-only process: raw input is not allowed".into());
+        }
+        if self.confinement().is_some() {
+            return Err("sandboxed process: raw input is not allowed".into());
+        }
+        self.process.manual.store(true, Ordering::Relaxed);
+        // Raw input may change anything
+        self.cache().clear();
+
+        let mut stdin = self
+            .process
+            .stdin
+            .lock()
+            .map_err(|e| format!("stdin lock failed: {}", e))?;
+
+        stdin
+            .write_all(data)
+            .map_err(|e| format!("write failed: {}", e))?;
+        self.process.wire_log.record(Direction::Stdin, &[data]);
+
+        stdin
+            .flush()
+            .map_err(|e| format!("flush failed: {}", e).into())
+    }
+
+    /// Read raw bytes up to and including `pattern`, switching the process
+    /// to manual mode.
+    ///
+    /// Returns `None` if `timeout` passes first; what was read so far is
+    /// kept for the next call. Bytes after the pattern are kept too.
+    pub fn recv_until(
+        &self,
+        pattern: &[u8],
+        timeout: Duration,
+    ) -> Result<Option<Vec<u8>>, Failure> {
+        self.check_lifecycle()?;
+        self.process.manual.store(true, Ordering::Relaxed);
+        let deadline = Instant::now() + timeout;
+
+        let mut pending = self
+            .process
+            .pending
+            .lock()
+            .map_err(|e| format!("pending lock failed: {}", e))?;
+        let mut stdout = self
+            .process
+            .stdout
+            .lock()
+            .map_err(|e| format!("stdout lock failed: {}", e))?;
+
+        // Only the new bytes, plus a pattern's length of overlap, need searching
+        let mut searched = 0;
+
+        loop {
+            if let Some(at) = pending[searched..]
+                .windows(pattern.len())
+                .position(|window| window == pattern)
+            {
+                let end = searched + at + pattern.len();
+                return Ok(Some(pending.drain(..end).collect()));
+            }
+            searched = pending.len().saturating_sub(pattern.len() - 1);
+
+            if stdout.buffer().is_empty() {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if !self.await_stdout(stdout.get_ref(), Some(remaining))? {
+                    return Ok(None);
+                }
+            }
+
+            let chunk = stdout
+                .fill_buf()
+                .map_err(|e| format!("read failed: {}", e))?;
+
+            if chunk.is_empty() {
+                drop(stdout);
+                drop(pending);
+                let reason = self.exit_reason()?;
+                self.process.close(&reason);
+                return Err(reason);
+            }
+
+            let len = chunk.len();
+            self.process.wire_log.record(Direction::Stdout, &[chunk]);
+            append(&mut pending, chunk);
+            stdout.consume(len);
+        }
+    }
+
+    /// Leave manual mode once Maude is back at its prompt.
+    ///
+    /// Sends a marker command and discards everything up to its result,
+    /// so output from earlier raw input can't be mistaken for a response.
+    /// The marker is a `Bool` variable reduced in the current module, so
+    /// the module selection is left as it was; a current module that
+    /// doesn't include `BOOL` can't parse it, and resync times out.
+    /// Fails, staying in manual mode, if the marker doesn't come back
+    /// within `timeout` - e.g. because Maude is still waiting for the rest
+    /// of an unterminated command.
+    pub fn resync(&self, timeout: Duration) -> Result<(), Failure> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let marker = format!("ex_maude_resync_{}", NEXT.fetch_add(1, Ordering::Relaxed));
+
+        self.trusted(|| self.send_bytes(format!("reduce {}:Bool .\n", marker).as_bytes()))?;
+
+        let expected = format!("result Bool: {}:Bool\n{}", marker, PROMPT);
+        if self.recv_until(expected.as_bytes(), timeout)?.is_none() {
+            return Err("resync timed out".into());
+        }
+
+        self.process
+            .pending
+            .lock()
+            .map_err(|e| format!("pending lock failed: {}", e))?
+            .clear();
+        self.take_stderr()?;
+        self.process.search_active.store(false, Ordering::Relaxed);
+        // Raw input may have selected anything
+        self.record_selection(None);
+        self.process.manual.store(false, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Drain everything Maude has written to stderr so far.
+    ///
+    /// Maude finishes writing warnings before it prints the next prompt, so
+    /// calling this after `read_until_prompt` collects the diagnostics of
+    /// that command. The pipe is non-blocking (peeked first on Windows); this
+    /// never waits. Past [`STDERR_LIMIT`] bytes since the last call, the
+    /// rest is dropped and a note says how much.
+    pub fn take_stderr(&self) -> Result<String, Failure> {
+        let mut stderr = self
+            .process
+            .stderr
+            .lock()
+            .map_err(|e| format!("stderr lock failed: {}", e))?;
+        self.drain_stderr(&mut stderr)?;
+        drop(stderr);
+
+        Ok(std::mem::take(&mut *self.held()).into_text())
+    }
+}
+
+/// `elapsed` in microseconds, saturating.
+pub fn micros(elapsed: Duration) -> u64 {
+    u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX)
+}
+
+/// A name for a load marker no other marker has; see
+/// [`Exchange::read_marked`].
+fn next_marker() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    format!("ex_maude_marker_{}", NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+/// The command that writes `marker`.
+fn marker_command(marker: &str) -> String {
+    format!("select {} .", marker)
+}
+
+/// The output of a load's `segments`, one per prompt, as one response.
+///
+/// Segments without output are dropped. Several that are left must all be
+/// in memory; one that spilled is an error naming its file.
+fn join(segments: Vec<Response>) -> Result<Response, Failure> {
+    let mut segments: Vec<Response> = segments
+        .into_iter()
+        .filter(|segment| !matches!(segment, Response::Text(text) if text.is_empty()))
+        .collect();
+    if segments.len() <= 1 {
+        return Ok(segments.pop().unwrap_or(Response::Text(String::new())));
+    }
+
+    let mut output = Vec::new();
+    for segment in segments {
+        match segment {
+            Response::Text(text) => output.extend_from_slice(text.as_bytes()),
+            Response::Invalid { raw, .. } => output.extend_from_slice(&raw),
+            spilled => return spilled.into_text().map(Response::Text),
+        }
+    }
+
+    Ok(Response::from_bytes(output))
+}
+
+/// The first `COMMAND_HEAD` bytes of a command, and whether that is all of it.
+fn command_head(parts: &[&[u8]]) -> (String, bool) {
+    let mut head = Vec::with_capacity(COMMAND_HEAD);
+
+    for part in parts {
+        let room = COMMAND_HEAD - head.len();
+        head.extend_from_slice(&part[..room.min(part.len())]);
+    }
+
+    let whole = parts.iter().map(|part| part.len()).sum::<usize>() <= COMMAND_HEAD;
+    (String::from_utf8_lossy(&head).into_owned(), whole)
+}
+
+/// Bytes of `chunk` up to the end of the first prompt in `output` followed
+/// by `chunk`, if one ends in `chunk`.
+fn prompt_end(output: &[u8], chunk: &[u8]) -> Option<usize> {
+    let prompt = PROMPT.as_bytes();
+    // A prompt may have started at the end of the output so far
+    let carried = output.len().min(prompt.len() - 1);
+    let mut window = output[output.len() - carried..].to_vec();
+    window.extend_from_slice(chunk);
+
+    window
+        .windows(prompt.len())
+        .position(|w| w == prompt)
+        .map(|at| at + prompt.len() - carried)
+}
+
+/// Classify an error from starting the executable itself.
+fn spawn_failure(maude_path: &str, e: std::io::Error) -> SpawnError {
+    #[cfg(unix)]
+    let exec_format = e.raw_os_error() == Some(libc::ENOEXEC);
+    // ERROR_BAD_EXE_FORMAT
+    #[cfg(windows)]
+    let exec_format = e.raw_os_error() == Some(193);
+    #[cfg(not(any(unix, windows)))]
+    let exec_format = false;
+
+    if exec_format {
+        return SpawnError::startup(
+            StartupFailure::ExecFormatError,
+            "",
+            &format!("{}: {}", maude_path, e),
+        );
+    }
+
+    SpawnError::from(format!("spawn failed: {}", e))
+}
+
+/// Wait up to `timeout` for `pipe` to become readable.
+#[cfg(unix)]
+fn wait_readable(pipe: &impl std::os::fd::AsRawFd, timeout: Duration) -> std::io::Result<bool> {
+    let mut poll = libc::pollfd {
+        fd: pipe.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+
+    loop {
+        // SAFETY: `poll` points to one valid pollfd for the duration of the call.
+        match unsafe { libc::poll(&mut poll, 1, timeout) } {
+            n if n > 0 => return Ok(true),
+            0 => return Ok(false),
+            _ => {
+                let e = std::io::Error::last_os_error();
+                if e.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// Wait up to `timeout` for `pipe` to become readable.
+///
+/// Anonymous pipes can't be waited on, so the pipe is peeked every
+/// [`crate::windows::POLL_INTERVAL`]; a closed pipe counts as readable, so
+/// the read that follows sees EOF.
+#[cfg(windows)]
+fn wait_readable(
+    pipe: &impl std::os::windows::io::AsRawHandle,
+    timeout: Duration,
+) -> std::io::Result<bool> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if crate::windows::available(pipe)? != Some(0) {
+            return Ok(true);
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+        std::thread::sleep(remaining.min(crate::windows::POLL_INTERVAL));
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn wait_readable<T>(_pipe: &T, _timeout: Duration) -> std::io::Result<bool> {
+    Ok(true)
+}
+
+/// Diagnostics drained from stderr but not yet taken, up to
+/// [`STDERR_LIMIT`] bytes; anything past that is only counted.
+#[derive(Debug, Default)]
+struct Held {
+    bytes: Vec<u8>,
+    dropped: u64,
+}
+
+impl Held {
+    fn push(&mut self, data: &[u8]) {
+        let kept = STDERR_LIMIT
+            .saturating_sub(self.bytes.len())
+            .min(data.len());
+        self.bytes.extend_from_slice(&data[..kept]);
+        self.dropped += (data.len() - kept) as u64;
+    }
+
+    fn into_text(self) -> String {
+        let mut text = String::from_utf8_lossy(&self.bytes).into_owned();
+        if self.dropped > 0 {
+            if !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+            text.push_str(&format!(
+                "({} more bytes of stderr dropped)\n",
+                self.dropped
+            ));
+        }
+        text
+    }
+}
+
+/// Which of stdout and stderr [`wait_either`] found readable.
+#[derive(Debug, Clone, Copy)]
+struct Ready {
+    stdout: bool,
+    stderr: bool,
+}
+
+/// Wait up to `timeout`, or indefinitely if `None`, for `stdout` or
+/// `stderr` to become readable.
+#[cfg(unix)]
+fn wait_either(
+    stdout: &ChildStdout,
+    stderr: &ChildStderr,
+    timeout: Option<Duration>,
+) -> std::io::Result<Ready> {
+    use std::os::fd::AsRawFd;
+
+    let mut polls = [stdout.as_raw_fd(), stderr.as_raw_fd()].map(|fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    });
+    let timeout = timeout.map_or(-1, |timeout| {
+        timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int
+    });
+
+    loop {
+        // SAFETY: `polls` points to two valid pollfds for the duration of the call.
+        match unsafe { libc::poll(polls.as_mut_ptr(), 2, timeout) } {
+            n if n >= 0 => {
+                // A hangup or error is readable too: the read reports it
+                return Ok(Ready {
+                    stdout: polls[0].revents != 0,
+                    stderr: polls[1].revents != 0,
+                });
+            }
+            _ => {
+                let e = std::io::Error::last_os_error();
+                if e.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// Wait up to `timeout`, or indefinitely if `None`, for `stdout` or
+/// `stderr` to become readable, peeking both every
+/// [`crate::windows::POLL_INTERVAL`]; a closed pipe counts as readable.
+#[cfg(windows)]
+fn wait_either(
+    stdout: &ChildStdout,
+    stderr: &ChildStderr,
+    timeout: Option<Duration>,
+) -> std::io::Result<Ready> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    loop {
+        let ready = Ready {
+            stdout: crate::windows::available(stdout)? != Some(0),
+            stderr: crate::windows::available(stderr)? != Some(0),
+        };
+        if ready.stdout || ready.stderr {
+            return Ok(ready);
+        }
+
+        let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        if remaining.is_some_and(|remaining| remaining.is_zero()) {
+            return Ok(ready);
+        }
+        let nap = crate::windows::POLL_INTERVAL;
+        std::thread::sleep(remaining.map_or(nap, |remaining| remaining.min(nap)));
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn wait_either(
+    _stdout: &ChildStdout,
+    _stderr: &ChildStderr,
+    _timeout: Option<Duration>,
+) -> std::io::Result<Ready> {
+    Ok(Ready {
+        stdout: true,
+        stderr: false,
+    })
+}
+
+/// Whether a read of stderr can return without waiting.
+///
+/// On Unix the pipe is non-blocking and the read itself says so.
+#[cfg(not(windows))]
+fn stderr_ready<T>(_pipe: &T) -> std::io::Result<bool> {
+    Ok(true)
+}
+
+/// Whether a read of stderr can return without waiting: data is buffered,
+/// or the pipe is closed and the read returns EOF.
+#[cfg(windows)]
+fn stderr_ready(pipe: &ChildStderr) -> std::io::Result<bool> {
+    Ok(crate::windows::available(pipe)? != Some(0))
+}
+
+/// Append a chunk read from stdout to `output`.
+#[cfg(not(windows))]
+fn append(output: &mut Vec<u8>, chunk: &[u8]) {
+    output.extend_from_slice(chunk);
+}
+
+/// Append a chunk read from stdout to `output`, turning CRLF line endings
+/// into LF so responses, prompt detection, and `resync` markers look the
+/// same as on Unix. A CR ending one chunk is matched with an LF starting
+/// the next.
+#[cfg(windows)]
+fn append(output: &mut Vec<u8>, chunk: &[u8]) {
+    if output.last() == Some(&b'\r') && chunk.first() == Some(&b'\n') {
+        output.pop();
+    }
+
+    for (i, &byte) in chunk.iter().enumerate() {
+        if byte != b'\r' || chunk.get(i + 1) != Some(&b'\n') {
+            output.push(byte);
+        }
+    }
+}
+
+impl Drop for MaudeProcess {
+    fn drop(&mut self) {
+        // A handle dropped without `stop/1` takes its child with it, reaped
+        // so it isn't left a zombie; `orphan_check` finds any that escape
+        if let Ok(child) = self.child.get_mut() {
+            if matches!(child.try_wait(), Ok(None)) {
+                #[cfg(windows)]
+                self.job.terminate();
+                let _ = child.kill();
+            }
+            // Untracked before it is reaped, while its pid can't be reused
+            if let Some(pid) = child.id() {
+                crate::orphan::untrack(pid);
+            }
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Put a pipe into non-blocking mode so it can be drained opportunistically.
+#[cfg(unix)]
+fn set_nonblocking(pipe: &impl std::os::fd::AsRawFd) -> std::io::Result<()> {
+    let fd = pipe.as_raw_fd();
+
+    // SAFETY: `fd` is an open descriptor owned by `pipe` for this call.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Anonymous pipes can't be made non-blocking on Windows; `take_stderr`
+/// peeks before each read instead.
+#[cfg(not(unix))]
+fn set_nonblocking<T>(_pipe: &T) -> std::io::Result<()> {
+    Ok(())
+}
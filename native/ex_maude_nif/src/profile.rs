@@ -0,0 +1,351 @@
+//! Parsed results of Maude's profiler.
+//!
+//! With `set profile on .` Maude counts what each statement did during a
+//! command, and `show profile .` prints one block per statement that was
+//! used:
+//!
+//! ```text
+//! op _+_ : [Nat] [Nat] -> [Nat] .
+//! built-in eq rewrites: 5 (45.4545%)
+//!
+//! eq [base] : f(0) = 0 .
+//! rewrites: 1 (9.09091%)
+//!
+//! crl [step] : c(N) => c(s N) if N < 3 = true .
+//! lhs matches: 4    rewrites: 3 (37.5%)
+//! Fragment    Initial tries    Resolve tries    Successes    Failures
+//! 1           4                0                3            1
+//! ```
+//!
+//! with tabs between the columns, where the percentage is of all rewrites
+//! in the command. Conditional statements also report how often their
+//! left-hand side matched and, per condition fragment, how often it was
+//! tried and held.
+//!
+//! `execute_profiled/2` switches profiling on for one command and back to
+//! what it was afterwards. Maude clears the counts when a command starts
+//! unless `set clear profile off .` is in effect, in which case they
+//! include earlier commands too.
+
+use crate::diagnostics::Outcome;
+use crate::failure::Failure;
+use crate::input::Input;
+use crate::process::{Exchange, MaudeProcess, Response};
+use crate::reply;
+use crate::settings::Switch;
+use crate::trace;
+use rustler::{Encoder, Env, LocalPid, NifMap, NifResult, ResourceArc, Term};
+
+rustler::atoms! {
+    equation,
+    rule,
+    membership,
+    strategy,
+    builtin,
+}
+
+/// Counts of one condition fragment of a conditional statement.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Fragment {
+    /// Fragment number, counted from 1.
+    pub number: u64,
+    pub initial_tries: u64,
+    pub resolve_tries: u64,
+    pub successes: u64,
+    pub failures: u64,
+}
+
+/// Kind of statement a profile entry counts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Equation,
+    Rule,
+    Membership,
+    Strategy,
+    /// An operator's built-in equations.
+    Builtin,
+}
+
+impl Encoder for Kind {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            Kind::Equation => equation(),
+            Kind::Rule => rule(),
+            Kind::Membership => membership(),
+            Kind::Strategy => strategy(),
+            Kind::Builtin => builtin(),
+        }
+        .encode(env)
+    }
+}
+
+/// What one statement did during the profiled command.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct ProfileEntry {
+    /// `:equation`, `:rule`, `:membership`, `:strategy`, or `:builtin` for
+    /// an operator's built-in equations.
+    pub kind: Kind,
+    /// Statement label, if it has one.
+    pub label: Option<String>,
+    /// The statement as Maude prints it, e.g. `"eq [base] : f(0) = 0 ."`.
+    pub statement: String,
+    pub rewrites: u64,
+    /// Share of all the command's rewrites, in percent.
+    pub percent: f64,
+    /// Left-hand side matches of a conditional statement.
+    pub matches: Option<u64>,
+    /// Condition fragments of a conditional statement.
+    pub fragments: Vec<Fragment>,
+}
+
+/// Result of `execute_profiled/2`.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Profiled {
+    /// Command output.
+    pub output: String,
+    /// Statements used, in the order `show profile` prints them.
+    pub entries: Vec<ProfileEntry>,
+}
+
+/// Parse the output of `show profile .`.
+pub fn parse(output: &str) -> Result<Vec<ProfileEntry>, String> {
+    let mut entries = Vec::new();
+    let mut block: Vec<&str> = Vec::new();
+
+    for line in output.lines().chain([""]) {
+        if !line.trim().is_empty() {
+            block.push(line);
+        } else if !block.is_empty() {
+            entries.push(parse_entry(&block)?);
+            block.clear();
+        }
+    }
+
+    Ok(entries)
+}
+
+fn parse_entry(block: &[&str]) -> Result<ProfileEntry, String> {
+    let counts = block
+        .iter()
+        .position(|line| counts(line).is_some())
+        .ok_or_else(|| format!("no rewrite count for {:?}", block[0]))?;
+    let statement = block[..counts].join("\n");
+    let (matches, rewrites, percent) = parse_counts(block[counts])
+        .ok_or_else(|| format!("invalid rewrite count: {:?}", block[counts]))?;
+
+    let keyword = statement.split_whitespace().next().unwrap_or("");
+    let kind = match keyword {
+        "eq" | "ceq" => Kind::Equation,
+        "rl" | "crl" => Kind::Rule,
+        "mb" | "cmb" => Kind::Membership,
+        "sd" | "csd" => Kind::Strategy,
+        _ => Kind::Builtin,
+    };
+    let label = if kind == Kind::Builtin {
+        None
+    } else {
+        trace::statement_label(&statement)
+    };
+
+    // Rows under the `Fragment` header
+    let fragments = block[counts + 1..]
+        .iter()
+        .filter(|line| !line.starts_with("Fragment"))
+        .map(|line| parse_fragment(line))
+        .collect::<Result<_, _>>()?;
+
+    Ok(ProfileEntry {
+        kind,
+        label,
+        statement,
+        rewrites,
+        percent,
+        matches,
+        fragments,
+    })
+}
+
+/// The lhs matches, if given, and the rest of a counts line from
+/// `rewrites:` on; `None` if `line` isn't one.
+fn counts(line: &str) -> Option<(Option<&str>, &str)> {
+    let line = line.trim();
+    let (matches, rest) = match line.strip_prefix("lhs matches: ") {
+        Some(rest) => {
+            let (matches, rest) = rest.split_once(char::is_whitespace)?;
+            (Some(matches), rest.trim_start())
+        }
+        None => (None, line),
+    };
+    let rest = rest
+        .strip_prefix("rewrites: ")
+        .or_else(|| rest.strip_prefix("built-in eq rewrites: "))?;
+    Some((matches, rest))
+}
+
+/// The `(lhs matches, rewrites, percent)` of a counts line.
+fn parse_counts(line: &str) -> Option<(Option<u64>, u64, f64)> {
+    let (matches, rest) = counts(line)?;
+    let (rewrites, percent) = rest.split_once(" (")?;
+    let percent = percent.strip_suffix("%)")?.parse().ok()?;
+    let matches = match matches {
+        Some(matches) => Some(matches.parse().ok()?),
+        None => None,
+    };
+    Some((matches, rewrites.parse().ok()?, percent))
+}
+
+fn parse_fragment(line: &str) -> Result<Fragment, String> {
+    let numbers = line
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<u64>, _>>()
+        .map_err(|_| format!("invalid fragment counts: {:?}", line))?;
+
+    match numbers[..] {
+        [number, initial_tries, resolve_tries, successes, failures] => Ok(Fragment {
+            number,
+            initial_tries,
+            resolve_tries,
+            successes,
+            failures,
+        }),
+        _ => Err(format!("invalid fragment counts: {:?}", line)),
+    }
+}
+
+/// Set profiling on or off; restored afterwards, so allowed on a read-only
+/// process.
+fn set_profile(exchange: &Exchange, value: bool) -> Result<(), Failure> {
+    let command = Switch::Profile.command(value);
+    exchange.trusted(|| exchange.execute(&command)).map(drop)
+}
+
+fn profiled(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    command: &Input,
+) -> Result<Profiled, Failure> {
+    // One exchange, so no other caller's command is profiled
+    let exchange = process.begin_by(caller)?;
+    let previous = process.settings().get(Switch::Profile);
+
+    set_profile(&exchange, true)?;
+    let result = exchange
+        .execute_response(&command.parts())
+        .and_then(Response::into_text)
+        .and_then(|output| {
+            let profile = exchange.execute("show profile .")?;
+            Ok((output, profile))
+        });
+    let restored = set_profile(&exchange, previous);
+    drop(exchange);
+
+    let (output, profile) = result?;
+    restored?;
+
+    let entries = parse(&profile).map_err(|e| format!("parse failed: {}", e))?;
+    Ok(Profiled { output, entries })
+}
+
+/// Execute a command with Maude's profiler on and parse what it counted.
+///
+/// Profiling is switched on for this command only and set back to its
+/// previous value after `show profile .` has been read, even if the
+/// command fails.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `command` - Maude command to execute
+///
+/// # Returns
+/// * `Ok(Profiled)` - `%{output: String.t(), entries: [entry]}`
+/// * `Err` - If I/O fails or the profile can't be parsed
+#[rustler::nif(schedule = "DirtyCpu")]
+fn execute_profiled<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    command: Input<'a>,
+) -> NifResult<Outcome<Profiled>> {
+    reply(profiled(&process, env.pid(), &command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_show_profile() {
+        let output = "\
+op _+_ : [Nat] [Nat] -> [Nat] .
+built-in eq rewrites: 5 (45.4545%)
+
+eq [base] : f(0) = 0 .
+rewrites: 1 (9.09091%)
+
+crl [step] : c(N) => c(s N) if N < 3 = true .
+lhs matches: 4\trewrites: 3 (37.5%)
+Fragment\tInitial tries\tResolve tries\tSuccesses\tFailures
+1\t4\t0\t3\t1
+
+mb s N:Nat : NzNat [label pos] .
+rewrites: 1 (9.09091%)
+
+sd again := step ; again .
+rewrites: 0 (0%)
+";
+        let entries = parse(output).unwrap();
+
+        assert_eq!(entries.len(), 5);
+
+        assert_eq!(entries[0].kind, Kind::Builtin);
+        assert_eq!(entries[0].label, None);
+        assert_eq!(entries[0].statement, "op _+_ : [Nat] [Nat] -> [Nat] .");
+        assert_eq!(entries[0].rewrites, 5);
+        assert_eq!(entries[0].percent, 45.4545);
+        assert_eq!(entries[0].matches, None);
+
+        assert_eq!(entries[1].kind, Kind::Equation);
+        assert_eq!(entries[1].label.as_deref(), Some("base"));
+        assert_eq!(entries[1].rewrites, 1);
+        assert!(entries[1].fragments.is_empty());
+
+        let step = &entries[2];
+        assert_eq!(step.kind, Kind::Rule);
+        assert_eq!(step.label.as_deref(), Some("step"));
+        assert_eq!(step.matches, Some(4));
+        assert_eq!((step.rewrites, step.percent), (3, 37.5));
+        assert_eq!(step.fragments.len(), 1);
+        let fragment = &step.fragments[0];
+        assert_eq!(
+            (
+                fragment.number,
+                fragment.initial_tries,
+                fragment.resolve_tries,
+                fragment.successes,
+                fragment.failures
+            ),
+            (1, 4, 0, 3, 1)
+        );
+
+        assert_eq!(entries[3].kind, Kind::Membership);
+        assert_eq!(entries[3].label.as_deref(), Some("pos"));
+        assert_eq!(entries[4].kind, Kind::Strategy);
+        assert_eq!(entries[4].percent, 0.0);
+    }
+
+    #[test]
+    fn parses_an_empty_profile() {
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse("\n\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        assert!(parse("eq f(0) = 0 .").is_err());
+        assert!(parse("eq f(0) = 0 .\nrewrites: one (9%)").is_err());
+        assert!(parse("crl a => b if c .\nrewrites: 1 (100%)\n1\t4\t0").is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! Read-only sessions.
+//!
+//! A process started with `read_only: true` keeps the modules, selection,
+//! and settings it was set up with: its preload files and `:select` are
+//! run as usual, but afterwards every command that would change them is
+//! refused before it is written - `load`, `select`, `set`, module and view
+//! definitions, `quit`, and the like. That lets a warm process be shared
+//! between callers that mustn't see each other's changes, or make any.
+//! `erewrite` is refused too: its external objects can write files and
+//! start processes, which are changes other callers see.
+//!
+//! The input of execute calls is free text, possibly several commands on
+//! several lines, so [`keywords`] splits it the way Maude does: tokens end at
+//! whitespace and at brackets and commas, string literals and comments are
+//! skipped, and a command starts after a token ending in `.`. It doesn't
+//! count brackets, so a `.` that Maude would read as part of a term still
+//! starts a command here; that can refuse an odd query, but nothing Maude
+//! runs as a command gets past. Raw input through `send_bytes/2` can't be
+//! split reliably - a command may come in pieces - and is refused outright.
+
+use crate::command;
+
+/// Commands refused beyond those [`command::is_mutating`] names: leaving,
+/// changing directory, tracing, debugging and printing controls, memo
+/// clearing, the read-eval-print loop, and rewriting with external objects.
+const REFUSED: &[&str] = &[
+    "quit", "q", "eof", "cd", "push", "pop", "trace", "break", "print", "do", "debug", "loop",
+    "erewrite", "erew",
+];
+
+/// Bytes of a token kept to compare against the refused keywords.
+const KEYWORD: usize = 8;
+
+// A longer keyword would be compared cut short, and never match
+const _: () = assert!(longest(REFUSED) <= KEYWORD && longest(command::MUTATING) <= KEYWORD);
+
+/// Length of the longest of `keywords`.
+const fn longest(keywords: &[&str]) -> usize {
+    let mut longest = 0;
+    let mut at = 0;
+    while at < keywords.len() {
+        if keywords[at].len() > longest {
+            longest = keywords[at].len();
+        }
+        at += 1;
+    }
+    longest
+}
+
+/// Whether a command starting with `keyword` is refused.
+fn refused(keyword: &str) -> bool {
+    command::is_mutating(keyword) || REFUSED.contains(&keyword)
+}
+
+/// The token being read: its start, and its last byte.
+#[derive(Default)]
+struct Token {
+    head: Vec<u8>,
+    last: Option<u8>,
+}
+
+impl Token {
+    fn push(&mut self, byte: u8) {
+        if self.head.len() < KEYWORD {
+            self.head.push(byte);
+        }
+        self.last = Some(byte);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.last.is_none()
+    }
+
+    /// Whether the token so far opens a comment.
+    fn is_comment(&self) -> bool {
+        self.head == b"***" || self.head == b"---"
+    }
+}
+
+/// Where commands start, as tokens go by.
+struct Commands {
+    /// Whether the next token starts a command.
+    expecting: bool,
+    /// The keyword of each command so far.
+    keywords: Vec<String>,
+}
+
+impl Commands {
+    /// Take one token.
+    fn token(&mut self, token: &Token) {
+        if self.expecting {
+            let keyword = String::from_utf8_lossy(&token.head);
+            // Full Maude commands come in parentheses, one after another
+            if keyword != "(" && keyword != ")" {
+                self.keywords.push(keyword.into_owned());
+                self.expecting = false;
+            }
+        }
+        if token.last == Some(b'.') {
+            self.expecting = true;
+        }
+    }
+
+    /// End the token being read, if any.
+    fn end(&mut self, token: &mut Token) {
+        if !token.is_empty() {
+            self.token(token);
+        }
+        *token = Token::default();
+    }
+}
+
+/// Check that a command, given as consecutive parts, changes no state.
+pub fn check(parts: &[&[u8]]) -> Result<(), String> {
+    match keywords(parts).into_iter().find(|keyword| refused(keyword)) {
+        Some(keyword) => Err(format!("read-only process: `{}` is not allowed", keyword)),
+        None => Ok(()),
+    }
+}
+
+/// The keyword starting each command in `parts`, cut to [`KEYWORD`] bytes.
+pub fn keywords(parts: &[&[u8]]) -> Vec<String> {
+    let mut bytes = parts
+        .iter()
+        .flat_map(|part| part.iter().copied())
+        .peekable();
+    let mut commands = Commands {
+        expecting: true,
+        keywords: Vec::new(),
+    };
+    let mut token = Token::default();
+
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'"' => {
+                commands.end(&mut token);
+                // Literals are single tokens that start no command
+                while let Some(byte) = bytes.next() {
+                    match byte {
+                        b'\\' => {
+                            bytes.next();
+                        }
+                        b'"' => break,
+                        _ => {}
+                    }
+                }
+                token.push(b'"');
+                commands.end(&mut token);
+            }
+            b'(' | b')' | b'[' | b']' | b'{' | b'}' | b',' => {
+                commands.end(&mut token);
+                token.push(byte);
+                commands.end(&mut token);
+            }
+            byte if byte.is_ascii_whitespace() => commands.end(&mut token),
+            byte => {
+                token.push(byte);
+                if !token.is_comment() {
+                    continue;
+                }
+                token = Token::default();
+
+                if bytes.peek() == Some(&b'(') {
+                    // `***(` runs to the matching `)`
+                    let mut depth = 0;
+                    for byte in bytes.by_ref() {
+                        match byte {
+                            b'(' => depth += 1,
+                            b')' if depth == 1 => break,
+                            b')' => depth -= 1,
+                            _ => {}
+                        }
+                    }
+                } else {
+                    // Anything else to the end of the line
+                    bytes.by_ref().find(|&byte| byte == b'\n');
+                }
+            }
+        }
+    }
+
+    commands.end(&mut token);
+    commands.keywords
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checked(input: &str) -> Result<(), String> {
+        check(&[input.as_bytes()])
+    }
+
+    #[test]
+    fn refuses_a_later_command() {
+        assert!(checked("red 1 .").is_ok());
+        assert!(checked("red 1 . select FOO .").is_err());
+        assert!(checked("red 1 .\nset trace on .").is_err());
+        assert!(checked("red 1 .\nloop init .").is_err());
+        assert!(checked("erew <> .").is_err());
+    }
+
+    #[test]
+    fn skips_comments() {
+        assert!(checked("***( select FOO . ) red 1 .").is_ok());
+        assert!(checked("red 1 . ***( (nested) select FOO . )").is_ok());
+        assert!(checked("red 1 . *** select FOO .").is_ok());
+        assert!(checked("***( comment ) select FOO .").is_err());
+        assert!(checked("--- comment\nselect FOO .").is_err());
+    }
+
+    #[test]
+    fn checks_full_maude_commands() {
+        assert!(checked("(red 1 .)").is_ok());
+        assert!(checked("(select FOO .)").is_err());
+        assert!(checked("(red 1 .) (select FOO .)").is_err());
+    }
+
+    #[test]
+    fn finds_every_command() {
+        assert_eq!(
+            keywords(&[b"red 1 . (select FOO .) *** set\nset trace on ."]),
+            ["red", "select", "set"]
+        );
+        assert_eq!(keywords(&[b"reduce in NAT : \"a.\" ."]), ["reduce"]);
+    }
+
+    #[test]
+    fn splits_across_parts() {
+        assert!(check(&[b"red 1 . sel", b"ect FOO ."]).is_err());
+        assert!(check(&[b"red 1 . ***", b"( select FOO . )"]).is_ok());
+    }
+}
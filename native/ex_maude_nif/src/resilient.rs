@@ -0,0 +1,279 @@
+//! Maude processes that are replaced when they die.
+//!
+//! A resilient process runs its commands on one Maude subprocess, like a
+//! plain one, but a subprocess found dead - before a command, or because
+//! it exited while answering one - is replaced by a new one and the
+//! command runs there. The new subprocess is brought to the state of the
+//! old by its preload files and the journal of state-mutating commands
+//! (see [`command::is_mutating`]), as a hibernating process is on wake.
+//!
+//! Each call restarts at most once, so a command that crashes Maude every
+//! time it runs fails after one retry rather than looping; the next call
+//! starts from a fresh subprocess again. A command that exceeded a
+//! resource limit isn't retried, nor one that asks Maude to exit. With
+//! `meta: true` the `restarted` field of the meta map tells whether the
+//! call restarted.
+
+use crate::command;
+use crate::diagnostics::Outcome;
+use crate::error;
+use crate::events::Event;
+use crate::failure::Failure;
+use crate::format::{self, Meta, OutputOptions};
+use crate::input::Input;
+use crate::options::SpawnOptions;
+use crate::process::{MaudeProcess, Response};
+use crate::startup::SpawnError;
+use rustler::{Atom, LocalPid, NifMap, NifResult, ResourceArc};
+use std::sync::{Mutex, MutexGuard};
+
+rustler::atoms! {
+    ok,
+}
+
+struct Resilient {
+    process: MaudeProcess,
+    /// Every mutating command so far, replayed on restart.
+    journal: Vec<String>,
+    restarts: u64,
+    stopped: bool,
+}
+
+/// Handle to a resilient process shared with Elixir.
+pub struct ResilientProcess {
+    maude_path: String,
+    options: SpawnOptions,
+    state: Mutex<Resilient>,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for ResilientProcess {}
+
+/// Report returned by `resilient_status/1`.
+#[derive(Debug, NifMap)]
+pub struct ResilienceStatus {
+    /// Whether the current subprocess is running.
+    pub alive: bool,
+    /// Number of times a dead subprocess was replaced.
+    pub restarts: u64,
+    /// Number of mutating commands in the journal.
+    pub journal: usize,
+}
+
+/// Whether `command` asks Maude to exit, so its subprocess dying is no
+/// failure.
+fn exits(command: &str) -> bool {
+    let keyword = command.split_whitespace().next().unwrap_or("");
+    matches!(keyword, "quit" | "q" | "eof")
+}
+
+impl ResilientProcess {
+    fn state(&self) -> Result<MutexGuard<'_, Resilient>, String> {
+        self.state
+            .lock()
+            .map_err(|e| format!("resilient lock failed: {}", e))
+    }
+
+    fn execute(&self, command: &str, opts: &OutputOptions) -> Result<Outcome, Failure> {
+        let mut state = self.state()?;
+        if state.stopped {
+            return Err("process stopped".into());
+        }
+
+        let mut restarted = false;
+        if !state.process.is_alive() {
+            self.restart(&mut state)?;
+            restarted = true;
+        }
+
+        let (response, meta, stderr) = loop {
+            let result = run(&state.process, command, opts);
+            // Output cut short by the exit counts as a failure too
+            let crashed = !state.process.is_alive() && !exits(command);
+            match result {
+                Err(Failure::ResourceLimit) => return Err(Failure::ResourceLimit),
+                Err(Failure::Desync(details)) if !crashed => return Ok(Outcome::Desync(details)),
+                Ok(done) if !crashed => break done,
+                Err(e) if !crashed => return Err(e),
+                partial => {
+                    if let Ok((Response::Spilled(spill), ..)) = partial {
+                        let _ = std::fs::remove_file(&spill.path);
+                    }
+                    if restarted {
+                        return Err("maude exited again after restart".into());
+                    }
+                    self.restart(&mut state)?;
+                    restarted = true;
+                }
+            }
+        };
+
+        if let Some(failed) = Outcome::failed(&stderr) {
+            return Ok(failed);
+        }
+        if command::is_mutating(command) {
+            state.journal.push(command.to_string());
+        }
+        drop(state);
+
+        let meta = Meta { restarted, ..meta };
+        format::render_response(response, opts)
+            .map(|output| Outcome::Done(output.metered(opts, meta)))
+            .map_err(Failure::from)
+    }
+
+    /// Replace the subprocess with a new one brought to the same state.
+    ///
+    /// Diagnostics from the replay were already reported when the commands
+    /// first ran, so they are discarded rather than failing the restart.
+    fn restart(&self, state: &mut Resilient) -> Result<(), String> {
+        let process = MaudeProcess::spawn(&self.maude_path, &self.options)
+            .map_err(|e| format!("restart failed: {}", e))?;
+
+        let exchange = process.begin();
+        let replayed = state
+            .journal
+            .iter()
+            .try_for_each(|command| exchange.execute(command).map(drop))
+            .and_then(|()| exchange.take_stderr().map(drop));
+        drop(exchange);
+
+        if let Err(e) = replayed {
+            let _ = process.shutdown();
+            return Err(format!("restart failed: {}", e));
+        }
+
+        // The replay isn't reported, only the restart it completes
+        if let Err(e) = process.events().adopt(state.process.events()) {
+            let _ = process.shutdown();
+            return Err(format!("restart failed: {}", e));
+        }
+        let dead = std::mem::replace(&mut state.process, process);
+        let _ = dead.shutdown();
+        state.restarts += 1;
+        let restarts = state.restarts;
+        state.process.events().emit(|| Event::Restarted {
+            restarts,
+            os_pid: state.process.os_pid().ok(),
+        });
+        Ok(())
+    }
+
+    /// Send `pid` the events of every subprocess from now on; see
+    /// [`crate::events`].
+    pub fn subscribe_events(&self, pid: LocalPid) -> Result<(), String> {
+        self.state()?.process.events().subscribe(pid)
+    }
+
+    /// Stop sending events to `pid`, returning whether it was subscribed.
+    pub fn unsubscribe_events(&self, pid: LocalPid) -> Result<bool, Failure> {
+        Ok(self.state()?.process.events().unsubscribe(pid))
+    }
+}
+
+/// Run `command` in one exchange, with the diagnostics it left.
+fn run(
+    process: &MaudeProcess,
+    command: &str,
+    opts: &OutputOptions,
+) -> Result<(Response, Meta, String), Failure> {
+    let exchange = process.begin();
+    let (response, meta) = exchange.execute_metered(&[command.as_bytes()], opts)?;
+    let stderr = exchange.take_stderr()?;
+    Ok((response, meta, stderr))
+}
+
+/// Start a Maude process that is replaced whenever it dies.
+///
+/// # Arguments
+/// * `maude_path` - Path to the Maude executable
+/// * `opts` - Keyword list of spawn options, as for `start_with_opts/2`;
+///   every subprocess started for the handle uses them
+///
+/// # Returns
+/// * `Ok(ResourceArc<ResilientProcess>)` - Handle to the process
+/// * `Err` - If the first subprocess fails to start
+#[rustler::nif(schedule = "DirtyCpu")]
+fn start_resilient(
+    maude_path: String,
+    opts: SpawnOptions,
+) -> NifResult<ResourceArc<ResilientProcess>> {
+    let process = MaudeProcess::spawn(&maude_path, &opts).map_err(SpawnError::into_nif_error)?;
+
+    Ok(ResourceArc::new(ResilientProcess {
+        maude_path,
+        options: opts,
+        state: Mutex::new(Resilient {
+            process,
+            journal: Vec::new(),
+            restarts: 0,
+            stopped: false,
+        }),
+    }))
+}
+
+/// Execute a command, replacing the subprocess and retrying once if it
+/// dies.
+///
+/// An iolist command is joined here, since mutating commands are kept in
+/// the journal.
+///
+/// # Arguments
+/// * `process` - Handle to the process
+/// * `command` - Maude command to execute
+///
+/// # Returns
+/// * As for `execute/2`
+/// * `Err` - Also if the subprocess can't be restarted, or died again on
+///   the retry
+#[rustler::nif(schedule = "DirtyCpu")]
+fn resilient_execute<'a>(
+    process: ResourceArc<ResilientProcess>,
+    command: Input<'a>,
+) -> NifResult<Outcome> {
+    process
+        .execute(&command.to_string_lossy(), &OutputOptions::default())
+        .map_err(error)
+}
+
+/// `resilient_execute/2` with output options; see [`format`] for `:format`
+/// and `:meta`.
+#[rustler::nif(schedule = "DirtyCpu", name = "resilient_execute")]
+fn resilient_execute_with_opts<'a>(
+    process: ResourceArc<ResilientProcess>,
+    command: Input<'a>,
+    opts: OutputOptions,
+) -> NifResult<Outcome> {
+    process
+        .execute(&command.to_string_lossy(), &opts)
+        .map_err(error)
+}
+
+/// Report whether a resilient process is running and how often it
+/// restarted.
+///
+/// # Arguments
+/// * `process` - Handle to the process
+#[rustler::nif(schedule = "DirtyCpu")]
+fn resilient_status(process: ResourceArc<ResilientProcess>) -> NifResult<ResilienceStatus> {
+    let state = process.state().map_err(error)?;
+
+    Ok(ResilienceStatus {
+        alive: state.process.is_alive(),
+        restarts: state.restarts,
+        journal: state.journal.len(),
+    })
+}
+
+/// Stop a resilient process; it isn't restarted afterwards.
+///
+/// # Arguments
+/// * `process` - Handle to the process
+#[rustler::nif(schedule = "DirtyCpu")]
+fn resilient_stop(process: ResourceArc<ResilientProcess>) -> NifResult<Atom> {
+    let mut state = process.state().map_err(error)?;
+    state.stopped = true;
+    state.process.shutdown().map_err(error)?;
+
+    Ok(ok())
+}
@@ -0,0 +1,217 @@
+//! Confining file access to one directory.
+//!
+//! A process started with `sandbox: dir` runs Maude in `dir`, and once it
+//! is set up every command that would reach outside it is refused before
+//! it is written:
+//!
+//! * `load`, `sload` and `in` of an absolute path, a `~` path, or one
+//!   with a `..` component, and of a file in the sandbox that is a link
+//!   to one outside it; a file name may be quoted, as in `load "a b"`
+//! * those without a file name on their line, which Maude would go on
+//!   looking for
+//! * `cd`, `push` and `pop`, which would move Maude elsewhere, and `ls`
+//! * raw input through `send_bytes/2`, which can't be checked
+//!
+//! Maude resolves a load inside a loaded file against that file's
+//! directory, where the NIF can't see it coming. So before a file in the
+//! sandbox is loaded it is read and its own commands checked the same way,
+//! against its directory, to [`MAX_DEPTH`] files deep. A relative path
+//! matching no file in the sandbox is let through: Maude then looks for it
+//! in its library, `MAUDE_LIB`.
+//!
+//! Commands are found line by line: one starts a line, or follows a token
+//! ending in `.`, as in Maude. That is coarser than Maude's own reading -
+//! a line of a term that starts with `in` is checked too - and so errs on
+//! refusing. The preload files, `:select`, and the NIF's own commands
+//! aren't checked; `load_string/2` writes its file into the sandbox.
+
+use std::path::{Component, Path, PathBuf};
+
+/// Commands loading the file named after them.
+const LOADS: &[&str] = &["load", "sload", "in"];
+
+/// Commands refused outright, which change or list directories.
+const REFUSED: &[&str] = &["cd", "push", "pop", "ls"];
+
+/// Files checked through nested loads before one is refused as too deep.
+const MAX_DEPTH: usize = 16;
+
+/// The directory a process is confined to.
+#[derive(Debug)]
+pub struct Sandbox {
+    /// Canonical, so links and `.` can't disguise a path inside it.
+    root: PathBuf,
+}
+
+impl Sandbox {
+    /// Confine a process to `dir`, which must exist.
+    pub fn open(dir: &Path) -> Result<Sandbox, String> {
+        let root = dir
+            .canonicalize()
+            .map_err(|e| format!("sandbox setup failed: {}: {}", dir.display(), e))?;
+        if !root.is_dir() {
+            return Err(format!(
+                "sandbox setup failed: {} is not a directory",
+                dir.display()
+            ));
+        }
+        Ok(Sandbox { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Check that a command, given as consecutive parts, stays inside the
+    /// sandbox.
+    pub fn check(&self, parts: &[&[u8]]) -> Result<(), String> {
+        let text = String::from_utf8_lossy(&parts.concat()).into_owned();
+        self.check_text(&text, &self.root, 0)
+    }
+
+    /// Check every file command in `text`, read in `dir` at `depth`.
+    fn check_text(&self, text: &str, dir: &Path, depth: usize) -> Result<(), String> {
+        for (keyword, argument) in file_commands(text) {
+            if REFUSED.contains(&keyword) {
+                return Err(format!("sandboxed process: `{}` is not allowed", keyword));
+            }
+            // Maude would look further for what the NIF can't see
+            if argument.is_empty() {
+                return Err(format!(
+                    "sandboxed process: `{}` needs a file name on its line",
+                    keyword
+                ));
+            }
+            self.check_load(argument, dir, depth)?;
+        }
+        Ok(())
+    }
+
+    /// Check a load of `path` from `dir`, and the file it loads if that is
+    /// in the sandbox.
+    fn check_load(&self, path: &str, dir: &Path, depth: usize) -> Result<(), String> {
+        if !is_relative(path) {
+            return Err(format!(
+                "sandboxed process: {:?} is outside the sandbox",
+                path
+            ));
+        }
+
+        // Maude tries the name as given, then with `.maude` added
+        let candidates = [dir.join(path), dir.join(format!("{}.maude", path))];
+        for candidate in candidates {
+            let Ok(file) = candidate.canonicalize() else {
+                continue;
+            };
+            if !file.starts_with(&self.root) {
+                return Err(format!(
+                    "sandboxed process: {:?} is outside the sandbox",
+                    path
+                ));
+            }
+            if !file.is_file() {
+                continue;
+            }
+            if depth >= MAX_DEPTH {
+                return Err(format!(
+                    "sandboxed process: loads nest more than {} files deep",
+                    MAX_DEPTH
+                ));
+            }
+
+            let source = std::fs::read(&file)
+                .map_err(|e| format!("sandboxed process: can't check {:?}: {}", path, e))?;
+            let parent = file.parent().unwrap_or(&self.root);
+            return self.check_text(&String::from_utf8_lossy(&source), parent, depth + 1);
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `path` is relative and stays below the directory it is read
+/// from.
+fn is_relative(path: &str) -> bool {
+    !path.starts_with('~')
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// The file commands in `text` - loads and those in [`REFUSED`] - each
+/// with its argument, empty if there is none on its line.
+fn file_commands(text: &str) -> Vec<(&str, &str)> {
+    let mut found = Vec::new();
+
+    for line in text.lines() {
+        let mut expecting = true;
+        let mut rest = line.trim_start();
+
+        while !rest.is_empty() {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let (token, after) = rest.split_at(end);
+            rest = after.trim_start();
+
+            if token.starts_with("***") || token.starts_with("---") {
+                break;
+            }
+            // Full Maude commands come in parentheses
+            let keyword = token.trim_start_matches('(');
+            if expecting && (LOADS.contains(&keyword) || REFUSED.contains(&keyword)) {
+                found.push((keyword, file_name(rest)));
+                break;
+            }
+            expecting = token.ends_with('.');
+        }
+    }
+
+    found
+}
+
+/// The file name `rest` of a line starts with, as Maude reads it: quoted,
+/// spaces and all, or up to whitespace; empty if there is none, or its
+/// closing quote is missing.
+fn file_name(rest: &str) -> &str {
+    match rest.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"').map_or("", |(name, _)| name),
+        None => rest
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .trim_end_matches(')'),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_file_names() {
+        assert_eq!(file_commands("load a.maude"), [("load", "a.maude")]);
+        assert_eq!(file_commands("red 1 . in  b ."), [("in", "b")]);
+        assert_eq!(file_commands("(load c)"), [("load", "c")]);
+        assert_eq!(
+            file_commands("load \"a b.maude\" ."),
+            [("load", "a b.maude")]
+        );
+        assert_eq!(file_commands("(sload \"a b\")"), [("sload", "a b")]);
+        assert_eq!(file_commands("load \"a b"), [("load", "")]);
+        assert_eq!(file_commands("load\n  a.maude"), [("load", "")]);
+        assert_eq!(file_commands("*** load a\nred in NAT : 1 ."), []);
+    }
+
+    #[test]
+    fn refuses_loads_it_cannot_check() {
+        let sandbox = Sandbox::open(&std::env::temp_dir()).unwrap();
+        let check = |text: &str| sandbox.check(&[text.as_bytes()]);
+
+        assert!(check("load ex_maude_sandbox_test_missing").is_ok());
+        assert!(check("load /etc/passwd").is_err());
+        assert!(check("load \"/etc/passwd\"").is_err());
+        assert!(check("load \"a ../../etc/passwd\"").is_err());
+        assert!(check("load\n/etc/passwd").is_err());
+        assert!(check("load \"unterminated").is_err());
+        assert!(check("red 1 . cd /").is_err());
+    }
+}
@@ -0,0 +1,81 @@
+//! Incremental enumeration of `search` solutions.
+//!
+//! A search started with a bound, `search [n] in M : t =>* p .`, stops after
+//! `n` solutions and can be resumed with `continue n .`. The process tracks
+//! whether such a search is pending; `search_next/2` resumes it and parses
+//! the solutions Maude prints:
+//!
+//! ```text
+//! Solution 2 (state 1)
+//! states: 2  rewrites: 1 in 0ms cpu (0ms real) (~ rewrites/second)
+//! X:S --> b
+//! ```
+
+use crate::term::{self, Term};
+use rustler::{Encoder, Env, NifMap};
+
+rustler::atoms! {
+    exhausted,
+}
+
+/// One solution of a search.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Solution {
+    /// Solution number, counted from the start of the search.
+    pub number: u64,
+    /// Number of the state in the search graph that matched.
+    pub state: u64,
+    /// Variable bindings as `{"X:Sort", term}`, in Maude's order.
+    pub substitution: Vec<(String, Term)>,
+}
+
+/// Result of `search_next/2`: more solutions, or `:exhausted`.
+#[derive(Debug)]
+pub enum Page {
+    Solutions(Vec<Solution>),
+    Exhausted,
+}
+
+impl Encoder for Page {
+    fn encode<'a>(&self, env: Env<'a>) -> rustler::Term<'a> {
+        match self {
+            Page::Solutions(solutions) => solutions.encode(env),
+            Page::Exhausted => exhausted().encode(env),
+        }
+    }
+}
+
+/// Parse every `Solution N (state S)` block in a search output.
+pub fn parse_solutions(output: &str) -> Result<Vec<Solution>, String> {
+    let mut solutions: Vec<Solution> = Vec::new();
+
+    for line in output.lines().map(str::trim) {
+        if let Some(header) = line.strip_prefix("Solution ") {
+            solutions.push(parse_header(header)?);
+        } else if let Some((variable, value)) = line.split_once(" --> ") {
+            let solution = solutions
+                .last_mut()
+                .ok_or_else(|| format!("binding outside a solution: {:?}", line))?;
+            solution
+                .substitution
+                .push((variable.to_string(), term::parse(value)?));
+        }
+    }
+
+    Ok(solutions)
+}
+
+/// Parse `N (state S)`.
+fn parse_header(header: &str) -> Result<Solution, String> {
+    let invalid = || format!("invalid solution header: {:?}", header);
+
+    let (number, state) = header.split_once(" (state ").ok_or_else(invalid)?;
+    let state = state.strip_suffix(')').ok_or_else(invalid)?;
+
+    Ok(Solution {
+        number: number.parse().map_err(|_| invalid())?,
+        state: state.parse().map_err(|_| invalid())?,
+        substitution: Vec::new(),
+    })
+}
@@ -0,0 +1,130 @@
+//! Tracking of the module Maude has selected.
+//!
+//! Commands without `in <module>` run in Maude's current module, which
+//! `select` changes - and so do entering a module and loading a file, which
+//! leave the last module read selected. Each process remembers the current
+//! module once it is known: `select_module/2` sets it, and any command that
+//! may change it (see [`may_change`]) forgets it, so `current_module/1`
+//! asks Maude with `show module .` only when it has to.
+//!
+//! The `:select` spawn option selects a module once the preload files are
+//! in. Hibernating, shadowed, and pooled processes spawn from their
+//! options, so it is selected again whenever one of them starts a fresh
+//! Maude, and a later `select` is restored with the rest of their journal.
+
+use crate::command;
+use crate::diagnostics::Outcome;
+use crate::failure::Failure;
+use crate::process::{Exchange, MaudeProcess};
+use crate::reply;
+use rustler::{Atom, Env, NifResult, ResourceArc};
+
+rustler::atoms! {
+    ok,
+}
+
+/// Keywords that start a module or theory, each of which is selected once
+/// entered.
+pub const MODULES: &[&str] = &["fmod", "mod", "fth", "th", "smod", "sth", "omod", "oth"];
+
+/// Whether `command` may change the current module.
+pub fn may_change(command: &str) -> bool {
+    let keyword = command.split_whitespace().next().unwrap_or("");
+    matches!(keyword, "select" | "load" | "sload" | "in") || MODULES.contains(&keyword)
+}
+
+/// The module name in the header `show module .` prints, e.g. `fmod FOO is`.
+///
+/// A parameterized module's formal parameters are dropped, since it is
+/// selected by its bare name; an instantiation such as `LIST{Nat}` keeps
+/// its actual ones.
+fn parse_header(output: &str) -> Option<String> {
+    let header = output.lines().next()?.trim();
+    let (keyword, rest) = header.split_once(' ')?;
+    if !MODULES.contains(&keyword) {
+        return None;
+    }
+
+    let name = rest.strip_suffix(" is").unwrap_or(rest).trim();
+    let name = match name.split_once('{') {
+        Some((bare, parameters)) if parameters.contains("::") => bare,
+        _ => name,
+    };
+
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Run `select <module> .`, failing if Maude doesn't know the module.
+pub fn select(exchange: &Exchange, module: &str) -> Result<(), Failure> {
+    command::check_module(module).map_err(|e| format!("rejected command: {}", e))?;
+    exchange.execute(&format!("select {} .", module))?;
+
+    let diagnostics = exchange.take_stderr()?;
+    if diagnostics.contains("Warning:") || diagnostics.contains("Error:") {
+        return Err(format!("select failed: {}", diagnostics.trim()).into());
+    }
+
+    exchange.record_selection(Some(module.to_string()));
+    Ok(())
+}
+
+/// Make `module` Maude's current module.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `module` - Module to select
+///
+/// # Returns
+/// * `Ok(:ok)` - The module is selected
+/// * `Err` - If the name is invalid, Maude has no such module, or I/O fails
+#[rustler::nif(schedule = "DirtyCpu")]
+fn select_module(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+) -> NifResult<Outcome<Atom>> {
+    reply(
+        process
+            .begin_by(env.pid())
+            .and_then(|exchange| select(&exchange, &module))
+            .map(|()| ok()),
+    )
+}
+
+/// The module commands without `in <module>` run in.
+///
+/// Answered from the process's own record when it has one; otherwise Maude
+/// is asked with `show module .`, whose output is as long as the module.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `Ok(String)` - Name of the current module
+/// * `Ok(nil)` - No module is selected, e.g. under `-no-prelude`
+/// * `Err` - If I/O fails
+#[rustler::nif(schedule = "DirtyCpu")]
+fn current_module(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+) -> NifResult<Outcome<Option<String>>> {
+    reply(
+        process
+            .begin_by(env.pid())
+            .and_then(|exchange| current(&process, &exchange)),
+    )
+}
+
+/// The current module of `process`, from its record or else from Maude.
+pub fn current(process: &MaudeProcess, exchange: &Exchange) -> Result<Option<String>, Failure> {
+    if let Some(module) = process.selected_module() {
+        return Ok(Some(module));
+    }
+
+    let output = exchange.execute("show module .")?;
+    exchange.take_stderr()?;
+
+    let module = parse_header(&output);
+    exchange.record_selection(module.clone());
+    Ok(module)
+}
@@ -0,0 +1,116 @@
+//! Commands sent now and awaited later.
+//!
+//! `execute/2` writes a command and reads its response in one call, which
+//! holds the caller for as long as Maude computes. `send_command/2` does
+//! only the first half, returning once the command is written, and
+//! `await_output/2` the second: it waits up to a timeout for the prompt
+//! ending the response, and fails with `"timeout"` if it doesn't come, so
+//! the caller can do other work, give up, or wait again. Output read before
+//! a timeout is kept for the next `await_output/2`.
+//!
+//! To cancel a sent command, send the child `:sigint` with `signal/2`:
+//! Maude stops in its debugger, and the next `await_output/2` leaves it and
+//! fails with `"interrupted"`.
+//!
+//! One command may be sent at a time. Until its output has been awaited,
+//! every other command - another `send_command/2`, an execute call, a
+//! pipelined submit - fails with `:awaiting` rather than mixing its
+//! response with the one pending. A sent command isn't tagged with a
+//! sequence sentinel, and one larger than [`crate::writer::STREAMED`]
+//! bytes is refused, since its write could wait on a reader there isn't
+//! yet. Its output spills to a file past the process's threshold, as
+//! `execute/2`'s does, but is never chunked or filtered.
+
+use crate::diagnostics::Outcome;
+use crate::error;
+use crate::format::{self, OutputOptions};
+use crate::input::Input;
+use crate::process::MaudeProcess;
+use crate::spill::Spiller;
+use rustler::{Atom, Env, NifResult, ResourceArc};
+use std::time::{Duration, Instant};
+
+rustler::atoms! {
+    ok,
+}
+
+/// Message for a command written while a sent one awaits its output; the
+/// NIFs return the `:awaiting` atom instead.
+pub const AWAITING: &str = "a sent command is awaiting await_output/2";
+
+/// A command written by `send_command/2` whose output hasn't been awaited.
+pub struct Sent {
+    /// Start of the command, for the bookkeeping done once it is answered.
+    pub head: String,
+    pub whole: bool,
+    /// Whether it empties the cache once answered.
+    pub stale: bool,
+    /// Output read so far, without a prompt yet, or its last bytes once
+    /// the rest has spilled.
+    pub output: Vec<u8>,
+    /// The file output past the spill threshold went to, if any.
+    pub spiller: Option<Spiller>,
+    pub started: Instant,
+}
+
+impl Sent {
+    pub fn new(head: String, whole: bool, stale: bool) -> Sent {
+        Sent {
+            head,
+            whole,
+            stale,
+            output: Vec::new(),
+            spiller: None,
+            started: Instant::now(),
+        }
+    }
+}
+
+/// Write a command without waiting for its output.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `command` - Maude command to execute
+///
+/// # Returns
+/// * `Ok(:ok)` - The command is written; `await_output/2` reads its output
+/// * `Err` - If a sent command is still awaiting its output, the command
+///   is too large, or I/O fails
+#[rustler::nif(schedule = "DirtyIo")]
+fn send_command<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    command: Input<'a>,
+) -> NifResult<Atom> {
+    process
+        .begin_by(env.pid())
+        .and_then(|exchange| exchange.send_command(&command.parts()))
+        .map_err(error)?;
+    Ok(ok())
+}
+
+/// Wait for the output of the command `send_command/2` wrote.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `timeout` - Milliseconds to wait for the prompt
+///
+/// # Returns
+/// * As for `execute/2`
+/// * `Err` - Also `"timeout"` if the command is still running, which may
+///   be awaited again; `"interrupted"` if it was stopped by `:sigint`; or
+///   if no command was sent
+#[rustler::nif(schedule = "DirtyCpu")]
+fn await_output(env: Env, process: ResourceArc<MaudeProcess>, timeout: u64) -> NifResult<Outcome> {
+    let (response, stderr) = process
+        .begin_by(env.pid())
+        .and_then(|exchange| exchange.await_output(Duration::from_millis(timeout)))
+        .map_err(error)?;
+
+    if let Some(failed) = Outcome::failed(&stderr) {
+        return Ok(failed);
+    }
+    format::render_response(response, &OutputOptions::default())
+        .map(Outcome::Done)
+        .map_err(error)
+}
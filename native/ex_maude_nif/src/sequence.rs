@@ -0,0 +1,87 @@
+//! Checking that each response read belongs to the command just written.
+//!
+//! The reader takes whatever ends at the next prompt to be the response
+//! to the last command. Should Maude ever print a prompt no command asked
+//! for - after an interrupt that arrived while it was idle, say, or raw
+//! input that held two commands - every read from then on is one behind,
+//! and each call quietly returns the previous command's output.
+//!
+//! With `sequence_check: true` every execute is tagged with the next
+//! number of the process's sequence by a sentinel written just ahead of
+//! it:
+//!
+//! ```text
+//! select ex_maude_seq_41 .
+//! ```
+//!
+//! `select` of a module that doesn't exist changes nothing, prints nothing
+//! to stdout, and warns on stderr that `ex_maude_seq_41` isn't a module.
+//! Before the command's response, the reader takes the sentinel's: it must
+//! be empty, and bring the warning for this number. Anything else - output,
+//! or a prompt without the warning - means the reader was behind. It then
+//! reads on to the sentinel's prompt, discards the command's response, and
+//! the call returns `{:error, :desync, details}`, `details` saying what
+//! came in place of the sentinel. The process is back in step after
+//! that.
+//!
+//! The check costs one more prompt per command. Pipelined commands and
+//! those run with a deadline aren't tagged.
+
+use crate::failure::Failure;
+
+/// Prefix of the message for a broken sequence, before its details; the
+/// NIFs return `{:error, :desync, details}` instead.
+pub const DESYNC: &str = "desync: ";
+
+/// Bytes of stray output quoted in a desync's details.
+const QUOTED: usize = 200;
+
+/// The sentinel tagging one command.
+#[derive(Debug, Clone, Copy)]
+pub struct Sentinel {
+    number: u64,
+}
+
+impl Sentinel {
+    pub fn new(number: u64) -> Sentinel {
+        Sentinel { number }
+    }
+
+    /// The command that writes it.
+    pub fn command(&self) -> String {
+        format!("select ex_maude_seq_{} .", self.number)
+    }
+
+    /// The warning Maude answers it with, as it ends a stderr line.
+    pub fn warning(&self) -> String {
+        format!("no module ex_maude_seq_{}.", self.number)
+    }
+
+    /// The failure for reading `prompts` prompts and `output` before
+    /// finding this sentinel.
+    pub fn desync(&self, prompts: usize, output: &str) -> Failure {
+        let output = output.trim();
+        let quoted = &output[..floor_boundary(output, QUOTED)];
+        let ellipsis = if quoted.len() < output.len() {
+            "..."
+        } else {
+            ""
+        };
+
+        Failure::Desync(format!(
+            "command {}: read {} unexpected prompt(s) before its sentinel, with output {:?}{}",
+            self.number, prompts, quoted, ellipsis
+        ))
+    }
+}
+
+/// The largest char boundary of `text` at most `at`.
+fn floor_boundary(text: &str, at: usize) -> usize {
+    if at >= text.len() {
+        return text.len();
+    }
+    (0..=at)
+        .rev()
+        .find(|&i| text.is_char_boundary(i))
+        .unwrap_or(0)
+}
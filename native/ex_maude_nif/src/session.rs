@@ -0,0 +1,127 @@
+//! Named Maude sessions in a global registry.
+//!
+//! Sessions are addressed by name instead of by resource handle, for
+//! callers that can't conveniently pass a reference around (e.g. between
+//! unrelated processes on the node). A session lives until it is stopped
+//! with `stop_named/1`, independent of any Elixir process.
+
+use crate::diagnostics::Outcome;
+use crate::error;
+use crate::format::OutputOptions;
+use crate::input::Input;
+use crate::options::SpawnOptions;
+use crate::pool::Worker;
+use rustler::{Atom, Env, LocalPid, NifResult};
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+
+rustler::atoms! {
+    ok,
+}
+
+static SESSIONS: LazyLock<Mutex<HashMap<String, Arc<Worker>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn sessions() -> Result<MutexGuard<'static, HashMap<String, Arc<Worker>>>, String> {
+    SESSIONS
+        .lock()
+        .map_err(|e| format!("session registry lock failed: {}", e))
+}
+
+fn lookup(name: &str) -> Result<Arc<Worker>, String> {
+    sessions()?
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("no such session: {}", name))
+}
+
+/// Start a Maude process registered under `name`.
+///
+/// # Arguments
+/// * `name` - Session name; must not already be registered
+/// * `maude_path` - Path to the Maude executable
+///
+/// # Returns
+/// * `Ok(:ok)` - The session is registered
+/// * `Err` - If the name is taken or Maude fails to start
+#[rustler::nif(schedule = "DirtyCpu")]
+fn start_named(name: String, maude_path: String) -> NifResult<Atom> {
+    if sessions().map_err(error)?.contains_key(&name) {
+        return Err(error(format!("session already exists: {}", name)));
+    }
+
+    // Spawn outside the registry lock; recheck in case of a race
+    let worker = Worker::spawn(&maude_path, &SpawnOptions::default()).map_err(error)?;
+
+    let mut registry = sessions().map_err(error)?;
+    if registry.contains_key(&name) {
+        let _ = worker.process.shutdown();
+        return Err(error(format!("session already exists: {}", name)));
+    }
+    registry.insert(name, Arc::new(worker));
+
+    Ok(ok())
+}
+
+/// Execute a command in a named session.
+///
+/// Commands from different callers are serialized per session, each
+/// taking its turn on the caller's behalf as `execute/2` does.
+///
+/// # Arguments
+/// * `name` - Session name
+/// * `command` - Maude command to execute
+///
+/// # Returns
+/// * As for `execute/2`
+/// * `Err` - Also if there is no such session
+#[rustler::nif(schedule = "DirtyCpu")]
+fn execute_named<'a>(env: Env<'a>, name: String, command: Input<'a>) -> NifResult<Outcome> {
+    run(env.pid(), &name, &command, &OutputOptions::default())
+}
+
+/// `execute_named/2` with output options; see [`format`] for `:format`.
+#[rustler::nif(schedule = "DirtyCpu", name = "execute_named")]
+fn execute_named_with_opts<'a>(
+    env: Env<'a>,
+    name: String,
+    command: Input<'a>,
+    opts: OutputOptions,
+) -> NifResult<Outcome> {
+    run(env.pid(), &name, &command, &opts)
+}
+
+fn run(caller: LocalPid, name: &str, command: &Input, opts: &OutputOptions) -> NifResult<Outcome> {
+    let worker = lookup(name).map_err(error)?;
+    worker
+        .execute_by(caller, &command.parts(), opts)
+        .map_err(error)
+}
+
+/// Names of all registered sessions, sorted.
+#[rustler::nif]
+fn list_named() -> NifResult<Vec<String>> {
+    let mut names: Vec<String> = sessions().map_err(error)?.keys().cloned().collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Stop a named session and remove it from the registry.
+///
+/// # Arguments
+/// * `name` - Session name
+///
+/// # Returns
+/// * `Ok(:ok)` - The session was stopped
+/// * `Err` - If there is no such session
+#[rustler::nif(schedule = "DirtyCpu")]
+fn stop_named(name: String) -> NifResult<Atom> {
+    let worker = sessions()
+        .map_err(error)?
+        .remove(&name)
+        .ok_or_else(|| error(format!("no such session: {}", name)))?;
+
+    worker.shutdown().map_err(error)?;
+
+    Ok(ok())
+}
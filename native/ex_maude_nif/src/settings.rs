@@ -0,0 +1,431 @@
+//! Typed access to Maude's `set` switches.
+//!
+//! Maude has a `set <switch> on|off .` command for each runtime flag but no
+//! command that reports their current values. Each process therefore keeps
+//! a cache of its settings, seeded with Maude's defaults and updated by
+//! every `set` command it runs, typed or raw. Switches changed from inside
+//! a loaded file are not seen.
+
+use crate::diagnostics::Outcome;
+use crate::failure::Failure;
+use crate::options::SpawnOptions;
+use crate::process::MaudeProcess;
+use crate::reply;
+use rustler::{Atom, Decoder, Encoder, Env, LocalPid, NifResult, ResourceArc, Term};
+
+rustler::atoms! {
+    ok,
+    show_advisories,
+    show_stats,
+    show_timing,
+    show_breakdown,
+    show_loop_stats,
+    show_loop_timing,
+    show_command,
+    print_mixfix,
+    print_flat,
+    print_with_parentheses,
+    print_with_aliases,
+    print_conceal,
+    print_number,
+    print_rat,
+    print_color,
+    print_format,
+    print_graph,
+    print_attribute,
+    print_attribute_newline,
+    trace,
+    trace_condition,
+    trace_whole,
+    trace_substitution,
+    trace_select,
+    trace_mb,
+    trace_eq,
+    trace_rl,
+    profile,
+    break_ = "break",
+    clear_memo,
+    clear_rules,
+}
+
+/// A boolean Maude runtime switch, decoded from an atom such as
+/// `:print_attribute` or `:show_timing`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Switch {
+    ShowAdvisories,
+    ShowStats,
+    ShowTiming,
+    ShowBreakdown,
+    ShowLoopStats,
+    ShowLoopTiming,
+    ShowCommand,
+    PrintMixfix,
+    PrintFlat,
+    PrintWithParentheses,
+    PrintWithAliases,
+    PrintConceal,
+    PrintNumber,
+    PrintRat,
+    PrintColor,
+    PrintFormat,
+    PrintGraph,
+    PrintAttribute,
+    PrintAttributeNewline,
+    Trace,
+    TraceCondition,
+    TraceWhole,
+    TraceSubstitution,
+    TraceSelect,
+    TraceMb,
+    TraceEq,
+    TraceRl,
+    Profile,
+    Break,
+    ClearMemo,
+    ClearRules,
+}
+
+impl Switch {
+    /// Every switch, in the order `get_options/1` reports them.
+    pub const ALL: [Switch; 31] = [
+        Switch::ShowAdvisories,
+        Switch::ShowStats,
+        Switch::ShowTiming,
+        Switch::ShowBreakdown,
+        Switch::ShowLoopStats,
+        Switch::ShowLoopTiming,
+        Switch::ShowCommand,
+        Switch::PrintMixfix,
+        Switch::PrintFlat,
+        Switch::PrintWithParentheses,
+        Switch::PrintWithAliases,
+        Switch::PrintConceal,
+        Switch::PrintNumber,
+        Switch::PrintRat,
+        Switch::PrintColor,
+        Switch::PrintFormat,
+        Switch::PrintGraph,
+        Switch::PrintAttribute,
+        Switch::PrintAttributeNewline,
+        Switch::Trace,
+        Switch::TraceCondition,
+        Switch::TraceWhole,
+        Switch::TraceSubstitution,
+        Switch::TraceSelect,
+        Switch::TraceMb,
+        Switch::TraceEq,
+        Switch::TraceRl,
+        Switch::Profile,
+        Switch::Break,
+        Switch::ClearMemo,
+        Switch::ClearRules,
+    ];
+
+    /// Words between `set` and `on`/`off` in Maude's syntax.
+    pub fn keyword(self) -> &'static str {
+        match self {
+            Switch::ShowAdvisories => "show advisories",
+            Switch::ShowStats => "show stats",
+            Switch::ShowTiming => "show timing",
+            Switch::ShowBreakdown => "show breakdown",
+            Switch::ShowLoopStats => "show loop stats",
+            Switch::ShowLoopTiming => "show loop timing",
+            Switch::ShowCommand => "show command",
+            Switch::PrintMixfix => "print mixfix",
+            Switch::PrintFlat => "print flat",
+            Switch::PrintWithParentheses => "print with parentheses",
+            Switch::PrintWithAliases => "print with aliases",
+            Switch::PrintConceal => "print conceal",
+            Switch::PrintNumber => "print number",
+            Switch::PrintRat => "print rat",
+            Switch::PrintColor => "print color",
+            Switch::PrintFormat => "print format",
+            Switch::PrintGraph => "print graph",
+            Switch::PrintAttribute => "print attribute",
+            Switch::PrintAttributeNewline => "print attribute newline",
+            Switch::Trace => "trace",
+            Switch::TraceCondition => "trace condition",
+            Switch::TraceWhole => "trace whole",
+            Switch::TraceSubstitution => "trace substitution",
+            Switch::TraceSelect => "trace select",
+            Switch::TraceMb => "trace mb",
+            Switch::TraceEq => "trace eq",
+            Switch::TraceRl => "trace rl",
+            Switch::Profile => "profile",
+            Switch::Break => "break",
+            Switch::ClearMemo => "clear memo",
+            Switch::ClearRules => "clear rules",
+        }
+    }
+
+    fn atom(self) -> Atom {
+        match self {
+            Switch::ShowAdvisories => show_advisories(),
+            Switch::ShowStats => show_stats(),
+            Switch::ShowTiming => show_timing(),
+            Switch::ShowBreakdown => show_breakdown(),
+            Switch::ShowLoopStats => show_loop_stats(),
+            Switch::ShowLoopTiming => show_loop_timing(),
+            Switch::ShowCommand => show_command(),
+            Switch::PrintMixfix => print_mixfix(),
+            Switch::PrintFlat => print_flat(),
+            Switch::PrintWithParentheses => print_with_parentheses(),
+            Switch::PrintWithAliases => print_with_aliases(),
+            Switch::PrintConceal => print_conceal(),
+            Switch::PrintNumber => print_number(),
+            Switch::PrintRat => print_rat(),
+            Switch::PrintColor => print_color(),
+            Switch::PrintFormat => print_format(),
+            Switch::PrintGraph => print_graph(),
+            Switch::PrintAttribute => print_attribute(),
+            Switch::PrintAttributeNewline => print_attribute_newline(),
+            Switch::Trace => trace(),
+            Switch::TraceCondition => trace_condition(),
+            Switch::TraceWhole => trace_whole(),
+            Switch::TraceSubstitution => trace_substitution(),
+            Switch::TraceSelect => trace_select(),
+            Switch::TraceMb => trace_mb(),
+            Switch::TraceEq => trace_eq(),
+            Switch::TraceRl => trace_rl(),
+            Switch::Profile => profile(),
+            Switch::Break => break_(),
+            Switch::ClearMemo => clear_memo(),
+            Switch::ClearRules => clear_rules(),
+        }
+    }
+
+    /// Maude's value for the switch in a fresh process.
+    fn default(self) -> bool {
+        !matches!(
+            self,
+            Switch::ShowBreakdown
+                | Switch::PrintWithParentheses
+                | Switch::PrintConceal
+                | Switch::PrintColor
+                | Switch::PrintGraph
+                | Switch::PrintAttribute
+                | Switch::PrintAttributeNewline
+                | Switch::Trace
+                | Switch::TraceWhole
+                | Switch::TraceSelect
+                | Switch::Profile
+                | Switch::Break
+                | Switch::ClearMemo
+        )
+    }
+
+    /// The `set` command turning the switch on or off.
+    pub fn command(self, value: bool) -> String {
+        format!(
+            "set {} {} .",
+            self.keyword(),
+            if value { "on" } else { "off" }
+        )
+    }
+}
+
+impl<'a> Decoder<'a> for Switch {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let atom = term.decode::<Atom>()?;
+        Switch::ALL
+            .into_iter()
+            .find(|switch| switch.atom() == atom)
+            .ok_or(rustler::Error::BadArg)
+    }
+}
+
+/// Cached value of every switch for one process.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    values: [bool; Switch::ALL.len()],
+}
+
+impl Settings {
+    /// Maude's defaults, adjusted for the command-line flags in `options`.
+    pub fn new(options: &SpawnOptions) -> Settings {
+        let mut settings = Settings {
+            values: Switch::ALL.map(Switch::default),
+        };
+        if options.args.iter().any(|arg| arg == "-no-advise") {
+            settings.set(Switch::ShowAdvisories, false);
+        }
+        settings
+    }
+
+    pub fn get(&self, switch: Switch) -> bool {
+        self.values[switch as usize]
+    }
+
+    pub fn set(&mut self, switch: Switch, value: bool) {
+        self.values[switch as usize] = value;
+    }
+
+    /// Record the effect of each `set` of a known switch in `input`, which
+    /// may hold several commands.
+    pub fn observe(&mut self, input: &str) {
+        let words: Vec<&str> = input.split_whitespace().collect();
+        for command in words.split_inclusive(|word| *word == ".") {
+            if let Some((switch, value)) = parse_command(command) {
+                self.set(switch, value);
+            }
+        }
+    }
+}
+
+impl Encoder for Settings {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        Switch::ALL
+            .iter()
+            .map(|switch| (switch.atom(), self.get(*switch)))
+            .collect::<Vec<_>>()
+            .encode(env)
+    }
+}
+
+/// Parse the words of `set <switch> on|off .`.
+fn parse_command(words: &[&str]) -> Option<(Switch, bool)> {
+    let (value, words) = match words {
+        ["set", words @ .., value, "."] => (*value, words),
+        _ => return None,
+    };
+
+    let value = match value {
+        "on" => true,
+        "off" => false,
+        _ => return None,
+    };
+
+    let keyword = words.join(" ");
+    Switch::ALL
+        .into_iter()
+        .find(|switch| switch.keyword() == keyword)
+        .map(|switch| (switch, value))
+}
+
+fn set(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    option: Switch,
+    value: bool,
+) -> Result<Atom, Failure> {
+    let exchange = process.begin_by(caller)?;
+    let previous = process.settings().get(option);
+    exchange.execute(&option.command(value))?;
+
+    let diagnostics = exchange.take_stderr()?;
+    if diagnostics.contains("Warning:") || diagnostics.contains("Error:") {
+        exchange.record_setting(option, previous);
+        return Err(format!("set failed: {}", diagnostics.trim()).into());
+    }
+
+    Ok(ok())
+}
+
+/// Turn a Maude runtime switch on or off.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `option` - Switch atom, e.g. `:print_attribute`; see [`Switch`]
+/// * `value` - `true` for `on`, `false` for `off`
+///
+/// # Returns
+/// * `Ok(:ok)` - The switch was set
+/// * `Err` - If Maude rejected the command or I/O fails
+#[rustler::nif(schedule = "DirtyCpu")]
+fn set_option(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    option: Switch,
+    value: bool,
+) -> NifResult<Outcome<Atom>> {
+    reply(set(&process, env.pid(), option, value))
+}
+
+/// Current value of every Maude runtime switch, from the process's cache.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `Ok(Settings)` - Keyword list of `switch: boolean`
+#[rustler::nif]
+fn get_options(process: ResourceArc<MaudeProcess>) -> NifResult<Settings> {
+    Ok(process.settings())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observed(input: &str) -> Settings {
+        let mut settings = Settings::new(&SpawnOptions::default());
+        settings.observe(input);
+        settings
+    }
+
+    #[test]
+    fn observes_set_commands() {
+        let cases = [
+            ("set trace on .", Switch::Trace, true),
+            ("set show timing off .", Switch::ShowTiming, false),
+            (
+                "  set   print\n attribute   on  .\n",
+                Switch::PrintAttribute,
+                true,
+            ),
+            ("set clear memo on .", Switch::ClearMemo, true),
+        ];
+
+        for (input, switch, value) in cases {
+            let settings = observed(input);
+            assert_eq!(settings.get(switch), value, "for {:?}", input);
+            assert_ne!(switch.default(), value, "for {:?}", input);
+        }
+    }
+
+    #[test]
+    fn observes_every_command_of_the_input() {
+        let settings = observed(
+            "set trace on . red 1 + 1 .\n\
+             set print mixfix off .\n\
+             set trace whole on . set trace off .",
+        );
+
+        assert!(!settings.get(Switch::Trace));
+        assert!(!settings.get(Switch::PrintMixfix));
+        assert!(settings.get(Switch::TraceWhole));
+    }
+
+    #[test]
+    fn ignores_unknown_or_malformed_settings() {
+        let fresh = Settings::new(&SpawnOptions::default());
+
+        for input in [
+            "set verbose on .",
+            "set trace maybe .",
+            "set trace on",
+            "red set trace on .",
+            "show trace on .",
+            "set .",
+        ] {
+            let settings = observed(input);
+            for switch in Switch::ALL {
+                assert_eq!(settings.get(switch), fresh.get(switch), "for {:?}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn builds_commands_that_observe_reads_back() {
+        for switch in Switch::ALL {
+            for value in [true, false] {
+                assert_eq!(
+                    observed(&switch.command(value)).get(switch),
+                    value,
+                    "for {:?}",
+                    switch
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! Primary/standby pairs of Maude processes.
+//!
+//! A shadowed process runs every command on its primary and mirrors the
+//! state-mutating ones (see [`command::is_mutating`]) to a standby process
+//! on a background thread. If the primary dies, the standby - already in
+//! the same state - is promoted in place of a full replay, and a fresh
+//! standby is rebuilt from the command journal off the request path.
+
+use crate::command;
+use crate::diagnostics::Outcome;
+use crate::error;
+use crate::failure::Failure;
+use crate::format::{self, Output, OutputOptions, Trim};
+use crate::input::Input;
+use crate::options::SpawnOptions;
+use crate::process::{MaudeProcess, Response};
+use crate::reply;
+use crate::startup::SpawnError;
+use crate::threads;
+use rustler::{Atom, NifMap, NifResult, ResourceArc};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+rustler::atoms! {
+    ok,
+}
+
+/// Standby process, fed mirrored commands by its own thread.
+///
+/// The thread owns the process and hands it back when the queue is closed,
+/// after every command sent before the close has been applied.
+struct Standby {
+    queue: Sender<String>,
+    worker: JoinHandle<Result<MaudeProcess, Failure>>,
+}
+
+impl Standby {
+    /// Spawn a standby that replays `journal` before following the queue.
+    fn start(
+        maude_path: String,
+        options: SpawnOptions,
+        journal: Vec<String>,
+    ) -> Result<Standby, String> {
+        let (queue, commands) = mpsc::channel::<String>();
+
+        static STARTED: AtomicU64 = AtomicU64::new(0);
+        let name = format!(
+            "ex_maude-standby-{}",
+            STARTED.fetch_add(1, Ordering::Relaxed) + 1
+        );
+
+        let worker = threads::spawn(name, move || {
+            let process = MaudeProcess::spawn(&maude_path, &options)?;
+
+            for command in journal.into_iter().chain(commands) {
+                if let Err(e) = process.execute(&command) {
+                    let _ = process.shutdown();
+                    return Err(e);
+                }
+            }
+
+            Ok(process)
+        })?;
+
+        Ok(Standby { queue, worker })
+    }
+
+    /// Close the queue and wait for the standby to catch up.
+    fn finish(self) -> Result<MaudeProcess, Failure> {
+        drop(self.queue);
+        self.worker
+            .join()
+            .unwrap_or_else(|_| Err("standby panicked".into()))
+    }
+}
+
+struct Shadowed {
+    primary: MaudeProcess,
+    standby: Option<Standby>,
+    /// Every mutating command so far, for seeding replacement standbys.
+    journal: Vec<String>,
+    failovers: u64,
+}
+
+/// Handle to a primary/standby pair shared with Elixir.
+pub struct ShadowedProcess {
+    maude_path: String,
+    options: SpawnOptions,
+    state: Mutex<Shadowed>,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for ShadowedProcess {}
+
+/// Report returned by `shadowed_status/1`.
+#[derive(Debug, NifMap)]
+pub struct ShadowStatus {
+    /// Whether the primary process is running.
+    pub primary_alive: bool,
+    /// Whether a standby exists (it may still be replaying the journal).
+    pub standby: bool,
+    /// Number of times a standby has been promoted.
+    pub failovers: u64,
+    /// Number of mutating commands in the journal.
+    pub journal: usize,
+}
+
+impl ShadowedProcess {
+    fn execute(&self, command: &str, trim: Option<Trim>) -> Result<String, Failure> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|e| format!("shadow lock failed: {}", e))?;
+
+        if !state.primary.is_alive() {
+            self.failover(&mut state)?;
+        }
+
+        // A standby whose thread has returned while its queue is open has died
+        if let Some(standby) = state
+            .standby
+            .take_if(|standby| standby.worker.is_finished())
+        {
+            let _ = standby.finish().map(|process| process.shutdown());
+        }
+        // Rebuilt if it died, or if no thread could be started for it
+        if state.standby.is_none() {
+            state.standby = self.rebuild(&state.journal).ok();
+        }
+
+        let run = |primary: &MaudeProcess| {
+            primary
+                .execute_trimmed(&[command.as_bytes()], trim)
+                .and_then(Response::into_text)
+        };
+
+        let output = match run(&state.primary) {
+            Ok(output) => output,
+            Err(_) if !state.primary.is_alive() => {
+                self.failover(&mut state)?;
+                run(&state.primary)?
+            }
+            Err(e) => return Err(e),
+        };
+
+        if command::is_mutating(command) {
+            state.journal.push(command.to_string());
+            if let Some(standby) = &state.standby {
+                // A failed send means the standby died; it is rebuilt next time
+                let _ = standby.queue.send(command.to_string());
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Promote the standby and start rebuilding a new one in the background.
+    fn failover(&self, state: &mut Shadowed) -> Result<(), String> {
+        let standby = state
+            .standby
+            .take()
+            .ok_or_else(|| "primary exited and no standby is available".to_string())?;
+
+        let promoted = standby
+            .finish()
+            .map_err(|e| format!("primary exited and standby failed: {}", e))?;
+
+        let failed = std::mem::replace(&mut state.primary, promoted);
+        let _ = failed.shutdown();
+        state.failovers += 1;
+
+        // Without a thread for it, the next command tries again
+        state.standby = self.rebuild(&state.journal).ok();
+
+        Ok(())
+    }
+
+    fn rebuild(&self, journal: &[String]) -> Result<Standby, String> {
+        Standby::start(
+            self.maude_path.clone(),
+            self.options.clone(),
+            journal.to_vec(),
+        )
+    }
+}
+
+/// Start a Maude process with a standby shadowing its state.
+///
+/// # Arguments
+/// * `maude_path` - Path to the Maude executable
+/// * `opts` - Keyword list of spawn options, as for `start_with_opts/2`;
+///   both processes use them
+///
+/// # Returns
+/// * `Ok(ResourceArc<ShadowedProcess>)` - Handle to the pair
+/// * `Err` - If the primary fails to start
+#[rustler::nif(schedule = "DirtyCpu")]
+fn start_shadowed(
+    maude_path: String,
+    opts: SpawnOptions,
+) -> NifResult<ResourceArc<ShadowedProcess>> {
+    let primary = MaudeProcess::spawn(&maude_path, &opts).map_err(SpawnError::into_nif_error)?;
+    let standby = match Standby::start(maude_path.clone(), opts.clone(), Vec::new()) {
+        Ok(standby) => standby,
+        Err(e) => {
+            let _ = primary.shutdown();
+            return Err(error(e));
+        }
+    };
+
+    Ok(ResourceArc::new(ShadowedProcess {
+        maude_path,
+        options: opts,
+        state: Mutex::new(Shadowed {
+            primary,
+            standby: Some(standby),
+            journal: Vec::new(),
+            failovers: 0,
+        }),
+    }))
+}
+
+/// Execute a command on the primary, mirroring it to the standby if it
+/// changes state.
+///
+/// If the primary has exited, the standby is promoted and the command runs
+/// there instead. An iolist command is joined here, since mutating
+/// commands are kept in the journal.
+///
+/// # Arguments
+/// * `process` - Handle to the pair
+/// * `command` - Maude command to execute
+///
+/// # Returns
+/// * `Ok(String)` - Command output (without the prompt)
+/// * `Err` - If I/O fails, or the primary exited with no usable standby
+#[rustler::nif(schedule = "DirtyCpu")]
+fn shadowed_execute<'a>(
+    process: ResourceArc<ShadowedProcess>,
+    command: Input<'a>,
+) -> NifResult<Outcome<String>> {
+    reply(process.execute(&command.to_string_lossy(), None))
+}
+
+/// `shadowed_execute/2` with output options; see [`format`] for `:format`.
+#[rustler::nif(schedule = "DirtyCpu", name = "shadowed_execute")]
+fn shadowed_execute_with_opts<'a>(
+    process: ResourceArc<ShadowedProcess>,
+    command: Input<'a>,
+    opts: OutputOptions,
+) -> NifResult<Outcome<Output>> {
+    reply(
+        process
+            .execute(&command.to_string_lossy(), opts.trim)
+            .and_then(|output| format::render(output, &opts).map_err(Failure::from)),
+    )
+}
+
+/// Report the health of a primary/standby pair.
+///
+/// # Arguments
+/// * `process` - Handle to the pair
+#[rustler::nif(schedule = "DirtyCpu")]
+fn shadowed_status(process: ResourceArc<ShadowedProcess>) -> NifResult<ShadowStatus> {
+    let state = process
+        .state
+        .lock()
+        .map_err(|e| error(format!("shadow lock failed: {}", e)))?;
+
+    Ok(ShadowStatus {
+        primary_alive: state.primary.is_alive(),
+        standby: state
+            .standby
+            .as_ref()
+            .is_some_and(|standby| !standby.worker.is_finished()),
+        failovers: state.failovers,
+        journal: state.journal.len(),
+    })
+}
+
+/// Stop both processes of a pair.
+///
+/// # Arguments
+/// * `process` - Handle to the pair
+#[rustler::nif(schedule = "DirtyCpu")]
+fn shadowed_stop(process: ResourceArc<ShadowedProcess>) -> NifResult<Atom> {
+    let mut state = process
+        .state
+        .lock()
+        .map_err(|e| error(format!("shadow lock failed: {}", e)))?;
+
+    if let Some(Ok(standby)) = state.standby.take().map(Standby::finish) {
+        let _ = standby.shutdown();
+    }
+    state.primary.shutdown().map_err(error)?;
+
+    Ok(ok())
+}
@@ -0,0 +1,374 @@
+//! Parsed results of Maude's SMT commands.
+//!
+//! With `smt.maude` loaded (`sload smt .`), Maude hands formulas over the
+//! `BOOLEAN`, `INTEGER`, and `REAL` theories to its SMT solver. `check`
+//! decides satisfiability:
+//!
+//! ```text
+//! Result from sat solver is: sat
+//! ```
+//!
+//! where the verdict is `sat`, `unsat`, or `undecided` - the latter also
+//! when the solver gives up, say on non-linear arithmetic, with a warning
+//! on stderr. Maude prints no model for it. `smt-search` explores a
+//! rewrite theory whose rules carry SMT conditions symbolically, and each
+//! solution comes with the constraint its state is reachable under:
+//!
+//! ```text
+//! Solution 1
+//! rewrites: 1 in 0ms cpu (0ms real) (~ rewrites/second)
+//! state: c(#1-Y:Integer, b)
+//! A --> b
+//! where Y > 5 and #1-Y:Integer > 0 and Y === #1-Y:Integer
+//! ```
+//!
+//! The constraint is the model of a solution: the values its SMT variables
+//! can take, usually as `===` equations between the pattern's variables and
+//! the fresh ones (`#1-Y:Integer`) in the state.
+
+use crate::command::{self, CommandKind};
+use crate::diagnostics::Outcome;
+use crate::failure::Failure;
+use crate::process::MaudeProcess;
+use crate::reply;
+use crate::term::{self, Term};
+use rustler::{Atom, Decoder, Encoder, Env, LocalPid, NifMap, NifResult, ResourceArc};
+
+rustler::atoms! {
+    sat,
+    unsat,
+    unknown,
+    bound,
+    depth,
+}
+
+/// Prefix of the line `check` prints its verdict on.
+const VERDICT: &str = "Result from sat solver is: ";
+
+/// Options for `smt_search/4`, decoded from a keyword list.
+///
+/// * `:bound` - Maximum number of solutions (default: all)
+/// * `:depth` - Maximum number of rewrite steps from the initial state
+///   (default: unbounded)
+#[derive(Debug, Default)]
+pub struct SmtSearchOptions {
+    pub bound: Option<u64>,
+    pub depth: Option<u64>,
+}
+
+impl<'a> Decoder<'a> for SmtSearchOptions {
+    fn decode(term: rustler::Term<'a>) -> NifResult<Self> {
+        let mut options = SmtSearchOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, rustler::Term<'a>)>>()? {
+            if key == bound() {
+                options.bound = Some(value.decode()?);
+            } else if key == depth() {
+                options.depth = Some(value.decode()?);
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// One solution of an `smt-search`.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Solution {
+    /// Solution number, counted from 1.
+    pub number: u64,
+    /// The state that matched the pattern.
+    pub state: Term,
+    /// Bindings of the pattern's non-SMT variables as `{"X", term}`, in
+    /// Maude's order.
+    pub substitution: Vec<(String, Term)>,
+    /// Constraint over the SMT variables under which `state` is reached.
+    pub constraint: Term,
+}
+
+/// The solver's verdict, encoded as `:sat`, `:unsat`, or `:unknown`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Sat,
+    Unsat,
+    Unknown,
+}
+
+impl Encoder for Verdict {
+    fn encode<'a>(&self, env: Env<'a>) -> rustler::Term<'a> {
+        match self {
+            Verdict::Sat => sat(),
+            Verdict::Unsat => unsat(),
+            Verdict::Unknown => unknown(),
+        }
+        .encode(env)
+    }
+}
+
+/// The verdict of a `check` output.
+pub fn parse_verdict(output: &str) -> Option<Verdict> {
+    let verdict = output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(VERDICT))?;
+
+    match verdict.trim() {
+        "sat" => Some(Verdict::Sat),
+        "unsat" => Some(Verdict::Unsat),
+        "undecided" => Some(Verdict::Unknown),
+        _ => None,
+    }
+}
+
+/// Parse every `Solution N` block in an `smt-search` output.
+pub fn parse_solutions(output: &str) -> Result<Vec<Solution>, String> {
+    let mut blocks: Vec<(u64, Vec<&str>)> = Vec::new();
+
+    for line in output.lines().map(str::trim) {
+        if let Some(number) = line.strip_prefix("Solution ") {
+            let number = number
+                .parse()
+                .map_err(|_| format!("invalid solution header: {:?}", line))?;
+            blocks.push((number, Vec::new()));
+        } else if let Some((_, lines)) = blocks.last_mut() {
+            lines.push(line);
+        }
+    }
+
+    blocks
+        .into_iter()
+        .map(|(number, lines)| parse_solution(number, &lines))
+        .collect()
+}
+
+/// Parse the lines after `Solution N`.
+fn parse_solution(number: u64, lines: &[&str]) -> Result<Solution, String> {
+    let mut state = None;
+    let mut constraint = None;
+    let mut substitution = Vec::new();
+
+    for line in lines {
+        if let Some(text) = line.strip_prefix("state: ") {
+            state = Some(term::parse(text)?);
+        } else if let Some(text) = line.strip_prefix("where ") {
+            constraint = Some(term::parse(text)?);
+        } else if let Some((variable, value)) = line.split_once(" --> ") {
+            substitution.push((variable.to_string(), term::parse(value)?));
+        }
+    }
+
+    Ok(Solution {
+        number,
+        state: state.ok_or_else(|| format!("no state in solution {}", number))?,
+        substitution,
+        constraint: constraint.ok_or_else(|| format!("no constraint in solution {}", number))?,
+    })
+}
+
+/// Decide satisfiability of an SMT formula with `check in <module> : <formula> .`.
+///
+/// The formula is validated like the term of `execute_term/4`, so it may
+/// be untrusted. `smt.maude` must be loaded, and `module` must import the
+/// theory the formula is over (`INTEGER`, `REAL`, ...).
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `module` - Module to check in
+/// * `formula` - `Boolean` formula, e.g. `X:Integer > 2 and X:Integer < 5`
+///
+/// # Returns
+/// * `Ok(:sat | :unsat)` - The solver's verdict
+/// * `Ok(:unknown)` - The solver couldn't decide, e.g. on non-linear
+///   arithmetic
+/// * `Err` - If the input is rejected, I/O fails, or Maude gives no verdict
+///   (with its warning, e.g. for a formula that isn't SMT)
+#[rustler::nif(schedule = "DirtyCpu")]
+fn smt_check(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    formula: String,
+) -> NifResult<Outcome<Verdict>> {
+    reply(check(&process, env.pid(), &module, &formula))
+}
+
+fn check(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    module: &str,
+    formula: &str,
+) -> Result<Verdict, Failure> {
+    let command = command::build(CommandKind::Check, module, formula)
+        .map_err(|e| format!("rejected command: {}", e))?;
+
+    let exchange = process.begin_by(caller)?;
+    let output = exchange.execute(&command)?;
+    let diagnostics = exchange.take_stderr()?;
+    drop(exchange);
+
+    parse_verdict(&output).ok_or_else(|| match diagnostics.trim() {
+        "" => "check failed: no verdict".into(),
+        diagnostics => format!("check failed: {}", diagnostics).into(),
+    })
+}
+
+fn search(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    module: &str,
+    query: &str,
+    options: &SmtSearchOptions,
+) -> Result<Vec<Solution>, Failure> {
+    if options.bound == Some(0) {
+        return Err("bound must be positive".into());
+    }
+
+    let command = command::build_limited(
+        CommandKind::SmtSearch,
+        &[],
+        options.bound,
+        options.depth,
+        module,
+        query,
+    )
+    .map_err(|e| format!("rejected command: {}", e))?;
+
+    let exchange = process.begin_by(caller)?;
+    let output = exchange.execute(&command)?;
+    let diagnostics = exchange.take_stderr()?;
+    drop(exchange);
+
+    if diagnostics.contains("Warning:") || diagnostics.contains("Error:") {
+        return Err(format!("smt_search failed: {}", diagnostics.trim()).into());
+    }
+
+    parse_solutions(&output).map_err(|e| format!("parse failed: {}", e).into())
+}
+
+/// Run `smt-search in <module> : <query> .` and parse the solutions.
+///
+/// The query is validated like the term of `execute_term/4`, e.g.
+/// `< 0 > =>+ < Y > such that Y > 5 = true`. Searches over SMT
+/// constraints often don't terminate; use `smt_search/4` with `:bound` or
+/// `:depth` unless the state space is known to be finite.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `module` - Module to search in
+/// * `query` - Initial state, arrow, pattern, and optional `such that`
+///   condition
+///
+/// # Returns
+/// * `Ok([Solution])` - `%{number, state, substitution, constraint}` maps,
+///   empty if there is no solution
+/// * `Err` - If the input is rejected, Maude reports a warning, I/O fails,
+///   or a solution can't be parsed
+#[rustler::nif(schedule = "DirtyCpu")]
+fn smt_search(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    query: String,
+) -> NifResult<Outcome<Vec<Solution>>> {
+    reply(search(
+        &process,
+        env.pid(),
+        &module,
+        &query,
+        &SmtSearchOptions::default(),
+    ))
+}
+
+/// `smt_search/3` with options; see [`SmtSearchOptions`].
+#[rustler::nif(schedule = "DirtyCpu", name = "smt_search")]
+fn smt_search_with_opts(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    query: String,
+    opts: SmtSearchOptions,
+) -> NifResult<Outcome<Vec<Solution>>> {
+    reply(search(&process, env.pid(), &module, &query, &opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_verdict() {
+        let cases = [
+            (
+                "check in INTEGER : X:Integer > 2 and X:Integer < 5 .\n\
+                 Result from sat solver is: sat",
+                Some(Verdict::Sat),
+            ),
+            (
+                "check in INTEGER : X:Integer > 2 and X:Integer < 1 .\n\
+                 Result from sat solver is: unsat",
+                Some(Verdict::Unsat),
+            ),
+            (
+                "check in INTEGER : X:Integer * X:Integer === 2 .\n\
+                 Result from sat solver is: undecided",
+                Some(Verdict::Unknown),
+            ),
+            ("Result from sat solver is: maybe", None),
+            ("check in INTEGER : 1 .", None),
+        ];
+
+        for (output, verdict) in cases {
+            assert_eq!(parse_verdict(output), verdict, "for {:?}", output);
+        }
+    }
+
+    #[test]
+    fn parses_smt_search_solutions() {
+        let output = "\
+smt-search [2] in EX : c(X:Integer, a) =>* c(Y:Integer, A:Elt) such that Y:Integer > 5 = true .
+
+Solution 1
+rewrites: 1 in 0ms cpu (0ms real) (~ rewrites/second)
+state: c(#1-Y:Integer, b)
+A:Elt --> b
+where Y:Integer > 5 and #1-Y:Integer === Y:Integer
+
+Solution 2
+rewrites: 2 in 0ms cpu (0ms real) (~ rewrites/second)
+state: c(#2-Y:Integer, a)
+A:Elt --> a
+where Y:Integer > 5 and #2-Y:Integer === Y:Integer + 1
+
+No more solutions.";
+        let solutions = parse_solutions(output).unwrap();
+
+        assert_eq!(solutions.len(), 2);
+        assert_eq!(solutions[0].number, 1);
+        assert_eq!(
+            solutions[0].state,
+            term::parse("c(#1-Y:Integer, b)").unwrap()
+        );
+        assert_eq!(
+            solutions[0].substitution,
+            [("A:Elt".to_string(), term::parse("b").unwrap())]
+        );
+        assert_eq!(
+            solutions[1].constraint,
+            term::parse("Y:Integer > 5 and #2-Y:Integer === Y:Integer + 1").unwrap()
+        );
+    }
+
+    #[test]
+    fn parses_no_solution() {
+        let output = "smt-search in EX : c(0, a) =>* c(1, b) .\n\nNo solution.";
+
+        assert!(parse_solutions(output).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_solutions_without_a_state_or_constraint() {
+        assert!(parse_solutions("Solution 1\nwhere true").is_err());
+        assert!(parse_solutions("Solution 1\nstate: c(0, a)").is_err());
+        assert!(parse_solutions("Solution one").is_err());
+    }
+}
@@ -0,0 +1,195 @@
+//! Loading Maude source held in memory.
+//!
+//! `load_string/2` takes module source as iodata - generated theories,
+//! say - writes it to a file of its own in the system temp directory, and
+//! `load`s that, so callers don't manage temp files. Going through a file
+//! rather than stdin keeps the exchange to one prompt whatever the source
+//! contains, and Maude's diagnostics then give line numbers within the
+//! source. The file is readable only by its owner on Unix and is removed
+//! once loaded.
+
+use crate::diagnostics::Outcome;
+use crate::error;
+use crate::failure::Failure;
+use crate::input::Input;
+use crate::process::MaudeProcess;
+use crate::reply;
+use crate::selection::MODULES;
+use rustler::{Env, LocalPid, NifResult, ResourceArc};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Distinguishes source files from one OS process.
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+/// A temp file holding source to load, removed when dropped.
+struct SourceFile {
+    path: PathBuf,
+}
+
+impl SourceFile {
+    /// Write `parts` to a new file in `dir`.
+    fn write(parts: &[&[u8]], dir: &Path) -> Result<SourceFile, String> {
+        let name = format!(
+            "ex_maude-{}-{}.maude",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = dir.join(name);
+
+        // `load` ends the file name at whitespace
+        if path.to_string_lossy().contains(char::is_whitespace) {
+            return Err(format!(
+                "temp directory path contains whitespace: {}",
+                path.display()
+            ));
+        }
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options
+            .open(&path)
+            .map_err(|e| format!("source write failed: {}: {}", path.display(), e))?;
+        let source = SourceFile { path };
+
+        // Maude needs a line end after the last module
+        parts
+            .iter()
+            .chain([&b"\n"[..]].iter())
+            .try_for_each(|part| file.write_all(part))
+            .map_err(|e| format!("source write failed: {}", e))?;
+
+        Ok(source)
+    }
+}
+
+impl Drop for SourceFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Names of the modules and theories `source` declares, in order; a
+/// parameterized module's by its bare name.
+fn declared(source: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut inside = false;
+    let mut tokens = tokens(source).into_iter();
+
+    while let Some(token) = tokens.next() {
+        if !inside && MODULES.contains(&token) {
+            if let Some(name) = tokens.next() {
+                names.push(name.split('{').next().unwrap_or(name).to_string());
+            }
+            inside = true;
+        } else if inside && is_end(token) {
+            inside = false;
+        }
+    }
+
+    names
+}
+
+/// Whether `token` closes a module or theory: `endfm`, `endm`, `endth`,
+/// and so on.
+fn is_end(token: &str) -> bool {
+    token.strip_prefix("end").is_some_and(|kind| {
+        matches!(
+            kind,
+            "fm" | "m" | "fth" | "th" | "sm" | "sth" | "om" | "oth"
+        )
+    })
+}
+
+/// Whitespace-separated tokens of `source` outside comments.
+///
+/// `***` and `---` comment out the rest of the line; followed by `(`, they
+/// open a comment that runs to the matching `)`.
+fn tokens(source: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut depth = 0usize;
+
+    for line in source.lines() {
+        for word in line.split_whitespace() {
+            if depth > 0 {
+                depth = close(depth, word);
+            } else if word.starts_with("***(") || word.starts_with("---(") {
+                depth = close(1, &word[4..]);
+            } else if word.starts_with("***") || word.starts_with("---") {
+                break;
+            } else {
+                tokens.push(word);
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Comment nesting left after `text`, starting `depth` deep.
+fn close(mut depth: usize, text: &str) -> usize {
+    for c in text.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            break;
+        }
+    }
+    depth
+}
+
+/// Load `path` in one exchange, returning the diagnostics it left.
+fn load(process: &MaudeProcess, caller: LocalPid, path: &Path) -> Result<String, Failure> {
+    let exchange = process.begin_by(caller)?;
+    exchange.execute(&format!("load {}", path.display()))?;
+    exchange.take_stderr()
+}
+
+/// Load Maude source given as a binary or iodata.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `source` - Module source, as it would appear in a `.maude` file
+///
+/// # Returns
+/// * `Ok([String])` - Names of the modules and theories the source declares
+/// * `{:error, reason, raw}` - Maude reported a warning or error while
+///   loading it; see [`crate::diagnostics`]
+/// * `Err` - If the temp file can't be written or I/O fails
+#[rustler::nif(schedule = "DirtyCpu")]
+fn load_string<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    source: Input<'a>,
+) -> NifResult<Outcome<Vec<String>>> {
+    // A sandboxed process may only load what is in its sandbox, which is
+    // where Maude runs
+    let (dir, relative) = match process.sandbox() {
+        Some(sandbox) => (sandbox.root().to_path_buf(), true),
+        None => (std::env::temp_dir(), false),
+    };
+    let file = SourceFile::write(&source.parts(), &dir).map_err(error)?;
+    let path = if relative {
+        file.path.strip_prefix(&dir).unwrap_or(&file.path)
+    } else {
+        &file.path
+    };
+
+    let stderr = match load(&process, env.pid(), path) {
+        Ok(stderr) => stderr,
+        Err(failure) => return reply(Err(failure)),
+    };
+
+    if let Some(failed) = Outcome::failed(&stderr) {
+        return Ok(failed);
+    }
+    Ok(Outcome::Done(declared(&source.to_string_lossy())))
+}
@@ -0,0 +1,131 @@
+//! Spilling oversized responses to disk.
+//!
+//! Maude can print a single result hundreds of megabytes long. Rather than
+//! growing one buffer for all of it, the reader hands everything past the
+//! process's spill threshold to a [`Spiller`], which streams it to a file
+//! and keeps only its short lines in memory - the `rewrites:`, `states:`,
+//! and `Solution` lines the stats and search bookkeeping scan for.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Lines longer than this are not kept in the sample.
+const SHORT_LINE: usize = 256;
+
+/// Upper bound on the sample, for output made of many short lines.
+const SAMPLE: usize = 64 * 1024;
+
+/// Distinguishes spill files from one OS process.
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+/// A response written to a file. The file belongs to the caller.
+#[derive(Debug)]
+pub struct Spill {
+    pub path: PathBuf,
+    pub bytes: u64,
+    /// The output's short lines, for scanning without reading the file.
+    pub sample: String,
+}
+
+/// The short lines of output fed to it piece by piece.
+#[derive(Debug, Default)]
+pub struct Sample {
+    /// Start of the line being fed, up to `SHORT_LINE` bytes.
+    line: Vec<u8>,
+    /// Whether the line being fed is already too long to keep.
+    long: bool,
+    sample: Vec<u8>,
+}
+
+impl Sample {
+    pub fn feed(&mut self, data: &[u8]) {
+        for piece in data.split_inclusive(|&byte| byte == b'\n') {
+            if !self.long {
+                self.line.extend_from_slice(piece);
+                self.long = self.line.len() > SHORT_LINE;
+            }
+            if piece.ends_with(b"\n") {
+                self.end_line();
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        if !self.long && self.sample.len() + self.line.len() <= SAMPLE {
+            self.sample.extend_from_slice(&self.line);
+        }
+        self.line.clear();
+        self.long = false;
+    }
+
+    /// The lines kept, the unterminated last one included.
+    pub fn finish(mut self) -> String {
+        self.end_line();
+        String::from_utf8_lossy(&self.sample).into_owned()
+    }
+}
+
+/// Streams output to a fresh file in a spill directory.
+pub struct Spiller {
+    file: BufWriter<File>,
+    path: PathBuf,
+    bytes: u64,
+    sample: Sample,
+}
+
+impl Spiller {
+    /// Create a new, uniquely named spill file in `dir`.
+    pub fn create(dir: &Path) -> Result<Spiller, String> {
+        let name = format!(
+            "ex_maude-{}-{}.out",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = dir.join(name);
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| format!("spill failed: {}: {}", path.display(), e))?;
+
+        Ok(Spiller {
+            file: BufWriter::new(file),
+            path,
+            bytes: 0,
+            sample: Sample::default(),
+        })
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        self.file
+            .write_all(data)
+            .map_err(|e| format!("spill failed: {}: {}", self.path.display(), e))?;
+        self.bytes += data.len() as u64;
+        self.sample.feed(data);
+
+        Ok(())
+    }
+
+    /// Remove the file, for output that won't be returned.
+    pub fn discard(self) {
+        let path = self.path.clone();
+        drop(self);
+        let _ = std::fs::remove_file(path);
+    }
+
+    /// Flush the file and describe what was written.
+    pub fn finish(mut self) -> Result<Spill, String> {
+        self.file
+            .flush()
+            .map_err(|e| format!("spill failed: {}: {}", self.path.display(), e))?;
+
+        Ok(Spill {
+            sample: self.sample.finish(),
+            path: self.path,
+            bytes: self.bytes,
+        })
+    }
+}
@@ -0,0 +1,158 @@
+//! Diagnostics for a Maude process that fails to start.
+//!
+//! A misconfigured `maude_path` - a shell script, another tool, a binary
+//! for the wrong platform - used to leave `start/1` waiting forever for a
+//! prompt that never came. Startup is now bounded by `:startup_timeout`,
+//! and the start NIFs report why it failed as `{:error, {reason, details}}`
+//! with the first lines the program wrote, so the cause is visible:
+//!
+//! * `:exec_format_error` - The OS couldn't execute the file at all
+//! * `:not_maude` - The program exited, or printed something other than
+//!   Maude's output, without showing a prompt
+//! * `:banner_timeout` - No prompt appeared within the timeout
+
+use crate::error;
+use crate::failure::Failure;
+use rustler::{Atom, Encoder, Env, NifMap, Term};
+use std::fmt;
+
+rustler::atoms! {
+    not_maude,
+    banner_timeout,
+    exec_format_error,
+}
+
+/// Lines of stdout and stderr kept for a startup error.
+const EXCERPT_LINES: usize = 10;
+
+/// Why a spawned program didn't become a ready Maude process.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StartupFailure {
+    NotMaude,
+    BannerTimeout,
+    ExecFormatError,
+}
+
+impl StartupFailure {
+    fn atom(self) -> Atom {
+        match self {
+            StartupFailure::NotMaude => not_maude(),
+            StartupFailure::BannerTimeout => banner_timeout(),
+            StartupFailure::ExecFormatError => exec_format_error(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            StartupFailure::NotMaude => "not_maude",
+            StartupFailure::BannerTimeout => "banner_timeout",
+            StartupFailure::ExecFormatError => "exec_format_error",
+        }
+    }
+}
+
+/// What the program wrote before startup failed, encoded as a map.
+#[derive(Debug, Clone, Default, NifMap)]
+pub struct StartupDetails {
+    /// First lines of stdout.
+    pub output: String,
+    /// First lines of stderr.
+    pub stderr: String,
+}
+
+/// Error from spawning a Maude process.
+#[derive(Debug)]
+pub enum SpawnError {
+    Failed(Failure),
+    Startup {
+        failure: StartupFailure,
+        details: StartupDetails,
+    },
+}
+
+impl SpawnError {
+    /// A startup failure, keeping only the first lines of what was written.
+    pub fn startup(failure: StartupFailure, output: &str, stderr: &str) -> SpawnError {
+        SpawnError::Startup {
+            failure,
+            details: StartupDetails {
+                output: excerpt(output),
+                stderr: excerpt(stderr),
+            },
+        }
+    }
+
+    /// Convert to the error returned by the start NIFs.
+    pub fn into_nif_error(self) -> rustler::Error {
+        match self {
+            SpawnError::Failed(failure) => error(failure),
+            SpawnError::Startup { failure, details } => {
+                rustler::Error::Term(Box::new(StartupError { failure, details }))
+            }
+        }
+    }
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnError::Failed(failure) => write!(f, "{}", failure),
+            SpawnError::Startup { failure, details } => {
+                write!(f, "startup failed: {}", failure.name())?;
+                for text in [&details.output, &details.stderr] {
+                    if !text.is_empty() {
+                        write!(f, ": {}", text)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<String> for SpawnError {
+    fn from(message: String) -> Self {
+        SpawnError::Failed(message.into())
+    }
+}
+
+impl From<Failure> for SpawnError {
+    fn from(failure: Failure) -> Self {
+        SpawnError::Failed(failure)
+    }
+}
+
+impl From<SpawnError> for Failure {
+    fn from(e: SpawnError) -> Self {
+        match e {
+            SpawnError::Failed(failure) => failure,
+            startup => Failure::Message(startup.to_string()),
+        }
+    }
+}
+
+impl From<SpawnError> for String {
+    fn from(e: SpawnError) -> Self {
+        e.to_string()
+    }
+}
+
+/// `{reason, details}`, the reason of a structured startup error.
+struct StartupError {
+    failure: StartupFailure,
+    details: StartupDetails,
+}
+
+impl Encoder for StartupError {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        (self.failure.atom(), &self.details).encode(env)
+    }
+}
+
+fn excerpt(text: &str) -> String {
+    text.trim()
+        .lines()
+        .take(EXCERPT_LINES)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,158 @@
+//! Cumulative rewrite statistics for a Maude process.
+//!
+//! Maude reports `rewrites: N in Xms cpu (Yms real) (Z rewrites/second)`
+//! after every reduce, rewrite, and search. The counters here add those
+//! lines up over the life of a process so throughput can be measured in
+//! rewrites rather than in requests.
+
+use crate::locks::LockWaits;
+use rustler::NifMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Running totals, updated from the output of each command.
+#[derive(Debug, Default)]
+pub struct Counters {
+    commands: AtomicU64,
+    rewrites: AtomicU64,
+    cpu_ms: AtomicU64,
+    real_ms: AtomicU64,
+    invalid_utf8: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    bytes_read: AtomicU64,
+    overloaded: AtomicU64,
+}
+
+/// Snapshot returned by `stats/1`.
+#[derive(Debug, NifMap)]
+pub struct ProcessStats {
+    /// Commands whose output included a stats line.
+    pub commands: u64,
+    /// Total rewrites performed.
+    pub rewrites: u64,
+    /// Total Maude CPU time in milliseconds.
+    pub cpu_ms: u64,
+    /// Total wall-clock time Maude reported, in milliseconds.
+    pub real_ms: u64,
+    /// `rewrites` over `cpu_ms`, or `nil` before any CPU time was recorded.
+    pub rewrites_per_second: Option<f64>,
+    /// Responses that weren't valid UTF-8.
+    pub invalid_utf8: u64,
+    /// Commands answered from the response cache; see [`crate::cache`].
+    pub cache_hits: u64,
+    /// Cacheable commands that had to go to Maude.
+    pub cache_misses: u64,
+    /// Bytes of responses read from Maude's stdout, prompts included.
+    pub bytes_read: u64,
+    /// Callers waiting for their turn right now.
+    pub queue_depth: u64,
+    /// Calls refused because `:max_queue` callers were already waiting;
+    /// see [`crate::backpressure`].
+    pub overloaded: u64,
+    /// Waits for a process lock longer than `:lock_wait_threshold`
+    /// (`lock-watch` builds only; otherwise 0).
+    pub slow_lock_waits: u64,
+    /// Longest of those waits, in milliseconds.
+    pub longest_lock_wait_ms: u64,
+    /// How long the oldest lock wait still in progress has lasted, in
+    /// milliseconds; 0 if nothing is waiting.
+    pub lock_waiting_ms: u64,
+}
+
+impl Counters {
+    /// Add every stats line found in `output` to the totals.
+    ///
+    /// A search prints a `states: ...` line after each solution with the
+    /// totals so far, so only the last one of each search is counted.
+    pub fn record(&self, output: &str) {
+        let mut search = None;
+
+        for line in output.lines().map(str::trim) {
+            if line.starts_with("search") {
+                self.add(search.take());
+            }
+            if let Some(rest) = line.strip_prefix("states: ") {
+                search = rest
+                    .split_once("rewrites: ")
+                    .and_then(|(_, rest)| parse_line(rest));
+            } else if let Some(rest) = line.strip_prefix("rewrites: ") {
+                self.add(parse_line(rest));
+            }
+        }
+
+        self.add(search);
+    }
+
+    fn add(&self, line: Option<(u64, u64, u64)>) {
+        if let Some((rewrites, cpu_ms, real_ms)) = line {
+            self.commands.fetch_add(1, Ordering::Relaxed);
+            self.rewrites.fetch_add(rewrites, Ordering::Relaxed);
+            self.cpu_ms.fetch_add(cpu_ms, Ordering::Relaxed);
+            self.real_ms.fetch_add(real_ms, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a response that wasn't valid UTF-8.
+    pub fn record_invalid(&self) {
+        self.invalid_utf8.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a cacheable command as answered from the cache or not.
+    pub fn record_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count `bytes` of a response read from stdout.
+    pub fn record_read(&self, bytes: usize) {
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Count a call refused because the queue was full.
+    pub fn record_overloaded(&self) {
+        self.overloaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self, waits: LockWaits, queue_depth: u64) -> ProcessStats {
+        let rewrites = self.rewrites.load(Ordering::Relaxed);
+        let cpu_ms = self.cpu_ms.load(Ordering::Relaxed);
+
+        ProcessStats {
+            commands: self.commands.load(Ordering::Relaxed),
+            rewrites,
+            cpu_ms,
+            real_ms: self.real_ms.load(Ordering::Relaxed),
+            rewrites_per_second: (cpu_ms > 0).then(|| rewrites as f64 * 1000.0 / cpu_ms as f64),
+            invalid_utf8: self.invalid_utf8.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read(),
+            queue_depth,
+            overloaded: self.overloaded.load(Ordering::Relaxed),
+            slow_lock_waits: waits.slow,
+            longest_lock_wait_ms: waits.longest_ms,
+            lock_waiting_ms: waits.waiting_ms,
+        }
+    }
+}
+
+/// Parse `N in Xms cpu (Yms real) ...` into `(N, X, Y)`.
+fn parse_line(line: &str) -> Option<(u64, u64, u64)> {
+    let (rewrites, rest) = line.split_once(" in ")?;
+    let (cpu_ms, rest) = rest.split_once("ms cpu (")?;
+    let (real_ms, _) = rest.split_once("ms real)")?;
+
+    Some((
+        rewrites.trim().parse().ok()?,
+        cpu_ms.trim().parse().ok()?,
+        real_ms.trim().parse().ok()?,
+    ))
+}
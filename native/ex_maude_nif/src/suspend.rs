@@ -0,0 +1,64 @@
+//! Pausing and resuming a Maude child.
+//!
+//! `pause/1` stops the child with `SIGSTOP` and `resume/1` continues it
+//! with `SIGCONT`, so a long computation - a background proof, say - can
+//! give way to interactive traffic and later carry on where it was. A
+//! paused child uses no CPU, and its time doesn't count against
+//! `:max_cpu_seconds`.
+//!
+//! Nothing else changes while it is paused: a command in progress, and any
+//! sent meanwhile, simply wait for its output. `stop/1` still works, since
+//! `SIGKILL` reaches a stopped process. Windows has no signals, and there
+//! both calls return an error.
+
+use crate::error;
+use crate::failure::Failure;
+use crate::process::MaudeProcess;
+use rustler::{Atom, NifResult, ResourceArc};
+
+rustler::atoms! {
+    ok,
+}
+
+#[cfg(unix)]
+fn set_paused(process: &MaudeProcess, paused: bool) -> Result<(), Failure> {
+    let signal = if paused { libc::SIGSTOP } else { libc::SIGCONT };
+    process.signal(signal, paused)
+}
+
+#[cfg(not(unix))]
+fn set_paused(_process: &MaudeProcess, _paused: bool) -> Result<(), Failure> {
+    Err("pause is not supported on this platform".into())
+}
+
+/// Stop the Maude child until `resume/1`.
+///
+/// Pausing a paused process is a no-op.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `Ok(:ok)` - The child is stopped
+/// * `Err` - If it has exited or the platform has no signals
+#[rustler::nif]
+fn pause(process: ResourceArc<MaudeProcess>) -> NifResult<Atom> {
+    set_paused(&process, true).map_err(error)?;
+    Ok(ok())
+}
+
+/// Continue a child stopped by `pause/1`.
+///
+/// Resuming a running process is a no-op.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `Ok(:ok)` - The child is running
+/// * `Err` - If it has exited or the platform has no signals
+#[rustler::nif]
+fn resume(process: ResourceArc<MaudeProcess>) -> NifResult<Atom> {
+    set_paused(&process, false).map_err(error)?;
+    Ok(ok())
+}
@@ -0,0 +1,802 @@
+//! Maude term parser.
+//!
+//! Converts terms printed by Maude into a nested `{op, sort, [args]}`
+//! structure. Both of Maude's print styles are understood:
+//!
+//! * **Prefix** (`set print mixfix off .`) - `_+_(N:Nat, _*_(7, 2))` is
+//!   parsed exactly, since every application is explicit.
+//! * **Mixfix** (the default) - `N:Nat + 7 * 2` is parsed with precedence
+//!   climbing over the precedences of the prelude operators. Operators
+//!   Maude doesn't tell us about fall back to its default precedences
+//!   (41 for infix, 15 for prefix), so user-defined mixfix syntax is a
+//!   best-effort parse.
+//!
+//! Operator names use Maude's underscore notation (`_+_`, `s_`, `__`), and
+//! chains of the same infix operator are flattened the way Maude prints
+//! associative operators in prefix form (`__('a, 'b, 'c)`).
+//!
+//! Only the root term carries a sort (taken from the `result Sort:` line)
+//! unless a subterm is a variable, whose sort is part of its name, or
+//! Maude qualified it with one, as in `(none).Configuration`.
+//!
+//! A result that is a constant of a builtin sort can instead be handed over
+//! as the Elixir value it denotes; see [`Native`].
+
+use crate::format::Bytes;
+use rustler::{Atom, Decoder, Encoder, Env, NifResult};
+use std::collections::HashMap;
+
+rustler::atoms! {
+    native,
+}
+
+/// Default precedence Maude gives to infix operators such as `_foo_`.
+const DEFAULT_INFIX_PREC: u32 = 41;
+/// Precedence used for prefix operators such as `s_` and `-_`.
+const DEFAULT_PREFIX_PREC: u32 = 15;
+/// Precedence of the empty-syntax juxtaposition operator `__`.
+const JUXTAPOSITION_PREC: u32 = 25;
+
+/// Precedences of the infix operators declared in the Maude prelude.
+const INFIX_PRECEDENCES: &[(&str, u32)] = &[
+    ("^", 29),
+    ("*", 31),
+    ("/", 31),
+    ("quo", 31),
+    ("rem", 31),
+    ("+", 33),
+    ("-", 33),
+    (">>", 35),
+    ("<<", 35),
+    ("<", 37),
+    ("<=", 37),
+    (">", 37),
+    (">=", 37),
+    ("==", 51),
+    ("=/=", 51),
+    ("divides", 51),
+    ("&", 53),
+    ("and", 55),
+    ("and-then", 55),
+    ("xor", 57),
+    ("or", 59),
+    ("or-else", 59),
+    ("implies", 61),
+    ("in", 41),
+    ("|", 41),
+    (";", 71),
+    ("=>", 71),
+    // From the SMT theories in `smt.maude`
+    ("===", 51),
+    ("=/==", 51),
+    ("div", 31),
+    ("mod", 31),
+    ("divisible", 51),
+];
+
+/// Deepest a term may nest before parsing gives up, well short of what
+/// the recursion takes to overflow a scheduler thread's stack.
+const MAX_DEPTH: usize = 256;
+
+/// Alphanumeric words that act as prefix operators in the prelude.
+const PREFIX_WORDS: &[&str] = &["s", "not", "p"];
+
+/// A parsed Maude term.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Term {
+    /// Operator name in underscore notation, or the token for constants,
+    /// literals, and variables (`0`, `"abc"`, `'foo`, `N:Nat`).
+    pub op: String,
+    /// Sort of the term when Maude reported it.
+    pub sort: Option<String>,
+    /// Arguments of the operator; empty for constants.
+    pub args: Vec<Term>,
+}
+
+impl Term {
+    fn constant(token: &str) -> Self {
+        Term {
+            op: token.to_string(),
+            sort: variable_sort(token),
+            args: Vec::new(),
+        }
+    }
+
+    fn app(op: String, args: Vec<Term>) -> Self {
+        Term {
+            op,
+            sort: None,
+            args,
+        }
+    }
+}
+
+impl Encoder for Term {
+    fn encode<'a>(&self, env: Env<'a>) -> rustler::Term<'a> {
+        (&self.op, &self.sort, &self.args).encode(env)
+    }
+}
+
+/// A result term encoded as the Elixir value it denotes when it is a
+/// constant of a builtin sort, and as `{op, sort, [args]}` otherwise.
+///
+/// | Sort | Value |
+/// |------|-------|
+/// | `Bool` | `true`, `false` |
+/// | `Zero`, `NzNat`, `Nat`, `NzInt`, `Int` | integer |
+/// | `FiniteFloat`, `Float` | float |
+/// | `String`, `Char` | binary, escapes decoded |
+/// | `Qid` | atom, without the quote, if it exists; else binary |
+///
+/// Values Elixir can't hold the same way stay terms: integers beyond 128
+/// bits and `Infinity`. Quoted identifiers never create atoms, which are
+/// never collected: one whose atom doesn't exist yet is a binary.
+#[derive(Debug)]
+pub struct Native(pub Term);
+
+impl Encoder for Native {
+    fn encode<'a>(&self, env: Env<'a>) -> rustler::Term<'a> {
+        value(&self.0, env).unwrap_or_else(|| self.0.encode(env))
+    }
+}
+
+/// The native value of `term`, if it has one.
+fn value<'a>(term: &Term, env: Env<'a>) -> Option<rustler::Term<'a>> {
+    if !term.args.is_empty() {
+        return None;
+    }
+
+    let op = term.op.as_str();
+    match term.sort.as_deref()? {
+        "Bool" => match op {
+            "true" => Some(true.encode(env)),
+            "false" => Some(false.encode(env)),
+            _ => None,
+        },
+        "Zero" | "NzNat" | "Nat" | "NzInt" | "Int" => {
+            op.parse::<i128>().ok().map(|n| n.encode(env))
+        }
+        // Rust would also read `Infinity` and `NaN`
+        "FiniteFloat" | "Float" => op
+            .parse::<f64>()
+            .ok()
+            .filter(|f| f.is_finite())
+            .map(|f| f.encode(env)),
+        "String" | "Char" => unescape(op).map(|bytes| Bytes(bytes).encode(env)),
+        "Qid" => {
+            op.strip_prefix('\'')
+                .map(|name| match Atom::try_from_bytes(env, name.as_bytes()) {
+                    Ok(Some(atom)) => atom.encode(env),
+                    _ => name.encode(env),
+                })
+        }
+        _ => None,
+    }
+}
+
+/// The bytes of a string literal as Maude prints it, quotes included:
+/// C escapes such as `\n` and `\"`, and `\ooo` in octal for other bytes.
+pub fn unescape(literal: &str) -> Option<Vec<u8>> {
+    let inner = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut chars = inner.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        let escaped = chars.next()?;
+        let byte = match escaped {
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0c,
+            'n' => b'\n',
+            'r' => b'\r',
+            't' => b'\t',
+            'v' => 0x0b,
+            '\\' | '"' | '\'' | '?' => escaped as u8,
+            '0'..='7' => {
+                let mut code = escaped.to_digit(8)?;
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                u8::try_from(code).ok()?
+            }
+            _ => return None,
+        };
+        bytes.push(byte);
+    }
+
+    Some(bytes)
+}
+
+/// Options for `execute_parsed/3`, decoded from a keyword list.
+///
+/// * `:native` - Return constants of builtin sorts as Elixir values; see
+///   [`Native`] (default: `true`). `false` keeps every result a term, for
+///   callers that need Maude's exact text.
+#[derive(Debug, Clone, Copy)]
+pub struct ParsedOptions {
+    pub native: bool,
+}
+
+impl Default for ParsedOptions {
+    fn default() -> Self {
+        ParsedOptions { native: true }
+    }
+}
+
+impl<'a> Decoder<'a> for ParsedOptions {
+    fn decode(term: rustler::Term<'a>) -> NifResult<Self> {
+        let mut options = ParsedOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, rustler::Term<'a>)>>()? {
+            if key == native() {
+                options.native = value.decode()?;
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Parse the `result Sort: term` line out of reduce/rewrite output.
+///
+/// The returned root term carries the reported sort.
+pub fn parse_result(output: &str) -> Result<Term, String> {
+    let line = output
+        .lines()
+        .find_map(|line| line.strip_prefix("result "))
+        .ok_or_else(|| "no result in output".to_string())?;
+
+    let (sort, term) = line
+        .split_once(": ")
+        .ok_or_else(|| format!("malformed result line: {}", line))?;
+
+    let mut term = parse(term)?;
+    term.sort = Some(sort.trim().to_string());
+    Ok(term)
+}
+
+/// Parse a single term in prefix or mixfix notation.
+pub fn parse(input: &str) -> Result<Term, String> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        objects: 0,
+        depth: 0,
+        parsed_objects: HashMap::new(),
+    };
+    let term = parser.expr(u32::MAX)?;
+
+    match parser.peek() {
+        None => Ok(term),
+        Some(token) => Err(format!("unexpected token: {}", token.text())),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Identifier, literal, or operator symbol.
+    Word(String),
+    /// Identifier immediately followed by `(`, i.e. a prefix application.
+    Call(String),
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+    Comma,
+}
+
+impl Token {
+    fn text(&self) -> &str {
+        match self {
+            Token::Word(w) | Token::Call(w) => w,
+            Token::Open => "(",
+            Token::Close => ")",
+            Token::OpenBracket => "[",
+            Token::CloseBracket => "]",
+            Token::Comma => ",",
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            '[' => {
+                tokens.push(Token::OpenBracket);
+                i += 1;
+            }
+            ']' => {
+                tokens.push(Token::CloseBracket);
+                i += 1;
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                i += 1;
+            }
+            '"' => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    return Err("unterminated string literal".to_string());
+                }
+                i += 1;
+                tokens.push(Token::Word(chars[start..i].iter().collect()));
+            }
+            _ => {
+                let start = i;
+                while i < chars.len() && !is_delimiter(chars[i]) {
+                    // A backquote escapes a delimiter in an operator name,
+                    // as in `_`,_` printed in prefix form
+                    if chars[i] == '`' {
+                        i += 1;
+                        if i == chars.len() {
+                            break;
+                        }
+                    }
+                    // Kind-sorted variables such as `X:[Nat]` keep the brackets
+                    if chars[i] == ':' && chars.get(i + 1) == Some(&'[') {
+                        while i < chars.len() && chars[i] != ']' {
+                            i += 1;
+                        }
+                    }
+                    i += 1;
+                }
+                let word: String = chars[start..i.min(chars.len())].iter().collect();
+
+                if chars.get(i) == Some(&'(') {
+                    tokens.push(Token::Call(word));
+                    i += 1;
+                } else {
+                    tokens.push(Token::Word(word));
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | ',' | '"')
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Depth of `< ... >` objects being parsed, where `>` closes instead
+    /// of being an infix operator.
+    objects: usize,
+    /// Expressions being parsed, one inside the other.
+    depth: usize,
+    /// Objects parsed so far by the position after their `<`, with the
+    /// position after their `>`; `None` where there was none.
+    parsed_objects: HashMap<usize, Option<(Term, usize)>>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Parse an expression whose operators bind at least as tightly as
+    /// `max_prec` (lower Maude precedence means tighter binding), failing
+    /// past [`MAX_DEPTH`] expressions deep.
+    fn expr(&mut self, max_prec: u32) -> Result<Term, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err("term nested too deeply".to_string());
+        }
+        self.depth += 1;
+        let term = self.climb(max_prec);
+        self.depth -= 1;
+        term
+    }
+
+    /// [`Parser::expr`] at the current depth.
+    fn climb(&mut self, max_prec: u32) -> Result<Term, String> {
+        let mut lhs = self.operand()?;
+        // Operator of the chain built in this call, used for flattening
+        let mut chain: Option<String> = None;
+
+        loop {
+            let (name, prec, consumes) = match self.peek().cloned() {
+                None | Some(Token::Close) | Some(Token::CloseBracket) | Some(Token::Comma) => break,
+                Some(Token::Word(w)) if w == ">" && self.objects > 0 => break,
+                Some(Token::Word(w)) if w == "<" && self.object_follows() => {
+                    ("__".to_string(), JUXTAPOSITION_PREC, false)
+                }
+                Some(Token::Word(w)) if infix_prec(&w).is_some() => {
+                    let prec = infix_prec(&w).unwrap_or(DEFAULT_INFIX_PREC);
+                    (format!("_{}_", w), prec, true)
+                }
+                Some(_) => ("__".to_string(), JUXTAPOSITION_PREC, false),
+            };
+
+            if prec > max_prec {
+                break;
+            }
+
+            if consumes {
+                self.next();
+            }
+
+            // Left-associative: the right operand must bind strictly tighter
+            let rhs = self.expr(prec.saturating_sub(1))?;
+
+            if chain.as_deref() == Some(name.as_str()) {
+                lhs.args.push(rhs);
+            } else {
+                lhs = Term::app(name.clone(), vec![lhs, rhs]);
+                chain = Some(name);
+            }
+        }
+
+        Ok(lhs)
+    }
+
+    fn operand(&mut self) -> Result<Term, String> {
+        match self.next() {
+            Some(Token::Open) => {
+                // A parenthesized comma list is a term of the `_,_` operator
+                let mut terms = self.list(Token::Close)?;
+                let mut term = match terms.len() {
+                    0 => return Err("empty parentheses".to_string()),
+                    1 => terms.remove(0),
+                    _ => Term::app("_,_".to_string(), terms),
+                };
+                // Maude disambiguates a term by its sort: `(none).Configuration`
+                if let Some(Token::Word(w)) = self.peek() {
+                    if let Some(sort) = w.strip_prefix('.').filter(|s| !s.is_empty()) {
+                        term.sort = Some(sort.to_string());
+                        self.next();
+                    }
+                }
+                Ok(term)
+            }
+            Some(Token::OpenBracket) => {
+                let args = self.list(Token::CloseBracket)?;
+                let op = format!("[{}]", vec!["_"; args.len()].join(","));
+                Ok(Term::app(op, args))
+            }
+            Some(Token::Word(w)) if w == "<" => {
+                let start = self.pos;
+                match self.object() {
+                    Some(object) => Ok(object),
+                    None => {
+                        self.pos = start;
+                        Ok(Term::constant(&w))
+                    }
+                }
+            }
+            Some(Token::Call(op)) => {
+                let args = self.list(Token::Close)?;
+                Ok(Term::app(op, args))
+            }
+            Some(Token::Word(w)) if self.starts_operand() && is_prefix_op(&w) => {
+                let arg = self.expr(DEFAULT_PREFIX_PREC)?;
+                Ok(Term::app(format!("{}_", w), vec![arg]))
+            }
+            Some(Token::Word(w)) => Ok(Term::constant(&w)),
+            Some(token) => Err(format!("unexpected token: {}", token.text())),
+            None => Err("unexpected end of term".to_string()),
+        }
+    }
+
+    /// Parse comma-separated terms up to and including `close`.
+    fn list(&mut self, close: Token) -> Result<Vec<Term>, String> {
+        let mut args = Vec::new();
+        if self.peek() == Some(&close) {
+            self.next();
+            return Ok(args);
+        }
+        loop {
+            args.push(self.expr(u32::MAX)?);
+            match self.next() {
+                Some(Token::Comma) => continue,
+                Some(token) if token == close => return Ok(args),
+                _ => return Err(format!("missing closing {}", close.text())),
+            }
+        }
+    }
+
+    /// Parse the rest of an object `< O : C | Attrs >` after the `<`.
+    ///
+    /// Returns `None` if the tokens don't have object shape, in which case
+    /// the caller rewinds and treats `<` as an ordinary token.
+    ///
+    /// Each position is parsed once: [`Parser::object_follows`] looks
+    /// ahead before the object is parsed for real, which would otherwise
+    /// take time exponential in how deeply objects nest.
+    fn object(&mut self) -> Option<Term> {
+        let start = self.pos;
+        if let Some(parsed) = self.parsed_objects.get(&start) {
+            let (object, end) = parsed.clone()?;
+            self.pos = end;
+            return Some(object);
+        }
+
+        self.objects += 1;
+        let object = self.object_parts();
+        self.objects -= 1;
+        self.parsed_objects
+            .insert(start, object.clone().map(|object| (object, self.pos)));
+        object
+    }
+
+    /// Whether the `<` at the current position opens an object, as in a
+    /// configuration of juxtaposed objects.
+    fn object_follows(&mut self) -> bool {
+        let start = self.pos;
+        self.pos += 1;
+        let follows = self.object().is_some();
+        self.pos = start;
+        follows
+    }
+
+    fn object_parts(&mut self) -> Option<Term> {
+        let oid = self.expr(DEFAULT_INFIX_PREC - 1).ok()?;
+        self.expect_word(":")?;
+        let class = self.expr(DEFAULT_INFIX_PREC - 1).ok()?;
+        self.expect_word("|")?;
+
+        let mut attrs = Vec::new();
+        loop {
+            attrs.push(self.expr(u32::MAX).ok()?);
+            match self.next() {
+                Some(Token::Comma) => continue,
+                Some(Token::Word(w)) if w == ">" => break,
+                _ => return None,
+            }
+        }
+
+        let attrs = if attrs.len() == 1 {
+            attrs.remove(0)
+        } else {
+            Term::app("_,_".to_string(), attrs)
+        };
+
+        Some(Term::app("<_:_|_>".to_string(), vec![oid, class, attrs]))
+    }
+
+    fn expect_word(&mut self, word: &str) -> Option<()> {
+        match self.next() {
+            Some(Token::Word(w)) if w == word => Some(()),
+            _ => None,
+        }
+    }
+
+    fn starts_operand(&self) -> bool {
+        match self.peek() {
+            Some(Token::Open) | Some(Token::OpenBracket) | Some(Token::Call(_)) => true,
+            Some(Token::Word(w)) => infix_prec(w).is_none(),
+            _ => false,
+        }
+    }
+}
+
+fn infix_prec(word: &str) -> Option<u32> {
+    if is_literal(word) || (word.contains(':') && word.len() > 1) {
+        return None;
+    }
+
+    INFIX_PRECEDENCES
+        .iter()
+        .find(|(op, _)| *op == word)
+        .map(|(_, prec)| *prec)
+        .or_else(|| is_symbolic(word).then_some(DEFAULT_INFIX_PREC))
+}
+
+fn is_prefix_op(word: &str) -> bool {
+    PREFIX_WORDS.contains(&word) || (is_symbolic(word) && !is_literal(word))
+}
+
+fn is_symbolic(word: &str) -> bool {
+    !word.is_empty() && !word.chars().any(|c| c.is_alphanumeric() || c == '\'')
+}
+
+fn is_literal(word: &str) -> bool {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some('"') | Some('\'') => true,
+        Some(c) if c.is_ascii_digit() => true,
+        Some('-') => chars.next().is_some_and(|c| c.is_ascii_digit()),
+        _ => false,
+    }
+}
+
+/// Sort of a variable token such as `N:Nat`, if the token is one.
+fn variable_sort(token: &str) -> Option<String> {
+    if is_literal(token) {
+        return None;
+    }
+
+    match token.split_once(':') {
+        Some((name, sort)) if !name.is_empty() && !sort.is_empty() => Some(sort.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(op: &str, args: Vec<Term>) -> Term {
+        Term::app(op.to_string(), args)
+    }
+
+    fn constant(token: &str) -> Term {
+        Term::constant(token)
+    }
+
+    #[test]
+    fn parses_prefix_applications() {
+        let term = parse("_+_(N:Nat, _*_(7, 2))").unwrap();
+        assert_eq!(
+            term,
+            app(
+                "_+_",
+                vec![
+                    constant("N:Nat"),
+                    app("_*_", vec![constant("7"), constant("2")])
+                ]
+            )
+        );
+        assert_eq!(term.args[0].sort.as_deref(), Some("Nat"));
+    }
+
+    #[test]
+    fn parses_mixfix_by_precedence() {
+        assert_eq!(
+            parse("N:Nat + 7 * 2").unwrap(),
+            parse("_+_(N:Nat, _*_(7, 2))").unwrap()
+        );
+        assert_eq!(
+            parse("(1 + 2) * 3").unwrap(),
+            app(
+                "_*_",
+                vec![
+                    app("_+_", vec![constant("1"), constant("2")]),
+                    constant("3")
+                ]
+            )
+        );
+        assert_eq!(
+            parse("s s 0").unwrap(),
+            app("s_", vec![app("s_", vec![constant("0")])])
+        );
+    }
+
+    #[test]
+    fn flattens_chains_of_one_operator() {
+        assert_eq!(
+            parse("'a 'b 'c").unwrap(),
+            app("__", vec![constant("'a"), constant("'b"), constant("'c")])
+        );
+        assert_eq!(
+            parse("1 + 2 + 3").unwrap(),
+            app("_+_", vec![constant("1"), constant("2"), constant("3")])
+        );
+    }
+
+    #[test]
+    fn parses_objects_and_sort_qualifications() {
+        let term = parse("< 'a : Account | bal: 10 > < 'b : Account | bal: 5 >").unwrap();
+        assert_eq!(term.op, "__");
+        assert_eq!(term.args.len(), 2);
+        assert_eq!(term.args[0].op, "<_:_|_>");
+        assert_eq!(term.args[0].args[1], constant("Account"));
+
+        let none = parse("(none).Configuration").unwrap();
+        assert_eq!(none.op, "none");
+        assert_eq!(none.sort.as_deref(), Some("Configuration"));
+    }
+
+    #[test]
+    fn keeps_literals_and_escaped_names_whole() {
+        assert_eq!(parse(r#""a \"b\" c""#).unwrap(), constant(r#""a \"b\" c""#));
+        assert_eq!(parse("X:[Nat]").unwrap().sort.as_deref(), Some("[Nat]"));
+        assert_eq!(
+            parse("_`,_(1, 2)").unwrap(),
+            app("_`,_", vec![constant("1"), constant("2")])
+        );
+    }
+
+    #[test]
+    fn reads_the_result_line() {
+        let output =
+            "reduce in NAT : 1 + 2 .\nrewrites: 1 in 0ms cpu (0ms real)\nresult NzNat: 3\n";
+        let term = parse_result(output).unwrap();
+        assert_eq!(term.op, "3");
+        assert_eq!(term.sort.as_deref(), Some("NzNat"));
+
+        assert!(parse_result("Warning: no module FOO.").is_err());
+        assert!(parse_result("result Nat").is_err());
+    }
+
+    #[test]
+    fn survives_input_ending_in_a_backquote() {
+        for input in ["`", "a`", "_`", "f(a`", "a `"] {
+            let _ = parse(input);
+        }
+        assert_eq!(parse("a`").unwrap(), constant("a`"));
+    }
+
+    #[test]
+    fn refuses_malformed_terms() {
+        assert!(parse("").is_err());
+        assert!(parse("f(1, 2").is_err());
+        assert!(parse("()").is_err());
+        assert!(parse("1 )").is_err());
+        assert!(parse(r#""open"#).is_err());
+    }
+
+    #[test]
+    fn refuses_terms_nested_too_deeply() {
+        let nested = |depth: usize| format!("{}0{}", "f(".repeat(depth), ")".repeat(depth));
+        assert!(parse(&nested(100)).is_ok());
+        assert_eq!(
+            parse(&nested(100_000)),
+            Err("term nested too deeply".to_string())
+        );
+        let parens = format!("{}0{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert_eq!(parse(&parens), Err("term nested too deeply".to_string()));
+    }
+
+    #[test]
+    fn parses_nested_objects_once() {
+        // Looked ahead at and parsed again at every level, this would take
+        // minutes
+        let nested = format!("{}0{}", "< 'a : C | x: ".repeat(60), " >".repeat(60));
+        let mut term = parse(&nested).unwrap();
+        for _ in 0..60 {
+            assert_eq!(term.op, "<_:_|_>");
+            term = term.args.pop().unwrap().args.pop().unwrap();
+        }
+        assert_eq!(term, constant("0"));
+    }
+
+    #[test]
+    fn decodes_string_escapes() {
+        assert_eq!(unescape(r#""a\nb""#), Some(b"a\nb".to_vec()));
+        assert_eq!(unescape(r#""\101\"""#), Some(b"A\"".to_vec()));
+        assert_eq!(unescape(r#""\q""#), None);
+        assert_eq!(unescape("abc"), None);
+    }
+}
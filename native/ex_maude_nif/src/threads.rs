@@ -0,0 +1,228 @@
+//! Background threads shared by every process.
+//!
+//! Short background jobs - rebuilding a pool's spare workers, draining
+//! a retired worker, bringing up a `start_async/2` process, stopping a
+//! hibernated one - run on one shared pool of at most `:max_threads`
+//! threads (default: 8) instead of a thread each, so a BEAM running
+//! hundreds of pooled workers keeps a small, fixed number of OS threads.
+//! Threads start on demand and stay parked when idle; jobs queue while
+//! every thread is busy.
+//!
+//! Every thread the NIF starts is named, so `top -H` or a debugger shows
+//! what it is for:
+//!
+//! * `ex_maude-worker-<n>` - shared pool threads
+//! * `ex_maude-watchdog` - the idle watchdog for hibernating processes
+//! * `ex_maude-standby-<n>` - a shadowed process's standby follower
+//! * `ex_maude-heartbeat` - one per process with a `subscribe/2` subscriber
+//! * `ex_maude-spawn` and `ex_maude-batch` - scoped helpers of one pool
+//!   call, gone when it returns
+//! * `ex_maude-probe` - one per candidate `discover_maude/0` runs, gone
+//!   when it returns
+//! * `ex_maude-mock` - one per `start_mock/1` process, answering its
+//!   requests until it stops
+//! * `ex_maude-writer` - one per execute call streaming a large command,
+//!   gone when it returns
+//! * `ex_maude-events` - one per process with a `subscribe_events/2`
+//!   subscriber, sending its events until the process is dropped
+//!
+//! Linux keeps only the first 15 bytes of a thread name, so `top -H` shows
+//! the role without the number; the full name appears in panic messages.
+//!
+//! Jobs must not wait for other jobs: a job blocked on one queued behind it
+//! would hold its thread forever once the pool is at its cap.
+
+use crate::error;
+use rustler::{Atom, Decoder, NifMap, NifResult, Term};
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+rustler::atoms! {
+    ok,
+    max_threads,
+}
+
+/// Default cap on shared pool threads.
+const DEFAULT_MAX_THREADS: usize = 8;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct Queue {
+    jobs: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+    max: usize,
+    /// Names the next thread started.
+    started: u64,
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    jobs: VecDeque::new(),
+    threads: 0,
+    idle: 0,
+    max: DEFAULT_MAX_THREADS,
+    started: 0,
+});
+static AVAILABLE: Condvar = Condvar::new();
+
+fn queue() -> MutexGuard<'static, Queue> {
+    // The queue stays consistent even if a holder panicked
+    QUEUE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Start a named thread; an error naming it if the OS refuses.
+pub fn spawn<F, T>(name: impl Into<String>, f: F) -> Result<JoinHandle<T>, String>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let name = name.into();
+    std::thread::Builder::new()
+        .name(name.clone())
+        .spawn(f)
+        .map_err(|e| format!("{} failed to start: {}", name, e))
+}
+
+/// Run `job` on the shared pool, starting a thread if none is idle and the
+/// cap allows.
+pub fn run(job: impl FnOnce() + Send + 'static) {
+    let mut queue = queue();
+    queue.jobs.push_back(Box::new(job));
+
+    if queue.idle > 0 {
+        AVAILABLE.notify_one();
+    } else {
+        grow(queue);
+    }
+}
+
+/// Start one more pool thread unless the pool is at its cap.
+fn grow(mut queue: MutexGuard<'static, Queue>) {
+    if queue.threads >= queue.max {
+        return;
+    }
+
+    queue.threads += 1;
+    queue.started += 1;
+    let name = format!("ex_maude-worker-{}", queue.started);
+    drop(queue);
+
+    if std::thread::Builder::new().name(name).spawn(work).is_err() {
+        // Run a job here rather than lose it if no thread can serve it
+        let mut queue = self::queue();
+        queue.threads -= 1;
+        if queue.threads == 0 {
+            if let Some(job) = queue.jobs.pop_front() {
+                drop(queue);
+                job();
+            }
+        }
+    }
+}
+
+/// Serve jobs until the pool shrinks below this thread.
+fn work() {
+    let mut queue = queue();
+
+    loop {
+        if queue.threads > queue.max {
+            queue.threads -= 1;
+            return;
+        }
+
+        let Some(job) = queue.jobs.pop_front() else {
+            queue.idle += 1;
+            queue = AVAILABLE.wait(queue).unwrap_or_else(|e| e.into_inner());
+            queue.idle -= 1;
+            continue;
+        };
+        drop(queue);
+
+        // A panicking job must not take the thread down with it
+        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+        queue = self::queue();
+    }
+}
+
+/// Options for `threads_configure/1`, decoded from a keyword list.
+///
+/// * `:max_threads` - Cap on shared pool threads (default: `8`)
+#[derive(Debug, Default)]
+pub struct ThreadOptions {
+    pub max_threads: Option<usize>,
+}
+
+impl<'a> Decoder<'a> for ThreadOptions {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut options = ThreadOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, Term<'a>)>>()? {
+            if key == max_threads() {
+                options.max_threads = Some(value.decode()?);
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Report returned by `threads_info/0`.
+#[derive(Debug, NifMap)]
+pub struct ThreadInfo {
+    /// Shared pool threads running.
+    pub threads: usize,
+    /// Of those, the ones waiting for a job.
+    pub idle: usize,
+    /// Jobs waiting for a thread.
+    pub queued: usize,
+    pub max_threads: usize,
+}
+
+/// Set the cap on shared background threads.
+///
+/// Lowering the cap stops surplus threads as they finish their current
+/// job; raising it lets queued jobs start at once.
+///
+/// # Arguments
+/// * `opts` - Keyword list; see [`ThreadOptions`]
+///
+/// # Returns
+/// * `Ok(:ok)` - The cap is in effect
+/// * `Err` - If `:max_threads` is zero
+#[rustler::nif]
+fn threads_configure(opts: ThreadOptions) -> NifResult<Atom> {
+    if let Some(max) = opts.max_threads {
+        if max == 0 {
+            return Err(error("max_threads must be positive"));
+        }
+
+        let mut queue = queue();
+        queue.max = max;
+        let startable = max.saturating_sub(queue.threads).min(queue.jobs.len());
+        drop(queue);
+
+        // Wake surplus idle threads so they can exit, and start threads for
+        // jobs that were queued behind the old cap
+        AVAILABLE.notify_all();
+        for _ in 0..startable {
+            grow(self::queue());
+        }
+    }
+
+    Ok(ok())
+}
+
+/// Report the shared background thread pool's size and backlog.
+#[rustler::nif]
+fn threads_info() -> ThreadInfo {
+    let queue = queue();
+
+    ThreadInfo {
+        threads: queue.threads,
+        idle: queue.idle,
+        queued: queue.jobs.len(),
+        max_threads: queue.max,
+    }
+}
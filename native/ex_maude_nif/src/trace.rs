@@ -0,0 +1,426 @@
+//! Structured capture of Maude's rewrite trace.
+//!
+//! With `set trace on .` Maude prints a block for every rewrite it
+//! performs:
+//!
+//! ```text
+//! *********** equation
+//! eq [e1] : f(X:S) = X:S .
+//! X:S --> b
+//! Old: f(b)
+//! f(b)
+//! --->
+//! b
+//! New: b
+//! ```
+//!
+//! The `Old:`/`New:` lines only appear with `set trace whole on .`. Blocks
+//! for condition solving (`trial #1`, `solving condition fragment`, ...)
+//! are not rewrites and are skipped.
+
+use crate::diagnostics::Outcome;
+use crate::failure::Failure;
+use crate::input::Input;
+use crate::process::{Exchange, MaudeProcess, Response};
+use crate::reply;
+use rustler::{Atom, Decoder, Encoder, Env, LocalPid, NifMap, NifResult, ResourceArc, Term};
+
+rustler::atoms! {
+    equation,
+    rule,
+    membership,
+    equations,
+    rules,
+    memberships,
+    condition,
+    substitution,
+    whole,
+}
+
+pub const HEADER: &str = "*********** ";
+
+/// Trace flags to set for one traced command, decoded from a keyword list.
+///
+/// * `:equations`, `:rules`, `:memberships` - Which statements to trace
+///   (Maude's default: all)
+/// * `:condition` - Trace condition solving (default: `true`)
+/// * `:substitution` - Print substitutions (default: `true`)
+/// * `:whole` - Print the whole term before and after each rewrite
+///   (default: `false`)
+#[derive(Debug, Default)]
+pub struct TraceOptions {
+    /// `(flag, value)` pairs given explicitly, in Maude's flag names.
+    flags: Vec<(&'static str, bool)>,
+}
+
+impl<'a> Decoder<'a> for TraceOptions {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let names = [
+            (equations(), "eq"),
+            (rules(), "rl"),
+            (memberships(), "mb"),
+            (condition(), "condition"),
+            (substitution(), "substitution"),
+            (whole(), "whole"),
+        ];
+        let mut flags = Vec::new();
+
+        for (key, value) in term.decode::<Vec<(Atom, Term<'a>)>>()? {
+            if let Some((_, flag)) = names.iter().find(|(name, _)| *name == key) {
+                flags.push((*flag, value.decode::<bool>()?));
+            }
+        }
+
+        Ok(TraceOptions { flags })
+    }
+}
+
+/// Maude's default for a trace flag, restored after the traced command.
+fn flag_default(flag: &str) -> bool {
+    flag != "whole"
+}
+
+/// Kind of statement a trace block applies.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Equation,
+    Rule,
+    Membership,
+}
+
+impl Encoder for Kind {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            Kind::Equation => equation(),
+            Kind::Rule => rule(),
+            Kind::Membership => membership(),
+        }
+        .encode(env)
+    }
+}
+
+/// One rewrite from the trace.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct TraceEvent {
+    /// `:equation`, `:rule`, or `:membership`.
+    pub kind: Kind,
+    /// Statement label, if it has one.
+    pub label: Option<String>,
+    /// The statement applied, e.g. `"rl [r1] : b => c ."`.
+    pub statement: String,
+    /// Matching substitution as `{"X:Sort", term}` pairs.
+    pub substitution: Vec<(String, String)>,
+    /// Subterm that was rewritten.
+    pub redex: String,
+    /// What the redex was rewritten to.
+    pub contractum: String,
+    /// Whole term before the rewrite (needs `whole: true`).
+    pub before: Option<String>,
+    /// Whole term after the rewrite (needs `whole: true`).
+    pub after: Option<String>,
+}
+
+/// Result of `execute_traced/3`.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Traced {
+    /// Command output with the trace removed.
+    pub output: String,
+    /// Rewrites in the order Maude performed them.
+    pub events: Vec<TraceEvent>,
+}
+
+/// Run `command` with tracing on, then switch tracing off again.
+pub fn execute(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    command: &[&[u8]],
+    options: &TraceOptions,
+) -> Result<Traced, Failure> {
+    // One exchange, so no other caller's command runs with tracing on
+    let exchange = process.begin_by(caller)?;
+    execute_raw(&exchange, command, options).map(|output| parse(&output))
+}
+
+/// Run `command` on `exchange` with tracing on, then switch tracing off
+/// again, returning the output with the trace left in.
+pub fn execute_raw(
+    exchange: &Exchange,
+    command: &[&[u8]],
+    options: &TraceOptions,
+) -> Result<String, Failure> {
+    for (flag, value) in &options.flags {
+        set_flag(exchange, flag, *value)?;
+    }
+    set_flag(exchange, "", true)?;
+
+    let result = exchange
+        .execute_response(command)
+        .and_then(Response::into_text);
+
+    // Restore tracing even if the command failed
+    let restored = set_flag(exchange, "", false).and_then(|()| {
+        options
+            .flags
+            .iter()
+            .try_for_each(|(flag, _)| set_flag(exchange, flag, flag_default(flag)))
+    });
+
+    let output = result?;
+    restored?;
+
+    Ok(output)
+}
+
+fn set_flag(exchange: &Exchange, flag: &str, value: bool) -> Result<(), Failure> {
+    let value = if value { "on" } else { "off" };
+    let command = if flag.is_empty() {
+        format!("set trace {} .", value)
+    } else {
+        format!("set trace {} {} .", flag, value)
+    };
+
+    // Restored afterwards, so allowed on a read-only process
+    exchange.trusted(|| exchange.execute(&command)).map(|_| ())
+}
+
+/// Split traced output into rewrite events and the remaining output.
+pub fn parse(output: &str) -> Traced {
+    let mut events = Vec::new();
+    let mut rest: Vec<&str> = Vec::new();
+    let mut lines = output.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let Some(header) = line.strip_prefix(HEADER) else {
+            rest.push(line);
+            continue;
+        };
+
+        // Block body runs until the next header or the command's stats line
+        let mut body = Vec::new();
+        while let Some(next) = lines.peek() {
+            if next.starts_with(HEADER) || next.starts_with("rewrites: ") {
+                break;
+            }
+            body.extend(lines.next());
+        }
+
+        let kind = match header {
+            "equation" => Kind::Equation,
+            "rule" => Kind::Rule,
+            "membership axiom" => Kind::Membership,
+            _ => continue,
+        };
+        events.extend(parse_event(kind, &body));
+    }
+
+    Traced {
+        output: rest.join("\n"),
+        events,
+    }
+}
+
+fn parse_event(kind: Kind, body: &[&str]) -> Option<TraceEvent> {
+    let (statement, lines) = body.split_first()?;
+    let arrow = lines.iter().position(|line| *line == "--->")?;
+
+    // The redex is printed right before the arrow, the whole term before it
+    let (redex, head) = lines[..arrow].split_last()?;
+    let (before, head) = match head.split_last() {
+        Some((line, rest)) if line.starts_with("Old: ") => (Some(line[5..].to_string()), rest),
+        _ => (None, head),
+    };
+
+    let substitution = head
+        .iter()
+        .filter_map(|line| line.split_once(" --> "))
+        .map(|(variable, value)| (variable.to_string(), value.to_string()))
+        .collect();
+
+    let after_arrow = &lines[arrow + 1..];
+    let contractum = after_arrow.first()?.to_string();
+    let after = after_arrow
+        .iter()
+        .find_map(|line| line.strip_prefix("New: "))
+        .map(str::to_string);
+
+    Some(TraceEvent {
+        kind,
+        label: statement_label(statement),
+        statement: statement.to_string(),
+        substitution,
+        redex: redex.to_string(),
+        contractum,
+        before,
+        after,
+    })
+}
+
+/// Label of a statement: `eq [l] : ...` or a trailing `[label l]` attribute.
+pub fn statement_label(statement: &str) -> Option<String> {
+    let (_, rest) = statement.split_once(' ')?;
+
+    if let Some(rest) = rest.strip_prefix('[') {
+        if let Some((label, _)) = rest.split_once("] :") {
+            return Some(label.to_string());
+        }
+    }
+
+    let (_, attributes) = statement.rsplit_once("[label ")?;
+    let (label, _) = attributes.split_once(|c: char| c == ']' || c.is_whitespace())?;
+    Some(label.to_string())
+}
+
+/// Execute a command with Maude's rewrite trace captured as events.
+///
+/// Tracing is switched on for this command only; the flags given in
+/// `trace_opts` are set beforehand and reset to Maude's defaults after.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `command` - Maude command to execute
+/// * `trace_opts` - Keyword list of trace flags; see [`TraceOptions`]
+///
+/// # Returns
+/// * `Ok(Traced)` - `%{output: String.t(), events: [event]}`
+/// * `Err` - If I/O fails
+#[rustler::nif(schedule = "DirtyCpu")]
+fn execute_traced<'a>(
+    env: Env<'a>,
+    process: ResourceArc<MaudeProcess>,
+    command: Input<'a>,
+    trace_opts: TraceOptions,
+) -> NifResult<Outcome<Traced>> {
+    reply(execute(&process, env.pid(), &command.parts(), &trace_opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_equation_blocks() {
+        let output = "\
+reduce in EX : f(b) .
+*********** equation
+eq [e1] : f(X:S) = X:S .
+X:S --> b
+Old: g(f(b))
+f(b)
+--->
+b
+New: g(b)
+rewrites: 1 in 0ms cpu (0ms real) (~ rewrites/second)
+result S: g(b)";
+        let traced = parse(output);
+
+        assert_eq!(
+            traced.output,
+            "reduce in EX : f(b) .\n\
+             rewrites: 1 in 0ms cpu (0ms real) (~ rewrites/second)\n\
+             result S: g(b)"
+        );
+        assert_eq!(traced.events.len(), 1);
+
+        let event = &traced.events[0];
+        assert_eq!(event.kind, Kind::Equation);
+        assert_eq!(event.label.as_deref(), Some("e1"));
+        assert_eq!(event.statement, "eq [e1] : f(X:S) = X:S .");
+        assert_eq!(event.substitution, [("X:S".to_string(), "b".to_string())]);
+        assert_eq!(event.redex, "f(b)");
+        assert_eq!(event.contractum, "b");
+        assert_eq!(event.before.as_deref(), Some("g(f(b))"));
+        assert_eq!(event.after.as_deref(), Some("g(b)"));
+    }
+
+    #[test]
+    fn parses_rule_and_membership_blocks() {
+        let output = "\
+rewrite in EX : b .
+*********** trial #1
+crl [r2] : b => d if c = c .
+empty substitution
+*********** rule
+rl b => c [label r1] .
+empty substitution
+b
+--->
+c
+*********** membership axiom
+mb s N:Nat : NzNat [label m1] .
+N:Nat --> 0
+Nat: s 0
+--->
+NzNat: s 0
+rewrites: 2 in 0ms cpu (0ms real) (~ rewrites/second)
+result C: c";
+        let traced = parse(output);
+
+        assert_eq!(traced.events.len(), 2);
+
+        let rule = &traced.events[0];
+        assert_eq!(rule.kind, Kind::Rule);
+        assert_eq!(rule.label.as_deref(), Some("r1"));
+        assert!(rule.substitution.is_empty());
+        assert_eq!((rule.redex.as_str(), rule.contractum.as_str()), ("b", "c"));
+        assert_eq!(
+            (rule.before.as_deref(), rule.after.as_deref()),
+            (None, None)
+        );
+
+        let membership = &traced.events[1];
+        assert_eq!(membership.kind, Kind::Membership);
+        assert_eq!(membership.label.as_deref(), Some("m1"));
+        assert_eq!(
+            membership.substitution,
+            [("N:Nat".to_string(), "0".to_string())]
+        );
+        assert_eq!(membership.redex, "Nat: s 0");
+        assert_eq!(membership.contractum, "NzNat: s 0");
+
+        assert!(!traced.output.contains(HEADER));
+        assert!(traced.output.ends_with("result C: c"));
+    }
+
+    #[test]
+    fn drops_a_truncated_block() {
+        let output = "\
+*********** equation
+eq f(X:S) = X:S .
+X:S --> a
+f(a)
+--->
+a
+*********** equation
+eq [e2] : g(X:S) = X:S .
+X:S --> a
+g(a)";
+        let traced = parse(output);
+
+        assert_eq!(traced.events.len(), 1);
+        assert_eq!(traced.events[0].label, None);
+        assert_eq!(traced.events[0].redex, "f(a)");
+        assert_eq!(traced.output, "");
+    }
+
+    #[test]
+    fn reads_statement_labels() {
+        let cases = [
+            ("eq [e1] : f(X) = X .", Some("e1")),
+            ("rl [step] : a => b .", Some("step")),
+            ("crl a => b if c [label r3] .", Some("r3")),
+            ("mb s N : NzNat [label m1 metadata \"x\"] .", Some("m1")),
+            ("eq f(X) = X .", None),
+        ];
+
+        for (statement, label) in cases {
+            assert_eq!(
+                statement_label(statement).as_deref(),
+                label,
+                "for {:?}",
+                statement
+            );
+        }
+    }
+}
@@ -0,0 +1,318 @@
+//! Parsed results of `unify` and `variant unify`.
+//!
+//! Both commands print one block per unifier:
+//!
+//! ```text
+//! Unifier 1
+//! X --> a
+//! Y --> #1:S
+//!
+//! No more unifiers.
+//! ```
+//!
+//! `variant unify` ends with `No more unifiers.` (or `No unifiers.`) once
+//! the set is exhausted, while `unify` computes the whole set up front and
+//! prints nothing after the last unifier (`No unifier.` if there is none).
+//! Neither says whether a bound `[n]` cut the set short, so a bounded
+//! command that returns exactly `n` unifiers without the end marker is
+//! reported as incomplete.
+
+use crate::command::{self, CommandKind};
+use crate::diagnostics::Outcome;
+use crate::failure::Failure;
+use crate::process::MaudeProcess;
+use crate::reply;
+use crate::term::{self, Term};
+use rustler::{Atom, Decoder, Env, LocalPid, NifMap, NifResult, ResourceArc};
+
+rustler::atoms! {
+    bound,
+}
+
+/// Printed when an incomplete unification algorithm was used.
+const MISSED: &str = "may have been missed";
+
+/// Options for `unify/4` and `variant_unify/4`, decoded from a keyword list.
+///
+/// * `:bound` - Maximum number of unifiers to compute (default: all)
+#[derive(Debug, Default)]
+pub struct UnifyOptions {
+    pub bound: Option<u64>,
+}
+
+impl<'a> Decoder<'a> for UnifyOptions {
+    fn decode(term: rustler::Term<'a>) -> NifResult<Self> {
+        let mut options = UnifyOptions::default();
+
+        for (key, value) in term.decode::<Vec<(Atom, rustler::Term<'a>)>>()? {
+            if key == bound() {
+                options.bound = Some(value.decode()?);
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// One unifier of a unification problem.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Unifier {
+    /// Unifier number, counted from 1.
+    pub number: u64,
+    /// Variable bindings as `{"X", term}`, in Maude's order.
+    pub substitution: Vec<(String, Term)>,
+}
+
+/// Result of `unify/3` and `variant_unify/3`.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct Unifiers {
+    pub unifiers: Vec<Unifier>,
+    /// Whether these are all the unifiers, rather than a set cut short by
+    /// the bound or by an incomplete unification algorithm.
+    pub complete: bool,
+}
+
+/// Parse every `Unifier N` block in a `unify` or `variant unify` output.
+pub fn parse_unifiers(output: &str) -> Result<Vec<Unifier>, String> {
+    let mut unifiers: Vec<Unifier> = Vec::new();
+
+    for line in output.lines().map(str::trim) {
+        if let Some(number) = line.strip_prefix("Unifier ") {
+            unifiers.push(Unifier {
+                number: number
+                    .parse()
+                    .map_err(|_| format!("invalid unifier header: {:?}", line))?,
+                substitution: Vec::new(),
+            });
+        } else if let Some((variable, value)) = line.split_once(" --> ") {
+            let unifier = unifiers
+                .last_mut()
+                .ok_or_else(|| format!("binding outside a unifier: {:?}", line))?;
+            unifier
+                .substitution
+                .push((variable.to_string(), term::parse(value)?));
+        }
+    }
+
+    Ok(unifiers)
+}
+
+/// Whether `output` holds every unifier of a command run with `bound`.
+fn is_complete(output: &str, diagnostics: &str, bound: Option<u64>, count: usize) -> bool {
+    if output.contains(MISSED) || diagnostics.contains(MISSED) {
+        return false;
+    }
+
+    let exhausted = output
+        .lines()
+        .map(str::trim)
+        .any(|line| matches!(line, "No unifier." | "No unifiers." | "No more unifiers."));
+
+    exhausted || bound.is_none_or(|n| (count as u64) < n)
+}
+
+fn run(
+    process: &MaudeProcess,
+    caller: LocalPid,
+    kind: CommandKind,
+    module: &str,
+    problem: &str,
+    options: &UnifyOptions,
+) -> Result<Unifiers, Failure> {
+    if options.bound == Some(0) {
+        return Err("bound must be positive".into());
+    }
+
+    let command = command::build_bounded(kind, options.bound, module, problem)
+        .map_err(|e| format!("rejected command: {}", e))?;
+
+    let exchange = process.begin_by(caller)?;
+    let output = exchange.execute(&command)?;
+    let diagnostics = exchange.take_stderr()?;
+    drop(exchange);
+
+    let unifiers = parse_unifiers(&output).map_err(|e| format!("parse failed: {}", e))?;
+    let complete = is_complete(&output, &diagnostics, options.bound, unifiers.len());
+
+    Ok(Unifiers { unifiers, complete })
+}
+
+/// Run `unify in <module> : <problem> .` and parse the unifiers.
+///
+/// The problem is validated like the term of `execute_term/4`, so it may
+/// be untrusted; conjunctions are written `t1 =? t2 /\ t3 =? t4`.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `module` - Module to unify in
+/// * `problem` - Unification problem, e.g. `f(X, Y) =? f(a, Z)`
+///
+/// # Returns
+/// * `Ok(Unifiers)` - `%{unifiers: [%{number, substitution}], complete}`
+/// * `Err` - If the input is rejected, I/O fails, or a unifier can't be parsed
+#[rustler::nif(schedule = "DirtyCpu")]
+fn unify(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    problem: String,
+) -> NifResult<Outcome<Unifiers>> {
+    reply(run(
+        &process,
+        env.pid(),
+        CommandKind::Unify,
+        &module,
+        &problem,
+        &UnifyOptions::default(),
+    ))
+}
+
+/// `unify/3` with options; see [`UnifyOptions`].
+#[rustler::nif(schedule = "DirtyCpu", name = "unify")]
+fn unify_with_opts(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    problem: String,
+    opts: UnifyOptions,
+) -> NifResult<Outcome<Unifiers>> {
+    reply(run(
+        &process,
+        env.pid(),
+        CommandKind::Unify,
+        &module,
+        &problem,
+        &opts,
+    ))
+}
+
+/// Run `variant unify in <module> : <problem> .` and parse the unifiers.
+///
+/// Variant unification only terminates for theories with the finite
+/// variant property; pass `:bound` when that isn't known.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+/// * `module` - Module to unify in
+/// * `problem` - Unification problem, e.g. `X + Y =? a`
+///
+/// # Returns
+/// * `Ok(Unifiers)` - As for `unify/3`
+/// * `Err` - If the input is rejected, I/O fails, or a unifier can't be parsed
+#[rustler::nif(schedule = "DirtyCpu")]
+fn variant_unify(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    problem: String,
+) -> NifResult<Outcome<Unifiers>> {
+    reply(run(
+        &process,
+        env.pid(),
+        CommandKind::VariantUnify,
+        &module,
+        &problem,
+        &UnifyOptions::default(),
+    ))
+}
+
+/// `variant_unify/3` with options; see [`UnifyOptions`].
+#[rustler::nif(schedule = "DirtyCpu", name = "variant_unify")]
+fn variant_unify_with_opts(
+    env: Env,
+    process: ResourceArc<MaudeProcess>,
+    module: String,
+    problem: String,
+    opts: UnifyOptions,
+) -> NifResult<Outcome<Unifiers>> {
+    reply(run(
+        &process,
+        env.pid(),
+        CommandKind::VariantUnify,
+        &module,
+        &problem,
+        &opts,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_UNIFIERS: &str = "\
+unify in NAT : X:Nat + Y:Nat =? 1 + Z:Nat .
+Decision time: 0ms cpu (0ms real)
+
+Unifier 1
+X:Nat --> 1
+Y:Nat --> Z:Nat
+
+Unifier 2
+X:Nat --> Z:Nat
+Y:Nat --> 1
+
+No more unifiers.";
+
+    fn binding(variable: &str, value: &str) -> (String, Term) {
+        (variable.to_string(), term::parse(value).unwrap())
+    }
+
+    #[test]
+    fn parses_every_unifier() {
+        let unifiers = parse_unifiers(TWO_UNIFIERS).unwrap();
+
+        assert_eq!(unifiers.len(), 2);
+        assert_eq!(unifiers[0].number, 1);
+        assert_eq!(
+            unifiers[0].substitution,
+            vec![binding("X:Nat", "1"), binding("Y:Nat", "Z:Nat")]
+        );
+        assert_eq!(unifiers[1].number, 2);
+        assert_eq!(
+            unifiers[1].substitution,
+            vec![binding("X:Nat", "Z:Nat"), binding("Y:Nat", "1")]
+        );
+        assert!(is_complete(TWO_UNIFIERS, "", None, 2));
+    }
+
+    #[test]
+    fn parses_no_unifier() {
+        let output = "\
+unify in NAT : 0 =? s(N:Nat) .
+Decision time: 0ms cpu (0ms real)
+
+No unifier.";
+
+        assert!(parse_unifiers(output).unwrap().is_empty());
+        assert!(is_complete(output, "", Some(1), 0));
+    }
+
+    #[test]
+    fn is_incomplete_when_unifiers_may_have_been_missed() {
+        let warning = "Warning: Unification modulo the theory of operator _+_ \
+                       has encountered an instance for which it may not be complete.\n\
+                       Warning: some unifiers may have been missed.";
+        let output = format!("{}\n\n{}", TWO_UNIFIERS, warning);
+
+        assert_eq!(parse_unifiers(&output).unwrap().len(), 2);
+        assert!(!is_complete(&output, "", None, 2));
+        assert!(!is_complete(TWO_UNIFIERS, warning, None, 2));
+    }
+
+    #[test]
+    fn is_incomplete_when_cut_short_by_the_bound() {
+        let output = TWO_UNIFIERS.trim_end_matches("No more unifiers.");
+
+        assert!(!is_complete(output, "", Some(2), 2));
+        assert!(is_complete(output, "", Some(3), 2));
+        assert!(is_complete(TWO_UNIFIERS, "", Some(2), 2));
+    }
+
+    #[test]
+    fn rejects_malformed_unifiers() {
+        assert!(parse_unifiers("Unifier one\nX:Nat --> 1").is_err());
+        assert!(parse_unifiers("X:Nat --> 1").is_err());
+    }
+}
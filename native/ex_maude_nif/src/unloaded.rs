@@ -0,0 +1,77 @@
+//! Stand-ins for the BEAM's NIF API in unit tests.
+//!
+//! The BEAM provides the `enif_*` functions when it loads the NIF, so a
+//! test executable has nothing to link them to. The tests exercise the
+//! parsers and checks, which never call them; each stand-in aborts the
+//! test run if one does. Code calling a function missing here fails to
+//! link under `cargo test` until it is added.
+
+macro_rules! unloaded {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[no_mangle]
+            extern "C" fn $name() {
+                panic!(concat!(stringify!($name), " called outside the BEAM"));
+            }
+        )*
+    };
+}
+
+unloaded! {
+    enif_alloc_binary,
+    enif_alloc_env,
+    enif_alloc_resource,
+    enif_binary_to_term,
+    enif_clear_env,
+    enif_compare,
+    enif_demonitor_process,
+    enif_free_env,
+    enif_get_double,
+    enif_get_int,
+    enif_get_list_cell,
+    enif_get_local_pid,
+    enif_get_long,
+    enif_get_resource,
+    enif_get_tuple,
+    enif_get_uint,
+    enif_get_ulong,
+    enif_inspect_binary,
+    enif_is_atom,
+    enif_is_binary,
+    enif_is_empty_list,
+    enif_is_identical,
+    enif_is_list,
+    enif_is_map,
+    enif_is_process_alive,
+    enif_keep_resource,
+    enif_make_atom_len,
+    enif_make_badarg,
+    enif_make_binary,
+    enif_make_copy,
+    enif_make_double,
+    enif_make_existing_atom_len,
+    enif_make_int,
+    enif_make_list_from_array,
+    enif_make_long,
+    enif_make_map_from_arrays,
+    enif_make_map_put,
+    enif_make_new_binary,
+    enif_make_new_map,
+    enif_make_resource,
+    enif_make_tuple_from_array,
+    enif_make_uint,
+    enif_make_ulong,
+    enif_map_iterator_create,
+    enif_map_iterator_destroy,
+    enif_map_iterator_get_pair,
+    enif_map_iterator_next,
+    enif_map_iterator_prev,
+    enif_monitor_process,
+    enif_raise_exception,
+    enif_release_binary,
+    enif_release_resource,
+    enif_schedule_nif,
+    enif_self,
+    enif_send,
+    enif_thread_type,
+}
@@ -0,0 +1,152 @@
+//! Operating-system resource usage of a Maude child.
+//!
+//! `stats/1` counts what Maude reports about its rewriting; `proc_info/1`
+//! asks the OS what the child itself costs - resident memory, CPU time,
+//! and threads - so workers can be sized and leaks spotted without
+//! shelling out to `ps`. The figures come from `/proc/<pid>/stat` on Linux
+//! and `proc_pidinfo` on macOS; other platforms report an error.
+//!
+//! Reading them doesn't touch the pipes, so it works while a command runs.
+
+use crate::error;
+use crate::process::MaudeProcess;
+use rustler::{NifMap, NifResult, ResourceArc};
+
+/// Report returned by `proc_info/1`.
+#[derive(Debug, NifMap)]
+pub struct ProcInfo {
+    pub os_pid: u32,
+    /// Resident set size in bytes.
+    pub rss_bytes: u64,
+    /// CPU time in user mode, in milliseconds.
+    pub user_cpu_ms: u64,
+    /// CPU time in the kernel on the child's behalf, in milliseconds.
+    pub system_cpu_ms: u64,
+    pub threads: u64,
+    /// Milliseconds since the child was launched.
+    pub uptime_ms: u64,
+    /// Whether the child is stopped by `pause/1`.
+    pub paused: bool,
+}
+
+/// Usage figures read from the OS, before the uptime is added.
+struct Usage {
+    rss_bytes: u64,
+    user_cpu_ms: u64,
+    system_cpu_ms: u64,
+    threads: u64,
+}
+
+#[cfg(target_os = "linux")]
+fn read(pid: u32) -> Result<Usage, String> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid))
+        .map_err(|e| format!("process info unavailable: {}", e))?;
+
+    // `pid (comm) state ...`; comm may itself contain parentheses, so the
+    // fields are counted from after the last one, starting at `state`
+    let close = stat
+        .rfind(')')
+        .ok_or("process info unavailable: malformed stat")?;
+    let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+    let field = |n: usize| -> Result<u64, String> {
+        fields
+            .get(n - 3)
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| "process info unavailable: malformed stat".to_string())
+    };
+
+    // SAFETY: sysconf only reads configuration values.
+    let (ticks, page) = unsafe {
+        (
+            libc::sysconf(libc::_SC_CLK_TCK),
+            libc::sysconf(libc::_SC_PAGESIZE),
+        )
+    };
+    let ticks = u64::try_from(ticks).unwrap_or(100).max(1);
+    let page = u64::try_from(page).unwrap_or(4096);
+
+    // Field numbers as in proc(5)
+    Ok(Usage {
+        rss_bytes: field(24)? * page,
+        user_cpu_ms: field(14)? * 1000 / ticks,
+        system_cpu_ms: field(15)? * 1000 / ticks,
+        threads: field(20)?,
+    })
+}
+
+#[cfg(target_os = "macos")]
+#[allow(deprecated)] // libc points at the mach2 crate for the timebase
+fn read(pid: u32) -> Result<Usage, String> {
+    // SAFETY: proc_taskinfo is plain integers, for which zero is valid.
+    let mut info: libc::proc_taskinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_taskinfo>() as libc::c_int;
+
+    // SAFETY: `info` is a writable proc_taskinfo of `size` bytes.
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid as libc::c_int,
+            libc::PROC_PIDTASKINFO,
+            0,
+            &mut info as *mut libc::proc_taskinfo as *mut libc::c_void,
+            size,
+        )
+    };
+    if written != size {
+        let e = std::io::Error::last_os_error();
+        return Err(format!("process info unavailable: {}", e));
+    }
+
+    // CPU times are in Mach absolute time units, not always nanoseconds
+    let mut timebase = libc::mach_timebase_info { numer: 0, denom: 0 };
+    // SAFETY: `timebase` is a writable mach_timebase_info.
+    unsafe {
+        libc::mach_timebase_info(&mut timebase);
+    }
+    let (numer, denom) = if timebase.numer > 0 && timebase.denom > 0 {
+        (u128::from(timebase.numer), u128::from(timebase.denom))
+    } else {
+        (1, 1)
+    };
+    let millis = |units: u64| (u128::from(units) * numer / denom / 1_000_000) as u64;
+
+    Ok(Usage {
+        rss_bytes: info.pti_resident_size,
+        user_cpu_ms: millis(info.pti_total_user),
+        system_cpu_ms: millis(info.pti_total_system),
+        threads: u64::try_from(info.pti_threadnum).unwrap_or(0),
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn read(_pid: u32) -> Result<Usage, String> {
+    Err("process info is not supported on this platform".to_string())
+}
+
+/// Resource usage of the Maude child, as the OS reports it.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `Ok(ProcInfo)` - Map of `os_pid`, `rss_bytes`, `user_cpu_ms`,
+///   `system_cpu_ms`, `threads`, `uptime_ms`, and `paused`
+/// * `Err` - If the process has stopped or the platform isn't supported
+#[rustler::nif]
+fn proc_info(process: ResourceArc<MaudeProcess>) -> NifResult<ProcInfo> {
+    if !process.is_alive() {
+        return Err(error("maude exited"));
+    }
+
+    let os_pid = process.config().os_pid;
+    let usage = read(os_pid).map_err(error)?;
+
+    Ok(ProcInfo {
+        os_pid,
+        rss_bytes: usage.rss_bytes,
+        user_cpu_ms: usage.user_cpu_ms,
+        system_cpu_ms: usage.system_cpu_ms,
+        threads: usage.threads,
+        uptime_ms: process.uptime().as_millis() as u64,
+        paused: process.is_paused(),
+    })
+}
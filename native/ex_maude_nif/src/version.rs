@@ -0,0 +1,164 @@
+//! The Maude version a process runs, and what it can do.
+//!
+//! Features arrived across Maude releases - the narrowing commands among
+//! them - and SMT support depends on how Maude was built, so a command a
+//! given binary lacks fails with a bare syntax error. `version/1` reports
+//! the version `maude --version` prints and probes each capability with a
+//! trivial command in `BOOL`, so callers can branch instead:
+//!
+//! ```elixir
+//! %{version: "3.5.1", release: {3, 5, 1},
+//!   capabilities: %{variant_unify: true, get_variants: true,
+//!                   vu_narrow: true, fvu_narrow: true, smt: true}}
+//! ```
+//!
+//! The probes run once per process, on the first call, and the module
+//! selected before them is selected again afterwards. A capability is
+//! available when its probe draws no complaint; `smt` is when Maude's
+//! complaint about `check in BOOL : true .` comes from the SMT layer, as
+//! `true` is no SMT expression.
+
+use crate::diagnostics::Outcome;
+use crate::failure::Failure;
+use crate::install::probe_version;
+use crate::process::{Exchange, MaudeProcess};
+use crate::reply;
+use crate::selection;
+use rustler::{Env, LocalPid, NifMap, NifResult, ResourceArc};
+
+/// What the process's Maude can do, by feature.
+#[derive(Debug, Clone, Default, NifMap)]
+pub struct Capabilities {
+    pub variant_unify: bool,
+    pub get_variants: bool,
+    pub vu_narrow: bool,
+    pub fvu_narrow: bool,
+    /// Whether Maude was built with an SMT solver (`check`, `smt-search`).
+    pub smt: bool,
+}
+
+/// A capability's field, and a command answered cleanly only where the
+/// feature exists.
+type Probe = (fn(&mut Capabilities) -> &mut bool, &'static str);
+
+const PROBES: &[Probe] = &[
+    (
+        |c| &mut c.variant_unify,
+        "variant unify in BOOL : true =? true .",
+    ),
+    (|c| &mut c.get_variants, "get variants in BOOL : true ."),
+    (|c| &mut c.vu_narrow, "vu-narrow in BOOL : true =>* true ."),
+    (
+        |c| &mut c.fvu_narrow,
+        "fvu-narrow in BOOL : true =>* true .",
+    ),
+];
+
+/// Result of `version/1`.
+#[derive(Debug, Clone, Default, NifMap)]
+pub struct Fingerprint {
+    /// As printed by `maude --version`, or `nil` if it printed nothing.
+    pub version: Option<String>,
+    /// The version's numbers, or `nil` if it isn't `major.minor.patch`.
+    pub release: Option<(u32, u32, u32)>,
+    pub capabilities: Capabilities,
+}
+
+/// `major.minor.patch` out of a version such as `3.5.1` or `3.4`, or out
+/// of a banner line such as `Maude 3.5.1 built: Oct 1 2024 12:00:00`.
+fn parse_release(version: &str) -> Option<(u32, u32, u32)> {
+    let mut numbers = version
+        .split_whitespace()
+        .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))?
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .next()?
+        .split('.');
+    let major = numbers.next()?.parse().ok()?;
+    let minor = numbers.next()?.parse().ok()?;
+    let patch = numbers.next().map_or(Some(0), |n| n.parse().ok())?;
+    Some((major, minor, patch))
+}
+
+/// Probe every capability in one exchange, then restore the selection.
+fn probe(process: &MaudeProcess, exchange: &Exchange) -> Result<Capabilities, Failure> {
+    let selected = selection::current(process, exchange)?;
+    let mut capabilities = Capabilities::default();
+
+    for (field, command) in PROBES {
+        exchange.execute(command)?;
+        let stderr = exchange.take_stderr()?;
+        *field(&mut capabilities) = !stderr.contains("Warning:") && !stderr.contains("Error:");
+    }
+
+    exchange.execute("check in BOOL : true .")?;
+    capabilities.smt = exchange.take_stderr()?.contains("SMT Boolean expression");
+
+    // Every probe ran `in BOOL`, which selects it
+    match selected {
+        Some(module) => selection::select(exchange, &module)?,
+        None => exchange.record_selection(None),
+    }
+    Ok(capabilities)
+}
+
+fn fingerprint(process: &MaudeProcess, caller: LocalPid) -> Result<Fingerprint, Failure> {
+    if let Some(fingerprint) = process.fingerprint() {
+        return Ok(fingerprint);
+    }
+
+    let exchange = process.begin_by(caller)?;
+    let capabilities = probe(process, &exchange)?;
+    drop(exchange);
+
+    let version = probe_version(&process.config().maude_path);
+    let fingerprint = Fingerprint {
+        release: version.as_deref().and_then(parse_release),
+        version,
+        capabilities,
+    };
+    Ok(process.remember_fingerprint(fingerprint))
+}
+
+/// The Maude version of the process, and the features it supports.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `Ok(Fingerprint)` - Map of `version`, `release` as
+///   `{major, minor, patch}`, and `capabilities`, a map of booleans
+/// * `Err` - If I/O fails or the selection can't be restored
+#[rustler::nif(schedule = "DirtyCpu")]
+fn version(env: Env, process: ResourceArc<MaudeProcess>) -> NifResult<Outcome<Fingerprint>> {
+    reply(fingerprint(&process, env.pid()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_release_numbers() {
+        let cases = [
+            ("3.5.1", Some((3, 5, 1))),
+            ("3.4", Some((3, 4, 0))),
+            ("3.0", Some((3, 0, 0))),
+            ("3.5.1-dev", Some((3, 5, 1))),
+            ("3.3beta", Some((3, 3, 0))),
+            ("Maude 3.5.1 built: Oct  1 2024 12:00:00", Some((3, 5, 1))),
+            (
+                "\t    Maude 3.2.2 built: Jan 11 2022 16:38:05",
+                Some((3, 2, 2)),
+            ),
+            ("alpha150", None),
+            ("Maude alpha150 built: Oct 10 2024 12:00:00", None),
+            ("3", None),
+            ("3.x", None),
+            ("", None),
+        ];
+
+        for (version, release) in cases {
+            assert_eq!(parse_release(version), release, "for {:?}", version);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Win32 pieces of subprocess management.
+//!
+//! Windows has no signals and no `poll` on anonymous pipes, so the Unix
+//! shims in [`crate::process`] are replaced by:
+//!
+//! * a job object per Maude child, created with "kill on close": stopping
+//!   the process terminates the whole job, so a `maude.bat` or `.cmd`
+//!   wrapper can't leave the real Maude running behind it, and a handle
+//!   that is dropped without `stop/1` takes its Maude down with it
+//! * `PeekNamedPipe` to see whether stdout or stderr has data, standing in
+//!   for `poll` and for non-blocking reads
+//!
+//! A child runs briefly before it is assigned to its job; anything it
+//! starts in that window is outside the job. Only the handful of kernel32
+//! functions used here are declared, which keeps the crate free of a
+//! Windows bindings dependency.
+
+use std::ffi::c_void;
+use std::io;
+use std::os::windows::io::AsRawHandle;
+use std::process::Child;
+use std::ptr;
+use std::time::Duration;
+
+/// How often a pipe is peeked while waiting for it to become readable.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+type Handle = *mut c_void;
+
+const ERROR_BROKEN_PIPE: i32 = 109;
+const JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE: u32 = 0x2000;
+const JOB_OBJECT_EXTENDED_LIMIT_INFORMATION: i32 = 9;
+
+#[repr(C)]
+#[derive(Default)]
+struct BasicLimitInformation {
+    per_process_user_time_limit: i64,
+    per_job_user_time_limit: i64,
+    limit_flags: u32,
+    minimum_working_set_size: usize,
+    maximum_working_set_size: usize,
+    active_process_limit: u32,
+    affinity: usize,
+    priority_class: u32,
+    scheduling_class: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct IoCounters {
+    read_operation_count: u64,
+    write_operation_count: u64,
+    other_operation_count: u64,
+    read_transfer_count: u64,
+    write_transfer_count: u64,
+    other_transfer_count: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ExtendedLimitInformation {
+    basic_limit_information: BasicLimitInformation,
+    io_info: IoCounters,
+    process_memory_limit: usize,
+    job_memory_limit: usize,
+    peak_process_memory_used: usize,
+    peak_job_memory_used: usize,
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn PeekNamedPipe(
+        pipe: Handle,
+        buffer: *mut c_void,
+        size: u32,
+        read: *mut u32,
+        available: *mut u32,
+        left: *mut u32,
+    ) -> i32;
+    fn CreateJobObjectW(attributes: *mut c_void, name: *const u16) -> Handle;
+    fn SetInformationJobObject(job: Handle, class: i32, info: *const c_void, length: u32) -> i32;
+    fn AssignProcessToJobObject(job: Handle, process: Handle) -> i32;
+    fn TerminateJobObject(job: Handle, exit_code: u32) -> i32;
+    fn CloseHandle(handle: Handle) -> i32;
+}
+
+/// Bytes waiting in `pipe`, or `None` once the writer has closed it.
+pub fn available(pipe: &impl AsRawHandle) -> io::Result<Option<u32>> {
+    let mut available = 0;
+
+    // SAFETY: the handle is open for the call and only `available` is
+    // written; a null buffer of size zero reads nothing.
+    let peeked = unsafe {
+        PeekNamedPipe(
+            pipe.as_raw_handle() as Handle,
+            ptr::null_mut(),
+            0,
+            ptr::null_mut(),
+            &mut available,
+            ptr::null_mut(),
+        )
+    };
+
+    if peeked != 0 {
+        return Ok(Some(available));
+    }
+
+    let e = io::Error::last_os_error();
+    if e.raw_os_error() == Some(ERROR_BROKEN_PIPE) {
+        Ok(None)
+    } else {
+        Err(e)
+    }
+}
+
+/// A job object holding one Maude child; closing it kills the child.
+pub struct Job(Handle);
+
+// SAFETY: a job handle may be used and closed from any thread.
+unsafe impl Send for Job {}
+unsafe impl Sync for Job {}
+
+impl Job {
+    /// Put `child` in a new kill-on-close job.
+    pub fn assign(child: &Child) -> io::Result<Job> {
+        // SAFETY: null attributes and name create an unnamed job with
+        // default security; the handle is owned by the returned `Job`.
+        let handle = unsafe { CreateJobObjectW(ptr::null_mut(), ptr::null()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let job = Job(handle);
+
+        let mut info = ExtendedLimitInformation::default();
+        info.basic_limit_information.limit_flags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+
+        // SAFETY: `info` is a valid JOBOBJECT_EXTENDED_LIMIT_INFORMATION
+        // for the call, and both handles are open.
+        unsafe {
+            if SetInformationJobObject(
+                job.0,
+                JOB_OBJECT_EXTENDED_LIMIT_INFORMATION,
+                &info as *const ExtendedLimitInformation as *const c_void,
+                std::mem::size_of::<ExtendedLimitInformation>() as u32,
+            ) == 0
+                || AssignProcessToJobObject(job.0, child.as_raw_handle() as Handle) == 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(job)
+    }
+
+    /// Kill every process in the job.
+    pub fn terminate(&self) {
+        // SAFETY: the handle is open until `drop`; a job whose processes
+        // are already gone just has nothing to terminate.
+        unsafe {
+            TerminateJobObject(self.0, 1);
+        }
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        // SAFETY: the handle is owned by this `Job` and closed only here.
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! A log of the raw bytes exchanged with Maude.
+//!
+//! With the `:wire_log` spawn option set, every byte the process writes to
+//! Maude's stdin and reads from its stdout and stderr is appended to the
+//! given file as it goes over the pipe, before any trimming, splitting, or
+//! decoding. Each write or read is one record: a header line with the wall
+//! clock time in seconds since the Unix epoch, the direction, and the
+//! number of bytes, then the bytes themselves and a newline:
+//!
+//! ```text
+//! 1760457600.123456 > 15
+//! reduce 1 + 2 .
+//!
+//! 1760457600.124012 < 108
+//! reduce in CONVERSION : 1 + 2 .
+//! rewrites: 1 in 0ms cpu (0ms real) (~ rewrites/second)
+//! result NzNat: 3
+//! Maude>
+//! ```
+//!
+//! `>` is stdin, `<` stdout, and `!` stderr; a `*` record notes the launch.
+//! The byte count makes the file parseable however odd the output, and
+//! the prompt's lack of a trailing newline visible.
+//!
+//! Records go straight to the file, unbuffered, so a log survives the
+//! BEAM crashing with it. The log is only a witness: a write that fails
+//! stops the logging, never the command.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Which way bytes went.
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+    Stdin,
+    Stdout,
+    Stderr,
+    /// Not bytes of the session but a note about it.
+    Note,
+}
+
+impl Direction {
+    fn mark(self) -> &'static str {
+        match self {
+            Direction::Stdin => ">",
+            Direction::Stdout => "<",
+            Direction::Stderr => "!",
+            Direction::Note => "*",
+        }
+    }
+}
+
+/// The log file of one process, if it has one. A leaf lock.
+#[derive(Debug, Default)]
+pub struct WireLog {
+    file: Option<Mutex<File>>,
+    /// Set once a write failed; nothing is logged afterwards.
+    failed: AtomicBool,
+}
+
+impl WireLog {
+    /// Open `path` for appending, creating it if need be.
+    pub fn open(path: &Path) -> Result<WireLog, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("wire log open failed: {}: {}", path.display(), e))?;
+
+        Ok(WireLog {
+            file: Some(Mutex::new(file)),
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    /// Append one record of `parts`, back to back, going `direction`.
+    pub fn record(&self, direction: Direction, parts: &[&[u8]]) {
+        self.write(direction, parts, b"");
+    }
+
+    /// [`WireLog::record`] of `parts` with a newline after them, as a
+    /// command line is written.
+    pub fn record_line(&self, direction: Direction, parts: &[&[u8]]) {
+        self.write(direction, parts, b"\n");
+    }
+
+    fn write(&self, direction: Direction, parts: &[&[u8]], end: &[u8]) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        if self.failed.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let bytes: usize = parts.iter().map(|part| part.len()).sum::<usize>() + end.len();
+        let header = format!(
+            "{}.{:06} {} {}\n",
+            since.as_secs(),
+            since.subsec_micros(),
+            direction.mark(),
+            bytes
+        );
+
+        let Ok(mut file) = file.lock() else {
+            return;
+        };
+        let written = std::iter::once(header.as_bytes())
+            .chain(parts.iter().copied())
+            .chain([end, b"\n"])
+            .try_for_each(|part| file.write_all(part));
+        if written.is_err() {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+}
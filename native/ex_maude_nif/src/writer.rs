@@ -0,0 +1,114 @@
+//! Streaming commands larger than a pipe holds.
+//!
+//! A pipe holds about 64 KiB. A bigger command - a generated configuration
+//! of a few megabytes, say - is written only as fast as Maude reads it,
+//! and Maude may stop reading to print: a warning per token it can't
+//! parse, or the output of an earlier command in the same input. Nobody
+//! reads that while the caller is still writing, so once stdout or stderr
+//! fills up both sides wait on each other for good.
+//!
+//! An execute call whose command is longer than [`STREAMED`] bytes writes
+//! it on a thread of its own, `ex_maude-writer`, in pieces of [`PIECE`]
+//! bytes, while the calling thread reads the response as it comes,
+//! draining stderr on the way. The writer follows the command with a load
+//! marker, and the reader takes everything up to the marker's warning, so
+//! output Maude prints before the last byte is written is read, not left
+//! in the pipe. The call returns once both are done, with the write's
+//! error first if it failed. `write_progress/1` shows how far a write has
+//! got, as `%{written, total}` in bytes, or `nil` while none is under way.
+//!
+//! Only execute calls stream. Pipelined commands are read well after they
+//! are written, by design, and raw input through `send_bytes/2` is the
+//! caller's to interleave with `recv_until/3`.
+
+use crate::failure::Failure;
+use crate::process::MaudeProcess;
+use rustler::{NifMap, ResourceArc};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Commands longer than this are streamed.
+pub const STREAMED: usize = 64 * 1024;
+
+/// Most bytes written to Maude at once while streaming.
+pub const PIECE: usize = 64 * 1024;
+
+/// How far a streamed command has been written.
+#[derive(Debug, NifMap)]
+#[rustler(encode)]
+pub struct WriteProgress {
+    pub written: u64,
+    pub total: u64,
+}
+
+/// The progress of a process's streamed write, if one is under way.
+#[derive(Debug, Default)]
+pub struct Progress {
+    written: AtomicU64,
+    /// Size of the command being written; zero if none is.
+    total: AtomicU64,
+}
+
+impl Progress {
+    pub fn start(&self, total: usize) {
+        self.written.store(0, Ordering::Relaxed);
+        self.total.store(total as u64, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, written: usize) {
+        self.written.fetch_add(written as u64, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self) {
+        self.total.store(0, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Option<WriteProgress> {
+        let total = self.total.load(Ordering::Relaxed);
+        (total > 0).then(|| WriteProgress {
+            written: self.written.load(Ordering::Relaxed).min(total),
+            total,
+        })
+    }
+}
+
+/// Whether a command made of `parts` is streamed.
+pub fn streams(parts: &[&[u8]]) -> bool {
+    parts.iter().map(|part| part.len()).sum::<usize>() > STREAMED
+}
+
+/// Run `write` on a writer thread while `read` runs on this one.
+///
+/// # Returns
+/// * `Ok(T)` - What `read` returned, both having succeeded
+/// * `Err` - The write's error if it failed, else the read's
+pub fn alongside<T>(
+    write: impl FnOnce() -> Result<(), Failure> + Send,
+    read: impl FnOnce() -> Result<T, Failure>,
+) -> Result<T, Failure> {
+    std::thread::scope(|scope| {
+        let writer = std::thread::Builder::new()
+            .name("ex_maude-writer".to_string())
+            .spawn_scoped(scope, write)
+            .map_err(|e| format!("writer failed to start: {}", e))?;
+
+        let read = read();
+        let written = writer
+            .join()
+            .unwrap_or_else(|_| Err("writer panicked".into()));
+        written.and(read)
+    })
+}
+
+/// Report how far the command being streamed to Maude has been written.
+///
+/// # Arguments
+/// * `process` - Handle to the Maude process
+///
+/// # Returns
+/// * `WriteProgress` - `%{written, total}` in bytes, while an execute call
+///   streams its command
+/// * `nil` - If none does
+#[rustler::nif]
+fn write_progress(process: ResourceArc<MaudeProcess>) -> Option<WriteProgress> {
+    process.write_progress()
+}
@@ -84,12 +84,22 @@ defmodule ExMaude.Backend.NIFTest do
       assert String.contains?(error.message, "not yet implemented")
     end
 
+    test "execute accepts a scheduler option", %{server: pid} do
+      assert {:error, error} = NIF.execute(pid, "reduce in NAT : 1 + 1 .", scheduler: :cpu)
+      assert error.type == :not_implemented
+    end
+
     test "load_file returns not_implemented error", %{server: pid} do
       result = NIF.load_file(pid, "/path/to/file.maude")
 
       assert {:error, error} = result
       assert error.type == :not_implemented
     end
+
+    test "stats returns not_implemented error", %{server: pid} do
+      assert {:error, error} = NIF.stats(pid)
+      assert error.type == :not_implemented
+    end
   end
 
   describe "availability" do
@@ -38,6 +38,12 @@ defmodule ExMaude.TelemetryTest do
       assert [:ex_maude, :pool, :checkout, :stop] in events
     end
 
+    test "includes nif backend events" do
+      events = Telemetry.events()
+
+      assert [:ex_maude, :server, :stats] in events
+    end
+
     test "includes iot events" do
       events = Telemetry.events()
 